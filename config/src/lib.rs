@@ -1,6 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crypto::{generate_production_keypair, PublicKey, SecretKey};
-use log::info;
+use log::{info, warn};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
@@ -10,6 +10,10 @@ use std::io::Write as _;
 use std::net::SocketAddr;
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -23,6 +27,9 @@ pub enum ConfigError {
 
     #[error("Failed to write config file '{file}': {message}")]
     ExportError { file: String, message: String },
+
+    #[error("Failed to read key pair from environment variable '{var}': {message}")]
+    KeyEnvError { var: String, message: String },
 }
 
 pub trait Import: DeserializeOwned {
@@ -57,6 +64,51 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+pub type Epoch = u64;
+
+/// How many parents (by stake) a primary waits for before proposing a new header.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParentQuorumPolicy {
+    /// Wait for 2f+1 parents.
+    #[default]
+    Quorum,
+    /// Wait for f+1 parents only. Rounds are faster but safety is reduced; only use for research.
+    Validity,
+}
+
+impl ParentQuorumPolicy {
+    /// Returns the stake of parents required by this policy.
+    pub fn threshold(&self, committee: &Committee) -> Stake {
+        match self {
+            Self::Quorum => committee.quorum_threshold(),
+            Self::Validity => committee.validity_threshold(),
+        }
+    }
+}
+
+/// What a worker does with a batch that not enough workers acknowledged by the quorum-wait deadline.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuorumWaitFallback {
+    /// Re-broadcast the batch to the workers that did not acknowledge it yet (and keep waiting).
+    #[default]
+    Rebroadcast,
+    /// Deliver the batch as soon as the specified stake (including our own) acknowledged it.
+    LowerThreshold(Stake),
+}
+
+/// What a primary does when one of its tasks panics.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SupervisionPolicy {
+    /// Log the panic and shut the node down (rather than leaving it half-dead).
+    #[default]
+    Abort,
+    /// Log the panic and run the task again, rebuilt from its configuration (dropping the in-memory state
+    /// the panic may have left inconsistent). Fatal panics, such as storage failures, still shut the node down.
+    Restart,
+}
 
 #[derive(Deserialize, Clone)]
 pub struct Parameters {
@@ -71,16 +123,231 @@ pub struct Parameters {
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
     pub sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request. These nodes
-    /// are picked at random from the committee.
+    /// are picked at random from the committee, favoring those with a large stake that responded
+    /// quickly to the previous requests.
     pub sync_retry_nodes: usize,
     /// The preferred batch size. The workers seal a batch of transactions when it reaches this size.
     /// Denominated in bytes.
     pub batch_size: usize,
-    /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
+    /// The delay after which the workers seal a batch of transactions, even if `batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
     /// Whether brokers verifiy transactions or not
     pub enable_verification: bool,
+    /// The number of transactions remembered by each generation of the workers' duplicate filter.
+    /// The filter is rotated once a generation is full. Deduplication is disabled if set to 0.
+    #[serde(default)]
+    pub dedup_capacity: usize,
+    /// The target false-positive rate of each generation of the workers' duplicate filter.
+    #[serde(default = "Parameters::default_dedup_false_positive_rate")]
+    pub dedup_false_positive_rate: f64,
+    /// The maximum number of simultaneous incoming connections accepted by each network receiver
+    /// of the primary and workers. There is no limit if set to 0.
+    #[serde(default)]
+    pub max_inbound_connections: usize,
+    /// The maximum number of batches each worker serves per second to sync other workers. Requests
+    /// exceeding the limit are dropped (the requestors retry later). There is no limit if set to 0.
+    #[serde(default)]
+    pub helper_rate_limit: usize,
+    /// The maximum number of batches each worker serves per second to a single peer. There is no
+    /// limit if set to 0.
+    #[serde(default)]
+    pub helper_peer_rate_limit: usize,
+    /// The stake of parents the primary waits for before proposing a new header (2f+1 by default).
+    #[serde(default)]
+    pub parent_quorum_policy: ParentQuorumPolicy,
+    /// Whether the primary drops certificates it already processed before verifying them again
+    /// (certificates typically arrive from several peers). The certificates found in the store
+    /// count as processed, so this also holds across restarts.
+    #[serde(default = "Parameters::default_dedup_certificates")]
+    pub dedup_certificates: bool,
+    /// The number of certificate verification verdicts (valid or invalid) the primary remembers to
+    /// avoid verifying the same certificate again. The cache is disabled if set to 0.
+    #[serde(default)]
+    pub verification_cache_size: usize,
+    /// The number of committed rounds between two compactions of the round index entries the garbage
+    /// collector deleted from the primary's store. Compaction is disabled if set to 0.
+    #[serde(default)]
+    pub compaction_interval: u64,
+    /// The time after which the primary raises an alarm for certificates that are stored but still
+    /// not committed. Denominated in ms. The alarm is disabled if set to 0.
+    #[serde(default)]
+    pub certificate_age_threshold: u64,
+    /// The delay after which the primary re-sends its latest header to the authorities that did not
+    /// vote for it yet. Denominated in ms. Votes are never re-requested if set to 0.
+    #[serde(default)]
+    pub vote_timeout: u64,
+    /// Whether primaries agree on the message format version before exchanging consensus messages.
+    /// Peers speaking an incompatible version are disconnected. All primaries must agree on this flag.
+    #[serde(default)]
+    pub enable_handshake: bool,
+    /// The maximum number of weak links to certificates of earlier rounds (that did not make it as
+    /// parents) the primary includes in its headers. Weak links are disabled if set to 0. The primary
+    /// also rejects the headers of other authorities carrying more weak links, so all primaries of the
+    /// committee should use the same value.
+    #[serde(default)]
+    pub max_weak_links: usize,
+    /// The minimum number of distinct authors among the parents of our headers (capped at the committee
+    /// size). The proposer waits for parents from that many authors, on top of the stake required by the
+    /// `parent_quorum_policy`. Only the `parent_quorum_policy` applies if set to 0.
+    #[serde(default)]
+    pub min_distinct_parent_authors: usize,
+    /// Whether we also reject the headers of other authorities whose parents come from fewer than
+    /// `min_distinct_parent_authors` distinct authors.
+    #[serde(default)]
+    pub enforce_parent_diversity: bool,
+    /// The number of rounds a primary may lag behind its peers (f+1 stake of them) before it stops
+    /// proposing. Until it caught up, it only range-syncs certificates from its peers. Catch-up mode is
+    /// disabled if set to 0.
+    #[serde(default)]
+    pub catch_up_round_gap: u64,
+    /// The number of rounds a header may be ahead of our dag before the primary range-syncs the
+    /// missing rounds (rather than requesting the header's missing parents one by one). Range sync
+    /// is disabled if set to 0.
+    #[serde(default)]
+    pub sync_round_gap: u64,
+    /// Whether the workers interleave the transactions of their clients (round-robin across client
+    /// connections) when making batches, rather than batching them in arrival order.
+    #[serde(default)]
+    pub fair_batching: bool,
+    /// Whether the primary skips the signatures verification of votes and certificates (their structure
+    /// is still checked). This is INSECURE and only meant for local test networks of trusted nodes, so
+    /// it must be explicitly enabled.
+    #[serde(default)]
+    pub trusted_network: bool,
+    /// The maximum delay between two headers of the primary when there is no payload to propose (no new
+    /// batch and none in the recent rounds of the dag). Until then, empty headers are skipped. Denominated
+    /// in ms. Empty headers are never skipped if set to 0.
+    #[serde(default)]
+    pub empty_header_delay: u64,
+    /// The size after which the primary's audit log (if enabled) is rotated. The audit log thus never
+    /// takes more than twice this size on disk. Denominated in bytes.
+    #[serde(default = "Parameters::default_audit_log_size")]
+    pub audit_log_size: u64,
+    /// The hard maximum size of the workers' batches. The workers seal a batch early rather than exceeding
+    /// it, and reject (with a reply to the client) the transactions larger than it. Denominated in bytes.
+    /// There is no limit if set to 0.
+    #[serde(default)]
+    pub max_batch_size: usize,
+    /// The maximum number of transactions of the workers' batches. There is no limit if set to 0.
+    #[serde(default)]
+    pub max_batch_txs: usize,
+    /// The stake of workers (including our own) that must acknowledge a batch before the worker reports
+    /// it to its primary, that is, the replication factor of the batches (capped at the total stake).
+    /// The workers wait for a quorum (2f+1) if set to 0.
+    #[serde(default)]
+    pub batch_ack_threshold: Stake,
+    /// The delay after which the primary re-broadcasts its certificates that a quorum of authorities did
+    /// not acknowledge yet (e.g., after a network outage). Pending certificates are persisted so they are
+    /// re-broadcast after a restart. Denominated in ms. Certificates are never re-broadcast if set to 0.
+    #[serde(default)]
+    pub certificate_rebroadcast_delay: u64,
+    /// The number of committed rounds between two checkpoints of the commit sequence output by the
+    /// primary (see `Primary::spawn_with_output`). Checkpoints are never emitted if set to 0.
+    #[serde(default)]
+    pub checkpoint_interval: u64,
+    /// The policy applied when a task of the primary panics, per task name ("core", "proposer",
+    /// "garbage_collector", "header_waiter", "certificate_waiter", or "helper"). The tasks not listed abort
+    /// the node.
+    #[serde(default)]
+    pub supervision_policies: HashMap<String, SupervisionPolicy>,
+    /// The maximum number of rounds the primary may advance past the last committed round. This is a hard
+    /// stop bounding the memory of the dag during long partitions (when consensus cannot commit). There is
+    /// no limit if set to 0.
+    #[serde(default)]
+    pub max_dag_depth: u64,
+    /// The maximum number of outstanding sync requests (for missing parents or batches) of the primary.
+    /// Further syncs wait for earlier ones to complete, pacing the recovery of a primary that fell behind.
+    /// There is no limit if set to 0.
+    #[serde(default)]
+    pub max_pending_sync_requests: usize,
+    /// Whether the primary initially sends its headers to a random subset of authorities holding a
+    /// quorum of stake (plus `header_broadcast_margin`) rather than to all of them. The header is sent to
+    /// the remaining authorities if their votes are still needed after `vote_timeout`, so this option
+    /// has no effect if `vote_timeout` is 0.
+    #[serde(default)]
+    pub partial_header_broadcast: bool,
+    /// The stake beyond the quorum threshold of the authorities we initially send our headers to (if
+    /// `partial_header_broadcast` is set).
+    #[serde(default = "Parameters::default_header_broadcast_margin")]
+    pub header_broadcast_margin: Stake,
+    /// The number of consecutive headers of ours that must miss the certificates of an authority before we
+    /// consider it slow and stop waiting for its certificate when it is the leader. Slow authorities still
+    /// count towards every quorum. Authorities are never considered slow if set to 0.
+    #[serde(default)]
+    pub slow_peer_rounds: u64,
+    /// The delay after which the workers apply the `quorum_wait_fallback` to the batches that not enough
+    /// workers acknowledged (see `batch_ack_threshold`). Denominated in ms. The workers wait for the
+    /// acknowledgements indefinitely if set to 0.
+    #[serde(default)]
+    pub quorum_wait_deadline: u64,
+    /// What the workers do with the batches that not enough workers acknowledged by the `quorum_wait_deadline`.
+    #[serde(default)]
+    pub quorum_wait_fallback: QuorumWaitFallback,
+    /// The number of rounds below the garbage collection round (`gc_depth` rounds below the last committed
+    /// round) during which the consensus keeps the certificates of its dag, so that commits in flight can
+    /// still traverse them (they are not committed anymore). Certificates are pruned at the garbage
+    /// collection round if set to 0.
+    #[serde(default)]
+    pub gc_grace_rounds: u64,
+    /// The time during which the primary holds its votes for the headers of an authority, to send them
+    /// in a single message. Denominated in ms. Votes are sent as soon as they are made if set to 0.
+    #[serde(default)]
+    pub vote_batch_window: u64,
+    /// Whether the primary prioritizes its headers for the rounds it leads (which are needed to commit):
+    /// it proposes them as soon as it has enough parents (without waiting for a full payload or the
+    /// `max_header_delay`), and sends them to every authority (even with `partial_header_broadcast`).
+    #[serde(default)]
+    pub leader_priority: bool,
+    /// The time (in ms) after which a certificate waiting for its ancestors is dropped (and left to the
+    /// anti-entropy mechanisms to re-deliver), bounding the memory held by certificates whose ancestors
+    /// never arrive. 0 disables the limit.
+    #[serde(default)]
+    pub waiting_certificate_max_age: u64,
+    /// The number of certificates whose writes the primary buffers, to write them to the store in a single
+    /// (atomic) batch. The certificates are only handed to the consensus and the proposer once written.
+    /// Certificates are written one by one if set to 0.
+    #[serde(default)]
+    pub certificate_write_batch_size: usize,
+    /// The maximum time during which the primary buffers certificate writes (when batching them), even if
+    /// the batch is not full. Denominated in ms.
+    #[serde(default = "Parameters::default_certificate_write_batch_delay")]
+    pub certificate_write_batch_delay: u64,
+    /// Whether the primary compresses (with zstd) its replies to range and missing certificates requests,
+    /// which may carry many certificates. Replies to the requests of specific certificates are never
+    /// compressed. Peers agree on the message format (including compressed replies) during the handshake,
+    /// so replies are only compressed if `enable_handshake` is set.
+    #[serde(default)]
+    pub compress_sync_responses: bool,
+    /// The number of workers (including its own) each worker expects to acknowledge its batches. The workers
+    /// report to their primary how many workers acknowledged each of their batches by the time they deliver
+    /// it (see `batch_ack_threshold`), and the primary's health lists the batches below this target. The
+    /// workers do not report the replication of their batches if set to 0.
+    #[serde(default)]
+    pub batch_replication_target: usize,
+    /// The maximum number of headers the primary buffers while they wait for their dependencies (or for a
+    /// sync permit, see `max_pending_sync_requests`). The lowest-round headers are dropped beyond this
+    /// limit; they are synced again if received again. There is no limit if set to 0.
+    #[serde(default)]
+    pub max_pending_headers: usize,
+    /// The time (in ms) after which the workers close the connections of clients that did not send any
+    /// transaction, freeing their resources. The connections between authorities are never closed for
+    /// being idle. There is no timeout if set to 0.
+    #[serde(default)]
+    pub client_idle_timeout: u64,
+    /// The maximum number of transactions per second each worker accepts from a single client connection.
+    /// The excess transactions are dropped and the client is told so. There is no limit if set to 0.
+    #[serde(default)]
+    pub max_client_transaction_rate: usize,
+    /// The maximum number of headers the primary verifies concurrently when they arrive in a burst. They
+    /// are still processed one at a time, in the order they arrived. The headers are verified one at a
+    /// time if set to 0 or 1.
+    #[serde(default)]
+    pub header_verification_parallelism: usize,
+    /// Whether the primary persists its latest header and the votes it gathered for it, so that it resumes
+    /// collecting the votes after a restart (instead of waiting for a new header to be certified).
+    #[serde(default)]
+    pub persist_pending_votes: bool,
 }
 
 impl Default for Parameters {
@@ -94,6 +361,53 @@ impl Default for Parameters {
             batch_size: 500_000,
             max_batch_delay: 100,
             enable_verification: false,
+            dedup_capacity: 0,
+            dedup_false_positive_rate: Self::default_dedup_false_positive_rate(),
+            max_inbound_connections: 0,
+            helper_rate_limit: 0,
+            helper_peer_rate_limit: 0,
+            parent_quorum_policy: ParentQuorumPolicy::default(),
+            dedup_certificates: Self::default_dedup_certificates(),
+            verification_cache_size: 0,
+            compaction_interval: 0,
+            certificate_age_threshold: 0,
+            vote_timeout: 0,
+            enable_handshake: false,
+            max_weak_links: 0,
+            min_distinct_parent_authors: 0,
+            enforce_parent_diversity: false,
+            catch_up_round_gap: 0,
+            sync_round_gap: 0,
+            fair_batching: false,
+            trusted_network: false,
+            empty_header_delay: 0,
+            audit_log_size: Self::default_audit_log_size(),
+            max_batch_size: 0,
+            max_batch_txs: 0,
+            batch_ack_threshold: 0,
+            certificate_rebroadcast_delay: 0,
+            checkpoint_interval: 0,
+            supervision_policies: HashMap::new(),
+            max_dag_depth: 0,
+            max_pending_sync_requests: 0,
+            partial_header_broadcast: false,
+            header_broadcast_margin: Self::default_header_broadcast_margin(),
+            slow_peer_rounds: 0,
+            quorum_wait_deadline: 0,
+            quorum_wait_fallback: QuorumWaitFallback::default(),
+            gc_grace_rounds: 0,
+            vote_batch_window: 0,
+            leader_priority: false,
+            waiting_certificate_max_age: 0,
+            certificate_write_batch_size: 0,
+            certificate_write_batch_delay: Self::default_certificate_write_batch_delay(),
+            compress_sync_responses: false,
+            batch_replication_target: 0,
+            max_pending_headers: 0,
+            client_idle_timeout: 0,
+            max_client_transaction_rate: 0,
+            header_verification_parallelism: 0,
+            persist_pending_votes: false,
         }
     }
 }
@@ -101,14 +415,277 @@ impl Default for Parameters {
 impl Import for Parameters {}
 
 impl Parameters {
+    // The defaults of the optional parameters that are not the default of their type.
+    fn default_dedup_false_positive_rate() -> f64 {
+        0.001
+    }
+
+    fn default_dedup_certificates() -> bool {
+        true
+    }
+
+    fn default_audit_log_size() -> u64 {
+        100_000_000
+    }
+
+    fn default_header_broadcast_margin() -> Stake {
+        1
+    }
+
+    fn default_certificate_write_batch_delay() -> u64 {
+        10
+    }
+
     pub fn log(&self) {
         info!("Header size set to {} B", self.header_size);
         info!("Max header delay set to {} ms", self.max_header_delay);
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
+        if self.gc_grace_rounds > 0 {
+            info!(
+                "Garbage collection grace set to {} rounds",
+                self.gc_grace_rounds
+            );
+        } else {
+            info!("Garbage collection grace DISABLED");
+        }
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        if self.max_batch_size > 0 {
+            info!("Max batch size set to {} B", self.max_batch_size);
+        } else {
+            info!("Max batch size UNLIMITED");
+        }
+        if self.max_batch_txs > 0 {
+            info!("Max batch transactions set to {}", self.max_batch_txs);
+        } else {
+            info!("Max batch transactions UNLIMITED");
+        }
+        if self.batch_ack_threshold > 0 {
+            info!("Batch ack threshold set to {} stake", self.batch_ack_threshold);
+        } else {
+            info!("Batch ack threshold set to a quorum");
+        }
+        if self.quorum_wait_deadline > 0 {
+            info!(
+                "Quorum wait deadline set to {} ms (fallback {:?})",
+                self.quorum_wait_deadline, self.quorum_wait_fallback
+            );
+        } else {
+            info!("Quorum wait deadline DISABLED");
+        }
+        match self.batch_replication_target {
+            0 => info!("Batch replication reporting DISABLED"),
+            x => info!("Batch replication target set to {} workers", x),
+        }
+        if self.dedup_capacity > 0 {
+            info!(
+                "Transactions deduplication set to {} txs (false-positive rate {})",
+                self.dedup_capacity, self.dedup_false_positive_rate
+            );
+        } else {
+            info!("Transactions deduplication DISABLED");
+        }
+        if self.max_inbound_connections > 0 {
+            info!(
+                "Max inbound connections set to {}",
+                self.max_inbound_connections
+            );
+        } else {
+            info!("Max inbound connections UNLIMITED");
+        }
+        match self.client_idle_timeout {
+            0 => info!("Client idle timeout DISABLED"),
+            x => info!("Client idle timeout set to {} ms", x),
+        }
+        match self.max_client_transaction_rate {
+            0 => info!("Max client transaction rate UNLIMITED"),
+            x => info!("Max client transaction rate set to {} tx/s", x),
+        }
+        if self.helper_rate_limit > 0 {
+            info!("Helper rate limit set to {} batches/s", self.helper_rate_limit);
+        } else {
+            info!("Helper rate limit UNLIMITED");
+        }
+        if self.helper_peer_rate_limit > 0 {
+            info!(
+                "Helper peer rate limit set to {} batches/s",
+                self.helper_peer_rate_limit
+            );
+        } else {
+            info!("Helper peer rate limit UNLIMITED");
+        }
+        info!(
+            "Parent quorum policy set to {:?}",
+            self.parent_quorum_policy
+        );
+        if self.dedup_certificates {
+            info!("Certificates deduplication ENABLED");
+        } else {
+            info!("Certificates deduplication DISABLED");
+        }
+        if self.verification_cache_size > 0 {
+            info!(
+                "Verification cache size set to {} certificates",
+                self.verification_cache_size
+            );
+        } else {
+            info!("Verification cache DISABLED");
+        }
+        if self.compaction_interval > 0 {
+            info!(
+                "Store compaction interval set to {} rounds",
+                self.compaction_interval
+            );
+        } else {
+            info!("Store compaction DISABLED");
+        }
+        if self.certificate_age_threshold > 0 {
+            info!(
+                "Certificate age threshold set to {} ms",
+                self.certificate_age_threshold
+            );
+        } else {
+            info!("Certificate age alarm DISABLED");
+        }
+        if self.vote_timeout > 0 {
+            info!("Vote timeout set to {} ms", self.vote_timeout);
+        } else {
+            info!("Votes re-request DISABLED");
+        }
+        if self.vote_batch_window > 0 {
+            info!("Vote batch window set to {} ms", self.vote_batch_window);
+        } else {
+            info!("Votes batching DISABLED");
+        }
+        if self.certificate_write_batch_size > 0 {
+            info!(
+                "Certificate write batches of up to {} certificates (flushed within {} ms)",
+                self.certificate_write_batch_size, self.certificate_write_batch_delay
+            );
+        } else {
+            info!("Certificate write batching DISABLED");
+        }
+        if self.compress_sync_responses && self.enable_handshake {
+            info!("Sync responses compression ENABLED");
+        } else {
+            info!("Sync responses compression DISABLED");
+        }
+        if self.leader_priority {
+            info!("Leader headers priority ENABLED");
+        } else {
+            info!("Leader headers priority DISABLED");
+        }
+        match self.waiting_certificate_max_age {
+            0 => info!("Waiting certificates max age DISABLED"),
+            x => info!("Waiting certificates max age set to {} ms", x),
+        }
+        match (self.partial_header_broadcast, self.vote_timeout > 0) {
+            (true, true) => info!(
+                "Partial header broadcast ENABLED (margin of {} stake)",
+                self.header_broadcast_margin
+            ),
+            (true, false) => warn!("Partial header broadcast requires a vote timeout: DISABLED"),
+            (false, _) => info!("Partial header broadcast DISABLED"),
+        }
+        if self.slow_peer_rounds > 0 {
+            info!("Slow peers detected after {} rounds", self.slow_peer_rounds);
+        } else {
+            info!("Slow peers detection DISABLED");
+        }
+        if self.enable_handshake {
+            info!("Peers handshake ENABLED");
+        } else {
+            info!("Peers handshake DISABLED");
+        }
+        if self.max_weak_links > 0 {
+            info!("Max weak links set to {}", self.max_weak_links);
+        } else {
+            info!("Weak links DISABLED");
+        }
+        if self.catch_up_round_gap > 0 {
+            info!("Catch-up round gap set to {} rounds", self.catch_up_round_gap);
+        } else {
+            info!("Catch-up mode DISABLED");
+        }
+        if self.sync_round_gap > 0 {
+            info!("Sync round gap set to {} rounds", self.sync_round_gap);
+        } else {
+            info!("Range sync DISABLED");
+        }
+        if self.fair_batching {
+            info!("Fair batching ENABLED");
+        } else {
+            info!("Fair batching DISABLED");
+        }
+        if self.empty_header_delay > 0 {
+            info!("Empty header delay set to {} ms", self.empty_header_delay);
+        } else {
+            info!("Empty headers suppression DISABLED");
+        }
+        info!("Audit log size set to {} B", self.audit_log_size);
+        if self.certificate_rebroadcast_delay > 0 {
+            info!(
+                "Certificate rebroadcast delay set to {} ms",
+                self.certificate_rebroadcast_delay
+            );
+        } else {
+            info!("Certificates rebroadcast DISABLED");
+        }
+        if self.checkpoint_interval > 0 {
+            info!("Checkpoint interval set to {} rounds", self.checkpoint_interval);
+        } else {
+            info!("Commit checkpoints DISABLED");
+        }
+        if self.min_distinct_parent_authors > 0 {
+            info!(
+                "Min distinct parent authors set to {}{}",
+                self.min_distinct_parent_authors,
+                if self.enforce_parent_diversity { " (enforced)" } else { "" }
+            );
+        } else {
+            info!("Parent diversity requirement DISABLED");
+        }
+        if self.max_dag_depth > 0 {
+            info!("Max dag depth set to {} rounds", self.max_dag_depth);
+        } else {
+            info!("Max dag depth UNLIMITED");
+        }
+        if self.max_pending_sync_requests > 0 {
+            info!(
+                "Max pending sync requests set to {}",
+                self.max_pending_sync_requests
+            );
+        } else {
+            info!("Max pending sync requests UNLIMITED");
+        }
+        if self.max_pending_headers > 0 {
+            info!("Max pending headers set to {}", self.max_pending_headers);
+        } else {
+            info!("Max pending headers UNLIMITED");
+        }
+        if self.header_verification_parallelism > 1 {
+            info!(
+                "Header verification parallelism set to {}",
+                self.header_verification_parallelism
+            );
+        } else {
+            info!("Parallel header verification DISABLED");
+        }
+        if self.persist_pending_votes {
+            info!("Pending votes persistence ENABLED");
+        } else {
+            info!("Pending votes persistence DISABLED");
+        }
+        let mut supervision_policies: Vec<_> = self.supervision_policies.iter().collect();
+        supervision_policies.sort_by_key(|(task, _)| *task);
+        for (task, policy) in supervision_policies {
+            info!("Supervision policy of task {} set to {:?}", task, policy);
+        }
+        if self.trusted_network {
+            warn!("Trusted network ENABLED: votes and certificates signatures are NOT verified (INSECURE)");
+        }
         if self.enable_verification {
             info!("Verification ENABLED");
         } else {
@@ -160,7 +737,14 @@ impl Subscriptions {
 
 #[derive(Clone, Deserialize)]
 pub struct Committee {
+    /// The epoch of the committee, incremented with every reconfiguration.
+    #[serde(default)]
+    pub epoch: Epoch,
     pub authorities: BTreeMap<PublicKey, Authority>,
+    /// The read-only nodes following the dag (e.g., analytics or gateways) and the addresses of their
+    /// primary. Observers hold no stake: they never propose nor vote.
+    #[serde(default)]
+    pub observers: BTreeMap<PublicKey, PrimaryAddresses>,
 }
 
 impl Import for Committee {}
@@ -201,10 +785,42 @@ impl Committee {
         (total_votes + 2) / 3
     }
 
-    /// Returns a leader node in a round-robin fashion.
+    /// Returns the maximum stake an adversary may control (f) without breaking the protocol: the
+    /// honest stake must reach a quorum on its own and f stake must stay below the validity threshold.
+    pub fn fault_tolerance(&self) -> Stake {
+        let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
+        let quorum_slack = total_votes.saturating_sub(self.quorum_threshold());
+        let validity_slack = self.validity_threshold().saturating_sub(1);
+        std::cmp::min(quorum_slack, validity_slack)
+    }
+
+    /// Returns whether the stake distribution is safe, that is, no single authority holds more stake
+    /// than the committee can tolerate as adversarial (see `fault_tolerance`).
+    pub fn is_safe(&self) -> bool {
+        let f = self.fault_tolerance();
+        self.authorities.values().all(|x| x.stake <= f)
+    }
+
+    /// Returns the committee of the next epoch, where the listed authorities hold the specified stake (the
+    /// others keep their current stake). All quorum computations of the new committee use the updated
+    /// stakes. The membership cannot change this way: every listed authority must be in the committee.
+    pub fn with_stakes(&self, stakes: &BTreeMap<PublicKey, Stake>) -> Result<Self, ConfigError> {
+        let mut committee = self.clone();
+        committee.epoch += 1;
+        for (name, stake) in stakes {
+            committee
+                .authorities
+                .get_mut(name)
+                .ok_or(ConfigError::NotInCommittee(*name))?
+                .stake = *stake;
+        }
+        Ok(committee)
+    }
+
+    /// Returns a leader node in a round-robin fashion. Authorities take turns in the order of their public
+    /// keys (whatever their stake), so that all nodes agree on the leader of every round.
     pub fn leader(&self, seed: usize) -> PublicKey {
-        let mut keys: Vec<_> = self.authorities.keys().cloned().collect();
-        keys.sort();
+        let keys: Vec<_> = self.authorities.keys().cloned().collect();
         keys[seed % self.size()]
     }
 
@@ -216,6 +832,27 @@ impl Committee {
             .ok_or_else(|| ConfigError::NotInCommittee(*to))
     }
 
+    /// Returns the primary addresses of the target observer.
+    pub fn observer(&self, to: &PublicKey) -> Result<PrimaryAddresses, ConfigError> {
+        self.observers
+            .get(to)
+            .cloned()
+            .ok_or(ConfigError::NotInCommittee(*to))
+    }
+
+    /// Returns the primary addresses of the target authority or observer.
+    pub fn primary_or_observer(&self, to: &PublicKey) -> Result<PrimaryAddresses, ConfigError> {
+        self.primary(to).or_else(|_| self.observer(to))
+    }
+
+    /// Returns the addresses of all observers.
+    pub fn observers_primaries(&self) -> Vec<(PublicKey, PrimaryAddresses)> {
+        self.observers
+            .iter()
+            .map(|(name, addresses)| (*name, addresses.clone()))
+            .collect()
+    }
+
     /// Returns the addresses of all primaries except `myself`.
     pub fn others_primaries(&self, myself: &PublicKey) -> Vec<(PublicKey, PrimaryAddresses)> {
         self.authorities
@@ -297,3 +934,31 @@ impl Default for KeyPair {
         Self::new()
     }
 }
+
+/// Where a node loads its key pair from. Deployments keeping the secret key in an external system (e.g.,
+/// a KMS) rather provide the primary with a `crypto::Signer`, so that the node never sees the raw key.
+#[derive(Clone, Debug)]
+pub enum KeySource {
+    /// A key file (as printed by `generate_keys`).
+    File(String),
+    /// An environment variable holding the content of a key file.
+    Env(String),
+}
+
+impl KeySource {
+    pub fn load(&self) -> Result<KeyPair, ConfigError> {
+        match self {
+            Self::File(path) => KeyPair::import(path),
+            Self::Env(var) => {
+                let reader = || -> Result<KeyPair, String> {
+                    let data = std::env::var(var).map_err(|e| e.to_string())?;
+                    serde_json::from_str(&data).map_err(|e| e.to_string())
+                };
+                reader().map_err(|message| ConfigError::KeyEnvError {
+                    var: var.clone(),
+                    message,
+                })
+            }
+        }
+    }
+}
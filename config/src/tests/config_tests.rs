@@ -0,0 +1,120 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn committee(stakes: &[Stake]) -> Committee {
+    let address = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    Committee {
+        epoch: 0,
+        authorities: stakes
+            .iter()
+            .map(|stake| {
+                let (name, _) = generate_production_keypair();
+                let authority = Authority {
+                    stake: *stake,
+                    primary: PrimaryAddresses {
+                        primary_to_primary: address,
+                        worker_to_primary: address,
+                    },
+                    workers: HashMap::new(),
+                };
+                (name, authority)
+            })
+            .collect(),
+        observers: BTreeMap::new(),
+    }
+}
+
+#[test]
+fn fault_tolerance_balanced() {
+    // N = 3f + 1 + k tolerates f faults.
+    assert_eq!(committee(&[1; 4]).fault_tolerance(), 1);
+    assert_eq!(committee(&[1; 6]).fault_tolerance(), 1);
+    assert_eq!(committee(&[1; 7]).fault_tolerance(), 2);
+    assert_eq!(committee(&[1; 10]).fault_tolerance(), 3);
+    assert!(committee(&[1; 4]).is_safe());
+
+    // Small committees do not tolerate any fault.
+    assert_eq!(committee(&[1; 3]).fault_tolerance(), 0);
+    assert!(!committee(&[1; 3]).is_safe());
+}
+
+#[test]
+fn fault_tolerance_skewed() {
+    // The tolerance only depends on the total stake...
+    assert_eq!(committee(&[2, 2, 2, 1]).fault_tolerance(), 2);
+    assert_eq!(committee(&[1, 1, 1, 7]).fault_tolerance(), 3);
+
+    // ...but a single authority may hold more than what can be tolerated.
+    assert!(committee(&[2, 2, 2, 1]).is_safe());
+    assert!(!committee(&[1, 1, 1, 7]).is_safe());
+}
+
+#[test]
+fn load_keys_from_env() {
+    let keypair = KeyPair::new();
+    let data = serde_json::to_string(&keypair).unwrap();
+    std::env::set_var("NARWHAL_TEST_KEYS", data);
+
+    let loaded = KeySource::Env("NARWHAL_TEST_KEYS".to_string()).load().unwrap();
+    assert_eq!(loaded.name, keypair.name);
+    assert_eq!(loaded.secret.encode_base64(), keypair.secret.encode_base64());
+
+    // A missing variable is reported as such.
+    match KeySource::Env("NARWHAL_TEST_MISSING_KEYS".to_string()).load() {
+        Err(ConfigError::KeyEnvError { var, .. }) => assert_eq!(var, "NARWHAL_TEST_MISSING_KEYS"),
+        x => panic!("Unexpected result: {:?}", x.map(|x| x.name)),
+    }
+}
+
+#[test]
+fn update_stakes_across_epochs() {
+    let committee = committee(&[1; 4]);
+    assert_eq!(committee.quorum_threshold(), 3);
+    assert_eq!(committee.validity_threshold(), 2);
+
+    // One authority gets more stake in the next epoch.
+    let name = *committee.authorities.keys().next().unwrap();
+    let stakes = [(name, 4)].iter().cloned().collect();
+    let next = committee.with_stakes(&stakes).unwrap();
+    assert_eq!(next.epoch, committee.epoch + 1);
+    assert_eq!(next.stake(&name), 4);
+    assert_eq!(next.quorum_threshold(), 5);
+    assert_eq!(next.validity_threshold(), 3);
+
+    // The current epoch is unaffected.
+    assert_eq!(committee.stake(&name), 1);
+    assert_eq!(committee.quorum_threshold(), 3);
+
+    // Stakes can only be assigned to members.
+    let (stranger, _) = generate_production_keypair();
+    let stakes = [(stranger, 1)].iter().cloned().collect();
+    assert!(matches!(
+        committee.with_stakes(&stakes),
+        Err(ConfigError::NotInCommittee(x)) if x == stranger
+    ));
+}
+
+#[test]
+fn default_optional_parameters() {
+    // The optional parameters take their default value when missing.
+    let data = r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 50,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100,
+        "enable_verification": false,
+        "max_weak_links": 2
+    }"#;
+    let parameters: Parameters = serde_json::from_str(data).unwrap();
+    assert_eq!(parameters.max_weak_links, 2);
+    assert!(parameters.dedup_certificates);
+    assert_eq!(parameters.header_broadcast_margin, 1);
+    assert_eq!(parameters.certificate_write_batch_delay, 10);
+
+    // The original parameters are still required.
+    let data = r#"{ "header_size": 1000 }"#;
+    assert!(serde_json::from_str::<Parameters>(data).is_err());
+}
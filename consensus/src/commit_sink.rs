@@ -0,0 +1,81 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use log::warn;
+use primary::Certificate;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+/// The capacity of the queue of sub-dags waiting to be persisted in fire-and-forget mode.
+const PERSIST_QUEUE_CAPACITY: usize = 1_000;
+
+/// The delay (in ms) before trying again to persist a sub-dag in synchronous mode.
+const PERSIST_RETRY_DELAY: u64 = 100;
+
+/// The certificates committed by a leader, in commit order (see `canonical_order`; the leader comes last).
+#[derive(Clone, Debug)]
+pub struct CommittedSubDag {
+    /// The certificate of the committed leader.
+    pub leader: Certificate,
+    /// The certificates of the sub-dag, including the leader.
+    pub certificates: Vec<Certificate>,
+}
+
+/// A hook to durably record the committed sub-dags in an external system (a database, a log...).
+/// Sub-dags are always persisted one at a time and in commit order.
+#[async_trait]
+pub trait CommitSink: Send + Sync + 'static {
+    async fn persist(&self, commit: &CommittedSubDag) -> io::Result<()>;
+}
+
+/// How the consensus waits for the `CommitSink`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Persistence {
+    /// The consensus does not output a sub-dag (nor process further certificates) until it is
+    /// persisted. Failures are retried until the sink succeeds.
+    Synchronous,
+    /// Sub-dags are persisted in the background; failures are logged and skipped.
+    FireAndForget,
+}
+
+/// Persists the committed sub-dags on behalf of the consensus.
+pub(crate) enum Persister {
+    Synchronous(Arc<dyn CommitSink>),
+    FireAndForget(Sender<CommittedSubDag>),
+}
+
+impl Persister {
+    pub fn new(sink: Arc<dyn CommitSink>, persistence: Persistence) -> Self {
+        match persistence {
+            Persistence::Synchronous => Self::Synchronous(sink),
+            Persistence::FireAndForget => {
+                let (tx, mut rx) = channel::<CommittedSubDag>(PERSIST_QUEUE_CAPACITY);
+                tokio::spawn(async move {
+                    while let Some(commit) = rx.recv().await {
+                        if let Err(e) = sink.persist(&commit).await {
+                            warn!("Failed to persist commit of {:?}: {}", commit.leader, e);
+                        }
+                    }
+                });
+                Self::FireAndForget(tx)
+            }
+        }
+    }
+
+    pub async fn persist(&self, commit: &CommittedSubDag) {
+        match self {
+            Self::Synchronous(sink) => {
+                while let Err(e) = sink.persist(commit).await {
+                    warn!("Failed to persist commit of {:?}: {}", commit.leader, e);
+                    sleep(Duration::from_millis(PERSIST_RETRY_DELAY)).await;
+                }
+            }
+            Self::FireAndForget(tx) => {
+                if tx.send(commit.clone()).await.is_err() {
+                    warn!("Failed to queue commit of {:?} for persistence", commit.leader);
+                }
+            }
+        }
+    }
+}
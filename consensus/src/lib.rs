@@ -1,33 +1,41 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use config::{Committee, Stake};
+use crate::commit_sink::Persister;
+use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use log::{debug, info, log_enabled, warn};
 use primary::{Certificate, Round};
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
 
+mod commit_sink;
+mod ordering;
+
+pub use crate::commit_sink::{CommitSink, CommittedSubDag, Persistence};
+pub use crate::ordering::{canonical_order, OrderingRule, Tusk};
+
 #[cfg(test)]
 #[path = "tests/consensus_tests.rs"]
 pub mod consensus_tests;
 
 /// The representation of the DAG in memory.
-type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
+pub type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
 
 /// The state that needs to be persisted for crash-recovery.
-struct State {
+pub struct ConsensusState {
     /// The last committed round.
-    last_committed_round: Round,
+    pub last_committed_round: Round,
     // Keeps the last committed round for each authority. This map is used to clean up the dag and
     // ensure we don't commit twice the same certificate.
-    last_committed: HashMap<PublicKey, Round>,
+    pub last_committed: HashMap<PublicKey, Round>,
     /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
     /// must be regularly cleaned up through the function `update`.
-    dag: Dag,
+    pub dag: Dag,
 }
 
-impl State {
+impl ConsensusState {
     fn new(genesis: Vec<Certificate>) -> Self {
         let genesis = genesis
             .into_iter()
@@ -41,8 +49,9 @@ impl State {
         }
     }
 
-    /// Update and clean up internal state base on committed certificates.
-    fn update(&mut self, certificate: &Certificate, gc_depth: Round) {
+    /// Update and clean up internal state base on committed certificates. The rounds of the dag more
+    /// than `gc_depth` rounds below the last committed round are pruned.
+    pub fn update(&mut self, certificate: &Certificate, gc_depth: Round) {
         self.last_committed
             .entry(certificate.origin())
             .and_modify(|r| *r = max(*r, certificate.round()))
@@ -62,12 +71,14 @@ impl State {
     }
 }
 
-pub struct Consensus {
-    /// The committee information.
-    committee: Committee,
-    /// The depth of the garbage collector.
-    gc_depth: Round,
+/// Elects the leader of the round associated with the specified seed. The election only depends on the
+/// seed and on the committee (authorities take turns in the order of their public keys, whatever their
+/// stake), so all honest nodes elect the same leader.
+pub fn elect_leader(committee: &Committee, seed: Round) -> PublicKey {
+    committee.leader(seed as usize)
+}
 
+pub struct Consensus {
     /// Receives new certificates from the primary. The primary should send us new certificates only
     /// if it already sent us its whole history.
     rx_primary: Receiver<Certificate>,
@@ -75,6 +86,10 @@ pub struct Consensus {
     tx_primary: Sender<Certificate>,
     /// Outputs the sequence of ordered certificates to the application layer.
     tx_output: Sender<Certificate>,
+    /// Persists the committed sub-dags (if the application provided a `CommitSink`).
+    persister: Option<Persister>,
+    /// Decides which certificates of the dag get committed.
+    ordering_rule: Box<dyn OrderingRule>,
 
     /// The genesis certificates.
     genesis: Vec<Certificate>,
@@ -84,17 +99,40 @@ impl Consensus {
     pub fn spawn(
         committee: Committee,
         gc_depth: Round,
+        gc_grace: Round,
+        rx_primary: Receiver<Certificate>,
+        tx_primary: Sender<Certificate>,
+        tx_output: Sender<Certificate>,
+        commit_sink: Option<(Arc<dyn CommitSink>, Persistence)>,
+    ) {
+        let ordering_rule = Box::new(Tusk::new(committee.clone(), gc_depth, gc_grace));
+        Self::spawn_with_ordering_rule(
+            committee,
+            rx_primary,
+            tx_primary,
+            tx_output,
+            commit_sink,
+            ordering_rule,
+        );
+    }
+
+    /// Spawn the consensus with a custom commit rule (instead of Tusk). The rule is in charge of the
+    /// garbage collection of the dag (see `ConsensusState::update`).
+    pub fn spawn_with_ordering_rule(
+        committee: Committee,
         rx_primary: Receiver<Certificate>,
         tx_primary: Sender<Certificate>,
         tx_output: Sender<Certificate>,
+        commit_sink: Option<(Arc<dyn CommitSink>, Persistence)>,
+        ordering_rule: Box<dyn OrderingRule>,
     ) {
         tokio::spawn(async move {
             Self {
-                committee: committee.clone(),
-                gc_depth,
                 rx_primary,
                 tx_primary,
                 tx_output,
+                persister: commit_sink.map(|(sink, persistence)| Persister::new(sink, persistence)),
+                ordering_rule,
                 genesis: Certificate::genesis(&committee),
             }
             .run()
@@ -104,7 +142,7 @@ impl Consensus {
 
     async fn run(&mut self) {
         // The consensus state (everything else is immutable).
-        let mut state = State::new(self.genesis.clone());
+        let mut state = ConsensusState::new(self.genesis.clone());
 
         // Listen to incoming certificates.
         while let Some(certificate) = self.rx_primary.recv().await {
@@ -118,55 +156,11 @@ impl Consensus {
                 .or_insert_with(HashMap::new)
                 .insert(certificate.origin(), (certificate.digest(), certificate));
 
-            // Try to order the dag to commit. Start from the previous round and check if it is a leader round.
-            let r = round - 1;
-
-            // We only elect leaders for even round numbers.
-            if r % 2 != 0 || r < 2 {
-                continue;
-            }
-
-            // Get the certificate's digest of the leader. If we already ordered this leader, there is nothing to do.
-            let leader_round = r;
-            if leader_round <= state.last_committed_round {
+            // Let the ordering rule commit what it can.
+            let sequence = self.ordering_rule.order(&mut state, round);
+            if sequence.is_empty() {
                 continue;
             }
-            let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
-                Some(x) => x,
-                None => continue,
-            };
-
-            // Check if the leader has f+1 support from its children (ie. round r-1).
-            let stake: Stake = state
-                .dag
-                .get(&round)
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(_, x)| x.header.parents.contains(leader_digest))
-                .map(|(_, x)| self.committee.stake(&x.origin()))
-                .sum();
-
-            // If it is the case, we can commit the leader. But first, we need to recursively go back to
-            // the last committed leader, and commit all preceding leaders in the right order. Committing
-            // a leader block means committing all its dependencies.
-            if stake < self.committee.validity_threshold() {
-                debug!("Leader {:?} does not have enough support", leader);
-                continue;
-            }
-
-            // Get an ordered list of past leaders that are linked to the current leader.
-            debug!("Leader {:?} has enough support", leader);
-            let mut sequence = Vec::new();
-            for leader in self.order_leaders(leader, &state).iter().rev() {
-                // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
-                for x in self.order_dag(leader, &state) {
-                    // Update and clean up internal state.
-                    state.update(&x, self.gc_depth);
-
-                    // Add the certificate to the sequence.
-                    sequence.push(x);
-                }
-            }
 
             // Log the latest committed round of every authority (for debug).
             if log_enabled!(log::Level::Debug) {
@@ -175,126 +169,33 @@ impl Consensus {
                 }
             }
 
-            // Output the sequence in the right order.
-            for certificate in sequence {
-                #[cfg(not(feature = "benchmark"))]
-                info!("Committed {}", certificate.header);
-
-                #[cfg(feature = "benchmark")]
-                for digest in certificate.header.payload.keys() {
-                    // NOTE: This log entry is used to compute performance.
-                    info!("Committed {} -> {:?}", certificate.header, digest);
-                }
-
-                self.tx_primary
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send certificate to primary");
-
-                if let Err(e) = self.tx_output.send(certificate).await {
-                    warn!("Failed to output certificate: {}", e);
+            // Output the sequence in the right order. Each sub-dag is handed to the commit sink before
+            // any of its certificates is output.
+            for sub_dag in sequence {
+                if let Some(persister) = &self.persister {
+                    persister.persist(&sub_dag).await;
                 }
-            }
-        }
-    }
-
-    /// Returns the certificate (and the certificate's digest) originated by the leader of the
-    /// specified round (if any).
-    fn leader<'a>(&self, round: Round, dag: &'a Dag) -> Option<&'a (Digest, Certificate)> {
-        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
-        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
-        // compute the coin). We currently just use round-robin.
-        #[cfg(test)]
-        let seed = 0;
-        #[cfg(not(test))]
-        let seed = round;
-
-        // Elect the leader.
-        let leader = self.committee.leader(seed as usize);
 
-        // Return its certificate and the certificate's digest.
-        dag.get(&round).map(|x| x.get(&leader)).flatten()
-    }
+                for certificate in sub_dag.certificates {
+                    #[cfg(not(feature = "benchmark"))]
+                    info!("Committed {}", certificate.header);
 
-    /// Order the past leaders that we didn't already commit.
-    fn order_leaders(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
-        let mut to_commit = vec![leader.clone()];
-        let mut leader = leader;
-        for r in (state.last_committed_round + 2..=leader.round() - 2)
-            .rev()
-            .step_by(2)
-        {
-            // Get the certificate proposed by the previous leader.
-            let (_, prev_leader) = match self.leader(r, &state.dag) {
-                Some(x) => x,
-                None => continue,
-            };
+                    #[cfg(feature = "benchmark")]
+                    for digest in certificate.header.payload_digests() {
+                        // NOTE: This log entry is used to compute performance.
+                        info!("Committed {} -> {:?}", certificate.header, digest);
+                    }
 
-            // Check whether there is a path between the last two leaders.
-            if self.linked(leader, prev_leader, &state.dag) {
-                to_commit.push(prev_leader.clone());
-                leader = prev_leader;
-            }
-        }
-        to_commit
-    }
+                    self.tx_primary
+                        .send(certificate.clone())
+                        .await
+                        .expect("Failed to send certificate to primary");
 
-    /// Checks if there is a path between two leaders.
-    fn linked(&self, leader: &Certificate, prev_leader: &Certificate, dag: &Dag) -> bool {
-        let mut parents = vec![leader];
-        for r in (prev_leader.round()..leader.round()).rev() {
-            parents = dag
-                .get(&(r))
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(digest, _)| parents.iter().any(|x| x.header.parents.contains(digest)))
-                .map(|(_, certificate)| certificate)
-                .collect();
-        }
-        parents.contains(&prev_leader)
-    }
-
-    /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
-    /// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
-    fn order_dag(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
-        debug!("Processing sub-dag of {:?}", leader);
-        let mut ordered = Vec::new();
-        let mut already_ordered = HashSet::new();
-
-        let mut buffer = vec![leader];
-        while let Some(x) = buffer.pop() {
-            debug!("Sequencing {:?}", x);
-            ordered.push(x.clone());
-            for parent in &x.header.parents {
-                let (digest, certificate) = match state
-                    .dag
-                    .get(&(x.round() - 1))
-                    .map(|x| x.values().find(|(x, _)| x == parent))
-                    .flatten()
-                {
-                    Some(x) => x,
-                    None => continue, // We already ordered or GC up to here.
-                };
-
-                // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
-                // committed for this authority.
-                let mut skip = already_ordered.contains(&digest);
-                skip |= state
-                    .last_committed
-                    .get(&certificate.origin())
-                    .map_or_else(|| false, |r| r == &certificate.round());
-                if !skip {
-                    buffer.push(certificate);
-                    already_ordered.insert(digest);
+                    if let Err(e) = self.tx_output.send(certificate).await {
+                        warn!("Failed to output certificate: {}", e);
+                    }
                 }
             }
         }
-
-        // Ensure we do not commit garbage collected certificates.
-        ordered.retain(|x| x.round() + self.gc_depth >= state.last_committed_round);
-
-        // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
-        ordered.sort_by_key(|x| x.round());
-        ordered
     }
 }
@@ -0,0 +1,210 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{elect_leader, CommittedSubDag, ConsensusState, Dag};
+use config::{Committee, Stake};
+use crypto::{Digest, Hash as _};
+use log::debug;
+use primary::{Certificate, Round};
+use std::collections::HashSet;
+
+/// A commit rule of the consensus (e.g., Tusk or Bullshark). The consensus adds every certificate it
+/// receives to the dag of its state and then asks the rule which certificates got committed.
+pub trait OrderingRule: Send + 'static {
+    /// Called after a new certificate of the specified round was added to the dag. Returns the newly
+    /// committed sub-dags, in commit order. The rule must call `ConsensusState::update` for every
+    /// certificate it commits (this prevents committing it twice and cleans up the dag).
+    fn order(&mut self, state: &mut ConsensusState, round: Round) -> Vec<CommittedSubDag>;
+}
+
+/// Sorts the certificates of a committed sub-dag in the canonical order: by round, then by author (public
+/// key bytes), then by digest. The order only depends on the certificates themselves (not on the order
+/// they were received or traversed), so all honest nodes output the same sequence. Ordering rules should
+/// apply it to every sub-dag they commit; the leader, being of the highest round, comes last.
+pub fn canonical_order(certificates: &mut [Certificate]) {
+    certificates.sort_by_cached_key(|x| (x.round(), x.origin(), x.digest()));
+}
+
+/// The Tusk commit rule (the default): the leader of every even round is committed once it gets f+1
+/// support from the next round, along with the uncommitted leaders it links to.
+pub struct Tusk {
+    /// The committee information.
+    committee: Committee,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// The number of rounds below the garbage collection round we keep in the dag, for the commits
+    /// in flight to traverse.
+    gc_grace: Round,
+}
+
+impl Tusk {
+    pub fn new(committee: Committee, gc_depth: Round, gc_grace: Round) -> Self {
+        Self {
+            committee,
+            gc_depth,
+            gc_grace,
+        }
+    }
+
+    /// Returns the certificate (and the certificate's digest) originated by the leader of the
+    /// specified round (if any).
+    fn leader<'a>(&self, round: Round, dag: &'a Dag) -> Option<&'a (Digest, Certificate)> {
+        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
+        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
+        // compute the coin). We currently just use round-robin.
+        #[cfg(test)]
+        let seed = 0;
+        #[cfg(not(test))]
+        let seed = round;
+
+        // Elect the leader.
+        let leader = elect_leader(&self.committee, seed);
+
+        // Return its certificate and the certificate's digest.
+        dag.get(&round).and_then(|x| x.get(&leader))
+    }
+
+    /// Order the past leaders that we didn't already commit.
+    fn order_leaders(&self, leader: &Certificate, state: &ConsensusState) -> Vec<Certificate> {
+        let mut to_commit = vec![leader.clone()];
+        let mut leader = leader;
+        for r in (state.last_committed_round + 2..=leader.round() - 2)
+            .rev()
+            .step_by(2)
+        {
+            // Get the certificate proposed by the previous leader.
+            let (_, prev_leader) = match self.leader(r, &state.dag) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            // Check whether there is a path between the last two leaders.
+            if self.linked(leader, prev_leader, &state.dag) {
+                to_commit.push(prev_leader.clone());
+                leader = prev_leader;
+            }
+        }
+        to_commit
+    }
+
+    /// Checks if there is a path between two leaders.
+    fn linked(&self, leader: &Certificate, prev_leader: &Certificate, dag: &Dag) -> bool {
+        let mut parents = vec![leader];
+        for r in (prev_leader.round()..leader.round()).rev() {
+            parents = dag
+                .get(&(r))
+                .expect("We should have the whole history by now")
+                .values()
+                .filter(|(digest, _)| parents.iter().any(|x| x.header.parents().contains(digest)))
+                .map(|(_, certificate)| certificate)
+                .collect();
+        }
+        parents.contains(&prev_leader)
+    }
+
+    /// Flatten the dag referenced by the input certificate. This is a classic depth-first search (pre-order):
+    /// https://en.wikipedia.org/wiki/Tree_traversal#Pre-order
+    fn order_dag(&self, leader: &Certificate, state: &ConsensusState) -> Vec<Certificate> {
+        debug!("Processing sub-dag of {:?}", leader);
+        let mut ordered = Vec::new();
+        let mut already_ordered = HashSet::new();
+
+        let mut buffer = vec![leader];
+        while let Some(x) = buffer.pop() {
+            debug!("Sequencing {:?}", x);
+            ordered.push(x.clone());
+            // Follow the parents (in the previous round) and the weak links (in any earlier round). The
+            // certificates missing from the dag were already ordered or garbage collected.
+            let parents = x.header.parents().iter().filter_map(|parent| {
+                state
+                    .dag
+                    .get(&(x.round() - 1))
+                    .and_then(|x| x.values().find(|(x, _)| x == parent))
+            });
+            let weak_links = x.header.weak_links.iter().filter_map(|link| {
+                state
+                    .dag
+                    .iter()
+                    .filter(|(r, _)| **r + 1 < x.round())
+                    .find_map(|(_, x)| x.values().find(|(x, _)| x == link))
+            });
+            for (digest, certificate) in parents.chain(weak_links) {
+                // We skip the certificate if we (1) already processed it or (2) we reached a round that we already
+                // committed for this authority.
+                let mut skip = already_ordered.contains(&digest);
+                skip |= state
+                    .last_committed
+                    .get(&certificate.origin())
+                    .map_or_else(|| false, |r| r == &certificate.round());
+                if !skip {
+                    buffer.push(certificate);
+                    already_ordered.insert(digest);
+                }
+            }
+        }
+
+        // Ensure we do not commit garbage collected certificates.
+        ordered.retain(|x| x.round() + self.gc_depth >= state.last_committed_round);
+
+        // Order the output canonically, so every node outputs the same sequence.
+        canonical_order(&mut ordered);
+        ordered
+    }
+}
+
+impl OrderingRule for Tusk {
+    fn order(&mut self, state: &mut ConsensusState, round: Round) -> Vec<CommittedSubDag> {
+        // Try to order the dag to commit. Start from the previous round and check if it is a leader round.
+        let r = round - 1;
+
+        // We only elect leaders for even round numbers.
+        if !r.is_multiple_of(2) || r < 2 {
+            return Vec::new();
+        }
+
+        // Get the certificate's digest of the leader. If we already ordered this leader, there is nothing to do.
+        let leader_round = r;
+        if leader_round <= state.last_committed_round {
+            return Vec::new();
+        }
+        let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+
+        // Check if the leader has f+1 support from its children (ie. round r-1).
+        let stake: Stake = state
+            .dag
+            .get(&round)
+            .expect("We should have the whole history by now")
+            .values()
+            .filter(|(_, x)| x.header.parents().contains(leader_digest))
+            .map(|(_, x)| self.committee.stake(&x.origin()))
+            .sum();
+
+        // If it is the case, we can commit the leader. But first, we need to recursively go back to
+        // the last committed leader, and commit all preceding leaders in the right order. Committing
+        // a leader block means committing all its dependencies.
+        if stake < self.committee.validity_threshold() {
+            debug!("Leader {:?} does not have enough support", leader);
+            return Vec::new();
+        }
+
+        // Get an ordered list of past leaders that are linked to the current leader.
+        debug!("Leader {:?} has enough support", leader);
+        let mut sequence = Vec::new();
+        for leader in self.order_leaders(leader, state).iter().rev() {
+            // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
+            let certificates = self.order_dag(leader, state);
+            for x in &certificates {
+                // Update and clean up internal state (sparing the grace rounds).
+                state.update(x, self.gc_depth + self.gc_grace);
+            }
+
+            // Add the sub-dag to the sequence.
+            sequence.push(CommittedSubDag {
+                leader: leader.clone(),
+                certificates,
+            });
+        }
+        sequence
+    }
+}
@@ -1,12 +1,16 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
+use async_trait::async_trait;
 use config::{Authority, PrimaryAddresses};
 use crypto::{generate_keypair, SecretKey};
-use primary::Header;
+use primary::HeaderBuilder;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Mutex;
 use tokio::sync::mpsc::channel;
+use tokio::sync::Semaphore;
+use tokio::time::{sleep, timeout, Duration};
 
 // Fixture
 fn keys() -> Vec<(PublicKey, SecretKey)> {
@@ -17,6 +21,7 @@ fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn mock_committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .map(|(id, _)| {
@@ -33,6 +38,7 @@ pub fn mock_committee() -> Committee {
                 )
             })
             .collect(),
+        observers: BTreeMap::new(),
     }
 }
 
@@ -43,12 +49,10 @@ fn mock_certificate(
     parents: BTreeSet<Digest>,
 ) -> (Digest, Certificate) {
     let certificate = Certificate {
-        header: Header {
-            author: origin,
-            round,
-            parents,
-            ..Header::default()
-        },
+        header: HeaderBuilder::new(origin)
+            .round(round)
+            .parents(parents)
+            .build_unsigned(),
         ..Certificate::default()
     };
     (certificate.digest(), certificate)
@@ -104,9 +108,11 @@ async fn commit_one() {
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        /* gc_grace */ 0,
         rx_waiter,
         tx_primary,
         tx_output,
+        /* commit_sink */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -149,9 +155,11 @@ async fn dead_node() {
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        /* gc_grace */ 0,
         rx_waiter,
         tx_primary,
         tx_output,
+        /* commit_sink */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -239,9 +247,11 @@ async fn not_enough_support() {
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        /* gc_grace */ 0,
         rx_waiter,
         tx_primary,
         tx_output,
+        /* commit_sink */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -304,9 +314,11 @@ async fn missing_leader() {
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        /* gc_grace */ 0,
         rx_waiter,
         tx_primary,
         tx_output,
+        /* commit_sink */ None,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -332,3 +344,384 @@ async fn missing_leader() {
     let certificate = rx_output.recv().await.unwrap();
     assert_eq!(certificate.round(), 4);
 }
+
+#[test]
+fn elect_same_leader() {
+    // The authorities sorted by the bytes of their public key (rather than in the order we generated them).
+    let expected = [keys()[0].0, keys()[2].0, keys()[1].0, keys()[3].0];
+    let mut sorted: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    sorted.sort_by_key(|x| x.0);
+    assert_eq!(sorted, expected);
+
+    // Ensure the authorities take turns in that order, whatever their stake.
+    let mut committee = mock_committee();
+    committee.authorities.get_mut(&expected[0]).unwrap().stake = 10;
+    for round in 0..8 {
+        assert_eq!(
+            elect_leader(&committee, round),
+            expected[round as usize % 4]
+        );
+    }
+}
+
+// A commit sink recording the round of the committed leaders. Each call to `persist` waits for a
+// permit of the gate.
+struct RecordingSink {
+    gate: Semaphore,
+    commits: Mutex<Vec<Round>>,
+}
+
+#[async_trait]
+impl CommitSink for RecordingSink {
+    async fn persist(&self, commit: &CommittedSubDag) -> std::io::Result<()> {
+        self.gate.acquire().await.unwrap().forget();
+        assert_eq!(commit.certificates.last(), Some(&commit.leader));
+        self.commits.lock().unwrap().push(commit.leader.round());
+        Ok(())
+    }
+}
+
+// Run for 4 dag rounds (plus f+1 certificates of round 5) to commit the leaders of rounds 2 and 4,
+// persisting each sub-dag with the specified mode.
+fn spawn_with_sink(persistence: Persistence) -> (Arc<RecordingSink>, Receiver<Certificate>) {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, parents.clone());
+    certificates.push_back(certificate);
+    let (_, certificate) = mock_certificate(keys[1], 5, parents);
+    certificates.push_back(certificate);
+
+    let sink = Arc::new(RecordingSink {
+        gate: Semaphore::new(0),
+        commits: Mutex::new(Vec::new()),
+    });
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, rx_output) = channel(100);
+    Consensus::spawn(
+        mock_committee(),
+        /* gc_depth */ 50,
+        /* gc_grace */ 0,
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* commit_sink */ Some((sink.clone(), persistence)),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+    (sink, rx_output)
+}
+
+#[tokio::test]
+async fn synchronous_commit_sink() {
+    let (sink, mut rx_output) = spawn_with_sink(Persistence::Synchronous);
+
+    // Nothing is output until the sink persists the first sub-dag.
+    assert!(timeout(Duration::from_millis(200), rx_output.recv())
+        .await
+        .is_err());
+    assert!(sink.commits.lock().unwrap().is_empty());
+
+    // Persist the sub-dag of the leader of round 2: its certificates are then output.
+    sink.gate.add_permits(1);
+    for _ in 1..=4 {
+        assert_eq!(rx_output.recv().await.unwrap().round(), 1);
+    }
+    assert_eq!(rx_output.recv().await.unwrap().round(), 2);
+    assert!(timeout(Duration::from_millis(200), rx_output.recv())
+        .await
+        .is_err());
+    assert_eq!(*sink.commits.lock().unwrap(), vec![2]);
+
+    // Persist the sub-dag of the leader of round 4.
+    sink.gate.add_permits(1);
+    for _ in 1..=3 {
+        assert_eq!(rx_output.recv().await.unwrap().round(), 2);
+    }
+    for _ in 1..=4 {
+        assert_eq!(rx_output.recv().await.unwrap().round(), 3);
+    }
+    assert_eq!(rx_output.recv().await.unwrap().round(), 4);
+    assert_eq!(*sink.commits.lock().unwrap(), vec![2, 4]);
+}
+
+#[tokio::test]
+async fn fire_and_forget_commit_sink() {
+    let (sink, mut rx_output) = spawn_with_sink(Persistence::FireAndForget);
+
+    // The commits are output without waiting for the sink.
+    for _ in 0..13 {
+        rx_output.recv().await.unwrap();
+    }
+    assert!(sink.commits.lock().unwrap().is_empty());
+
+    // The sink then persists the sub-dags in commit order.
+    sink.gate.add_permits(2);
+    timeout(Duration::from_secs(1), async {
+        while sink.commits.lock().unwrap().len() < 2 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(*sink.commits.lock().unwrap(), vec![2, 4]);
+}
+
+// A commit rule committing every round once it holds a certificate of each authority (the certificates
+// are ordered by author and the last one acts as the leader).
+struct CommitFullRounds {
+    authorities: usize,
+}
+
+impl OrderingRule for CommitFullRounds {
+    fn order(&mut self, state: &mut ConsensusState, round: Round) -> Vec<CommittedSubDag> {
+        let certificates = match state.dag.get(&round) {
+            Some(x) if x.len() == self.authorities => x,
+            _ => return Vec::new(),
+        };
+        let mut certificates: Vec<_> = certificates.values().map(|(_, x)| x.clone()).collect();
+        certificates.sort_by_key(|x| x.origin());
+        for x in &certificates {
+            state.update(x, /* gc_depth */ 50);
+        }
+        vec![CommittedSubDag {
+            leader: certificates.last().unwrap().clone(),
+            certificates,
+        }]
+    }
+}
+
+// Run for 2 dag rounds with a custom commit rule. The commit sequence should follow that rule rather
+// than Tusk (which would not commit anything).
+#[tokio::test]
+async fn custom_ordering_rule() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 2, &genesis, &keys);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn_with_ordering_rule(
+        mock_committee(),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        /* commit_sink */ None,
+        Box::new(CommitFullRounds {
+            authorities: keys.len(),
+        }),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    // Feed the certificates to the consensus in reverse order (the last round first).
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_back() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    // Each round is committed as a whole (as soon as it is full), ordered by author.
+    let mut sorted = keys.clone();
+    sorted.sort();
+    for round in (1..=2).rev() {
+        for name in &sorted {
+            let certificate = rx_output.recv().await.unwrap();
+            assert_eq!(certificate.round(), round);
+            assert_eq!(&certificate.origin(), name);
+        }
+    }
+}
+
+// Feed 9 dag rounds of 3 authorities to Tusk (committing the leaders of rounds 2, 4, 6, and 8), along with
+// a certificate of the last authority at round 1 that nobody references. Returns whether that certificate
+// is still in the dag after each commit (keyed by the last committed round).
+fn orphan_survival(gc_depth: Round, gc_grace: Round) -> Vec<(Round, bool)> {
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort(); // Ensure we don't remove one of the leaders.
+    let orphan = keys.pop().unwrap();
+
+    let genesis = Certificate::genesis(&mock_committee());
+    let digests = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &digests, &keys);
+    let (_, orphan_certificate) = mock_certificate(orphan, 1, digests);
+
+    let mut tusk = Tusk::new(mock_committee(), gc_depth, gc_grace);
+    let mut state = ConsensusState::new(genesis);
+    let mut survival = Vec::new();
+    for certificate in std::iter::once(orphan_certificate).chain(certificates) {
+        let round = certificate.round();
+        state
+            .dag
+            .entry(round)
+            .or_default()
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+        if !tusk.order(&mut state, round).is_empty() {
+            let alive = state.dag.get(&1).is_some_and(|x| x.contains_key(&orphan));
+            survival.push((state.last_committed_round, alive));
+        }
+    }
+    survival
+}
+
+// The certificates within the grace margin below the garbage collection round survive until the commit
+// frontier advances past the margin.
+#[test]
+fn gc_grace_spares_certificates() {
+    // Without grace, the orphan is pruned once the last committed round is more than 2 rounds above it.
+    assert_eq!(
+        orphan_survival(/* gc_depth */ 2, /* gc_grace */ 0),
+        vec![(2, true), (4, false), (6, false), (8, false)]
+    );
+
+    // With a grace of 2 rounds, it survives the commit of round 4 and is pruned with the commit of round 6.
+    assert_eq!(
+        orphan_survival(/* gc_depth */ 2, /* gc_grace */ 2),
+        vec![(2, true), (4, true), (6, false), (8, false)]
+    );
+}
+
+// Feed 3 dag rounds (committing the leader of round 2) to Tusk, in an order shuffled with the specified
+// seed. Returns the committed sub-dags.
+fn commit_shuffled(seed: u64) -> Vec<CommittedSubDag> {
+    use rand::seq::SliceRandom as _;
+
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee());
+    let digests = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 2, &digests, &keys);
+    let mut certificates: Vec<_> = certificates.into_iter().collect();
+    certificates.shuffle(&mut StdRng::seed_from_u64(seed));
+
+    let mut tusk = Tusk::new(mock_committee(), /* gc_depth */ 50, /* gc_grace */ 0);
+    let mut state = ConsensusState::new(genesis);
+    for certificate in certificates {
+        state
+            .dag
+            .entry(certificate.round())
+            .or_default()
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+    }
+
+    // Add the round 3 certificates (f+1) triggering the commit.
+    let mut committed = Vec::new();
+    for name in keys.iter().take(2) {
+        let (digest, certificate) = mock_certificate(*name, 3, next_parents.clone());
+        state
+            .dag
+            .entry(3)
+            .or_default()
+            .insert(certificate.origin(), (digest, certificate));
+        committed.extend(tusk.order(&mut state, 3));
+    }
+    committed
+}
+
+// The certificates of a committed sub-dag are ordered by round, then author, then digest, regardless of the
+// order in which the dag was built.
+#[test]
+fn canonical_sub_dag_order() {
+    let committed = commit_shuffled(0);
+    assert_eq!(committed.len(), 1);
+    let certificates = &committed[0].certificates;
+    assert_eq!(certificates.len(), 5);
+    assert_eq!(certificates.last(), Some(&committed[0].leader));
+
+    let keys = certificates
+        .iter()
+        .map(|x| (x.round(), x.origin(), x.digest()))
+        .collect::<Vec<_>>();
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(keys, sorted);
+
+    // The order is stable across runs.
+    for seed in 1..10 {
+        let other = commit_shuffled(seed);
+        assert_eq!(other.len(), 1);
+        assert_eq!(&other[0].certificates, certificates);
+    }
+
+    // Ties between certificates of the same round and author (which only custom rules may commit) are
+    // broken by digest.
+    let (_, mut first) = mock_certificate(keys[0].1, 1, BTreeSet::new());
+    let mut second = first.clone();
+    first.header.id = Digest([1; 32]);
+    second.header.id = Digest([2; 32]);
+    let mut one = vec![first.clone(), second.clone()];
+    let mut other = vec![second, first];
+    canonical_order(&mut one);
+    canonical_order(&mut other);
+    assert_eq!(one, other);
+    assert!(one[0].digest() < one[1].digest());
+}
+
+// Feed 5 dag rounds of 3 authorities to Tusk (committing the leaders of rounds 2 and 4), along with a
+// certificate of the last authority at round 1 that no certificate has as parent. Optionally, a certificate
+// of round 3 weakly links to it. Returns the committed certificates.
+fn commit_with_orphan(weak_link: bool) -> (Certificate, Vec<Certificate>) {
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort(); // Ensure we don't remove one of the leaders.
+    let orphan = keys.pop().unwrap();
+
+    let genesis = Certificate::genesis(&mock_committee());
+    let digests = genesis.iter().map(|x| x.digest()).collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 2, &digests, &keys);
+    let (orphan_digest, orphan_certificate) = mock_certificate(orphan, 1, digests);
+
+    // Make the certificates of round 3, the first of them weakly linking to the orphan.
+    let mut next_parents = BTreeSet::new();
+    for (i, name) in keys.iter().enumerate() {
+        let (_, mut certificate) = mock_certificate(*name, 3, parents.clone());
+        if weak_link && i == 0 {
+            certificate.header.weak_links = std::iter::once(orphan_digest.clone()).collect();
+        }
+        next_parents.insert(certificate.digest());
+        certificates.push_back(certificate);
+    }
+    let (others, _) = make_certificates(4, 5, &next_parents, &keys);
+    certificates.extend(others);
+
+    let mut tusk = Tusk::new(
+        mock_committee(),
+        /* gc_depth */ 50,
+        /* gc_grace */ 0,
+    );
+    let mut state = ConsensusState::new(genesis);
+    let mut committed = Vec::new();
+    for certificate in std::iter::once(orphan_certificate.clone()).chain(certificates) {
+        let round = certificate.round();
+        state
+            .dag
+            .entry(round)
+            .or_default()
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+        for sub_dag in tusk.order(&mut state, round) {
+            committed.extend(sub_dag.certificates);
+        }
+    }
+    (orphan_certificate, committed)
+}
+
+// A certificate that is not the parent of any other certificate is only committed if a certificate of a
+// committed sub-dag weakly links to it.
+#[test]
+fn commit_weakly_linked_certificate() {
+    let (orphan, committed) = commit_with_orphan(/* weak_link */ false);
+    assert!(!committed.contains(&orphan));
+
+    let (orphan, committed) = commit_with_orphan(/* weak_link */ true);
+    assert_eq!(committed.iter().filter(|x| **x == orphan).count(), 1);
+}
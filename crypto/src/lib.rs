@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
 use ed25519_dalek as dalek;
 use ed25519_dalek::ed25519;
 use ed25519_dalek::Signer as _;
@@ -72,9 +73,10 @@ impl PublicKey {
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..32]
-            .try_into()
-            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        let array = bytes
+            .get(..32)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
     }
 }
@@ -127,9 +129,10 @@ impl SecretKey {
 
     pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
         let bytes = base64::decode(s)?;
-        let array = bytes[..64]
-            .try_into()
-            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        let array = bytes
+            .get(..64)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
     }
 }
@@ -219,7 +222,32 @@ impl Signature {
     }
 }
 
-/// This service holds the node's private key. It takes digests as input and returns a signature
+/// Signs digests on behalf of the node. Implementations may keep the secret key outside of the node
+/// (e.g., in a KMS), so that the node never sees the raw key.
+#[async_trait]
+pub trait Signer: Send + Sync + 'static {
+    async fn sign(&self, digest: &Digest) -> Signature;
+}
+
+/// A signer holding the secret key in memory.
+pub struct LocalSigner {
+    secret: SecretKey,
+}
+
+impl LocalSigner {
+    pub fn new(secret: SecretKey) -> Self {
+        Self { secret }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    async fn sign(&self, digest: &Digest) -> Signature {
+        Signature::new(digest, &self.secret)
+    }
+}
+
+/// This service holds the node's signer. It takes digests as input and returns a signature
 /// over the digest (through a oneshot channel).
 #[derive(Clone)]
 pub struct SignatureService {
@@ -228,10 +256,15 @@ pub struct SignatureService {
 
 impl SignatureService {
     pub fn new(secret: SecretKey) -> Self {
+        Self::with_signer(LocalSigner::new(secret))
+    }
+
+    /// Make a service requesting its signatures from the specified signer.
+    pub fn with_signer<S: Signer>(signer: S) -> Self {
         let (tx, mut rx): (Sender<(_, oneshot::Sender<_>)>, _) = channel(100);
         tokio::spawn(async move {
             while let Some((digest, sender)) = rx.recv().await {
-                let signature = Signature::new(&digest, &secret);
+                let signature = signer.sign(&digest).await;
                 let _ = sender.send(signature);
             }
         });
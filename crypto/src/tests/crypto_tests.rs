@@ -46,6 +46,14 @@ fn import_export_secret_key() {
     assert_eq!(import.unwrap(), secret_key);
 }
 
+#[test]
+fn import_short_keys() {
+    // Well-formed base64 strings that are too short must be rejected (not panic).
+    assert!(PublicKey::decode_base64("AAAA").is_err());
+    assert!(PublicKey::decode_base64("").is_err());
+    assert!(SecretKey::decode_base64("AAAA").is_err());
+}
+
 #[test]
 fn verify_valid_signature() {
     // Get a keypair.
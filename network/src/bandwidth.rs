@@ -0,0 +1,62 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/bandwidth_tests.rs"]
+pub mod bandwidth_tests;
+
+/// Accounts the bytes of the messages (excluding framing) we exchange with each peer. Outgoing traffic is
+/// accounted per destination address and incoming traffic per address the peer advertised during the
+/// handshake (incoming connections use ephemeral ports); incoming traffic without handshake is not accounted.
+/// Each connection looks its counter up once, so accounting a message is a single atomic add. Clones share
+/// the same counters.
+#[derive(Clone, Default)]
+pub struct Bandwidth {
+    /// The bytes sent to each peer.
+    sent: Arc<Mutex<HashMap<SocketAddr, Arc<AtomicU64>>>>,
+    /// The bytes received from each peer.
+    received: Arc<Mutex<HashMap<SocketAddr, Arc<AtomicU64>>>>,
+}
+
+impl Bandwidth {
+    /// The counter of the bytes sent to `address`.
+    pub(crate) fn sent_counter(&self, address: SocketAddr) -> Arc<AtomicU64> {
+        self.sent
+            .lock()
+            .expect("Bandwidth counters poisoned")
+            .entry(address)
+            .or_default()
+            .clone()
+    }
+
+    /// The counter of the bytes received from `address`.
+    pub(crate) fn received_counter(&self, address: SocketAddr) -> Arc<AtomicU64> {
+        self.received
+            .lock()
+            .expect("Bandwidth counters poisoned")
+            .entry(address)
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the number of bytes sent to `address`.
+    pub fn sent(&self, address: &SocketAddr) -> u64 {
+        self.sent
+            .lock()
+            .expect("Bandwidth counters poisoned")
+            .get(address)
+            .map_or(0, |x| x.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of bytes received from `address`.
+    pub fn received(&self, address: &SocketAddr) -> u64 {
+        self.received
+            .lock()
+            .expect("Bandwidth counters poisoned")
+            .get(address)
+            .map_or(0, |x| x.load(Ordering::Relaxed))
+    }
+}
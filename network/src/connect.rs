@@ -0,0 +1,29 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+#[cfg(test)]
+#[path = "tests/connect_tests.rs"]
+pub mod connect_tests;
+
+/// The default time to wait for a TCP connection to be established (in ms).
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 5_000;
+
+/// Try to connect to the specified address, giving up after `connect_timeout` ms (rather than
+/// waiting for the OS default, which may be several minutes for unreachable peers).
+pub async fn connect(address: SocketAddr, connect_timeout: u64) -> io::Result<TcpStream> {
+    match timeout(
+        Duration::from_millis(connect_timeout),
+        TcpStream::connect(address),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("connection timed out after {} ms", connect_timeout),
+        )),
+    }
+}
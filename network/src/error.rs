@@ -8,6 +8,9 @@ pub enum NetworkError {
     #[error("Failed to connect to {0} (retry {1}): {2}")]
     FailedToConnect(SocketAddr, u16, std::io::Error),
 
+    #[error("Failed to resolve the address of {0} (retry {1}): {2}")]
+    FailedToResolve(SocketAddr, u16, std::io::Error),
+
     #[error("Failed to accept connection: {0}")]
     FailedToListen(std::io::Error),
 
@@ -22,4 +25,13 @@ pub enum NetworkError {
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Failed to complete handshake with {0}")]
+    FailedToHandshake(SocketAddr),
+
+    #[error("Timed out waiting for the handshake of {0}")]
+    HandshakeTimeout(SocketAddr),
+
+    #[error("Refusing incompatible peer {0}: it speaks message format version {2} (we speak {1})")]
+    IncompatiblePeer(SocketAddr, u32, u32),
 }
@@ -0,0 +1,109 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use bytes::{Buf as _, BufMut as _, Bytes, BytesMut};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::debug;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/handshake_tests.rs"]
+pub mod handshake_tests;
+
+/// The version of the message format spoken by this node. Bump it whenever the serialized form of
+/// the messages exchanged between peers changes in an incompatible way.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Prefix of every handshake frame, so that we do not mistake a regular message for a handshake.
+const MAGIC: &[u8; 4] = b"NRWL";
+
+/// How long we wait for the handshake frame of a peer before dropping the connection, so that a silent
+/// peer does not hold on to the connection (and the resources attached to it) forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(5_000);
+
+/// Convenient alias for a framed TCP connection.
+type Transport = Framed<TcpStream, LengthDelimitedCodec>;
+
+/// Announce our message format version (along with the address at which our peers reach us, if any)
+/// to the peer we just connected to and wait for its own version. Fails if the peer does not reply with
+/// the same version.
+pub async fn initiate(
+    transport: &mut Transport,
+    peer: SocketAddr,
+    version: u32,
+    identity: Option<SocketAddr>,
+) -> Result<(), NetworkError> {
+    send(transport, peer, version, identity).await?;
+    let (theirs, _) = receive(transport, peer).await?;
+    check(peer, version, theirs)
+}
+
+/// Wait for the message format version of a peer that just connected to us and reply with our own.
+/// We always reply (even on mismatch) so that both ends can log a meaningful error. Returns the address
+/// the peer advertised (if any). It is not authenticated, so it only serves to attribute the peer's traffic.
+pub async fn accept(
+    transport: &mut Transport,
+    peer: SocketAddr,
+    version: u32,
+) -> Result<Option<SocketAddr>, NetworkError> {
+    let (theirs, identity) = receive(transport, peer).await?;
+    send(transport, peer, version, None).await?;
+    check(peer, version, theirs)?;
+    Ok(identity)
+}
+
+fn check(peer: SocketAddr, ours: u32, theirs: u32) -> Result<(), NetworkError> {
+    if ours != theirs {
+        return Err(NetworkError::IncompatiblePeer(peer, ours, theirs));
+    }
+    debug!("Agreed on message format version {} with {}", ours, peer);
+    Ok(())
+}
+
+async fn send(
+    transport: &mut Transport,
+    peer: SocketAddr,
+    version: u32,
+    identity: Option<SocketAddr>,
+) -> Result<(), NetworkError> {
+    let identity = identity.map(|x| x.to_string()).unwrap_or_default();
+    let mut frame = BytesMut::with_capacity(MAGIC.len() + 4 + identity.len());
+    frame.put_slice(MAGIC);
+    frame.put_u32(version);
+    frame.put_slice(identity.as_bytes());
+    transport
+        .send(frame.freeze())
+        .await
+        .map_err(|e| NetworkError::FailedToSendMessage(peer, e))
+}
+
+async fn receive(
+    transport: &mut Transport,
+    peer: SocketAddr,
+) -> Result<(u32, Option<SocketAddr>), NetworkError> {
+    let frame = timeout(HANDSHAKE_TIMEOUT, transport.next())
+        .await
+        .map_err(|_| NetworkError::HandshakeTimeout(peer))?;
+    match frame {
+        Some(Ok(frame)) => parse(frame.freeze()).ok_or(NetworkError::FailedToHandshake(peer)),
+        Some(Err(e)) => Err(NetworkError::FailedToReceiveMessage(peer, e)),
+        None => Err(NetworkError::FailedToHandshake(peer)),
+    }
+}
+
+/// Parse a handshake frame: the magic prefix, the version, and the (optional) address of the sender.
+fn parse(mut frame: Bytes) -> Option<(u32, Option<SocketAddr>)> {
+    if frame.len() < MAGIC.len() + 4 || &frame[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    frame.advance(MAGIC.len());
+    let version = frame.get_u32();
+    let identity = match frame.is_empty() {
+        true => None,
+        false => Some(std::str::from_utf8(&frame).ok()?.parse().ok()?),
+    };
+    Some((version, identity))
+}
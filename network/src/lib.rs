@@ -1,13 +1,25 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod bandwidth;
+mod connect;
 mod error;
+mod handshake;
+mod probe;
 mod receiver;
 mod reliable_sender;
+mod resolver;
 mod simple_sender;
+mod warning_limiter;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
-pub use crate::reliable_sender::{CancelHandler, ReliableSender};
+pub use crate::bandwidth::Bandwidth;
+pub use crate::connect::DEFAULT_CONNECT_TIMEOUT;
+pub use crate::handshake::PROTOCOL_VERSION;
+pub use crate::probe::{probe, Reachability};
+pub use crate::receiver::{MessageHandler, Receiver, ReceiverOptions, Writer};
+pub use crate::reliable_sender::{CancelHandler, FlushPolicy, Priority, ReliableSender, MAX_COALESCED_BYTES};
+pub use crate::resolver::{AddressResolver, StaticResolver};
 pub use crate::simple_sender::SimpleSender;
+pub use crate::warning_limiter::{WarningLimiter, DEFAULT_WARNING_PERIOD};
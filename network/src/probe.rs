@@ -0,0 +1,73 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::connect::connect;
+use crate::error::NetworkError;
+use crate::handshake;
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// The outcome of probing the address of a peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reachability {
+    /// We failed to connect to the address.
+    Unreachable(String),
+    /// The peer accepted the connection (we did not attempt a handshake).
+    Reachable,
+    /// The peer accepted the connection and agreed on our message format version.
+    Compatible(u32),
+    /// The peer speaks another message format version.
+    Incompatible { ours: u32, theirs: u32 },
+    /// The peer accepted the connection but did not complete the handshake (it may not expect one).
+    NoHandshake,
+}
+
+impl Reachability {
+    /// Whether the peer is reachable and able to talk to us.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Self::Reachable | Self::Compatible(_) | Self::NoHandshake)
+    }
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unreachable(e) => write!(f, "UNREACHABLE ({})", e),
+            Self::Reachable => write!(f, "reachable"),
+            Self::Compatible(version) => write!(f, "reachable (version {})", version),
+            Self::Incompatible { ours, theirs } => write!(
+                f,
+                "INCOMPATIBLE (speaks version {}, we speak {})",
+                theirs, ours
+            ),
+            Self::NoHandshake => write!(f, "reachable (no handshake)"),
+        }
+    }
+}
+
+/// Connect to the address and, if `protocol_version` is set, try to agree on the message format
+/// version with the peer. Every step gives up after `connect_timeout` ms.
+pub async fn probe(
+    address: SocketAddr,
+    connect_timeout: u64,
+    protocol_version: Option<u32>,
+) -> Reachability {
+    let stream = match connect(address, connect_timeout).await {
+        Ok(x) => x,
+        Err(e) => return Reachability::Unreachable(e.to_string()),
+    };
+    let version = match protocol_version {
+        Some(x) => x,
+        None => return Reachability::Reachable,
+    };
+
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let handshake = handshake::initiate(&mut transport, address, version, None);
+    match timeout(Duration::from_millis(connect_timeout), handshake).await {
+        Ok(Ok(())) => Reachability::Compatible(version),
+        Ok(Err(NetworkError::IncompatiblePeer(_, ours, theirs))) => {
+            Reachability::Incompatible { ours, theirs }
+        }
+        Ok(Err(_)) | Err(_) => Reachability::NoHandshake,
+    }
+}
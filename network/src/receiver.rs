@@ -1,5 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::bandwidth::Bandwidth;
 use crate::error::NetworkError;
+use crate::handshake;
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::stream::SplitSink;
@@ -7,7 +9,11 @@ use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, Duration};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
@@ -24,6 +30,55 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
     /// responses or acknowledgements to the sender machine (see unit tests for examples).
     async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+
+    /// Called with the address of the peer before handling any of its messages. The handler is cloned
+    /// for each connection, so it may use this to tell connections apart.
+    fn set_peer(&mut self, _peer: SocketAddr) {}
+}
+
+/// The options of a network receiver. By default, the receiver accepts any number of connections,
+/// does not handshake with its peers, does not account the bytes it receives, and keeps idle connections.
+#[derive(Clone, Default)]
+pub struct ReceiverOptions {
+    /// The maximum number of simultaneous incoming connections (no limit if set to 0).
+    max_connections: usize,
+    /// The message format version every peer must agree on before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// Accounts the bytes we receive from each peer (if enabled).
+    bandwidth: Option<Bandwidth>,
+    /// The time after which we close the connections that did not send any frame (never if set to 0).
+    idle_timeout: u64,
+}
+
+impl ReceiverOptions {
+    /// Accept at most `max_connections` simultaneous connections (no limit if set to 0). Excess
+    /// connections are closed right after being accepted.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// First agree on the message format version with every incoming peer (if `protocol_version` is
+    /// set). Incompatible peers are disconnected before any of their messages reach the handler.
+    pub fn with_handshake(mut self, protocol_version: Option<u32>) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Account the bytes we receive from each peer in `bandwidth` (under the address the peer
+    /// advertises during the handshake).
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Close the connections that did not send any frame for `idle_timeout` ms (never if set to 0).
+    /// This is meant for the connections of clients, freeing their resources; the connections between
+    /// authorities may legitimately stay idle.
+    pub fn with_idle_timeout(mut self, idle_timeout: u64) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -33,13 +88,36 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// Bounds the number of simultaneous incoming connections (if any).
+    connections: Option<Arc<Semaphore>>,
+    /// The message format version every peer must agree on before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// Accounts the bytes we receive from each peer (if enabled).
+    bandwidth: Option<Bandwidth>,
+    /// Closes the connections that did not send any frame for this long (if any).
+    idle_timeout: Option<Duration>,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
-    pub fn spawn(address: SocketAddr, handler: Handler) {
+    /// Spawn a new network receiver handling connections from any incoming peer, configured by `options`.
+    pub fn spawn(address: SocketAddr, handler: Handler, options: ReceiverOptions) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                handler,
+                connections: match options.max_connections {
+                    0 => None,
+                    x => Some(Arc::new(Semaphore::new(x))),
+                },
+                protocol_version: options.protocol_version,
+                bandwidth: options.bandwidth,
+                idle_timeout: match options.idle_timeout {
+                    0 => None,
+                    x => Some(Duration::from_millis(x)),
+                },
+            }
+            .run()
+            .await;
         });
     }
 
@@ -58,20 +136,79 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                     continue;
                 }
             };
+            let permit = match &self.connections {
+                Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        warn!("Too many incoming connections: rejecting {}", peer);
+                        continue;
+                    }
+                },
+                None => None,
+            };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            Self::spawn_runner(
+                socket,
+                peer,
+                self.handler.clone(),
+                permit,
+                self.protocol_version,
+                self.bandwidth.clone(),
+                self.idle_timeout,
+            )
+            .await;
         }
     }
 
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// using the provided handler. The connection slot (if any) is released when the runner exits, which
+    /// also happens if the peer stays idle for longer than `idle_timeout` (if set). The received bytes are
+    /// accounted under the address the peer advertised during the handshake (if any).
+    async fn spawn_runner(
+        socket: TcpStream,
+        peer: SocketAddr,
+        mut handler: Handler,
+        permit: Option<OwnedSemaphorePermit>,
+        protocol_version: Option<u32>,
+        bandwidth: Option<Bandwidth>,
+        idle_timeout: Option<Duration>,
+    ) {
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let _permit = permit;
+            handler.set_peer(peer);
+            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let mut identity = None;
+            if let Some(version) = protocol_version {
+                match handshake::accept(&mut transport, peer, version).await {
+                    Ok(x) => identity = x,
+                    Err(e) => {
+                        warn!("{}", e);
+                        return;
+                    }
+                }
+            }
+            let received = bandwidth.zip(identity).map(|(x, y)| x.received_counter(y));
             let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
+            loop {
+                let frame = match idle_timeout {
+                    Some(idle_timeout) => match timeout(idle_timeout, reader.next()).await {
+                        Ok(frame) => frame,
+                        Err(_) => {
+                            debug!("Closing connection with {} (idle for {:?})", peer, idle_timeout);
+                            return;
+                        }
+                    },
+                    None => reader.next().await,
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => break,
+                };
                 match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
                     Ok(message) => {
+                        if let Some(received) = &received {
+                            received.fetch_add(message.len() as u64, Ordering::Relaxed);
+                        }
                         if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
                             warn!("{}", e);
                             return;
@@ -1,9 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::bandwidth::Bandwidth;
+use crate::connect::{connect, DEFAULT_CONNECT_TIMEOUT};
 use crate::error::NetworkError;
+use crate::handshake;
+use crate::resolver::{AddressResolver, StaticResolver};
+use crate::warning_limiter::WarningLimiter;
 use bytes::Bytes;
-use futures::sink::SinkExt as _;
+use futures::sink::{Sink, SinkExt as _};
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::info;
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
@@ -11,6 +16,8 @@ use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
@@ -24,6 +31,57 @@ pub mod reliable_sender_tests;
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
+/// Convenient alias for a framed TCP connection.
+type Transport = Framed<TcpStream, LengthDelimitedCodec>;
+
+/// The priority class of a message. Each connection transmits its high priority messages ahead of
+/// its normal priority ones (messages of the same class are transmitted in order).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Small and latency-critical messages (e.g., headers and votes).
+    High,
+    /// Any other message (e.g., certificates or bulk sync responses).
+    Normal,
+}
+
+/// When a connection flushes the messages of a priority class to the socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush every message as soon as it is written (lowest latency).
+    Immediate,
+    /// Write the queued messages back to back and flush them together (once no more messages are queued
+    /// or after `MAX_COALESCED_BYTES`), saving system calls and packets for bulk transfers.
+    Coalesce,
+}
+
+/// The maximum number of bytes written to a connection between two flushes of coalesced messages.
+pub const MAX_COALESCED_BYTES: usize = 256 * 1024;
+
+/// The flush policy of each priority class.
+#[derive(Clone, Copy, Debug)]
+struct FlushPolicies {
+    high: FlushPolicy,
+    normal: FlushPolicy,
+}
+
+impl Default for FlushPolicies {
+    fn default() -> Self {
+        Self {
+            high: FlushPolicy::Immediate,
+            normal: FlushPolicy::Coalesce,
+        }
+    }
+}
+
+impl FlushPolicies {
+    fn get(&self, priority: Priority) -> FlushPolicy {
+        match priority {
+            Priority::High => self.high,
+            Priority::Normal => self.normal,
+        }
+    }
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
@@ -33,6 +91,20 @@ pub struct ReliableSender {
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The time to wait for a connection to be established (in ms).
+    connect_timeout: u64,
+    /// The message format version to agree on with every peer before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// The address we advertise to our peers during the handshake (if any).
+    identity: Option<SocketAddr>,
+    /// Coalesces the identical warnings of all our connections.
+    warnings: WarningLimiter,
+    /// When our connections flush the messages of each priority class.
+    flush_policies: FlushPolicies,
+    /// Accounts the bytes we send to each peer (if enabled).
+    bandwidth: Option<Bandwidth>,
+    /// Resolves the address at which to reach each peer.
+    resolver: Arc<dyn AddressResolver>,
 }
 
 impl std::default::Default for ReliableSender {
@@ -43,27 +115,127 @@ impl std::default::Default for ReliableSender {
 
 impl ReliableSender {
     pub fn new() -> Self {
+        Self::with_connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    /// Make a sender giving up on connection attempts after `connect_timeout` ms.
+    pub fn with_connect_timeout(connect_timeout: u64) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            connect_timeout,
+            protocol_version: None,
+            identity: None,
+            warnings: WarningLimiter::default(),
+            flush_policies: FlushPolicies::default(),
+            bandwidth: None,
+            resolver: Arc::new(StaticResolver),
         }
     }
 
+    /// Make a sender that first agrees on the message format version with every peer (if
+    /// `protocol_version` is set). Messages are never sent to incompatible peers.
+    pub fn with_handshake(protocol_version: Option<u32>) -> Self {
+        Self {
+            protocol_version,
+            ..Self::new()
+        }
+    }
+
+    /// Set when the messages of a priority class are flushed to the socket. By default, high priority
+    /// messages are flushed immediately and normal priority messages are coalesced.
+    pub fn with_flush_policy(mut self, priority: Priority, policy: FlushPolicy) -> Self {
+        match priority {
+            Priority::High => self.flush_policies.high = policy,
+            Priority::Normal => self.flush_policies.normal = policy,
+        }
+        self
+    }
+
+    /// Advertise `identity` (the address at which our peers reach us, if set) during the handshake, so
+    /// that our peers can attribute our traffic to us.
+    pub fn with_identity(mut self, identity: Option<SocketAddr>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Account the bytes we send to each peer (including re-transmissions) in `bandwidth`.
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Resolve the address at which to reach each peer through `resolver` (rather than connecting to
+    /// the address we are given). The resolver is consulted before every connection attempt.
+    pub fn with_resolver(mut self, resolver: Arc<dyn AddressResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_connection(
+        address: SocketAddr,
+        connect_timeout: u64,
+        protocol_version: Option<u32>,
+        identity: Option<SocketAddr>,
+        warnings: WarningLimiter,
+        flush_policies: FlushPolicies,
+        sent: Option<Arc<AtomicU64>>,
+        resolver: Arc<dyn AddressResolver>,
+    ) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(
+            address,
+            connect_timeout,
+            protocol_version,
+            identity,
+            warnings,
+            flush_policies,
+            sent,
+            resolver,
+            rx,
+        );
         tx
     }
 
     /// Reliably send a message to a specific address.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
+        self.send_with_priority(address, data, Priority::Normal).await
+    }
+
+    /// Reliably send a message to a specific address, ahead of the pending messages of lower priority.
+    pub async fn send_with_priority(
+        &mut self,
+        address: SocketAddr,
+        data: Bytes,
+        priority: Priority,
+    ) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
+        let connect_timeout = self.connect_timeout;
+        let protocol_version = self.protocol_version;
+        let identity = self.identity;
+        let warnings = &self.warnings;
+        let flush_policies = self.flush_policies;
+        let bandwidth = &self.bandwidth;
+        let resolver = &self.resolver;
         self.connections
             .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .or_insert_with(|| {
+                Self::spawn_connection(
+                    address,
+                    connect_timeout,
+                    protocol_version,
+                    identity,
+                    warnings.clone(),
+                    flush_policies,
+                    bandwidth.as_ref().map(|x| x.sent_counter(address)),
+                    resolver.clone(),
+                )
+            })
             .send(InnerMessage {
                 data,
+                priority,
                 cancel_handler: sender,
             })
             .await
@@ -77,10 +249,21 @@ impl ReliableSender {
         &mut self,
         addresses: Vec<SocketAddr>,
         data: Bytes,
+    ) -> Vec<CancelHandler> {
+        self.broadcast_with_priority(addresses, data, Priority::Normal).await
+    }
+
+    /// Broadcast the message to all specified addresses in a reliable manner, ahead of the pending messages
+    /// of lower priority. It returns a vector of cancel handlers ordered as the input `addresses` vector.
+    pub async fn broadcast_with_priority(
+        &mut self,
+        addresses: Vec<SocketAddr>,
+        data: Bytes,
+        priority: Priority,
     ) -> Vec<CancelHandler> {
         let mut handlers = Vec::new();
         for address in addresses {
-            let handler = self.send(address, data.clone()).await;
+            let handler = self.send_with_priority(address, data.clone(), priority).await;
             handlers.push(handler);
         }
         handlers
@@ -105,6 +288,8 @@ impl ReliableSender {
 struct InnerMessage {
     /// The data to transmit.
     data: Bytes,
+    /// The priority class of the message.
+    priority: Priority,
     /// The cancel handler allowing the caller task to cancel the transmission of this message
     /// and to be notified of its successfully transmission.
     cancel_handler: oneshot::Sender<Bytes>,
@@ -112,24 +297,59 @@ struct InnerMessage {
 
 /// A connection is responsible to reliably establish (and keep alive) a connection with a single peer.
 struct Connection {
-    /// The destination address.
+    /// The destination address (as advertised by the peer).
     address: SocketAddr,
+    /// The time to wait for a connection to be established (in ms).
+    connect_timeout: u64,
+    /// The message format version to agree on with the peer before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// The address we advertise to the peer during the handshake (if any).
+    identity: Option<SocketAddr>,
+    /// Coalesces identical warnings (shared with the other connections of the sender).
+    warnings: WarningLimiter,
+    /// When to flush the messages of each priority class.
+    flush_policies: FlushPolicies,
+    /// Accounts the bytes we send to the peer (if enabled).
+    sent: Option<Arc<AtomicU64>>,
+    /// Resolves the address at which to reach the peer.
+    resolver: Arc<dyn AddressResolver>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
     /// The initial delay to wait before re-attempting a connection (in ms).
     retry_delay: u64,
-    /// Buffer keeping all messages that need to be re-transmitted.
+    /// Buffer keeping all normal priority messages that need to be re-transmitted.
     buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    /// Buffer keeping all high priority messages that need to be re-transmitted.
+    urgent: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        address: SocketAddr,
+        connect_timeout: u64,
+        protocol_version: Option<u32>,
+        identity: Option<SocketAddr>,
+        warnings: WarningLimiter,
+        flush_policies: FlushPolicies,
+        sent: Option<Arc<AtomicU64>>,
+        resolver: Arc<dyn AddressResolver>,
+        receiver: Receiver<InnerMessage>,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
+                connect_timeout,
+                protocol_version,
+                identity,
+                warnings,
+                flush_policies,
+                sent,
+                resolver,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                urgent: VecDeque::new(),
             }
             .run()
             .await;
@@ -141,8 +361,8 @@ impl Connection {
         let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
-                Ok(stream) => {
+            match self.establish(retry).await {
+                Ok(transport) => {
                     info!("Outgoing connection established with {}", self.address);
 
                     // Reset the delay.
@@ -151,11 +371,11 @@ impl Connection {
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
-                    warn!("{}", error);
+                    let error = self.keep_alive(transport).await;
+                    self.warnings.warn(error.to_string());
                 }
                 Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
+                    self.warnings.warn(e.to_string());
                     let timer = sleep(Duration::from_millis(delay));
                     tokio::pin!(timer);
 
@@ -170,9 +390,10 @@ impl Connection {
 
                             // Drain the channel into the buffer to not saturate the channel and block the caller task.
                             // The caller is responsible to cleanup the buffer through the cancel handlers.
-                            Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
+                            Some(message) = self.receiver.recv() => {
+                                self.enqueue(message);
                                 self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                self.urgent.retain(|(_, handler)| !handler.is_closed());
                             }
                         }
                     }
@@ -181,44 +402,145 @@ impl Connection {
         }
     }
 
+    /// Add a message at the back of the buffer of its priority class.
+    fn enqueue(&mut self, message: InnerMessage) {
+        let InnerMessage {
+            data,
+            priority,
+            cancel_handler,
+        } = message;
+        self.requeue(priority, data, cancel_handler, /* front */ false);
+    }
+
+    /// Put a message (back) into the buffer of its priority class.
+    fn requeue(
+        &mut self,
+        priority: Priority,
+        data: Bytes,
+        handler: oneshot::Sender<Bytes>,
+        front: bool,
+    ) {
+        let buffer = match priority {
+            Priority::High => &mut self.urgent,
+            Priority::Normal => &mut self.buffer,
+        };
+        if front {
+            buffer.push_front((data, handler));
+        } else {
+            buffer.push_back((data, handler));
+        }
+    }
+
+    /// Pick the next message to transmit. We first move the messages waiting in the channel to the
+    /// buffers, so that high priority messages overtake the normal priority ones already received.
+    fn dequeue(&mut self) -> Option<(Priority, Bytes, oneshot::Sender<Bytes>)> {
+        while let Ok(message) = self.receiver.try_recv() {
+            self.enqueue(message);
+        }
+        match self.urgent.pop_front() {
+            Some((data, handler)) => Some((Priority::High, data, handler)),
+            None => self
+                .buffer
+                .pop_front()
+                .map(|(data, handler)| (Priority::Normal, data, handler)),
+        }
+    }
+
+    /// Resolve the address of the peer, connect to it, and agree on the message format version (if required).
+    async fn establish(&self, retry: u16) -> Result<Transport, NetworkError> {
+        let address = self
+            .resolver
+            .resolve(self.address)
+            .await
+            .map_err(|e| NetworkError::FailedToResolve(self.address, retry, e))?;
+        let stream = connect(address, self.connect_timeout)
+            .await
+            .map_err(|e| NetworkError::FailedToConnect(address, retry, e))?;
+        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        if let Some(version) = self.protocol_version {
+            handshake::initiate(&mut transport, address, version, self.identity).await?;
+        }
+        Ok(transport)
+    }
+
+    /// Write all the buffered messages (by decreasing priority) and flush them according to the flush policy of
+    /// their priority class. The written messages are moved to `pending_replies` while we wait for their ACK.
+    async fn transmit<W>(
+        &mut self,
+        writer: &mut W,
+        pending_replies: &mut VecDeque<(Priority, Bytes, oneshot::Sender<Bytes>)>,
+    ) -> Result<(), std::io::Error>
+    where
+        W: Sink<Bytes, Error = std::io::Error> + Unpin,
+    {
+        // The number of bytes written since the last flush.
+        let mut unflushed = 0;
+        while let Some((priority, data, handler)) = self.dequeue() {
+            // Skip messages that have been cancelled.
+            if handler.is_closed() {
+                continue;
+            }
+
+            // Try to write the message.
+            let size = data.len();
+            let result = match self.flush_policies.get(priority) {
+                FlushPolicy::Immediate => writer.send(data.clone()).await,
+                FlushPolicy::Coalesce => writer.feed(data.clone()).await,
+            };
+            match result {
+                Ok(()) => {
+                    if let Some(sent) = &self.sent {
+                        sent.fetch_add(size as u64, Ordering::Relaxed);
+                    }
+
+                    // The message has been written, we remove it from the buffer and add it to
+                    // `pending_replies` while we wait for an ACK.
+                    pending_replies.push_back((priority, data, handler));
+                }
+                Err(e) => {
+                    // We failed to write the message, we put it back into the buffer.
+                    self.requeue(priority, data, handler, /* front */ true);
+                    return Err(e);
+                }
+            }
+
+            // Sending a message flushes all the messages written before it.
+            unflushed = match self.flush_policies.get(priority) {
+                FlushPolicy::Immediate => 0,
+                FlushPolicy::Coalesce => unflushed + size,
+            };
+            if unflushed >= MAX_COALESCED_BYTES {
+                writer.flush().await?;
+                unflushed = 0;
+            }
+        }
+        if unflushed > 0 {
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(&mut self, transport: Transport) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        let (mut writer, mut reader) = transport.split();
         let error = 'connection: loop {
-            // Try to send all messages of the buffer.
-            while let Some((data, handler)) = self.buffer.pop_front() {
-                // Skip messages that have been cancelled.
-                if handler.is_closed() {
-                    continue;
-                }
-
-                // Try to send the message.
-                match writer.send(data.clone()).await {
-                    Ok(()) => {
-                        // The message has been sent, we remove it from the buffer and add it to
-                        // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
-                    }
-                    Err(e) => {
-                        // We failed to send the message, we put it back into the buffer.
-                        self.buffer.push_front((data, handler));
-                        break 'connection NetworkError::FailedToSendMessage(self.address, e);
-                    }
-                }
+            // Try to send all messages of the buffers (by decreasing priority).
+            if let Err(e) = self.transmit(&mut writer, &mut pending_replies).await {
+                break 'connection NetworkError::FailedToSendMessage(self.address, e);
             }
 
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
+                Some(message) = self.receiver.recv() => {
                     // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                    self.enqueue(message);
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
+                    let (priority, data, handler) = match pending_replies.pop_front() {
                         Some(message) => message,
                         None => break 'connection NetworkError::UnexpectedAck(self.address)
                     };
@@ -230,7 +552,7 @@ impl Connection {
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
                             // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
+                            pending_replies.push_front((priority, data, handler));
                             break 'connection NetworkError::FailedToReceiveAck(self.address);
                         }
                     }
@@ -240,8 +562,8 @@ impl Connection {
 
         // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
         // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
-        while let Some(message) = pending_replies.pop_back() {
-            self.buffer.push_front(message);
+        while let Some((priority, data, handler)) = pending_replies.pop_back() {
+            self.requeue(priority, data, handler, /* front */ true);
         }
         error
     }
@@ -0,0 +1,25 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+
+/// Resolves the address at which to reach a peer. Peers are identified by the address advertised in
+/// the committee; the senders consult the resolver every time they (re-)establish a connection, so a
+/// resolver backed by service discovery (e.g., for nodes behind NAT) may return a different address
+/// each time. Failures are retried like failed connection attempts.
+#[async_trait]
+pub trait AddressResolver: Send + Sync + 'static {
+    /// The address at which to reach the peer advertised at `address`.
+    async fn resolve(&self, address: SocketAddr) -> io::Result<SocketAddr>;
+}
+
+/// The default resolver: peers are reached at the address of the committee (config) file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StaticResolver;
+
+#[async_trait]
+impl AddressResolver for StaticResolver {
+    async fn resolve(&self, address: SocketAddr) -> io::Result<SocketAddr> {
+        Ok(address)
+    }
+}
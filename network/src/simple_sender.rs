@@ -1,15 +1,21 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::bandwidth::Bandwidth;
+use crate::connect::{connect, DEFAULT_CONNECT_TIMEOUT};
 use crate::error::NetworkError;
+use crate::handshake;
+use crate::resolver::{AddressResolver, StaticResolver};
+use crate::warning_limiter::WarningLimiter;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::info;
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
@@ -24,6 +30,18 @@ pub struct SimpleSender {
     connections: HashMap<SocketAddr, Sender<Bytes>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The time to wait for a connection to be established (in ms).
+    connect_timeout: u64,
+    /// The message format version to agree on with every peer before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// The address we advertise to our peers during the handshake (if any).
+    identity: Option<SocketAddr>,
+    /// Coalesces the identical warnings of all our connections.
+    warnings: WarningLimiter,
+    /// Accounts the bytes we send to each peer (if enabled).
+    bandwidth: Option<Bandwidth>,
+    /// Resolves the address at which to reach each peer.
+    resolver: Arc<dyn AddressResolver>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -34,16 +52,74 @@ impl std::default::Default for SimpleSender {
 
 impl SimpleSender {
     pub fn new() -> Self {
+        Self::with_connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    /// Make a sender giving up on connection attempts after `connect_timeout` ms.
+    pub fn with_connect_timeout(connect_timeout: u64) -> Self {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            connect_timeout,
+            protocol_version: None,
+            identity: None,
+            warnings: WarningLimiter::default(),
+            bandwidth: None,
+            resolver: Arc::new(StaticResolver),
+        }
+    }
+
+    /// Make a sender that first agrees on the message format version with every peer (if
+    /// `protocol_version` is set). Messages are never sent to incompatible peers.
+    pub fn with_handshake(protocol_version: Option<u32>) -> Self {
+        Self {
+            protocol_version,
+            ..Self::new()
         }
     }
 
+    /// Advertise `identity` (the address at which our peers reach us, if set) during the handshake, so
+    /// that our peers can attribute our traffic to us.
+    pub fn with_identity(mut self, identity: Option<SocketAddr>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Account the bytes we send to each peer in `bandwidth`.
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Resolve the address at which to reach each peer through `resolver` (rather than connecting to
+    /// the address we are given). The resolver is consulted every time we make a new connection.
+    pub fn with_resolver(mut self, resolver: Arc<dyn AddressResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_connection(
+        address: SocketAddr,
+        connect_timeout: u64,
+        protocol_version: Option<u32>,
+        identity: Option<SocketAddr>,
+        warnings: WarningLimiter,
+        sent: Option<Arc<AtomicU64>>,
+        resolver: Arc<dyn AddressResolver>,
+    ) -> Sender<Bytes> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(
+            address,
+            connect_timeout,
+            protocol_version,
+            identity,
+            warnings,
+            sent,
+            resolver,
+            rx,
+        );
         tx
     }
 
@@ -58,7 +134,15 @@ impl SimpleSender {
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = Self::spawn_connection(
+            address,
+            self.connect_timeout,
+            self.protocol_version,
+            self.identity,
+            self.warnings.clone(),
+            self.bandwidth.as_ref().map(|x| x.sent_counter(address)),
+            self.resolver.clone(),
+        );
         if tx.send(data).await.is_ok() {
             self.connections.insert(address, tx);
         }
@@ -87,32 +171,83 @@ impl SimpleSender {
 
 /// A connection is responsible to establish and keep alive (if possible) a connection with a single peer.
 struct Connection {
-    /// The destination address.
+    /// The destination address (as advertised by the peer).
     address: SocketAddr,
+    /// The time to wait for a connection to be established (in ms).
+    connect_timeout: u64,
+    /// The message format version to agree on with the peer before sending messages (if any).
+    protocol_version: Option<u32>,
+    /// The address we advertise to the peer during the handshake (if any).
+    identity: Option<SocketAddr>,
+    /// Coalesces identical warnings (shared with the other connections of the sender).
+    warnings: WarningLimiter,
+    /// Accounts the bytes we send to the peer (if enabled).
+    sent: Option<Arc<AtomicU64>>,
+    /// Resolves the address at which to reach the peer.
+    resolver: Arc<dyn AddressResolver>,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Bytes>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        address: SocketAddr,
+        connect_timeout: u64,
+        protocol_version: Option<u32>,
+        identity: Option<SocketAddr>,
+        warnings: WarningLimiter,
+        sent: Option<Arc<AtomicU64>>,
+        resolver: Arc<dyn AddressResolver>,
+        receiver: Receiver<Bytes>,
+    ) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                connect_timeout,
+                protocol_version,
+                identity,
+                warnings,
+                sent,
+                resolver,
+                receiver,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
-        // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+        // Resolve the address of the peer and try to connect to it.
+        let address = match self.resolver.resolve(self.address).await {
+            Ok(address) => address,
             Err(e) => {
-                warn!(
-                    "{}",
-                    NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
+                self.warnings.warn(
+                    NetworkError::FailedToResolve(self.address, /* retry */ 0, e).to_string(),
                 );
                 return;
             }
         };
+        let mut transport = match connect(address, self.connect_timeout).await {
+            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()),
+            Err(e) => {
+                self.warnings
+                    .warn(NetworkError::FailedToConnect(address, /* retry */ 0, e).to_string());
+                return;
+            }
+        };
+
+        // Agree on the message format version (if required).
+        if let Some(version) = self.protocol_version {
+            if let Err(e) =
+                handshake::initiate(&mut transport, address, version, self.identity).await
+            {
+                self.warnings.warn(e.to_string());
+                return;
+            }
+        }
+        let (mut writer, mut reader) = transport.split();
         info!("Outgoing connection established with {}", self.address);
 
         // Transmit messages once we have established a connection.
@@ -120,10 +255,14 @@ impl Connection {
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
                 Some(data) = self.receiver.recv() => {
+                    let size = data.len() as u64;
                     if let Err(e) = writer.send(data).await {
-                        warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
+                        self.warnings.warn(NetworkError::FailedToSendMessage(self.address, e).to_string());
                         return;
                     }
+                    if let Some(sent) = &self.sent {
+                        sent.fetch_add(size, Ordering::Relaxed);
+                    }
                 },
                 response = reader.next() => {
                     match response {
@@ -132,7 +271,7 @@ impl Connection {
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            warn!("{}", NetworkError::FailedToReceiveAck(self.address));
+                            self.warnings.warn(NetworkError::FailedToReceiveAck(self.address).to_string());
                             return;
                         }
                     }
@@ -0,0 +1,71 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::handshake::PROTOCOL_VERSION;
+use crate::receiver::{MessageHandler, Receiver, ReceiverOptions, Writer};
+use crate::reliable_sender::ReliableSender;
+use crate::simple_sender::SimpleSender;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::try_join_all;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn account_exchanged_bytes() {
+    // Make a network receiver accounting the bytes it receives.
+    let address = "127.0.0.1:6300".parse::<SocketAddr>().unwrap();
+    let receiver_bandwidth = Bandwidth::default();
+    let (tx, mut rx) = channel(10);
+    let options = ReceiverOptions::default()
+        .with_handshake(Some(PROTOCOL_VERSION))
+        .with_bandwidth(receiver_bandwidth.clone());
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Send messages of known sizes through both kinds of senders, sharing the same counters and
+    // advertising the same identity.
+    let identity = "127.0.0.1:6302".parse::<SocketAddr>().unwrap();
+    let sender_bandwidth = Bandwidth::default();
+    let mut reliable = ReliableSender::with_handshake(Some(PROTOCOL_VERSION))
+        .with_identity(Some(identity))
+        .with_bandwidth(sender_bandwidth.clone());
+    let mut simple = SimpleSender::with_handshake(Some(PROTOCOL_VERSION))
+        .with_identity(Some(identity))
+        .with_bandwidth(sender_bandwidth.clone());
+    let handlers = vec![
+        reliable.send(address, Bytes::from(vec![0u8; 100])).await,
+        reliable.send(address, Bytes::from(vec![0u8; 1_000])).await,
+    ];
+    assert!(try_join_all(handlers).await.is_ok());
+    simple.send(address, Bytes::from(vec![0u8; 10])).await;
+    for _ in 0..3 {
+        rx.recv().await.unwrap();
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the counters match the size of the messages.
+    assert_eq!(sender_bandwidth.sent(&address), 1_110);
+    assert_eq!(receiver_bandwidth.received(&identity), 1_110);
+
+    // Nothing was exchanged with other peers.
+    let other = "127.0.0.1:6301".parse::<SocketAddr>().unwrap();
+    assert_eq!(sender_bandwidth.sent(&other), 0);
+    assert_eq!(sender_bandwidth.received(&address), 0);
+    assert_eq!(receiver_bandwidth.received(&other), 0);
+}
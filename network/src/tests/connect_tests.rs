@@ -0,0 +1,25 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::net::TcpSocket;
+use tokio::time::Instant;
+
+#[tokio::test]
+async fn connect_timeout() {
+    // Make a peer that never accepts connections and saturate its backlog: any further connection
+    // attempt hangs (its SYN packets are dropped).
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(address).unwrap();
+    let _listener = socket.listen(1).unwrap();
+    let mut streams = Vec::new();
+    while let Ok(Ok(stream)) = timeout(Duration::from_millis(100), TcpStream::connect(address)).await
+    {
+        streams.push(stream);
+    }
+
+    // Ensure the connection attempt fails within the configured timeout.
+    let now = Instant::now();
+    let result = connect(address, /* connect_timeout */ 200).await;
+    assert!(result.is_err());
+    assert!(now.elapsed() < Duration::from_millis(1_000));
+}
@@ -0,0 +1,84 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::net::TcpListener;
+
+async fn negotiate(
+    address: SocketAddr,
+    ours: u32,
+    theirs: u32,
+    identity: Option<SocketAddr>,
+) -> (
+    Result<(), NetworkError>,
+    Result<Option<SocketAddr>, NetworkError>,
+) {
+    // Spawn a peer accepting a single connection.
+    let listener = TcpListener::bind(&address).await.unwrap();
+    let handle = tokio::spawn(async move {
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept(&mut transport, peer, theirs).await
+    });
+
+    // Connect to the peer and run the handshake.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let initiated = initiate(&mut transport, address, ours, identity).await;
+    (initiated, handle.await.unwrap())
+}
+
+#[tokio::test]
+async fn handshake() {
+    let address = "127.0.0.1:4300".parse::<SocketAddr>().unwrap();
+    let (initiated, accepted) = negotiate(address, PROTOCOL_VERSION, PROTOCOL_VERSION, None).await;
+    assert!(initiated.is_ok());
+    assert_eq!(accepted.unwrap(), None);
+}
+
+#[tokio::test]
+async fn advertise_identity() {
+    let address = "127.0.0.1:4350".parse::<SocketAddr>().unwrap();
+    let identity = "127.0.0.1:4351".parse::<SocketAddr>().unwrap();
+    let (initiated, accepted) =
+        negotiate(address, PROTOCOL_VERSION, PROTOCOL_VERSION, Some(identity)).await;
+    assert!(initiated.is_ok());
+    assert_eq!(accepted.unwrap(), Some(identity));
+}
+
+#[tokio::test]
+async fn reject_incompatible_peer() {
+    let address = "127.0.0.1:4400".parse::<SocketAddr>().unwrap();
+    let (initiated, accepted) =
+        negotiate(address, PROTOCOL_VERSION, PROTOCOL_VERSION + 1, None).await;
+    match initiated {
+        Err(NetworkError::IncompatiblePeer(_, ours, theirs)) => {
+            assert_eq!(ours, PROTOCOL_VERSION);
+            assert_eq!(theirs, PROTOCOL_VERSION + 1);
+        }
+        x => panic!("Unexpected handshake result: {:?}", x),
+    }
+    match accepted {
+        Err(NetworkError::IncompatiblePeer(_, ours, theirs)) => {
+            assert_eq!(ours, PROTOCOL_VERSION + 1);
+            assert_eq!(theirs, PROTOCOL_VERSION);
+        }
+        x => panic!("Unexpected handshake result: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn drop_silent_peer() {
+    let address = "127.0.0.1:4450".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&address).await.unwrap();
+
+    // Connect to the listener but never send our handshake.
+    let _stream = TcpStream::connect(address).await.unwrap();
+    let (socket, peer) = listener.accept().await.unwrap();
+
+    // The handshake times out instead of waiting forever.
+    tokio::time::pause();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    match accept(&mut transport, peer, PROTOCOL_VERSION).await {
+        Err(NetworkError::HandshakeTimeout(x)) => assert_eq!(x, peer),
+        x => panic!("Unexpected handshake result: {:?}", x),
+    }
+}
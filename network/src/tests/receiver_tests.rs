@@ -30,7 +30,11 @@ async fn receive() {
     // Make the network receiver.
     let address = "127.0.0.1:4000".parse::<SocketAddr>().unwrap();
     let (tx, mut rx) = channel(1);
-    Receiver::spawn(address, TestHandler { deliver: tx });
+    Receiver::spawn(
+        address,
+        TestHandler { deliver: tx },
+        ReceiverOptions::default(),
+    );
     sleep(Duration::from_millis(50)).await;
 
     // Send a message.
@@ -46,3 +50,105 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[tokio::test]
+async fn reject_excess_connections() {
+    // Make a network receiver accepting a single connection.
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let options = ReceiverOptions::default().with_max_connections(1);
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Open a first connection and ensure it is served.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut first = Framed::new(stream, LengthDelimitedCodec::new());
+    first.send(bytes.clone()).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+
+    // Open a second connection: it exceeds the limit and gets closed.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut second = Framed::new(stream, LengthDelimitedCodec::new());
+    let _ = second.send(bytes.clone()).await;
+    assert!(matches!(second.next().await, None | Some(Err(_))));
+
+    // Close the first connection; a new connection is now served.
+    drop(first);
+    sleep(Duration::from_millis(50)).await;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut third = Framed::new(stream, LengthDelimitedCodec::new());
+    third.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+}
+
+#[tokio::test]
+async fn close_idle_connections() {
+    // Make a network receiver closing the connections idle for 200 ms.
+    let address = "127.0.0.1:4500".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    let options = ReceiverOptions::default().with_idle_timeout(200);
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure an active connection stays open.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    for _ in 0..3 {
+        sleep(Duration::from_millis(100)).await;
+        transport.send(bytes.clone()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), sent);
+        assert_eq!(transport.next().await.unwrap().unwrap(), "Ack");
+    }
+
+    // Ensure the connection is closed once it stays idle past the timeout.
+    let closed = tokio::time::timeout(Duration::from_secs(1), transport.next()).await;
+    assert!(matches!(closed, Ok(None) | Ok(Some(Err(_)))));
+}
+
+#[tokio::test]
+async fn combine_options() {
+    // Make a network receiver accepting a single connection, handshaking with its peers, accounting the
+    // bytes it receives, and closing the connections idle for 200 ms.
+    let address = "127.0.0.1:4600".parse::<SocketAddr>().unwrap();
+    let bandwidth = Bandwidth::default();
+    let (tx, mut rx) = channel(1);
+    let options = ReceiverOptions::default()
+        .with_max_connections(1)
+        .with_handshake(Some(handshake::PROTOCOL_VERSION))
+        .with_bandwidth(bandwidth.clone())
+        .with_idle_timeout(200);
+    Receiver::spawn(address, TestHandler { deliver: tx }, options);
+    sleep(Duration::from_millis(50)).await;
+
+    // Handshake (advertising our identity) and send a message.
+    let identity = "127.0.0.1:4601".parse::<SocketAddr>().unwrap();
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut first = Framed::new(stream, LengthDelimitedCodec::new());
+    handshake::initiate(
+        &mut first,
+        address,
+        handshake::PROTOCOL_VERSION,
+        Some(identity),
+    )
+    .await
+    .unwrap();
+    first.send(bytes.clone()).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+    assert_eq!(bandwidth.received(&identity), bytes.len() as u64);
+
+    // A second connection exceeds the limit and gets closed.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut second = Framed::new(stream, LengthDelimitedCodec::new());
+    let _ = second.send(bytes).await;
+    assert!(matches!(second.next().await, None | Some(Err(_))));
+
+    // The first connection is closed once idle.
+    assert_eq!(first.next().await.unwrap().unwrap(), "Ack");
+    assert!(matches!(first.next().await, None | Some(Err(_))));
+}
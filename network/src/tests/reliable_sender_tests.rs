@@ -1,7 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::listener;
+use async_trait::async_trait;
 use futures::future::try_join_all;
+use std::io;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
 
 #[tokio::test]
 async fn send() {
@@ -65,3 +70,183 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+/// A resolver (e.g., backed by service discovery) returning the address currently set, if any.
+#[derive(Default)]
+struct MockResolver {
+    address: Mutex<Option<SocketAddr>>,
+}
+
+#[async_trait]
+impl AddressResolver for MockResolver {
+    async fn resolve(&self, address: SocketAddr) -> io::Result<SocketAddr> {
+        self.address.lock().unwrap().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not registered", address),
+            )
+        })
+    }
+}
+
+#[tokio::test]
+async fn resolve_address() {
+    // Send a message to a peer whose address cannot be resolved yet.
+    let advertised = "127.0.0.1:5500".parse::<SocketAddr>().unwrap();
+    let resolver = Arc::new(MockResolver::default());
+    let mut sender = ReliableSender::new().with_resolver(resolver.clone());
+    let cancel_handler = sender.send(advertised, Bytes::from("First")).await;
+
+    // Register the peer and run its TCP server.
+    sleep(Duration::from_millis(50)).await;
+    let first = "127.0.0.1:5501".parse::<SocketAddr>().unwrap();
+    *resolver.address.lock().unwrap() = Some(first);
+    let handle = listener(first, "First".to_string());
+
+    // Ensure the resolution is retried and the message is delivered to the resolved address.
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+
+    // Move the peer to another address (the first server is gone).
+    let second = "127.0.0.1:5502".parse::<SocketAddr>().unwrap();
+    *resolver.address.lock().unwrap() = Some(second);
+    let handle = listener(second, "Second".to_string());
+
+    // Ensure the connection follows the peer to its new address.
+    let cancel_handler = sender.send(advertised, Bytes::from("Second")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn priority() {
+    // Enqueue a bulk message and then a vote while the peer is unreachable.
+    let address = "127.0.0.1:5400".parse::<SocketAddr>().unwrap();
+    let mut sender = ReliableSender::new();
+    let bulk_handler = sender
+        .send_with_priority(address, Bytes::from("Bulk"), Priority::Normal)
+        .await;
+    let vote_handler = sender
+        .send_with_priority(address, Bytes::from("Vote"), Priority::High)
+        .await;
+
+    // Run a TCP server expecting the vote first.
+    sleep(Duration::from_millis(50)).await;
+    let handle = listener(address, "Vote".to_string());
+
+    // Ensure the vote is acknowledged and the server received it first (ie. it did not panic).
+    assert!(vote_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+    drop(bulk_handler);
+}
+
+/// A sink recording the messages written between two flushes.
+#[derive(Default)]
+struct RecordingSink {
+    unflushed: Vec<Bytes>,
+    flushed: Vec<Vec<Bytes>>,
+}
+
+impl Sink<Bytes> for RecordingSink {
+    type Error = std::io::Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        self.unflushed.push(item);
+        Ok(())
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        if !self.unflushed.is_empty() {
+            let batch = std::mem::take(&mut self.unflushed);
+            self.flushed.push(batch);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[tokio::test]
+async fn flush_policies() {
+    let (tx, rx) = channel(10);
+    let mut connection = Connection {
+        address: "127.0.0.1:5401".parse().unwrap(),
+        connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        protocol_version: None,
+        identity: None,
+        warnings: WarningLimiter::default(),
+        flush_policies: FlushPolicies::default(),
+        sent: None,
+        resolver: Arc::new(StaticResolver),
+        receiver: rx,
+        retry_delay: 200,
+        buffer: VecDeque::new(),
+        urgent: VecDeque::new(),
+    };
+
+    // Queue a few bulk messages along with a vote.
+    let mut handlers = Vec::new();
+    for (data, priority) in [
+        ("Bulk 1", Priority::Normal),
+        ("Bulk 2", Priority::Normal),
+        ("Vote", Priority::High),
+        ("Bulk 3", Priority::Normal),
+    ] {
+        let (sender, receiver) = oneshot::channel();
+        let message = InnerMessage {
+            data: Bytes::from(data),
+            priority,
+            cancel_handler: sender,
+        };
+        tx.send(message).await.unwrap();
+        handlers.push(receiver);
+    }
+
+    // Ensure the vote is flushed on its own (first), while the bulk messages are flushed together.
+    let mut sink = RecordingSink::default();
+    let mut pending_replies = VecDeque::new();
+    connection
+        .transmit(&mut sink, &mut pending_replies)
+        .await
+        .unwrap();
+    assert_eq!(
+        sink.flushed,
+        vec![
+            vec![Bytes::from("Vote")],
+            vec![Bytes::from("Bulk 1"), Bytes::from("Bulk 2"), Bytes::from("Bulk 3")],
+        ]
+    );
+    assert_eq!(pending_replies.len(), 4);
+
+    // Ensure every message is flushed on its own when coalescing is disabled.
+    connection.flush_policies.normal = FlushPolicy::Immediate;
+    for data in ["Bulk 4", "Bulk 5"] {
+        let (sender, receiver) = oneshot::channel();
+        connection.requeue(Priority::Normal, Bytes::from(data), sender, /* front */ false);
+        handlers.push(receiver);
+    }
+    let mut sink = RecordingSink::default();
+    connection
+        .transmit(&mut sink, &mut pending_replies)
+        .await
+        .unwrap();
+    assert_eq!(
+        sink.flushed,
+        vec![vec![Bytes::from("Bulk 4")], vec![Bytes::from("Bulk 5")]]
+    );
+}
@@ -0,0 +1,22 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn coalesce_identical_warnings() {
+    let limiter = WarningLimiter::new(100);
+
+    // Only the first of many identical warnings is logged.
+    let logged = (0..10)
+        .filter(|_| limiter.warn("Failed to connect to peer".to_string()))
+        .count();
+    assert_eq!(logged, 1);
+
+    // Other warnings are not affected, even through a clone.
+    assert!(limiter.clone().warn("Failed to receive ACK".to_string()));
+
+    // The warning is logged again once the period elapsed.
+    sleep(Duration::from_millis(150)).await;
+    assert!(limiter.warn("Failed to connect to peer".to_string()));
+    assert!(!limiter.warn("Failed to connect to peer".to_string()));
+}
@@ -0,0 +1,73 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/warning_limiter_tests.rs"]
+pub mod warning_limiter_tests;
+
+/// The default period during which identical warnings are coalesced (in ms).
+pub const DEFAULT_WARNING_PERIOD: u64 = 5_000;
+
+/// The number of distinct warnings after which we forget the ones whose period elapsed.
+const MAX_TRACKED_WARNINGS: usize = 1_000;
+
+/// Coalesces identical warnings to keep the logs readable when the same failure repeats many times
+/// per second (e.g., an unreachable peer). The first occurrence of a warning is logged right away;
+/// the following ones are only counted until the period elapses, and then summarized along with the
+/// next occurrence. Clones share the same state.
+#[derive(Clone)]
+pub struct WarningLimiter {
+    /// The period during which identical warnings are coalesced.
+    period: Duration,
+    /// The time at which each warning was last logged and how many times it was suppressed since.
+    warnings: Arc<Mutex<HashMap<String, (Instant, u64)>>>,
+}
+
+impl std::default::Default for WarningLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_WARNING_PERIOD)
+    }
+}
+
+impl WarningLimiter {
+    /// Make a limiter coalescing identical warnings during `period` ms.
+    pub fn new(period: u64) -> Self {
+        Self {
+            period: Duration::from_millis(period),
+            warnings: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Log the warning unless it was already logged during the current period. Returns whether
+    /// the warning was logged.
+    pub fn warn(&self, message: String) -> bool {
+        let mut warnings = self.warnings.lock().expect("Warning limiter poisoned");
+        let now = Instant::now();
+        if let Some((since, suppressed)) = warnings.get_mut(&message) {
+            let elapsed = now.duration_since(*since);
+            if elapsed < self.period {
+                *suppressed += 1;
+                return false;
+            }
+            if *suppressed > 0 {
+                warn!(
+                    "Suppressed {} identical warnings in the last {} s: {}",
+                    suppressed,
+                    elapsed.as_secs(),
+                    message
+                );
+            }
+        }
+
+        if warnings.len() >= MAX_TRACKED_WARNINGS {
+            let period = self.period;
+            warnings.retain(|_, (since, _)| now.duration_since(*since) < period);
+        }
+        warn!("{}", message);
+        warnings.insert(message, (now, 0));
+        true
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::{info, warn};
+use primary::{set_round_log_range, PrimaryHealth, Round};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt as _, AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader};
+use tokio::net::TcpListener;
+use tokio::time::{timeout, Duration};
+
+#[cfg(test)]
+#[path = "tests/admin_tests.rs"]
+pub mod admin_tests;
+
+/// How long the admin endpoint waits for commands after sending the health snapshot (in ms).
+const COMMAND_TIMEOUT: u64 = 5_000;
+
+/// Where the admin endpoint listens.
+pub enum AdminEndpoint {
+    /// A TCP address.
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket, only accessible to the user running the node. Platforms without
+    /// Unix domain sockets fall back to an ephemeral TCP port of the loopback interface.
+    Unix(PathBuf),
+}
+
+/// Serves the health of the primary: every connection to the endpoint receives a snapshot of the health
+/// (in human-readable form), after which the endpoint stops writing to it. The connection may then send
+/// commands, one per line:
+///  * `log-rounds <start> <end>`: log the events of the rounds `[start, end]` verbosely;
+///  * `log-rounds off`: stop logging a round range verbosely.
+pub struct Admin;
+
+/// A command sent to the admin endpoint.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Log the events of a range of rounds verbosely (or stop doing so if `None`).
+    LogRounds(Option<(Round, Round)>),
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["log-rounds", "off"] => Ok(Self::LogRounds(None)),
+            ["log-rounds", start, end] => {
+                let parse = |x: &str| x.parse::<Round>().map_err(|e| format!("Invalid round {}: {}", x, e));
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("Empty round range [{}, {}]", start, end));
+                }
+                Ok(Self::LogRounds(Some((start, end))))
+            }
+            _ => Err(format!("Unknown command '{}'", line)),
+        }
+    }
+
+    fn apply(self) {
+        match self {
+            Self::LogRounds(Some((start, end))) => {
+                info!("Logging the events of rounds [{}, {}] verbosely", start, end);
+                set_round_log_range(Some((start, end)));
+            }
+            Self::LogRounds(None) => {
+                info!("Stopped logging a round range verbosely");
+                set_round_log_range(None);
+            }
+        }
+    }
+}
+
+impl Admin {
+    /// Bind the endpoint and serve the snapshots returned by `health`.
+    pub async fn spawn<F>(endpoint: AdminEndpoint, health: F) -> io::Result<()>
+    where
+        F: Fn() -> PrimaryHealth + Send + Sync + 'static,
+    {
+        let health = Arc::new(health);
+        match endpoint {
+            AdminEndpoint::Tcp(address) => Self::spawn_tcp(address, health).await,
+            AdminEndpoint::Unix(path) => Self::spawn_unix(path, health).await,
+        }
+    }
+
+    async fn spawn_tcp<F>(address: SocketAddr, health: Arc<F>) -> io::Result<()>
+    where
+        F: Fn() -> PrimaryHealth + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(address).await?;
+        info!("Admin endpoint listening on {}", listener.local_addr()?);
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::serve(stream, health()));
+                    }
+                    Err(e) => warn!("Failed to accept admin connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    async fn spawn_unix<F>(path: PathBuf, health: Arc<F>) -> io::Result<()>
+    where
+        F: Fn() -> PrimaryHealth + Send + Sync + 'static,
+    {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+        use tokio::net::UnixListener;
+
+        // Remove the socket left behind by a previous run (but nothing else).
+        if fs::metadata(&path).is_ok_and(|x| x.file_type().is_socket()) {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+        info!("Admin endpoint listening on {}", path.display());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::serve(stream, health()));
+                    }
+                    Err(e) => warn!("Failed to accept admin connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn spawn_unix<F>(path: PathBuf, health: Arc<F>) -> io::Result<()>
+    where
+        F: Fn() -> PrimaryHealth + Send + Sync + 'static,
+    {
+        warn!(
+            "Unix domain sockets are not supported on this platform: admin endpoint {} falls back to TCP",
+            path.display()
+        );
+        Self::spawn_tcp("127.0.0.1:0".parse().unwrap(), health).await
+    }
+
+    /// Write a health snapshot to the connection and stop writing to it, then apply the commands it
+    /// sends until it is closed (or for at most `COMMAND_TIMEOUT` ms).
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, health: PrimaryHealth) {
+        let report = format!("{:#?}\n", health);
+        if let Err(e) = stream.write_all(report.as_bytes()).await {
+            warn!("Failed to reply to admin connection: {}", e);
+        }
+        let _ = stream.shutdown().await;
+
+        let mut lines = BufReader::new(stream).lines();
+        let commands = async {
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match Command::parse(&line) {
+                    Ok(command) => command.apply(),
+                    Err(e) => warn!("Invalid admin command: {}", e),
+                }
+            }
+        };
+        let _ = timeout(Duration::from_millis(COMMAND_TIMEOUT), commands).await;
+    }
+}
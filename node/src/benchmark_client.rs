@@ -1,20 +1,31 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use anyhow::{Context, Result};
 use bytes::BufMut as _;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use clap::{crate_name, crate_version, App, AppSettings};
+use config::Import;
 use env_logger::Env;
 use futures::future::join_all;
 use futures::sink::SinkExt as _;
 use log::{info, warn};
 use primary::PrimaryClientReceiverHandlerNoPrint;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng as _};
+use serde::Deserialize;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpStream;
-use tokio::time::{interval, sleep, Duration, Instant};
+use tokio::time::{interval, sleep, sleep_until, Duration, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use primary::PrimaryClientReceiverHandler;
-use network::Receiver;
+use primary::InFlight;
+use primary::LatencySampler;
+use primary::TxEncoding;
+use network::{Receiver, ReceiverOptions};
+
+#[cfg(test)]
+#[path = "tests/benchmark_client_tests.rs"]
+mod benchmark_client_tests;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -28,6 +39,13 @@ async fn main() -> Result<()> {
         .args_from_usage("--port=<INT> 'Port to listen for batch deliveries'")
         .args_from_usage("--local 'Should run local or not'")
         .args_from_usage("--honest 'Make every sent transaction a sample transaction")
+        .args_from_usage("--tx-encoding=[ENCODING] 'The layout of sample transactions: counter (default), monotonic, or uuid'")
+        .args_from_usage("--seed=[INT] 'Seed the randomness of the client to produce a reproducible stream of transactions'")
+        .args_from_usage("--connections=[INT] 'The number of connections to open to the node (default 1)'")
+        .args_from_usage("--poisson 'Send the transactions as a Poisson process at the target rate rather than in bursts'")
+        .args_from_usage("--latency-samples=[INT] 'Measure the delivery latency of up to INT sample transactions at a time (default 0, disabled)'")
+        .args_from_usage("--template=[FILE] 'The file holding the template of the transactions (fixed and random fields)'")
+        .args_from_usage("--concurrency=[INT] 'Run in closed loop, keeping INT sample transactions in flight (requires --honest and deliveries reporting the delivered transactions)'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
@@ -66,6 +84,47 @@ async fn main() -> Result<()> {
         .is_present("local");
     let honest = matches
         .is_present("honest");
+    let encoding = matches
+        .value_of("tx-encoding")
+        .map_or_else(|| Ok(TxEncoding::default()), |x| x.parse::<TxEncoding>())
+        .map_err(anyhow::Error::msg)?;
+    let seed = matches
+        .value_of("seed")
+        .map(|x| x.parse::<u64>())
+        .transpose()
+        .context("The seed must be a non-negative integer")?;
+    let connections = matches
+        .value_of("connections")
+        .map_or(Ok(1), |x| x.parse::<usize>())
+        .context("The number of connections must be a positive integer")?
+        .max(1);
+    let poisson = matches.is_present("poisson");
+    let latency_samples = matches
+        .value_of("latency-samples")
+        .map_or(Ok(0), |x| x.parse::<usize>())
+        .context("The number of latency samples must be a non-negative integer")?;
+    let template = matches
+        .value_of("template")
+        .map(TxTemplate::import)
+        .transpose()
+        .context("Failed to load the transaction template")?
+        .unwrap_or_default();
+    let concurrency = matches
+        .value_of("concurrency")
+        .map(|x| x.parse::<usize>())
+        .transpose()
+        .context("The concurrency must be a positive integer")?;
+    if poisson && rate == 0 {
+        return Err(anyhow::Error::msg("Poisson arrivals require a positive rate"));
+    }
+    if concurrency.is_some() && !honest {
+        return Err(anyhow::Error::msg("Closed loop requires sample transactions (--honest)"));
+    }
+    if concurrency == Some(0) || (concurrency.is_some() && poisson) {
+        return Err(anyhow::Error::msg(
+            "Closed loop requires a positive concurrency (and no Poisson arrivals)",
+        ));
+    }
 
     info!("Node address: {}", target);
 
@@ -79,6 +138,24 @@ async fn main() -> Result<()> {
 
     info!("Honest: {}", honest);
 
+    info!("Transactions encoding: {:?}", encoding);
+
+    if let Some(seed) = seed {
+        info!("Seed: {}", seed);
+    }
+
+    info!("Connections: {}", connections);
+
+    info!("Poisson arrivals: {}", poisson);
+
+    info!("Latency samples: {}", latency_samples);
+
+    info!("Template fields: {}", template.fields.len());
+
+    if let Some(concurrency) = concurrency {
+        info!("Closed loop concurrency: {}", concurrency);
+    }
+
     let client = Client {
         target,
         size,
@@ -87,6 +164,14 @@ async fn main() -> Result<()> {
         port,
         local,
         honest,
+        encoding,
+        seed,
+        connections,
+        poisson,
+        template,
+        latency: (honest && latency_samples > 0)
+            .then(|| Arc::new(Mutex::new(LatencySampler::new(latency_samples)))),
+        in_flight: concurrency.map(InFlight::new),
     };
 
     // Wait for all nodes to be online and synchronized.
@@ -104,33 +189,50 @@ struct Client {
     port: u16,
     local: bool,
     honest: bool,
+    encoding: TxEncoding,
+    seed: Option<u64>,
+    connections: usize,
+    poisson: bool,
+    template: TxTemplate,
+    /// Measures the delivery latency of the sample transactions (if enabled).
+    latency: Option<Arc<Mutex<LatencySampler>>>,
+    /// Bounds the transactions in flight (if running in closed loop).
+    in_flight: Option<InFlight>,
 }
 
 impl Client {
     pub async fn send(&self) -> Result<()> {
         const BURST_DURATION: u64 = 1000;
 
-        // The transaction size must be at least 16 bytes to ensure all txs are different.
-        if self.size < 8 {
-            return Err(anyhow::Error::msg(
-                "Transaction size must be at least 8 bytes",
-            ));
+        // The transaction size must be large enough to ensure all txs are different.
+        let min_size = std::cmp::max(8, self.encoding.size());
+        if self.size < min_size {
+            return Err(anyhow::Error::msg(format!(
+                "Transaction size must be at least {} bytes",
+                min_size
+            )));
         }
+        self.template
+            .check(/* reserved */ min_size, self.size)
+            .map_err(anyhow::Error::msg)
+            .context("Invalid transaction template")?;
 
         // Connect to the mempool.
-        let stream = TcpStream::connect(self.target)
+        let mut pool = ConnectionPool::connect(self.target, self.connections)
             .await
             .context(format!("failed to connect to {}", self.target))?;
 
         // Submit all transactions.
         let burst = self.rate;
-        let mut tx = BytesMut::with_capacity(self.size);
         let mut counter = 0;
-        let mut r: u32 = rand::thread_rng().gen();
-        let load_client_rand: u32 = rand::thread_rng().gen();
+        let mut generator = TxGenerator::new(
+            self.size,
+            self.honest,
+            self.encoding,
+            self.template.clone(),
+            self.seed,
+        );
 
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
-        
         let interval = interval(Duration::from_millis(BURST_DURATION));
         tokio::pin!(interval);
 
@@ -141,57 +243,124 @@ impl Client {
         }.parse().unwrap();
 
         if self.honest {
-            Receiver::spawn(
-                address,
-                /* handler */
-                PrimaryClientReceiverHandler {},
-            );
+            let mut handler = match &self.latency {
+                Some(latency) => PrimaryClientReceiverHandler::default().with_latency(latency.clone()),
+                None => PrimaryClientReceiverHandler::default(),
+            };
+            if let Some(in_flight) = &self.in_flight {
+                handler = handler.with_in_flight(in_flight.clone());
+            }
+            Receiver::spawn(address, handler, ReceiverOptions::default());
         } else {
             Receiver::spawn(
                 address,
                 /* handler */
                 PrimaryClientReceiverHandlerNoPrint {},
+                ReceiverOptions::default(),
             );
         }
 
         // NOTE: This log entry is used to compute performance.
         info!("Start sending transactions");
 
-        'main: loop {
+        if let Some(in_flight) = &self.in_flight {
+            // Send a new transaction whenever a previous one is delivered (regardless of the rate).
+            let start = Instant::now();
+            loop {
+                let (bytes, sample) = generator.next();
+                let id = sample.expect("Closed loop requires sample transactions");
+                in_flight.sent(id).await;
+
+                if start.elapsed().as_secs() > counter {
+                    counter = start.elapsed().as_secs();
+                    self.report_latency(counter);
+                }
+                // NOTE: This log entry is used to compute performance.
+                info!("Sending sample transaction {}, (client {}, count {})", id, generator.client, counter);
+                self.record_sent(id);
+                pool.send(bytes).await;
+            }
+        }
+
+        if self.poisson {
+            let start = Instant::now();
+            let mut arrivals = PoissonArrivals::new(self.rate, self.seed);
+            let mut next_arrival = start;
+            loop {
+                sleep_until(next_arrival).await;
+                if next_arrival.elapsed().as_millis() > BURST_DURATION as u128 {
+                    // NOTE: This log entry is used to compute performance.
+                    warn!("Transaction rate too high for this client");
+                }
+
+                // Send all the transactions that arrived by now.
+                if start.elapsed().as_secs() > counter {
+                    counter = start.elapsed().as_secs();
+                    self.report_latency(counter);
+                }
+                while next_arrival <= Instant::now() {
+                    let (bytes, sample) = generator.next();
+                    if let Some(id) = sample {
+                        // NOTE: This log entry is used to compute performance.
+                        info!("Sending sample transaction {}, (client {}, count {})", id, generator.client, counter);
+                        self.record_sent(id);
+                    }
+                    pool.send(bytes).await;
+                    next_arrival += arrivals.next_delay();
+                }
+            }
+        }
+
+        loop {
             interval.as_mut().tick().await;
             let now = Instant::now();
 
             info!("Sending burst");
 
             for _ in 0..burst {
-                if self.honest {
+                let (bytes, sample) = generator.next();
+                if let Some(id) = sample {
                     // NOTE: This log entry is used to compute performance.
-                    info!("Sending sample transaction {}, (client {}, count {})", ((counter as u64) << 32) + load_client_rand as u64, load_client_rand, counter);
-
-                    let mut counter = (counter as u32).to_be_bytes();
-                    counter[0] = 0u8;
-                    tx.put_u32(u32::from_be_bytes(counter)); // This counter identifies the tx.
-                    tx.put_u32(load_client_rand) 
-                } else {
-                    r += 1;
-                    tx.put_u32(u32::MAX);
-                    tx.put_u32(r); // Ensures all clients send different txs.
-                };
-
-                tx.resize(self.size, 0u8);
-                let bytes = tx.split().freeze();
-                if let Err(e) = transport.send(bytes).await {
-                    warn!("Failed to send transaction: {}", e);
-                    break 'main;
+                    info!("Sending sample transaction {}, (client {}, count {})", id, generator.client, counter);
+                    self.record_sent(id);
                 }
+                pool.send(bytes).await;
             }
             if now.elapsed().as_millis() > BURST_DURATION as u128 {
                 // NOTE: This log entry is used to compute performance.
                 warn!("Transaction rate too high for this client");
             }
             counter += 1;
+            self.report_latency(counter);
+        }
+    }
+
+    /// Record the submission of a sample transaction (if measuring latency).
+    fn record_sent(&self, id: u64) {
+        if let Some(latency) = &self.latency {
+            latency
+                .lock()
+                .expect("Failed to lock the latency sampler")
+                .sent(id, Instant::now());
+        }
+    }
+
+    /// Print the latency percentiles of the delivered sample transactions every `LATENCY_REPORT_PERIOD`
+    /// seconds (if measuring latency).
+    fn report_latency(&self, seconds: u64) {
+        const LATENCY_REPORT_PERIOD: u64 = 10;
+
+        if !seconds.is_multiple_of(LATENCY_REPORT_PERIOD) {
+            return;
+        }
+        let summary = self.latency.as_ref().and_then(|x| {
+            x.lock()
+                .expect("Failed to lock the latency sampler")
+                .summary()
+        });
+        if let Some(summary) = summary {
+            info!("Delivery latency: {}", summary);
         }
-        Ok(())
     }
 
     pub async fn wait(&self) {
@@ -207,3 +376,227 @@ impl Client {
         .await;
     }
 }
+
+/// A field of a transaction template.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TemplateField {
+    /// The field holds the same bytes in every transaction.
+    Fixed { offset: usize, value: Vec<u8> },
+    /// The field holds `length` random bytes, drawn anew for every transaction.
+    Random { offset: usize, length: usize },
+}
+
+impl TemplateField {
+    /// The bytes of the transaction covered by the field.
+    fn range(&self) -> std::ops::Range<usize> {
+        match self {
+            Self::Fixed { offset, value } => *offset..offset + value.len(),
+            Self::Random { offset, length } => *offset..offset + length,
+        }
+    }
+}
+
+/// Shapes the transactions like the application's (e.g., fixed header fields followed by a random body).
+/// The template is a JSON file listing the fields, for instance:
+/// `{"fields": [{"fixed": {"offset": 8, "value": [1, 0]}}, {"random": {"offset": 10, "length": 54}}]}`.
+/// The beginning of the transactions is reserved for the id/counter encoding (see `TxEncoding`), and the
+/// bytes not covered by any field are zero.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct TxTemplate {
+    fields: Vec<TemplateField>,
+}
+
+impl Import for TxTemplate {}
+
+impl TxTemplate {
+    /// Ensures the fields fit in transactions of `size` bytes, leave the first `reserved` bytes untouched,
+    /// and do not overlap.
+    fn check(&self, reserved: usize, size: usize) -> Result<(), String> {
+        let mut ranges: Vec<_> = self.fields.iter().map(|x| x.range()).collect();
+        ranges.sort_by_key(|x| x.start);
+        let mut end = reserved;
+        for range in ranges {
+            if range.start < end {
+                return Err(format!(
+                    "Field at offset {} overlaps the reserved region or another field",
+                    range.start
+                ));
+            }
+            if range.end > size {
+                return Err(format!(
+                    "Field at offset {} exceeds the transaction size ({} B)",
+                    range.start, size
+                ));
+            }
+            end = range.end;
+        }
+        Ok(())
+    }
+
+    /// Write the fields into a transaction.
+    fn apply<R: Rng>(&self, tx: &mut [u8], rng: &mut R) {
+        for field in &self.fields {
+            let range = field.range();
+            match field {
+                TemplateField::Fixed { value, .. } => tx[range].copy_from_slice(value),
+                TemplateField::Random { .. } => rng.fill(&mut tx[range]),
+            }
+        }
+    }
+}
+
+/// Generates the transactions sent by the client. Seeding it makes the stream of transactions
+/// reproducible.
+struct TxGenerator {
+    size: usize,
+    honest: bool,
+    encoding: TxEncoding,
+    template: TxTemplate,
+    rng: StdRng,
+    /// The random id of the client (embedded in sample transactions).
+    client: u32,
+    /// Ensures all clients send different (non-sample) txs.
+    r: u32,
+    /// The number of sample transactions generated so far.
+    sent: u64,
+    tx: BytesMut,
+}
+
+impl TxGenerator {
+    fn new(
+        size: usize,
+        honest: bool,
+        encoding: TxEncoding,
+        template: TxTemplate,
+        seed: Option<u64>,
+    ) -> Self {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            size,
+            honest,
+            encoding,
+            template,
+            r: rng.gen(),
+            client: rng.gen(),
+            rng,
+            sent: 0,
+            tx: BytesMut::with_capacity(size),
+        }
+    }
+
+    /// Returns the next transaction along with its id if it is a sample transaction.
+    fn next(&mut self) -> (Bytes, Option<u64>) {
+        let sample = if self.honest {
+            // The encoded header identifies the tx.
+            let id = self
+                .encoding
+                .encode(&mut self.tx, self.sent, self.client, &mut self.rng);
+            self.sent += 1;
+            Some(id)
+        } else {
+            self.r = self.r.wrapping_add(1);
+            self.tx.put_u32(u32::MAX);
+            self.tx.put_u32(self.r); // Ensures all clients send different txs.
+            None
+        };
+
+        self.tx.resize(self.size, 0u8);
+        self.template.apply(&mut self.tx, &mut self.rng);
+        (self.tx.split().freeze(), sample)
+    }
+}
+
+/// Draws the inter-arrival times of a Poisson process: they are exponentially distributed with a mean of
+/// `1 / rate`, so the arrivals average `rate` per second over time.
+struct PoissonArrivals {
+    rate: f64,
+    rng: StdRng,
+}
+
+impl PoissonArrivals {
+    fn new(rate: u64, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rate: rate as f64,
+            rng,
+        }
+    }
+
+    /// Returns the delay until the next arrival.
+    fn next_delay(&mut self) -> Duration {
+        // Sample in (0, 1] to avoid taking the logarithm of 0.
+        let uniform = 1.0 - self.rng.gen::<f64>();
+        Duration::from_secs_f64(-uniform.ln() / self.rate)
+    }
+}
+
+/// The delay before the first attempt to reconnect to the node (in ms). It doubles after every failure.
+const MIN_RECONNECT_DELAY: u64 = 50;
+
+/// The maximum delay between two attempts to reconnect to the node (in ms).
+const MAX_RECONNECT_DELAY: u64 = 5_000;
+
+/// A small pool of connections to the node. Transactions are sent over the connections in turn. A
+/// connection that fails is re-established (with exponential backoff) rather than ending the benchmark.
+struct ConnectionPool {
+    target: SocketAddr,
+    connections: Vec<Option<Framed<TcpStream, LengthDelimitedCodec>>>,
+    next: usize,
+}
+
+impl ConnectionPool {
+    async fn connect(target: SocketAddr, size: usize) -> std::io::Result<Self> {
+        let mut connections = Vec::with_capacity(size);
+        for _ in 0..size {
+            let stream = TcpStream::connect(target).await?;
+            connections.push(Some(Framed::new(stream, LengthDelimitedCodec::new())));
+        }
+        Ok(Self {
+            target,
+            connections,
+            next: 0,
+        })
+    }
+
+    /// Connect to the node, retrying until it succeeds.
+    async fn reconnect(target: SocketAddr) -> Framed<TcpStream, LengthDelimitedCodec> {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            match TcpStream::connect(target).await {
+                Ok(stream) => {
+                    info!("Reconnected to {}", target);
+                    return Framed::new(stream, LengthDelimitedCodec::new());
+                }
+                Err(e) => warn!("Failed to reconnect to {}: {}", target, e),
+            }
+            sleep(Duration::from_millis(delay)).await;
+            delay = (2 * delay).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Send a transaction over the next connection of the pool, reconnecting it if needed.
+    async fn send(&mut self, data: Bytes) {
+        let index = self.next;
+        self.next = (self.next + 1) % self.connections.len();
+        loop {
+            let connection = match &mut self.connections[index] {
+                Some(x) => x,
+                None => self.connections[index].insert(Self::reconnect(self.target).await),
+            };
+            match connection.send(data.clone()).await {
+                Ok(()) => return,
+                Err(e) => {
+                    warn!("Failed to send transaction to {}: {}", self.target, e);
+                    self.connections[index] = None;
+                }
+            }
+        }
+    }
+}
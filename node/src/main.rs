@@ -1,20 +1,33 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
+mod admin;
+mod simulation;
+
+use crate::admin::{Admin, AdminEndpoint};
+use crate::simulation::Simulation;
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
 use config::Export as _;
 use config::Import as _;
 use config::Subscriptions;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{Committee, ConfigError, KeyPair, KeySource, Parameters, Stake, WorkerId};
 use consensus::Consensus;
+use crypto::{PublicKey, SignatureService};
 use env_logger::Env;
-use network::SimpleSender;
+use log::warn;
+use network::{probe, Reachability, SimpleSender, DEFAULT_CONNECT_TIMEOUT, PROTOCOL_VERSION};
 use primary::PrimaryClientMessage;
-use primary::{Certificate, Primary};
+use primary::{replay_audit_log, store_migrations, AuditLog, Certificate, ForkCheck, MessageTee, Primary, RoundStats};
+use std::fs::File;
+use std::io::{LineWriter, Write as _};
 use store::Store;
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 use worker::Worker;
 
+#[cfg(test)]
+#[path = "tests/main_tests.rs"]
+mod main_tests;
+
 /// The default channel capacity.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
@@ -29,14 +42,44 @@ async fn main() -> Result<()> {
                 .about("Print a fresh key pair to file")
                 .args_from_usage("--filename=<FILE> 'The file where to print the new key pair'"),
         )
+        .subcommand(
+            SubCommand::with_name("check_committee")
+                .about("Print the fault tolerance of a committee")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--reachability 'Also check that every advertised address is reachable'")
+                .args_from_usage("--timeout=[INT] 'The time to wait for each address to respond (in ms)'"),
+        )
+        .subcommand(
+            SubCommand::with_name("replay_audit_log")
+                .about("Replay an audit log and print the decisions that would not be valid")
+                .args_from_usage("--audit-log=<FILE> 'The audit log to replay'")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'"),
+        )
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .about("Estimate the latency and throughput of a committee over assumed link latencies")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
+                .args_from_usage("--latency=[INT] 'The one-way latency of the links between authorities (in ms)'")
+                .args_from_usage("--links=[FILE] 'The file setting the latency of specific links, one `<from> <to> <ms>` line per link (keys in base64)'")
+                .args_from_usage("--rounds=[INT] 'The number of rounds to simulate'")
+                .args_from_usage("--tx-size=[INT] 'The size of the transactions (in B)'"),
+        )
         .subcommand(
             SubCommand::with_name("run")
                 .about("Run a node")
-                .args_from_usage("--keys=<FILE> 'The file containing the node keys'")
+                .args_from_usage("--keys=[FILE] 'The file containing the node keys'")
+                .args_from_usage("--keys-env=[VAR] 'The environment variable containing the node keys'")
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
                 .args_from_usage("--clients<FILE> 'The file containing client ips'")
+                .args_from_usage("--audit-log=[FILE] 'The file where the primary records its state transitions'")
+                .args_from_usage("--dag-stats=[FILE] 'The CSV file where the primary writes per-round dag statistics'")
+                .args_from_usage("--capture=[FILE] 'The file where the primary captures the messages it exchanges with other primaries'")
+                .args_from_usage("--admin=[ADDR] 'The TCP address where the primary serves its health and accepts admin commands'")
+                .args_from_usage("--admin-socket=[PATH] 'The Unix domain socket where the primary serves its health and accepts admin commands'")
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
                 .subcommand(
                     SubCommand::with_name("worker")
@@ -64,24 +107,205 @@ async fn main() -> Result<()> {
         ("generate_keys", Some(sub_matches)) => KeyPair::new()
             .export(sub_matches.value_of("filename").unwrap())
             .context("Failed to generate key pair")?,
+        ("check_committee", Some(sub_matches)) => check_committee(sub_matches).await?,
+        ("replay_audit_log", Some(sub_matches)) => replay(sub_matches)?,
+        ("simulate", Some(sub_matches)) => simulate(sub_matches)?,
         ("run", Some(sub_matches)) => run(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
 }
 
+// Prints the thresholds and fault tolerance of a committee (and optionally the reachability of its
+// authorities).
+async fn check_committee(matches: &ArgMatches<'_>) -> Result<()> {
+    let committee_file = matches.value_of("committee").unwrap();
+    let committee =
+        Committee::import(committee_file).context("Failed to load the committee information")?;
+    let timeout = matches
+        .value_of("timeout")
+        .map(|x| x.parse::<u64>())
+        .transpose()
+        .context("The timeout must be a positive integer")?
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+
+    let total_stake: Stake = committee.authorities.values().map(|x| x.stake).sum();
+    println!("Authorities: {}", committee.size());
+    println!("Total stake: {}", total_stake);
+    println!("Quorum threshold: {}", committee.quorum_threshold());
+    println!("Validity threshold: {}", committee.validity_threshold());
+    println!("Fault tolerance: {}", committee.fault_tolerance());
+    if committee.is_safe() {
+        println!("Stake distribution: SAFE");
+    } else {
+        println!(
+            "Stake distribution: UNSAFE (some authorities hold more than {} stake)",
+            committee.fault_tolerance()
+        );
+    }
+
+    if matches.is_present("reachability") {
+        let reports = check_reachability(&committee, timeout).await;
+        for report in &reports {
+            println!("{}", report.name);
+            for (label, reachability) in &report.addresses {
+                println!("  {}: {}", label, reachability);
+            }
+        }
+        let unreachable = reports.iter().filter(|x| !x.is_ok()).count();
+        if unreachable == 0 {
+            println!("Reachability: OK");
+        } else {
+            println!("Reachability: {} authorities with FAILED addresses", unreachable);
+        }
+    }
+    Ok(())
+}
+
+/// The outcome of probing the addresses advertised by an authority.
+struct ReachabilityReport {
+    /// The public key of the authority.
+    name: PublicKey,
+    /// The outcome of probing each address (along with a description of the address).
+    addresses: Vec<(String, Reachability)>,
+}
+
+impl ReachabilityReport {
+    fn is_ok(&self) -> bool {
+        self.addresses.iter().all(|(_, x)| x.is_ok())
+    }
+}
+
+// Connects to every address of the committee. Only the primary-to-primary address attempts a handshake
+// (that is the only address of a node agreeing on the message format version).
+async fn check_reachability(committee: &Committee, timeout: u64) -> Vec<ReachabilityReport> {
+    let mut reports = Vec::new();
+    for (name, authority) in &committee.authorities {
+        let primary = &authority.primary;
+        let mut targets = vec![
+            ("primary".to_string(), primary.primary_to_primary, Some(PROTOCOL_VERSION)),
+            ("primary (workers)".to_string(), primary.worker_to_primary, None),
+        ];
+        let mut workers: Vec<_> = authority.workers.iter().collect();
+        workers.sort_by_key(|(id, _)| **id);
+        for (id, addresses) in workers {
+            targets.push((format!("worker {} (transactions)", id), addresses.transactions, None));
+            targets.push((format!("worker {} (workers)", id), addresses.worker_to_worker, None));
+            targets.push((format!("worker {} (primary)", id), addresses.primary_to_worker, None));
+        }
+
+        let mut addresses = Vec::new();
+        for (label, address, protocol_version) in targets {
+            addresses.push((label, probe(address, timeout, protocol_version).await));
+        }
+        reports.push(ReachabilityReport {
+            name: *name,
+            addresses,
+        });
+    }
+    reports
+}
+
+// Replays the decisions recorded in an audit log and prints those that would not be valid.
+fn replay(matches: &ArgMatches<'_>) -> Result<()> {
+    let audit_log_file = matches.value_of("audit-log").unwrap();
+    let committee_file = matches.value_of("committee").unwrap();
+    let committee =
+        Committee::import(committee_file).context("Failed to load the committee information")?;
+    let parameters = match matches.value_of("parameters") {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+
+    let events = AuditLog::read(audit_log_file).context("Failed to read the audit log")?;
+    let invalid = replay_audit_log(&events, &committee, parameters.parent_quorum_policy);
+    for decision in &invalid {
+        println!("#{} {:?}: {}", decision.index, decision.event, decision.error);
+    }
+    if !invalid.is_empty() {
+        bail!("{} of {} decisions are not valid", invalid.len(), events.len());
+    }
+    println!("All {} decisions are valid", events.len());
+    Ok(())
+}
+
+// Prints the estimates of a simulation of the committee.
+fn simulate(matches: &ArgMatches<'_>) -> Result<()> {
+    let committee_file = matches.value_of("committee").unwrap();
+    let committee =
+        Committee::import(committee_file).context("Failed to load the committee information")?;
+    let parameters = match matches.value_of("parameters") {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+    let parse = |name: &str, default: u64| {
+        matches
+            .value_of(name)
+            .map(|x| x.parse::<u64>())
+            .transpose()
+            .with_context(|| format!("The {} must be a positive integer", name))
+            .map(|x| x.unwrap_or(default))
+    };
+    let latency = parse("latency", 50)?;
+    let rounds = parse("rounds", 100)?;
+    let tx_size = parse("tx-size", 512)? as usize;
+
+    let mut simulation = Simulation::new(committee, parameters, latency, tx_size);
+    if let Some(links_file) = matches.value_of("links") {
+        let links = std::fs::read_to_string(links_file).context("Failed to read the links file")?;
+        for line in links.lines().filter(|x| !x.trim().is_empty()) {
+            let link: Vec<_> = line.split_whitespace().collect();
+            let (from, to, latency) = match link.as_slice() {
+                [from, to, latency] => (
+                    PublicKey::decode_base64(from).context("Invalid link origin")?,
+                    PublicKey::decode_base64(to).context("Invalid link destination")?,
+                    latency.parse::<u64>().context("Invalid link latency")?,
+                ),
+                _ => bail!("Invalid link '{}': expected `<from> <to> <ms>`", line),
+            };
+            simulation = simulation.with_link_latency(from, to, latency);
+        }
+    }
+    println!("{}", simulation.run(rounds));
+    Ok(())
+}
+
 // Runs either a worker or a primary.
 async fn run(matches: &ArgMatches<'_>) -> Result<()> {
-    let key_file = matches.value_of("keys").unwrap();
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let clients_file = matches.value_of("clients");
     let store_path = matches.value_of("store").unwrap();
+    let audit_log_file = matches.value_of("audit-log");
+    let dag_stats_file = matches.value_of("dag-stats");
+    let capture_file = matches.value_of("capture");
+    let admin_endpoint = match (matches.value_of("admin"), matches.value_of("admin-socket")) {
+        (Some(address), None) => Some(AdminEndpoint::Tcp(
+            address.parse().context("Invalid admin address")?,
+        )),
+        (None, Some(path)) => Some(AdminEndpoint::Unix(path.into())),
+        (None, None) => None,
+        _ => bail!("The admin endpoint must be specified either with --admin or with --admin-socket"),
+    };
 
-    // Read the committee and node's keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+    // Read the committee and node's keypair.
+    let key_source = match (matches.value_of("keys"), matches.value_of("keys-env")) {
+        (Some(file), None) => KeySource::File(file.to_string()),
+        (None, Some(var)) => KeySource::Env(var.to_string()),
+        _ => bail!("The node keys must be specified either with --keys or with --keys-env"),
+    };
+    let keypair = key_source
+        .load()
+        .context("Failed to load the node's keypair")?;
     let committee =
         Committee::import(committee_file).context("Failed to load the committee information")?;
+    let primary_only = matches!(matches.subcommand(), ("primary", _));
+    check_membership(&keypair.name, &committee, primary_only)
+        .context("The node's keys do not match the committee")?;
 
     // Load default parameters if none are specified.
     let parameters = match parameters_file {
@@ -98,33 +322,60 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
         None => Subscriptions::default(),
     } ;
 
-    // Make the data store.
-    let store = Store::new(store_path).context("Failed to create a store")?;
+    // Make the data store, refusing to start from a forked store.
+    let mut fork_check = ForkCheck::new(parameters.gc_depth);
+    let store = Store::open_checked(store_path, &store_migrations(), &mut fork_check)
+        .context("Failed to open the store")?;
 
     // Channels the sequence of certificates.
     let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
 
     // Check whether to run a primary, a worker, or an entire authority.
-    match matches.subcommand() {
+    let primary = match matches.subcommand() {
         // Spawn the primary and consensus core.
         ("primary", _) => {
             let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
             let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
-            Primary::spawn(
-                keypair,
+            let audit_log = audit_log_file
+                .map(|x| AuditLog::new(x, parameters.audit_log_size))
+                .transpose()
+                .context("Failed to open the audit log")?;
+            let dag_stats = dag_stats_file
+                .map(write_dag_stats)
+                .transpose()
+                .context("Failed to create the dag statistics file")?;
+            let message_tee = capture_file
+                .map(MessageTee::new)
+                .transpose()
+                .context("Failed to create the message capture file")?;
+            let handle = Primary::spawn(
+                keypair.name,
+                SignatureService::new(keypair.secret),
                 committee.clone(),
                 parameters.clone(),
                 store,
+                audit_log,
+                dag_stats,
+                message_tee,
                 /* tx_consensus */ tx_new_certificates,
                 /* rx_consensus */ rx_feedback,
             );
             Consensus::spawn(
                 committee,
                 parameters.gc_depth,
+                parameters.gc_grace_rounds,
                 /* rx_primary */ rx_new_certificates,
                 /* tx_primary */ tx_feedback,
                 tx_output,
+                /* commit_sink */ None,
             );
+            if let Some(endpoint) = admin_endpoint {
+                let health = handle.clone();
+                Admin::spawn(endpoint, move || health.health())
+                    .await
+                    .context("Failed to bind the admin endpoint")?;
+            }
+            Some(handle)
         }
 
         // Spawn a single worker.
@@ -135,24 +386,56 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 .parse::<WorkerId>()
                 .context("The worker id must be a positive integer")?;
             Worker::spawn(keypair.name, id, committee, parameters, store);
+            None
         }
         _ => unreachable!(),
-    }
+    };
 
-    // Analyze the consensus' output.
-    analyze(rx_output, subscriptions).await;
+    // Analyze the consensus' output, until a task of the primary fails.
+    match primary {
+        Some(handle) => tokio::select! {
+            _ = analyze(rx_output, subscriptions) => (),
+            task = handle.failure() => bail!("The primary task {} failed", task),
+        },
+        None => analyze(rx_output, subscriptions).await,
+    }
 
     // If this expression is reached, the program ends and all other tasks terminate.
     unreachable!();
 }
 
+/// Ensure the node is part of the committee: an authority, or an observer if it only runs a primary
+/// (workers only serve authorities). Otherwise the node would unexpectedly run as a non-participant.
+fn check_membership(name: &PublicKey, committee: &Committee, primary_only: bool) -> Result<()> {
+    let observer = primary_only && committee.observers.contains_key(name);
+    if !committee.authorities.contains_key(name) && !observer {
+        return Err(ConfigError::NotInCommittee(*name).into());
+    }
+    Ok(())
+}
+
+/// Writes the per-round dag statistics output by the primary to a CSV file.
+fn write_dag_stats(path: &str) -> Result<Sender<RoundStats>> {
+    let mut file = LineWriter::new(File::create(path)?);
+    writeln!(file, "{}", RoundStats::CSV_HEADER)?;
+    let (tx_stats, mut rx_stats) = channel::<RoundStats>(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(stats) = rx_stats.recv().await {
+            if let Err(e) = writeln!(file, "{}", stats.to_csv()) {
+                warn!("Failed to write dag statistics: {}", e);
+            }
+        }
+    });
+    Ok(tx_stats)
+}
+
 /// Receives an ordered list of certificates and apply any application-specific logic.
 async fn analyze(mut rx_output: Receiver<Certificate>, subscriptions: Subscriptions) {
     let mut sender = SimpleSender::new();
 
     while let Some(certificate) = rx_output.recv().await {
         // NOTE: Here goes the application logic.
-        for digest in certificate.header.payload.keys() {
+        for digest in certificate.header.payload_digests() {
             let message = PrimaryClientMessage::BatchDelivered(digest.clone());
             let data = Bytes::from(bincode::serialize(&message).unwrap());
             sender.broadcast(subscriptions.clients.clone(), data).await
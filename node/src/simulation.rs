@@ -0,0 +1,286 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::{Committee, Parameters, Stake};
+use consensus::elect_leader;
+use crypto::PublicKey;
+use primary::Round;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[cfg(test)]
+#[path = "tests/simulation_tests.rs"]
+pub mod simulation_tests;
+
+/// The size of a batch digest in the payload of a header (in bytes).
+const DIGEST_SIZE: usize = 32;
+
+/// Estimates the throughput and latency of a committee before launching it, by replaying the message flow
+/// of the protocol over assumed link latencies (there is no network nor clock involved, so the simulation
+/// is deterministic and instantaneous). Each worker waits for the acknowledgements of `batch_ack_threshold`
+/// stake before handing its batches to its primary; each primary proposes a header as soon as it holds a
+/// quorum of certificates of the previous round, certifies it with a quorum of votes, and commits the
+/// leaders of the even rounds once they get f+1 support (Tusk). The workers are assumed saturated, so every
+/// header carries `header_size` bytes of digests of full batches. Bandwidth and processing times are not
+/// modeled: the estimates are the bounds set by the latencies, the committee and the parameters.
+pub struct Simulation {
+    /// The committee information.
+    committee: Committee,
+    /// The parameters of the nodes.
+    parameters: Parameters,
+    /// The one-way latency of the links (in ms).
+    latency: u64,
+    /// The one-way latency of specific links (in ms), overriding `latency`.
+    links: HashMap<(PublicKey, PublicKey), u64>,
+    /// The size of the transactions (in bytes).
+    tx_size: usize,
+}
+
+/// The estimates of a simulation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    /// The number of rounds simulated.
+    pub rounds: Round,
+    /// The number of certificates committed.
+    pub committed_certificates: usize,
+    /// The average duration of a round (in ms).
+    pub round_duration: f64,
+    /// The average time between the creation of a header and its commit by its author (in ms).
+    pub consensus_latency: f64,
+    /// The average time between the sealing of a batch and its commit by its author (in ms).
+    pub end_to_end_latency: f64,
+    /// The committed transactions per second.
+    pub throughput: f64,
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Rounds: {}", self.rounds)?;
+        writeln!(f, "Committed certificates: {}", self.committed_certificates)?;
+        writeln!(f, "Round duration: {:.0} ms", self.round_duration)?;
+        writeln!(f, "Consensus latency: {:.0} ms", self.consensus_latency)?;
+        writeln!(f, "End-to-end latency: {:.0} ms", self.end_to_end_latency)?;
+        write!(f, "Throughput: {:.0} tx/s", self.throughput)
+    }
+}
+
+/// The certificates of a round.
+#[derive(Default)]
+struct SimulatedRound {
+    /// When each authority proposes its header.
+    proposals: HashMap<PublicKey, u64>,
+    /// When each authority assembles the certificate of its header.
+    certificates: HashMap<PublicKey, u64>,
+    /// The authors of the parents of each header (the certificates of the previous round).
+    parents: HashMap<PublicKey, BTreeSet<PublicKey>>,
+}
+
+impl Simulation {
+    pub fn new(committee: Committee, parameters: Parameters, latency: u64, tx_size: usize) -> Self {
+        Self {
+            committee,
+            parameters,
+            latency,
+            links: HashMap::new(),
+            tx_size,
+        }
+    }
+
+    /// Set the one-way latency of the link from `from` to `to` (in ms).
+    pub fn with_link_latency(mut self, from: PublicKey, to: PublicKey, latency: u64) -> Self {
+        self.links.insert((from, to), latency);
+        self
+    }
+
+    /// The one-way latency from `from` to `to` (in ms).
+    fn latency(&self, from: &PublicKey, to: &PublicKey) -> u64 {
+        if from == to {
+            return 0;
+        }
+        self.links
+            .get(&(*from, *to))
+            .copied()
+            .unwrap_or(self.latency)
+    }
+
+    /// The time at which the events (each attributed to an authority) gather `threshold` stake.
+    fn threshold_time(&self, mut events: Vec<(u64, PublicKey)>, threshold: Stake) -> Option<u64> {
+        events.sort();
+        let mut stake = 0;
+        for (time, name) in events {
+            stake += self.committee.stake(&name);
+            if stake >= threshold {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    /// The time it takes the workers of `name` to gather the acknowledgements of their batches.
+    fn ack_delay(&self, name: &PublicKey) -> u64 {
+        let total_stake: Stake = self.committee.authorities.values().map(|x| x.stake).sum();
+        let threshold = match self.parameters.batch_ack_threshold {
+            0 => self.committee.quorum_threshold(),
+            x => x.min(total_stake),
+        };
+        let acks = self
+            .committee
+            .authorities
+            .keys()
+            .map(|x| (self.latency(name, x) + self.latency(x, name), *x))
+            .collect();
+        self.threshold_time(acks, threshold).unwrap_or_default()
+    }
+
+    /// Build the dag of `rounds` rounds (plus the genesis), timing the headers and certificates.
+    fn build_dag(&self, rounds: Round) -> Vec<SimulatedRound> {
+        let names: Vec<_> = self.committee.authorities.keys().cloned().collect();
+        let quorum = self.committee.quorum_threshold();
+
+        // Everyone holds the genesis from the start.
+        let mut genesis = SimulatedRound::default();
+        for name in &names {
+            genesis.proposals.insert(*name, 0);
+            genesis.certificates.insert(*name, 0);
+        }
+        let mut dag = vec![genesis];
+
+        for r in 1..=rounds {
+            let previous = dag.last().unwrap();
+            let mut round = SimulatedRound::default();
+            for name in &names {
+                // Propose as soon as we hold a quorum of certificates of the previous round, referencing
+                // all those we hold by then.
+                let arrivals: Vec<_> = previous
+                    .certificates
+                    .iter()
+                    .map(|(x, time)| match r {
+                        1 => (0, *x),
+                        _ => (time + self.latency(x, name), *x),
+                    })
+                    .collect();
+                let proposal = self.threshold_time(arrivals.clone(), quorum).unwrap();
+                let parents = arrivals
+                    .into_iter()
+                    .filter(|(time, _)| *time <= proposal)
+                    .map(|(_, x)| x)
+                    .collect();
+
+                // Gather a quorum of votes (including our own).
+                let votes = names
+                    .iter()
+                    .map(|x| (proposal + self.latency(name, x) + self.latency(x, name), *x))
+                    .collect();
+                let certificate = self.threshold_time(votes, quorum).unwrap();
+
+                round.proposals.insert(*name, proposal);
+                round.certificates.insert(*name, certificate);
+                round.parents.insert(*name, parents);
+            }
+            dag.push(round);
+        }
+        dag
+    }
+
+    /// The certificates (round and author) in the causal history of a certificate, including itself.
+    fn history(dag: &[SimulatedRound], round: Round, name: PublicKey) -> Vec<(Round, PublicKey)> {
+        let mut history = vec![(round, name)];
+        let mut frontier: BTreeSet<_> = [name].iter().cloned().collect();
+        for r in (1..=round).rev() {
+            let mut parents = BTreeSet::new();
+            for x in &frontier {
+                parents.extend(dag[r as usize].parents[x].iter().cloned());
+            }
+            history.extend(parents.iter().map(|x| (r - 1, *x)));
+            frontier = parents;
+        }
+        history
+    }
+
+    /// The time at which `observer` commits each certificate of the dag (if it does).
+    fn commit_times(&self, dag: &[SimulatedRound], observer: &PublicKey) -> HashMap<(Round, PublicKey), u64> {
+        let last_round = (dag.len() - 1) as Round;
+
+        // Find when the leader of every even round gets f+1 support from the next round.
+        let mut commits = Vec::new();
+        for round in (2..last_round).step_by(2) {
+            let leader = elect_leader(&self.committee, round);
+            let support = dag[round as usize + 1]
+                .parents
+                .iter()
+                .filter(|(_, parents)| parents.contains(&leader))
+                .map(|(x, _)| (dag[round as usize + 1].certificates[x] + self.latency(x, observer), *x))
+                .collect();
+            if let Some(time) = self.threshold_time(support, self.committee.validity_threshold()) {
+                let leader_arrival = dag[round as usize].certificates[&leader] + self.latency(&leader, observer);
+                commits.push((time.max(leader_arrival), round));
+            }
+        }
+        commits.sort();
+
+        // Commit every leader along with its causal history (which holds the previous leaders it links to).
+        let mut committed = HashMap::new();
+        let mut last_committed_round = 0;
+        for (time, round) in commits {
+            if round <= last_committed_round {
+                continue;
+            }
+            let leader = elect_leader(&self.committee, round);
+            for x in Self::history(dag, round, leader) {
+                if x.0 > 0 {
+                    committed.entry(x).or_insert(time);
+                }
+            }
+            last_committed_round = round;
+        }
+        committed
+    }
+
+    /// Simulate `rounds` rounds and report the estimates.
+    pub fn run(&self, rounds: Round) -> SimulationReport {
+        let dag = self.build_dag(rounds);
+        let txs_per_header = std::cmp::max(1, self.parameters.header_size / DIGEST_SIZE)
+            * self.parameters.batch_size
+            / std::cmp::max(1, self.tx_size);
+
+        // Every authority observes the commit of its own certificates.
+        let mut committed_certificates = 0;
+        let (mut consensus_latency, mut end_to_end_latency) = (0, 0.0);
+        let mut last_commit = 0;
+        for name in self.committee.authorities.keys() {
+            let ack_delay = self.ack_delay(name) as f64;
+            for ((round, author), time) in self.commit_times(&dag, name) {
+                last_commit = last_commit.max(time);
+                if &author != name {
+                    continue;
+                }
+                let proposal = dag[round as usize].proposals[name];
+                let previous = dag[round as usize - 1].proposals[name];
+
+                // The header carries the batches acknowledged since our previous header.
+                let sealed = (previous + proposal) as f64 / 2.0 - ack_delay;
+                committed_certificates += 1;
+                consensus_latency += time - proposal;
+                end_to_end_latency += time as f64 - sealed;
+            }
+        }
+
+        let mut report = SimulationReport {
+            rounds,
+            committed_certificates,
+            ..SimulationReport::default()
+        };
+        if rounds > 1 {
+            let last_proposals = &dag[rounds as usize].proposals;
+            let total: u64 = last_proposals.values().sum();
+            report.round_duration = total as f64 / last_proposals.len() as f64 / (rounds - 1) as f64;
+        }
+        if committed_certificates > 0 {
+            report.consensus_latency = consensus_latency as f64 / committed_certificates as f64;
+            report.end_to_end_latency = end_to_end_latency / committed_certificates as f64;
+        }
+        if last_commit > 0 {
+            let committed_txs = (committed_certificates * txs_per_header) as f64;
+            report.throughput = committed_txs * 1_000.0 / last_commit as f64;
+        }
+        report
+    }
+}
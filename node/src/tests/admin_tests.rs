@@ -0,0 +1,176 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::{Authority, Committee, Parameters, PrimaryAddresses, WorkerAddresses};
+use crypto::{generate_keypair, SignatureService};
+use primary::Primary;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use store::Store;
+use tokio::io::AsyncReadExt as _;
+use tokio::sync::mpsc::channel;
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_health_on_unix_socket() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt as _;
+    use tokio::net::UnixStream;
+
+    let path = PathBuf::from(".db_test_admin.sock");
+    let _ = fs::remove_file(&path);
+
+    // Serve a health snapshot on a Unix domain socket.
+    let health = PrimaryHealth {
+        ready: true,
+        stalled_certificates: 3,
+        ..PrimaryHealth::default()
+    };
+    let expected = format!("{:#?}\n", health);
+    Admin::spawn(AdminEndpoint::Unix(path.clone()), move || health.clone())
+        .await
+        .unwrap();
+
+    // Ensure only our user may connect.
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // Ensure we receive the health snapshot.
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+    assert!(reply.contains("ready: true"));
+
+    let _ = fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn serve_health_on_tcp() {
+    let address: SocketAddr = "127.0.0.1:14980".parse().unwrap();
+
+    // Serve a health snapshot on a TCP port.
+    let expected = format!("{:#?}\n", PrimaryHealth::default());
+    Admin::spawn(AdminEndpoint::Tcp(address), PrimaryHealth::default)
+        .await
+        .unwrap();
+
+    // Ensure we receive the health snapshot.
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+}
+
+#[tokio::test]
+async fn set_log_rounds() {
+    let address: SocketAddr = "127.0.0.1:14982".parse().unwrap();
+    Admin::spawn(AdminEndpoint::Tcp(address), || PrimaryHealth {
+        log_rounds: primary::round_log_range(),
+        ..PrimaryHealth::default()
+    })
+    .await
+    .unwrap();
+
+    // Read the health snapshot, then ask to log a range of rounds verbosely.
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await.unwrap();
+    assert!(reply.contains("log_rounds: None"), "{}", reply);
+    stream.write_all(b"log-rounds 10 20\n").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    // Ensure the next health snapshot reports the range.
+    let mut reply = String::new();
+    for _ in 0..50 {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        reply.clear();
+        stream.read_to_string(&mut reply).await.unwrap();
+        if !reply.contains("log_rounds: None") {
+            break;
+        }
+    }
+    assert!(reply.contains("log_rounds: Some("), "{}", reply);
+    assert_eq!(primary::round_log_range(), Some((10, 20)));
+    primary::set_round_log_range(None);
+}
+
+#[test]
+fn parse_commands() {
+    assert_eq!(
+        Command::parse("log-rounds 3 5"),
+        Ok(Command::LogRounds(Some((3, 5))))
+    );
+    assert_eq!(Command::parse(" log-rounds  off "), Ok(Command::LogRounds(None)));
+    assert!(Command::parse("log-rounds 5 3").is_err());
+    assert!(Command::parse("log-rounds x 3").is_err());
+    assert!(Command::parse("pause").is_err());
+}
+
+// Make an authority whose addresses use the ports `base` to `base + 4`.
+fn authority(base: u16, stake: u32) -> Authority {
+    let address = |port: u16| format!("127.0.0.1:{}", port).parse().unwrap();
+    let workers = [(
+        0,
+        WorkerAddresses {
+            transactions: address(base + 2),
+            worker_to_worker: address(base + 3),
+            primary_to_worker: address(base + 4),
+        },
+    )];
+    Authority {
+        stake,
+        primary: PrimaryAddresses {
+            primary_to_primary: address(base),
+            worker_to_primary: address(base + 1),
+        },
+        workers: workers.iter().cloned().collect(),
+    }
+}
+
+#[tokio::test]
+async fn serve_committee_view() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, secret) = generate_keypair(&mut rng);
+    let (other, _) = generate_keypair(&mut rng);
+    let committee = Committee {
+        epoch: 2,
+        authorities: [(name, authority(14_985, 1)), (other, authority(14_990, 3))]
+            .iter()
+            .cloned()
+            .collect(),
+        observers: Default::default(),
+    };
+
+    // Spawn a primary and serve its health.
+    let path = ".db_test_serve_committee_view";
+    let _ = std::fs::remove_dir_all(path);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (_tx_feedback, rx_feedback) = channel(1);
+    let handle = Primary::spawn(
+        name,
+        SignatureService::new(secret),
+        committee.clone(),
+        Parameters::default(),
+        Store::new(path).unwrap(),
+        /* audit_log */ None,
+        /* dag_stats */ None,
+        /* message_tee */ None,
+        tx_consensus,
+        rx_feedback,
+    );
+    let address: SocketAddr = "127.0.0.1:14981".parse().unwrap();
+    Admin::spawn(AdminEndpoint::Tcp(address), move || handle.health())
+        .await
+        .unwrap();
+
+    // Ensure the health reports the configured epoch and committee.
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await.unwrap();
+    assert!(reply.contains("epoch: 2,"), "{}", reply);
+    for (name, authority) in &committee.authorities {
+        let expected = format!("{:?}: {},", name, authority.stake);
+        assert!(reply.contains(&expected), "{}", reply);
+    }
+}
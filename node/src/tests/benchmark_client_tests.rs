@@ -0,0 +1,118 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::convert::TryInto as _;
+
+fn first_burst(honest: bool, encoding: TxEncoding, seed: Option<u64>) -> Vec<Bytes> {
+    let mut generator = TxGenerator::new(/* size */ 32, honest, encoding, TxTemplate::default(), seed);
+    (0..100).map(|_| generator.next().0).collect()
+}
+
+#[test]
+fn same_seed_same_burst() {
+    for encoding in [TxEncoding::Counter, TxEncoding::Monotonic, TxEncoding::Uuid] {
+        for honest in [true, false] {
+            let burst = first_burst(honest, encoding, Some(7));
+            assert_eq!(burst, first_burst(honest, encoding, Some(7)));
+        }
+    }
+}
+
+#[test]
+fn different_seeds_different_bursts() {
+    let encoding = TxEncoding::default();
+    assert_ne!(
+        first_burst(/* honest */ false, encoding, Some(7)),
+        first_burst(/* honest */ false, encoding, Some(8))
+    );
+    assert_ne!(
+        first_burst(/* honest */ true, TxEncoding::Uuid, Some(7)),
+        first_burst(/* honest */ true, TxEncoding::Uuid, Some(8))
+    );
+}
+
+#[tokio::test]
+async fn reconnect_after_connection_drop() {
+    use futures::stream::StreamExt as _;
+    use tokio::net::TcpListener;
+
+    let address = "127.0.0.1:14000".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let handle = tokio::spawn(async move {
+        // Read one transaction and drop the connection.
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        transport.next().await.unwrap().unwrap();
+        drop(transport);
+
+        // The client should reconnect and resume sending.
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        transport.next().await.unwrap().unwrap()
+    });
+
+    let mut pool = ConnectionPool::connect(address, /* size */ 1).await.unwrap();
+    let mut sent = 0u64;
+    while !handle.is_finished() {
+        assert!(sent < 1_000, "The client did not resume sending");
+        pool.send(Bytes::from(sent.to_be_bytes().to_vec())).await;
+        sent += 1;
+        sleep(Duration::from_millis(10)).await;
+    }
+    let received = handle.await.unwrap();
+    assert!(u64::from_be_bytes(received[..].try_into().unwrap()) > 0);
+}
+
+#[test]
+fn poisson_arrivals_mean_rate() {
+    // Draw the arrivals of a 100 seconds window.
+    let rate = 1_000;
+    let mut arrivals = PoissonArrivals::new(rate, Some(7));
+    let window = Duration::from_secs(100);
+    let mut elapsed = Duration::default();
+    let mut count: u64 = 0;
+    while elapsed < window {
+        elapsed += arrivals.next_delay();
+        count += 1;
+    }
+
+    // Ensure the arrivals match the target rate (within 2%).
+    let expected = rate * window.as_secs();
+    assert!(count.abs_diff(expected) < expected / 50, "{} arrivals", count);
+}
+
+#[test]
+fn template_fixed_regions() {
+    let template: TxTemplate = serde_json::from_str(
+        r#"{"fields": [{"fixed": {"offset": 16, "value": [1, 2, 3, 4]}}, {"random": {"offset": 24, "length": 32}}]}"#,
+    )
+    .unwrap();
+    let encoding = TxEncoding::default();
+    let reserved = std::cmp::max(8, encoding.size());
+    assert!(template.check(reserved, /* size */ 64).is_ok());
+
+    for honest in [true, false] {
+        let mut generator = TxGenerator::new(/* size */ 64, honest, encoding, template.clone(), Some(7));
+        let txs: Vec<_> = (0..100).map(|_| generator.next().0).collect();
+        for tx in &txs {
+            // The fixed field holds its value and the bytes outside the fields are zero.
+            assert_eq!(tx.len(), 64);
+            assert_eq!(&tx[16..20], &[1, 2, 3, 4]);
+            assert!(tx[reserved..16].iter().all(|x| *x == 0));
+            assert!(tx[20..24].iter().all(|x| *x == 0));
+            assert!(tx[56..].iter().all(|x| *x == 0));
+        }
+
+        // The random field differs across transactions, and the reserved region still identifies them.
+        assert!(txs.windows(2).all(|x| x[0][24..56] != x[1][24..56]));
+        assert!(txs.windows(2).all(|x| x[0][..reserved] != x[1][..reserved]));
+    }
+
+    // Fields may not touch the reserved region, overlap, or exceed the transaction size.
+    let overlapping: TxTemplate = serde_json::from_str(
+        r#"{"fields": [{"fixed": {"offset": 16, "value": [1, 2]}}, {"random": {"offset": 17, "length": 4}}]}"#,
+    )
+    .unwrap();
+    assert!(overlapping.check(reserved, 64).is_err());
+    assert!(template.check(/* reserved */ 20, 64).is_err());
+    assert!(template.check(reserved, /* size */ 50).is_err());
+}
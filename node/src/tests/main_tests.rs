@@ -0,0 +1,373 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use async_trait::async_trait;
+use config::{Authority, PrimaryAddresses, WorkerAddresses};
+use crypto::generate_keypair;
+use crypto::Hash as _;
+use network::{MessageHandler, Receiver as NetworkReceiver, ReceiverOptions, Writer};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+// A primary rejecting every message.
+#[derive(Clone)]
+struct RejectHandler;
+
+#[async_trait]
+impl MessageHandler for RejectHandler {
+    async fn dispatch(&self, _writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        Err("Unexpected message".into())
+    }
+}
+
+// Accept (and ignore) every connection to the address.
+fn listen(address: SocketAddr) {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(address).await.unwrap();
+        while listener.accept().await.is_ok() {}
+    });
+}
+
+fn address(port: u16) -> SocketAddr {
+    format!("127.0.0.1:{}", port).parse().unwrap()
+}
+
+// Make an authority whose addresses use the ports `base` to `base + 4`.
+fn authority(base: u16) -> Authority {
+    let workers = [(
+        0,
+        WorkerAddresses {
+            transactions: address(base + 2),
+            worker_to_worker: address(base + 3),
+            primary_to_worker: address(base + 4),
+        },
+    )];
+    Authority {
+        stake: 1,
+        primary: PrimaryAddresses {
+            primary_to_primary: address(base),
+            worker_to_primary: address(base + 1),
+        },
+        workers: workers.iter().cloned().collect(),
+    }
+}
+
+#[tokio::test]
+async fn check_reachability_report() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mut names: Vec<_> = (0..3).map(|_| generate_keypair(&mut rng).0).collect();
+    names.sort();
+    let bases = [14_900, 14_910, 14_920];
+    let committee = Committee {
+        epoch: 0,
+        authorities: names
+            .iter()
+            .zip(bases.iter())
+            .map(|(name, base)| (*name, authority(*base)))
+            .collect(),
+        observers: BTreeMap::new(),
+    };
+
+    // The first authority is fully reachable and speaks our version.
+    let options = ReceiverOptions::default().with_handshake(Some(PROTOCOL_VERSION));
+    NetworkReceiver::spawn(address(14_900), RejectHandler, options);
+    (14_901..=14_904).for_each(|x| listen(address(x)));
+
+    // The second authority speaks another version.
+    let options = ReceiverOptions::default().with_handshake(Some(PROTOCOL_VERSION + 1));
+    NetworkReceiver::spawn(address(14_910), RejectHandler, options);
+    (14_911..=14_914).for_each(|x| listen(address(x)));
+
+    // The third authority does not expect handshakes and its worker is down.
+    NetworkReceiver::spawn(address(14_920), RejectHandler, ReceiverOptions::default());
+    listen(address(14_921));
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let reports = check_reachability(&committee, /* timeout */ 1_000).await;
+    let summary: Vec<_> = reports
+        .iter()
+        .map(|x| {
+            let outcomes: Vec<_> = x.addresses.iter().map(|(_, y)| y.clone()).collect();
+            (x.name, x.is_ok(), outcomes)
+        })
+        .collect();
+
+    let unreachable = |x: &Reachability| matches!(x, Reachability::Unreachable(_));
+    assert_eq!(summary[0].0, names[0]);
+    assert!(summary[0].1);
+    assert_eq!(summary[0].2[0], Reachability::Compatible(PROTOCOL_VERSION));
+    assert!(summary[0].2[1..].iter().all(|x| x == &Reachability::Reachable));
+
+    assert_eq!(summary[1].0, names[1]);
+    assert!(!summary[1].1);
+    assert_eq!(
+        summary[1].2[0],
+        Reachability::Incompatible {
+            ours: PROTOCOL_VERSION,
+            theirs: PROTOCOL_VERSION + 1
+        }
+    );
+    assert!(summary[1].2[1..].iter().all(|x| x == &Reachability::Reachable));
+
+    assert_eq!(summary[2].0, names[2]);
+    assert!(!summary[2].1);
+    assert_eq!(summary[2].2[0], Reachability::NoHandshake);
+    assert_eq!(summary[2].2[1], Reachability::Reachable);
+    assert!(summary[2].2[2..].iter().all(unreachable));
+
+    let labels: Vec<_> = reports[0].addresses.iter().map(|(x, _)| x.as_str()).collect();
+    assert_eq!(
+        labels,
+        vec![
+            "primary",
+            "primary (workers)",
+            "worker 0 (transactions)",
+            "worker 0 (workers)",
+            "worker 0 (primary)"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn observer_tracks_commits() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mut keys: Vec<_> = (0..5).map(|_| generate_keypair(&mut rng)).collect();
+    let (observer, observer_secret) = keys.pop().unwrap();
+    let bases = [14_930, 14_940, 14_950, 14_960];
+    let committee = Committee {
+        epoch: 0,
+        authorities: keys
+            .iter()
+            .zip(bases.iter())
+            .map(|((name, _), base)| (*name, authority(*base)))
+            .collect(),
+        observers: [(observer, authority(14_970).primary)]
+            .iter()
+            .cloned()
+            .collect(),
+    };
+
+    // Spawn the authorities and the observer, each with its own consensus.
+    let mut outputs = Vec::new();
+    let mut handles = Vec::new();
+    for (i, (name, secret)) in keys
+        .into_iter()
+        .chain(std::iter::once((observer, observer_secret)))
+        .enumerate()
+    {
+        let path = format!(".db_test_observer_tracks_commits_{}", i);
+        let _ = std::fs::remove_dir_all(&path);
+        let store = Store::new(&path).unwrap();
+        let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
+        let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+        let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+        handles.push(Primary::spawn(
+            name,
+            SignatureService::new(secret),
+            committee.clone(),
+            Parameters::default(),
+            store,
+            /* audit_log */ None,
+            /* dag_stats */ None,
+            /* message_tee */ None,
+            /* tx_consensus */ tx_new_certificates,
+            /* rx_consensus */ rx_feedback,
+        ));
+        Consensus::spawn(
+            committee.clone(),
+            Parameters::default().gc_depth,
+            Parameters::default().gc_grace_rounds,
+            /* rx_primary */ rx_new_certificates,
+            /* tx_primary */ tx_feedback,
+            tx_output,
+            /* commit_sink */ None,
+        );
+        outputs.push(rx_output);
+    }
+
+    // The observer never resumes.
+    let observer_handle = handles.pop().unwrap();
+    observer_handle.resume();
+    assert!(observer_handle.is_paused());
+
+    // Ensure the observer commits the same certificates as the authorities, and that only the
+    // authorities created and certified them.
+    let mut observer_output = outputs.pop().unwrap();
+    let mut authority_output = outputs.pop().unwrap();
+    let wait = std::time::Duration::from_secs(30);
+    for _ in 0..10 {
+        let expected = tokio::time::timeout(wait, authority_output.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let certificate = tokio::time::timeout(wait, observer_output.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(certificate.digest(), expected.digest());
+        assert!(committee.authorities.contains_key(&certificate.origin()));
+        assert!(certificate.votes.iter().all(|(x, _)| x != &observer));
+    }
+}
+
+#[tokio::test]
+async fn commit_with_catch_up_enabled() {
+    let mut rng = StdRng::from_seed([2; 32]);
+    let keys: Vec<_> = (0..4).map(|_| generate_keypair(&mut rng)).collect();
+    let bases = [16_000, 16_010, 16_020, 16_030];
+    let committee = Committee {
+        epoch: 0,
+        authorities: keys
+            .iter()
+            .zip(bases.iter())
+            .map(|((name, _), base)| (*name, authority(*base)))
+            .collect(),
+        observers: BTreeMap::new(),
+    };
+    let parameters = Parameters {
+        catch_up_round_gap: 5,
+        ..Parameters::default()
+    };
+
+    // Start the whole committee at once, with catch-up mode enabled.
+    let mut outputs = Vec::new();
+    let mut handles = Vec::new();
+    for (i, (name, secret)) in keys.into_iter().enumerate() {
+        let path = format!(".db_test_commit_with_catch_up_enabled_{}", i);
+        let _ = std::fs::remove_dir_all(&path);
+        let store = Store::new(&path).unwrap();
+        let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
+        let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+        let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+        handles.push(Primary::spawn(
+            name,
+            SignatureService::new(secret),
+            committee.clone(),
+            parameters.clone(),
+            store,
+            /* audit_log */ None,
+            /* dag_stats */ None,
+            /* message_tee */ None,
+            /* tx_consensus */ tx_new_certificates,
+            /* rx_consensus */ rx_feedback,
+        ));
+        Consensus::spawn(
+            committee.clone(),
+            parameters.gc_depth,
+            parameters.gc_grace_rounds,
+            /* rx_primary */ rx_new_certificates,
+            /* tx_primary */ tx_feedback,
+            tx_output,
+            /* commit_sink */ None,
+        );
+        outputs.push(rx_output);
+    }
+
+    // Ensure the committee makes progress (nobody waits for peers ahead of it).
+    let wait = std::time::Duration::from_secs(30);
+    for output in &mut outputs {
+        for _ in 0..5 {
+            tokio::time::timeout(wait, output.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+    }
+    assert!(handles.iter().all(|x| !x.is_paused()));
+}
+
+#[tokio::test]
+async fn attribute_received_bytes_to_authorities() {
+    let mut rng = StdRng::from_seed([3; 32]);
+    let keys: Vec<_> = (0..4).map(|_| generate_keypair(&mut rng)).collect();
+    let bases = [16_040, 16_050, 16_060, 16_070];
+    let committee = Committee {
+        epoch: 0,
+        authorities: keys
+            .iter()
+            .zip(bases.iter())
+            .map(|((name, _), base)| (*name, authority(*base)))
+            .collect(),
+        observers: BTreeMap::new(),
+    };
+    let parameters = Parameters {
+        enable_handshake: true,
+        ..Parameters::default()
+    };
+
+    // Start the whole committee (all on the same IP address), with the handshake enabled.
+    let mut outputs = Vec::new();
+    let mut handles = Vec::new();
+    for (i, (name, secret)) in keys.into_iter().enumerate() {
+        let path = format!(".db_test_attribute_received_bytes_to_authorities_{}", i);
+        let _ = std::fs::remove_dir_all(&path);
+        let store = Store::new(&path).unwrap();
+        let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
+        let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+        let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+        handles.push(Primary::spawn(
+            name,
+            SignatureService::new(secret),
+            committee.clone(),
+            parameters.clone(),
+            store,
+            /* audit_log */ None,
+            /* dag_stats */ None,
+            /* message_tee */ None,
+            /* tx_consensus */ tx_new_certificates,
+            /* rx_consensus */ rx_feedback,
+        ));
+        Consensus::spawn(
+            committee.clone(),
+            parameters.gc_depth,
+            parameters.gc_grace_rounds,
+            /* rx_primary */ rx_new_certificates,
+            /* tx_primary */ tx_feedback,
+            tx_output,
+            /* commit_sink */ None,
+        );
+        outputs.push(rx_output);
+    }
+
+    // Wait for the committee to commit.
+    let wait = std::time::Duration::from_secs(30);
+    for output in &mut outputs {
+        tokio::time::timeout(wait, output.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // Ensure every primary attributes the bytes it received to each of its peers.
+    for handle in &handles {
+        let bandwidth = handle.health().bandwidth;
+        assert_eq!(bandwidth.len(), 3);
+        assert!(bandwidth.iter().all(|x| x.sent > 0 && x.received > 0));
+    }
+}
+
+#[test]
+fn reject_key_absent_from_committee() {
+    let mut rng = StdRng::from_seed([1; 32]);
+    let names: Vec<_> = (0..3).map(|_| generate_keypair(&mut rng).0).collect();
+    let committee = Committee {
+        epoch: 0,
+        authorities: [(names[0], authority(14_960))].iter().cloned().collect(),
+        observers: [(names[1], authority(14_965).primary)].iter().cloned().collect(),
+    };
+
+    // Authorities may run any component, observers only a primary.
+    assert!(check_membership(&names[0], &committee, /* primary_only */ false).is_ok());
+    assert!(check_membership(&names[1], &committee, /* primary_only */ true).is_ok());
+    assert!(check_membership(&names[1], &committee, /* primary_only */ false).is_err());
+
+    // A node whose key is absent from the committee fails to start.
+    let error = check_membership(&names[2], &committee, /* primary_only */ true).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("Node {} is not in the committee", names[2])
+    );
+}
@@ -0,0 +1,70 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::{Authority, PrimaryAddresses};
+use crypto::generate_keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+// Make a committee of `size` authorities of equal stake (their addresses are never used).
+fn committee(size: usize) -> Committee {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let address = "127.0.0.1:0".parse().unwrap();
+    Committee {
+        epoch: 0,
+        authorities: (0..size)
+            .map(|_| {
+                let authority = Authority {
+                    stake: 1,
+                    primary: PrimaryAddresses {
+                        primary_to_primary: address,
+                        worker_to_primary: address,
+                    },
+                    workers: Default::default(),
+                };
+                (generate_keypair(&mut rng).0, authority)
+            })
+            .collect(),
+        observers: Default::default(),
+    }
+}
+
+#[test]
+fn simulate_committee() {
+    let committee = committee(4);
+    let parameters = Parameters {
+        header_size: 1_000,
+        batch_size: 500_000,
+        ..Parameters::default()
+    };
+    let latency = 50;
+    let report = Simulation::new(committee.clone(), parameters.clone(), latency, 512).run(50);
+
+    // A round takes a round-trip to gather the votes, and a trip to spread the certificates.
+    assert_eq!(report.rounds, 50);
+    assert_eq!(report.round_duration, (3 * latency) as f64);
+
+    // Ensure the estimates are plausible: most certificates are committed, a commit takes a few rounds,
+    // and transactions are committed.
+    assert!(report.committed_certificates > 40 * committee.size());
+    assert!(report.committed_certificates <= 50 * committee.size());
+    assert!(report.consensus_latency >= report.round_duration);
+    assert!(report.consensus_latency <= 4.0 * report.round_duration);
+    assert!(report.end_to_end_latency > report.consensus_latency);
+    assert!(report.throughput > 0.0);
+
+    // Ensure slowing down the links of two authorities (so no quorum remains connected by fast links)
+    // slows down the whole committee.
+    let names: Vec<_> = committee.authorities.keys().cloned().collect();
+    let mut slow = Simulation::new(committee, parameters, latency, 512);
+    for slow_name in &names[..2] {
+        for name in &names {
+            slow = slow
+                .with_link_latency(*slow_name, *name, 200)
+                .with_link_latency(*name, *slow_name, 200);
+        }
+    }
+    let slow = slow.run(50);
+    assert!(slow.round_duration > report.round_duration);
+    assert!(slow.consensus_latency > report.consensus_latency);
+    assert!(slow.throughput < report.throughput);
+}
@@ -5,11 +5,22 @@ use config::{Committee, Stake};
 use crypto::{PublicKey, Signature};
 use std::collections::HashSet;
 
+#[cfg(test)]
+#[path = "tests/aggregators_tests.rs"]
+pub mod aggregators_tests;
+
+/// Notified with the stake gathered so far and the stake required to make a certificate. It is called on
+/// the aggregation path, so it must be cheap and never block (e.g., update a metric).
+pub type ProgressCallback = Box<dyn Fn(Stake, Stake) + Send>;
+
 /// Aggregates votes for a particular header into a certificate.
 pub struct VotesAggregator {
     weight: Stake,
     votes: Vec<(PublicKey, Signature)>,
     used: HashSet<PublicKey>,
+    certified: bool,
+    /// Notified every time a vote adds stake (if any).
+    progress: Option<ProgressCallback>,
 }
 
 impl VotesAggregator {
@@ -18,7 +29,33 @@ impl VotesAggregator {
             weight: 0,
             votes: Vec::new(),
             used: HashSet::new(),
+            certified: false,
+            progress: None,
+        }
+    }
+
+    /// Notify `progress` of the stake gathered every time a vote is appended.
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The votes gathered so far (those carrying stake).
+    pub fn votes(&self) -> &[(PublicKey, Signature)] {
+        &self.votes
+    }
+
+    /// Returns the authorities whose votes are still missing (none once we made a certificate).
+    pub fn missing_voters(&self, committee: &Committee) -> Vec<PublicKey> {
+        if self.certified {
+            return Vec::new();
         }
+        committee
+            .authorities
+            .keys()
+            .filter(|name| !self.used.contains(name))
+            .cloned()
+            .collect()
     }
 
     pub fn append(
@@ -32,10 +69,20 @@ impl VotesAggregator {
         // Ensure it is the first time this authority votes.
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
+        // The votes of the authorities without stake do not count towards the quorum, and we leave them
+        // out of the certificate (whose votes must all carry stake).
+        let stake = committee.stake(&author);
+        if stake == 0 {
+            return Ok(None);
+        }
+
         self.votes.push((author, vote.signature));
-        self.weight += committee.stake(&author);
-        if self.weight >= committee.quorum_threshold() {
-            self.weight = 0; // Ensures quorum is only reached once.
+        self.weight += stake;
+        if let Some(progress) = &self.progress {
+            progress(self.weight, committee.quorum_threshold());
+        }
+        if !self.certified && self.weight >= committee.quorum_threshold() {
+            self.certified = true; // Ensures quorum is only reached once.
             return Ok(Some(Certificate {
                 header: header.clone(),
                 votes: self.votes.clone(),
@@ -45,16 +92,18 @@ impl VotesAggregator {
     }
 }
 
-/// Aggregate certificates and check if we reach a quorum.
+/// Aggregate certificates and check if we reach the parents' threshold.
 pub struct CertificatesAggregator {
+    threshold: Stake,
     weight: Stake,
     certificates: Vec<Certificate>,
     used: HashSet<PublicKey>,
 }
 
 impl CertificatesAggregator {
-    pub fn new() -> Self {
+    pub fn new(threshold: Stake) -> Self {
         Self {
+            threshold,
             weight: 0,
             certificates: Vec::new(),
             used: HashSet::new(),
@@ -75,7 +124,7 @@ impl CertificatesAggregator {
 
         self.certificates.push(certificate);
         self.weight += committee.stake(&origin);
-        if self.weight >= committee.quorum_threshold() {
+        if self.weight >= self.threshold {
             //self.weight = 0; // Ensures quorum is only reached once.
             return Ok(Some(self.certificates.drain(..).collect()));
         }
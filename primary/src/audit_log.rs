@@ -0,0 +1,117 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use crypto::{Digest, PublicKey};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+#[path = "tests/audit_log_tests.rs"]
+pub mod audit_log_tests;
+
+/// The state transitions of the `Core` recorded in the audit log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    /// We accepted (and stored) a header.
+    HeaderAccepted {
+        id: Digest,
+        round: Round,
+        author: PublicKey,
+    },
+    /// We voted for a header.
+    VoteCast {
+        id: Digest,
+        round: Round,
+        origin: PublicKey,
+    },
+    /// We assembled a certificate for our own header.
+    CertificateFormed { digest: Digest, round: Round },
+    /// We accepted (and stored) a certificate.
+    CertificateAccepted {
+        digest: Digest,
+        round: Round,
+        origin: PublicKey,
+    },
+    /// We gathered enough certificates of the previous round to move to this round.
+    RoundAdvanced { round: Round },
+}
+
+/// An append-only log of the `Core`'s state transitions, kept separately from the store. Events are
+/// appended (bincode-encoded) in the order the `Core` makes its decisions. Once the log reaches its
+/// maximum size, it is moved to `<path>.1` (replacing the previous one) and a new log is started, so
+/// the audit trail never takes more than twice the maximum size on disk.
+pub struct AuditLog {
+    /// The path of the current log.
+    path: PathBuf,
+    /// The size (in bytes) after which the log is rotated.
+    max_size: u64,
+    /// The current log.
+    file: File,
+    /// The size (in bytes) of the current log.
+    size: u64,
+}
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file,
+            size,
+        })
+    }
+
+    /// The path of the previous log (the one we rotated).
+    fn rotated(path: &Path) -> PathBuf {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        rotated.into()
+    }
+
+    /// Record an event. Failing to write the audit log does not stop the `Core`.
+    pub fn append(&mut self, event: &AuditEvent) {
+        if let Err(e) = self.try_append(event) {
+            warn!("Failed to write to the audit log: {}", e);
+        }
+    }
+
+    fn try_append(&mut self, event: &AuditEvent) -> io::Result<()> {
+        let bytes = bincode::serialize(event).expect("Failed to serialize audit event");
+        if self.size > 0 && self.size + bytes.len() as u64 > self.max_size {
+            fs::rename(&self.path, Self::rotated(&self.path))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = 0;
+        }
+        self.file.write_all(&bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Read all the events of an audit log (including the rotated log, if any), oldest first.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<AuditEvent>> {
+        let path = path.as_ref();
+        let mut events = Vec::new();
+        for file in [Self::rotated(path), path.to_path_buf()] {
+            let data = match fs::read(&file) {
+                Ok(x) => x,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut cursor = &data[..];
+            while !cursor.is_empty() {
+                let event = bincode::deserialize_from(&mut cursor)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::audit_log::AuditEvent;
+use crate::primary::Round;
+use config::{Committee, ParentQuorumPolicy, Stake};
+use crypto::{Digest, PublicKey};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/audit_replay_tests.rs"]
+pub mod audit_replay_tests;
+
+/// The reasons why a decision recorded in the audit log would not be valid on replay.
+#[derive(Clone, Debug, PartialEq, Error)]
+pub enum ReplayError {
+    #[error("Authority {0} is not in the committee")]
+    UnknownAuthority(PublicKey),
+
+    #[error("Voted for header {0} before accepting it")]
+    VoteForUnknownHeader(Digest),
+
+    #[error("Voted for two headers of {origin} at round {round}")]
+    DoubleVote { origin: PublicKey, round: Round },
+
+    #[error("Formed two certificates at round {0}")]
+    DoubleCertificate(Round),
+
+    #[error("Formed a certificate at round {0} before reaching that round")]
+    CertificateAheadOfRound(Round),
+
+    #[error("Accepted two certificates of {origin} at round {round}")]
+    ConflictingCertificates { origin: PublicKey, round: Round },
+
+    #[error("Advanced to round {0} without enough certificates of the previous round")]
+    RoundWithoutParents(Round),
+}
+
+/// A decision of the audit log that would not be valid on replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidDecision {
+    /// The position of the decision in the audit log.
+    pub index: usize,
+    /// The decision.
+    pub event: AuditEvent,
+    /// Why the decision is invalid.
+    pub error: ReplayError,
+}
+
+/// The state of the `Core` reconstructed from the decisions replayed so far.
+#[derive(Default)]
+struct ReplayState {
+    /// The headers we accepted (along with their round and author).
+    headers: HashMap<Digest, (Round, PublicKey)>,
+    /// The header we voted for, per round and author.
+    votes: HashMap<(Round, PublicKey), Digest>,
+    /// The certificates we formed for our own headers, per round.
+    formed: HashMap<Round, Digest>,
+    /// The certificates we accepted, per round and author.
+    certificates: HashMap<(Round, PublicKey), Digest>,
+    /// The rounds we advanced to.
+    rounds: HashSet<Round>,
+}
+
+impl ReplayState {
+    fn ensure_member(committee: &Committee, name: &PublicKey) -> Result<(), ReplayError> {
+        if !committee.authorities.contains_key(name) {
+            return Err(ReplayError::UnknownAuthority(*name));
+        }
+        Ok(())
+    }
+
+    /// Check the decision against the current state and apply it.
+    fn apply(
+        &mut self,
+        event: &AuditEvent,
+        committee: &Committee,
+        parent_quorum_policy: ParentQuorumPolicy,
+    ) -> Result<(), ReplayError> {
+        match event {
+            AuditEvent::HeaderAccepted { id, round, author } => {
+                Self::ensure_member(committee, author)?;
+                self.headers.insert(id.clone(), (*round, *author));
+            }
+            AuditEvent::VoteCast { id, round, origin } => {
+                Self::ensure_member(committee, origin)?;
+                if self.headers.get(id) != Some(&(*round, *origin)) {
+                    return Err(ReplayError::VoteForUnknownHeader(id.clone()));
+                }
+                let voted = self.votes.entry((*round, *origin)).or_insert_with(|| id.clone());
+                if voted != id {
+                    return Err(ReplayError::DoubleVote {
+                        origin: *origin,
+                        round: *round,
+                    });
+                }
+            }
+            AuditEvent::CertificateFormed { digest, round } => {
+                if *round > 1 && !self.rounds.contains(round) {
+                    return Err(ReplayError::CertificateAheadOfRound(*round));
+                }
+                let formed = self.formed.entry(*round).or_insert_with(|| digest.clone());
+                if formed != digest {
+                    return Err(ReplayError::DoubleCertificate(*round));
+                }
+            }
+            AuditEvent::CertificateAccepted {
+                digest,
+                round,
+                origin,
+            } => {
+                Self::ensure_member(committee, origin)?;
+                let accepted = self
+                    .certificates
+                    .entry((*round, *origin))
+                    .or_insert_with(|| digest.clone());
+                if accepted != digest {
+                    return Err(ReplayError::ConflictingCertificates {
+                        origin: *origin,
+                        round: *round,
+                    });
+                }
+            }
+            AuditEvent::RoundAdvanced { round } => {
+                let stake: Stake = self
+                    .certificates
+                    .keys()
+                    .filter(|(r, _)| r + 1 == *round)
+                    .map(|(_, origin)| committee.stake(origin))
+                    .sum();
+                if stake < parent_quorum_policy.threshold(committee) {
+                    return Err(ReplayError::RoundWithoutParents(*round));
+                }
+                self.rounds.insert(*round);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replay the decisions of an audit log (oldest first) and return those that would not be valid given
+/// the state reconstructed from the previous decisions. The committee and the parent quorum policy must
+/// be the ones the node ran with. Note that decisions relying on events older than the log (e.g., after
+/// the log was rotated twice) are flagged as well.
+pub fn replay_audit_log(
+    events: &[AuditEvent],
+    committee: &Committee,
+    parent_quorum_policy: ParentQuorumPolicy,
+) -> Vec<InvalidDecision> {
+    let mut state = ReplayState::default();
+    events
+        .iter()
+        .enumerate()
+        .filter_map(|(index, event)| {
+            state
+                .apply(event, committee, parent_quorum_policy)
+                .err()
+                .map(|error| InvalidDecision {
+                    index,
+                    event: event.clone(),
+                    error,
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,126 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::{Certificate, Header, Vote};
+use crate::primary::Round;
+use config::{Committee, WorkerId};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey, SecretKey, Signature};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(test)]
+#[path = "tests/builders_tests.rs"]
+pub mod builders_tests;
+
+/// Builds signed headers (for tests), e.g.
+/// `HeaderBuilder::new(author).round(1).genesis_parents(&committee).build(&secret)`.
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    /// Start an (empty) header of round 0 authored by `author`.
+    pub fn new(author: PublicKey) -> Self {
+        Self {
+            header: Header {
+                author,
+                ..Header::default()
+            },
+        }
+    }
+
+    pub fn round(mut self, round: Round) -> Self {
+        self.header.round = round;
+        self
+    }
+
+    /// Set the digests of the parent certificates.
+    pub fn parents(mut self, parents: BTreeSet<Digest>) -> Self {
+        self.header.parents = parents;
+        self
+    }
+
+    /// Set the parents to the genesis certificates of the committee (the parents of the headers of round 1).
+    pub fn genesis_parents(self, committee: &Committee) -> Self {
+        let parents = Certificate::genesis(committee)
+            .iter()
+            .map(|x| x.digest())
+            .collect();
+        self.parents(parents)
+    }
+
+    /// Set the digests of the certificates the header weakly links to.
+    pub fn weak_links(mut self, weak_links: BTreeSet<Digest>) -> Self {
+        self.header.weak_links = weak_links;
+        self
+    }
+
+    /// Set the batches referenced by the header (along with the id of the worker holding each of them).
+    pub fn payload(mut self, payload: BTreeMap<Digest, WorkerId>) -> Self {
+        self.header.payload = payload;
+        self
+    }
+
+    /// Make the header, signed with `secret` (the secret key of its author).
+    pub fn build(self, secret: &SecretKey) -> Header {
+        let id = self.header.digest();
+        Header {
+            signature: Signature::new(&id, secret),
+            id,
+            ..self.header
+        }
+    }
+
+    /// Make the header without id nor signature (enough for components that do not verify headers).
+    pub fn build_unsigned(self) -> Header {
+        self.header
+    }
+}
+
+/// Builds certificates (for tests) from the votes of the given authorities, e.g.
+/// `CertificateBuilder::new(header).signers(&keys).build()`.
+pub struct CertificateBuilder<'a> {
+    header: Header,
+    signers: Vec<(PublicKey, &'a SecretKey)>,
+}
+
+impl<'a> CertificateBuilder<'a> {
+    /// Start a certificate of `header`, without any vote.
+    pub fn new(header: Header) -> Self {
+        Self {
+            header,
+            signers: Vec::new(),
+        }
+    }
+
+    /// Add the vote of `author`, signed with `secret`.
+    pub fn signer(mut self, author: PublicKey, secret: &'a SecretKey) -> Self {
+        self.signers.push((author, secret));
+        self
+    }
+
+    /// Add the votes of all the given authorities.
+    pub fn signers(self, keys: &'a [(PublicKey, SecretKey)]) -> Self {
+        keys.iter().fold(self, |builder, (author, secret)| {
+            builder.signer(*author, secret)
+        })
+    }
+
+    /// Make the certificate. It is only valid if the signers gather a quorum of the committee.
+    pub fn build(self) -> Certificate {
+        let header = self.header;
+        let votes = self
+            .signers
+            .into_iter()
+            .map(|(author, secret)| {
+                let vote = Vote {
+                    id: header.id.clone(),
+                    round: header.round,
+                    origin: header.author,
+                    author,
+                    signature: Signature::default(),
+                };
+                (author, Signature::new(&vote.digest(), secret))
+            })
+            .collect();
+        Certificate { header, votes }
+    }
+}
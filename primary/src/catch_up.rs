@@ -0,0 +1,199 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::helper::MAX_RANGE_REQUEST;
+use crate::message_tee::{Direction, MessageTee};
+use crate::primary::{PrimaryMessage, Round};
+use bytes::Bytes;
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use log::{debug, info};
+use network::{Bandwidth, SimpleSender};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration};
+
+#[cfg(test)]
+#[path = "tests/catch_up_tests.rs"]
+pub mod catch_up_tests;
+
+/// Tracks how far our local dag is compared to the dag of our peers. It is updated by the `Core`.
+#[derive(Default)]
+pub struct DagProgress {
+    /// The highest round of the certificates we stored.
+    dag_round: AtomicU64,
+    /// The highest round of the (valid) headers we received from each authority.
+    rounds: Mutex<HashMap<PublicKey, Round>>,
+}
+
+impl DagProgress {
+    /// Record that we stored a certificate of the input round.
+    pub fn advance(&self, round: Round) {
+        self.dag_round.fetch_max(round, Ordering::Relaxed);
+    }
+
+    /// Record that an authority reached the input round.
+    pub fn observe(&self, author: PublicKey, round: Round) {
+        let mut rounds = self.rounds.lock().unwrap();
+        let entry = rounds.entry(author).or_insert(round);
+        *entry = (*entry).max(round);
+    }
+
+    pub fn dag_round(&self) -> Round {
+        self.dag_round.load(Ordering::Relaxed)
+    }
+
+    /// The highest round reached by peers (other than `name`) holding f+1 stake, so that at least one
+    /// honest peer reached it: a Byzantine authority cannot make us lag by advertising a huge round.
+    /// It is 0 until we heard from enough peers.
+    pub fn peers_round(&self, committee: &Committee, name: &PublicKey) -> Round {
+        let mut rounds: Vec<_> = self
+            .rounds
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(author, _)| *author != name)
+            .map(|(author, round)| (*round, committee.stake(author)))
+            .collect();
+        rounds.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut stake: Stake = 0;
+        for (round, x) in rounds {
+            stake += x;
+            if stake >= committee.validity_threshold() {
+                return round;
+            }
+        }
+        0
+    }
+}
+
+/// Keeps a primary lagging more than `round_gap` rounds behind its peers from proposing until it caught
+/// up with them (e.g., after a restart). Meanwhile, it periodically requests the certificates of the rounds
+/// we are missing. The primary leaves catch-up mode (and resumes) once it is within `round_gap` rounds of
+/// its peers. It never pauses a primary that has not observed its peers ahead, so a committee starting
+/// together makes progress.
+pub struct CatchUp {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The number of rounds we may lag behind our peers and still participate.
+    round_gap: Round,
+    /// The delay between two range requests (in ms).
+    sync_retry_delay: u64,
+    /// The number of peers to which we send each range request.
+    sync_retry_nodes: usize,
+    /// The progress of our dag and the dag of our peers.
+    progress: Arc<DagProgress>,
+    /// Whether the primary is paused. We set it while we catch up.
+    paused: Arc<AtomicBool>,
+    /// Whether the primary is ready to serve. We set it once we heard from our peers and are not
+    /// catching up.
+    ready: Arc<AtomicBool>,
+    /// A network sender to send the range requests.
+    network: SimpleSender,
+    /// Captures the messages we send to the other primaries (if enabled).
+    message_tee: Option<MessageTee>,
+}
+
+impl CatchUp {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        round_gap: Round,
+        sync_retry_delay: u64,
+        sync_retry_nodes: usize,
+        progress: Arc<DagProgress>,
+        paused: Arc<AtomicBool>,
+        ready: Arc<AtomicBool>,
+        protocol_version: Option<u32>,
+        message_tee: Option<MessageTee>,
+        bandwidth: Bandwidth,
+    ) {
+        let identity = committee
+            .primary_or_observer(&name)
+            .ok()
+            .map(|x| x.primary_to_primary);
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee,
+                round_gap,
+                sync_retry_delay,
+                sync_retry_nodes,
+                progress,
+                paused,
+                ready,
+                network: SimpleSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth),
+                message_tee,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        let mut timer = interval(Duration::from_millis(self.sync_retry_delay));
+        let mut catching_up = false;
+        // Whether the primary was already paused (e.g., by an operator) when we entered catch-up mode.
+        let mut was_paused = false;
+        loop {
+            timer.tick().await;
+
+            // We only know how far our peers are once we heard from them.
+            let ours = self.progress.dag_round();
+            let theirs = self.progress.peers_round(&self.committee, &self.name);
+            if theirs == 0 {
+                continue;
+            }
+            if theirs <= ours + self.round_gap {
+                if catching_up {
+                    info!(
+                        "Caught up at round {} (peers at round {}): leaving catch-up mode",
+                        ours, theirs
+                    );
+                    if !was_paused {
+                        self.paused.store(false, Ordering::Relaxed);
+                    }
+                    catching_up = false;
+                }
+                self.ready.store(true, Ordering::Relaxed);
+                continue;
+            }
+            if !catching_up {
+                info!(
+                    "Lagging at round {} (peers at round {}): entering catch-up mode",
+                    ours, theirs
+                );
+                was_paused = self.paused.swap(true, Ordering::Relaxed);
+                self.ready.store(false, Ordering::Relaxed);
+                catching_up = true;
+            }
+
+            // Request the next rounds we are missing.
+            let from = ours + 1;
+            let to = theirs.min(ours + MAX_RANGE_REQUEST);
+            debug!(
+                "Catching up rounds {} to {} (peers at round {})",
+                from, to, theirs
+            );
+            let addresses = self
+                .committee
+                .others_primaries(&self.name)
+                .iter()
+                .map(|(_, x)| x.primary_to_primary)
+                .collect();
+            let message = PrimaryMessage::CertificatesRangeRequest(from, to, self.name);
+            let bytes = bincode::serialize(&message).expect("Failed to serialize range request");
+            if let Some(tee) = &self.message_tee {
+                tee.capture(Direction::Outbound, &bytes);
+            }
+            self.network
+                .lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes)
+                .await;
+        }
+    }
+}
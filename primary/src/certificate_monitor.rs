@@ -0,0 +1,124 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::Round;
+use crypto::Digest;
+use crypto::Hash as _;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{interval, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/certificate_monitor_tests.rs"]
+pub mod certificate_monitor_tests;
+
+/// Sits between the `Core` and the consensus layer to track how long the certificates stay uncommitted.
+/// It raises an alarm (and updates the `stalled` metric) when certificates linger for too long.
+pub struct CertificateMonitor {
+    /// The age (in ms) after which an uncommitted certificate is considered stalled.
+    threshold: u64,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// The number of certificates currently stalled.
+    stalled: Arc<AtomicU64>,
+
+    /// Receives the certificates stored by the `Core`.
+    rx_core: Receiver<Certificate>,
+    /// Forwards the certificates to the consensus layer.
+    tx_consensus: Sender<Certificate>,
+    /// Receives the ordered certificates from the consensus layer.
+    rx_consensus: Receiver<Certificate>,
+    /// Forwards the ordered certificates to the `GarbageCollector`.
+    tx_garbage_collector: Sender<Certificate>,
+
+    /// The uncommitted certificates, along with the time they were delivered to the consensus layer
+    /// and whether we already raised an alarm for them.
+    pending: HashMap<Digest, (Round, Instant, bool)>,
+    /// The last round committed by the consensus layer.
+    last_committed_round: Round,
+}
+
+impl CertificateMonitor {
+    pub fn spawn(
+        threshold: u64,
+        gc_depth: Round,
+        stalled: Arc<AtomicU64>,
+        rx_core: Receiver<Certificate>,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<Certificate>,
+        tx_garbage_collector: Sender<Certificate>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                threshold,
+                gc_depth,
+                stalled,
+                rx_core,
+                tx_consensus,
+                rx_consensus,
+                tx_garbage_collector,
+                pending: HashMap::new(),
+                last_committed_round: 0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Raise an alarm for every certificate pending for longer than the threshold.
+    fn check_pending(&mut self) {
+        let threshold = Duration::from_millis(self.threshold);
+        let mut stalled = 0;
+        for (digest, (round, since, alarm)) in self.pending.iter_mut() {
+            let age = since.elapsed();
+            if age < threshold {
+                continue;
+            }
+            stalled += 1;
+            if !*alarm {
+                *alarm = true;
+                warn!(
+                    "Certificate {} (round {}) uncommitted for {} ms",
+                    digest,
+                    round,
+                    age.as_millis()
+                );
+            }
+        }
+        self.stalled.store(stalled, Ordering::Relaxed);
+    }
+
+    async fn run(&mut self) {
+        let mut timer = interval(Duration::from_millis(self.threshold));
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_core.recv() => {
+                    self.pending
+                        .insert(certificate.digest(), (certificate.round(), Instant::now(), false));
+                    if self.tx_consensus.send(certificate).await.is_err() {
+                        warn!("Failed to deliver certificate to the consensus");
+                    }
+                },
+                Some(certificate) = self.rx_consensus.recv() => {
+                    self.pending.remove(&certificate.digest());
+
+                    // Consensus never commits certificates below the garbage collection round.
+                    let round = certificate.round();
+                    if round > self.last_committed_round {
+                        self.last_committed_round = round;
+                        let gc_depth = self.gc_depth;
+                        self.pending.retain(|_, (r, _, _)| *r + gc_depth >= round);
+                    }
+
+                    self.tx_garbage_collector
+                        .send(certificate)
+                        .await
+                        .expect("Failed to deliver certificate to the garbage collector");
+                },
+                _ = timer.tick() => self.check_pending(),
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::message_tee::{Direction, MessageTee};
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use bytes::Bytes;
+use config::{Committee, Stake};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::{debug, warn};
+use network::{Bandwidth, CancelHandler, ReliableSender};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::time::{interval, Duration};
+
+#[cfg(test)]
+#[path = "tests/certificate_rebroadcaster_tests.rs"]
+pub mod certificate_rebroadcaster_tests;
+
+/// The store key holding the digests of our certificates that a quorum did not acknowledge yet.
+pub const PENDING_BROADCASTS_KEY: &[u8] = b"pending_broadcasts";
+
+/// One of our certificates waiting for the acknowledgements of a quorum of authorities.
+struct PendingBroadcast {
+    certificate: Certificate,
+    /// The authorities that acknowledged the certificate.
+    acks: HashSet<PublicKey>,
+    /// The cancel handlers of the latest transmission to the other authorities.
+    handlers: Vec<(PublicKey, CancelHandler)>,
+}
+
+/// Broadcasts the certificates we create and periodically re-broadcasts those that a quorum of authorities
+/// did not acknowledge yet, until they do (or the certificates are garbage collected). The digests of these
+/// certificates are persisted, so a primary restarting after an outage re-broadcasts them as well.
+pub struct CertificateRebroadcaster {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The delay between two re-broadcasts of the pending certificates (in ms).
+    rebroadcast_delay: u64,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// The current consensus round (used for cleanup).
+    consensus_round: Arc<AtomicU64>,
+
+    /// Receives our new certificates from the `Core`.
+    rx_core: Receiver<Certificate>,
+
+    /// The certificates not yet acknowledged by a quorum.
+    pending: HashMap<Digest, PendingBroadcast>,
+    /// The cancel handlers of the acknowledged certificates (we keep sending them to the remaining
+    /// authorities until they are garbage collected).
+    delivered: HashMap<Round, Vec<CancelHandler>>,
+    /// A network sender to broadcast our certificates.
+    network: ReliableSender,
+    /// Captures the messages we send to the other primaries (if enabled).
+    message_tee: Option<MessageTee>,
+}
+
+impl CertificateRebroadcaster {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        store: Store,
+        rebroadcast_delay: u64,
+        gc_depth: Round,
+        consensus_round: Arc<AtomicU64>,
+        protocol_version: Option<u32>,
+        message_tee: Option<MessageTee>,
+        bandwidth: Bandwidth,
+        rx_core: Receiver<Certificate>,
+    ) {
+        let identity = committee
+            .primary_or_observer(&name)
+            .ok()
+            .map(|x| x.primary_to_primary);
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee,
+                store,
+                rebroadcast_delay,
+                gc_depth,
+                consensus_round,
+                rx_core,
+                pending: HashMap::new(),
+                delivered: HashMap::new(),
+                network: ReliableSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth),
+                message_tee,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Send the certificate to the specified authorities.
+    async fn broadcast(
+        &mut self,
+        certificate: &Certificate,
+        authorities: Vec<PublicKey>,
+    ) -> Vec<(PublicKey, CancelHandler)> {
+        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate.clone()))
+            .expect("Failed to serialize our own certificate");
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        let bytes = Bytes::from(bytes);
+        let mut handlers = Vec::new();
+        for name in authorities {
+            let address = match self.committee.primary(&name) {
+                Ok(x) => x.primary_to_primary,
+                Err(e) => {
+                    warn!("Cannot send {:?} to {}: {}", certificate, name, e);
+                    continue;
+                }
+            };
+            let handler = self.network.send(address, bytes.clone()).await;
+            handlers.push((name, handler));
+        }
+        handlers
+    }
+
+    /// Start tracking (and broadcast) one of our certificates.
+    async fn add(&mut self, certificate: Certificate) {
+        let others = self
+            .committee
+            .others_primaries(&self.name)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let handlers = self.broadcast(&certificate, others).await;
+        let pending = PendingBroadcast {
+            certificate,
+            acks: HashSet::new(),
+            handlers,
+        };
+        self.pending.insert(pending.certificate.digest(), pending);
+    }
+
+    /// Persist the digests of the pending certificates.
+    async fn persist(&mut self) {
+        let mut digests: Vec<_> = self.pending.keys().cloned().collect();
+        digests.sort();
+        let bytes = bincode::serialize(&digests).expect("Failed to serialize pending broadcasts");
+        self.store.write(PENDING_BROADCASTS_KEY.to_vec(), bytes).await;
+    }
+
+    /// Load the certificates that were pending when we last stopped.
+    async fn load(&mut self) {
+        let digests: Vec<Digest> = match self.store.read(PENDING_BROADCASTS_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).expect("Failed to load pending broadcasts"),
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to load pending broadcasts: {}", e);
+                return;
+            }
+        };
+        for digest in digests {
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(bytes)) => match bincode::deserialize(&bytes) {
+                    Ok(certificate) => self.add(certificate).await,
+                    Err(e) => warn!("Failed to load pending certificate {}: {}", digest, e),
+                },
+                Ok(None) => debug!("Pending certificate {} is not in the store", digest),
+                Err(e) => warn!("Failed to load pending certificate {}: {}", digest, e),
+            }
+        }
+    }
+
+    /// Collect the acknowledgements of the pending certificates and re-broadcast those that a quorum
+    /// did not acknowledge yet to the authorities that did not acknowledge them.
+    async fn rebroadcast(&mut self) {
+        let round = self.consensus_round.load(Ordering::Relaxed);
+        let gc_round = round.saturating_sub(self.gc_depth);
+        self.pending.retain(|_, x| x.certificate.round() >= gc_round);
+        self.delivered.retain(|r, _| r >= &gc_round);
+
+        let (name, committee) = (&self.name, &self.committee);
+        let mut acknowledged = Vec::new();
+        let mut retry = Vec::new();
+        for (digest, pending) in self.pending.iter_mut() {
+            let mut waiting = Vec::new();
+            for (name, mut handler) in pending.handlers.drain(..) {
+                match handler.try_recv() {
+                    Ok(_) => {
+                        pending.acks.insert(name);
+                    }
+                    Err(TryRecvError::Empty) => waiting.push((name, handler)),
+                    Err(TryRecvError::Closed) => (),
+                }
+            }
+            pending.handlers = waiting;
+
+            let stake: Stake = pending
+                .acks
+                .iter()
+                .chain(std::iter::once(name))
+                .map(|x| committee.stake(x))
+                .sum();
+            if stake >= committee.quorum_threshold() {
+                acknowledged.push(digest.clone());
+            } else {
+                retry.push(digest.clone());
+            }
+        }
+
+        // Keep delivering the acknowledged certificates to the remaining authorities.
+        for digest in acknowledged {
+            let pending = self.pending.remove(&digest).unwrap();
+            debug!("{:?} acknowledged by a quorum", pending.certificate);
+            self.delivered
+                .entry(pending.certificate.round())
+                .or_default()
+                .extend(pending.handlers.into_iter().map(|(_, x)| x));
+        }
+
+        for digest in retry {
+            let pending = self.pending.get(&digest).unwrap();
+            let certificate = pending.certificate.clone();
+            let missing = self
+                .committee
+                .others_primaries(&self.name)
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|x| !pending.acks.contains(x))
+                .collect();
+            debug!("Re-broadcasting {:?}", certificate);
+            let handlers = self.broadcast(&certificate, missing).await;
+            self.pending.get_mut(&digest).unwrap().handlers = handlers;
+        }
+    }
+
+    async fn run(&mut self) {
+        self.load().await;
+        self.persist().await;
+
+        let mut timer = interval(Duration::from_millis(self.rebroadcast_delay));
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_core.recv() => {
+                    self.add(certificate).await;
+                },
+                _ = timer.tick() => self.rebroadcast().await,
+            }
+            self.persist().await;
+        }
+    }
+}
@@ -1,56 +1,103 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::messages::Certificate;
+use crate::supervisor::{fatal, Supervisor};
+use crypto::Hash as _;
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::error;
+use log::{error, warn};
+use std::future::Future;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{timeout_at, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/certificate_waiter_tests.rs"]
+pub mod certificate_waiter_tests;
 
 /// Waits to receive all the ancestors of a certificate before looping it back to the `Core`
-/// for further processing.
+/// for further processing. It sends no sync request (the `HeaderWaiter` fetches the ancestors), and
+/// thus takes no sync permit.
 pub struct CertificateWaiter {
     /// The persistent storage.
     store: Store,
+    /// The time (in ms) after which we drop a certificate still waiting for its ancestors (0 disables the
+    /// limit); the anti-entropy mechanisms re-deliver it later.
+    max_age: u64,
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<Certificate>,
     /// Loops back to the core certificates for which we got all parents.
     tx_core: Sender<Certificate>,
+    /// The number of certificates dropped because they waited for their ancestors for too long.
+    expired: u64,
 }
 
 impl CertificateWaiter {
     pub fn spawn(
         store: Store,
+        max_age: u64,
         rx_synchronizer: Receiver<Certificate>,
         tx_core: Sender<Certificate>,
+        supervisor: &Supervisor,
     ) {
-        tokio::spawn(async move {
+        supervisor.spawn(
+            "certificate_waiter",
             Self {
                 store,
+                max_age,
                 rx_synchronizer,
                 tx_core,
-            }
-            .run()
-            .await
-        });
+                expired: 0,
+            },
+            // The certificates waiting for their ancestors are dropped with the task; the anti-entropy
+            // mechanisms re-deliver them later.
+            std::convert::identity,
+            |x| Box::pin(x.run()),
+        );
     }
 
     /// Helper function. It waits for particular data to become available in the storage
-    /// and then delivers the specified header.
+    /// and then delivers the specified header (unless the deadline passes first).
     async fn waiter(
         mut missing: Vec<(Vec<u8>, Store)>,
         deliver: Certificate,
+        deadline: Option<Instant>,
     ) -> DagResult<Certificate> {
         let waiting: Vec<_> = missing
             .iter_mut()
             .map(|(x, y)| y.notify_read(x.to_vec()))
             .collect();
 
-        try_join_all(waiting)
-            .await
-            .map(|_| deliver)
-            .map_err(DagError::from)
+        let result = match deadline {
+            Some(deadline) => match timeout_at(deadline, try_join_all(waiting)).await {
+                Ok(result) => result,
+                Err(_) => return Err(DagError::CertificateExpired(deliver.digest(), deliver.round())),
+            },
+            None => try_join_all(waiting).await,
+        };
+        result.map(|_| deliver).map_err(DagError::from)
+    }
+
+    /// Add the certificate to the waiter pool. The waiter will return it to us when all its parents
+    /// are in the store.
+    fn wait_for_parents(
+        &self,
+        certificate: Certificate,
+        arrival: Instant,
+    ) -> impl Future<Output = DagResult<Certificate>> {
+        let wait_for = certificate
+            .header
+            .parents
+            .iter()
+            .cloned()
+            .map(|x| (x.to_vec(), self.store.clone()))
+            .collect();
+        let deadline = match self.max_age {
+            0 => None,
+            x => Some(arrival + Duration::from_millis(x)),
+        };
+        Self::waiter(wait_for, certificate, deadline)
     }
 
     async fn run(&mut self) {
@@ -59,25 +106,19 @@ impl CertificateWaiter {
         loop {
             tokio::select! {
                 Some(certificate) = self.rx_synchronizer.recv() => {
-                    // Add the certificate to the waiter pool. The waiter will return it to us
-                    // when all its parents are in the store.
-                    let wait_for = certificate
-                        .header
-                        .parents
-                        .iter()
-                        .cloned()
-                        .map(|x| (x.to_vec(), self.store.clone()))
-                        .collect();
-                    let fut = Self::waiter(wait_for, certificate);
-                    waiting.push(fut);
+                    waiting.push(self.wait_for_parents(certificate, Instant::now()));
                 }
                 Some(result) = waiting.next() => match result {
                     Ok(certificate) => {
                         self.tx_core.send(certificate).await.expect("Failed to send certificate");
                     },
+                    Err(e @ DagError::CertificateExpired(..)) => {
+                        self.expired += 1;
+                        warn!("{} (dropped {} expired certificates so far)", e, self.expired);
+                    },
                     Err(e) => {
                         error!("{}", e);
-                        panic!("Storage failure: killing node.");
+                        fatal("Storage failure: killing node.");
                     }
                 },
             }
@@ -0,0 +1,282 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::{PrimaryWorkerMessage, Round};
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::{debug, info, warn};
+use network::SimpleSender;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use store::{Store, StoreError};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/committed_output_tests.rs"]
+pub mod committed_output_tests;
+
+/// A client transaction (as assembled into batches by the workers).
+pub type Transaction = Vec<u8>;
+
+/// The store key holding the latest checkpoint of the commit sequence.
+pub const CHECKPOINT_KEY: &[u8] = b"commit_checkpoint";
+
+/// Summarizes the commit sequence output so far. A consumer resuming from a checkpoint does not need to
+/// re-execute the first `index` committed certificates.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The highest committed round.
+    pub round: Round,
+    /// The number of committed certificates output so far.
+    pub index: u64,
+    /// The digest of the last committed certificate output.
+    pub digest: Digest,
+}
+
+impl Checkpoint {
+    /// Read the latest checkpoint from the store (if any).
+    pub async fn load(store: &mut Store) -> Result<Option<Self>, StoreError> {
+        let checkpoint = store
+            .read(CHECKPOINT_KEY.to_vec())
+            .await?
+            .map(|x| bincode::deserialize(&x).expect("Failed to deserialize checkpoint"));
+        Ok(checkpoint)
+    }
+}
+
+/// The transactions of a committed certificate, ready to execute. Certificates are output in the
+/// order of the consensus (that is, sub-dag after sub-dag), and their transactions are listed in
+/// the order of the header's payload (sorted by batch digest) and then in the batches' order.
+#[derive(Clone, Debug)]
+pub struct CommittedTransactions {
+    /// The committed certificate.
+    pub certificate: Certificate,
+    /// The ordered transactions referenced by the certificate.
+    pub transactions: Vec<Transaction>,
+    /// The checkpoint of the commit sequence up to (and including) this certificate. It is set every
+    /// `checkpoint_interval` committed rounds.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+/// Sits between the consensus layer and the `GarbageCollector` to resolve the batches referenced by the
+/// committed certificates (they are held by our workers) and output their transactions in commit order.
+/// The output of a certificate is deferred until all its batches are available; it does not delay the
+/// garbage collector.
+pub struct CommitOutput {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The delay after which we request the missing batches again (in ms).
+    sync_retry_delay: u64,
+    /// The persistent storage (holding the latest checkpoint).
+    store: Store,
+    /// The number of committed rounds between two checkpoints (0 disables checkpoints).
+    checkpoint_interval: u64,
+
+    /// Receives the ordered certificates from the consensus layer.
+    rx_consensus: Receiver<Certificate>,
+    /// Forwards the ordered certificates to the `GarbageCollector`.
+    tx_garbage_collector: Sender<Certificate>,
+    /// Receives the content of the batches from our workers.
+    rx_workers: Receiver<(Digest, Vec<Transaction>)>,
+    /// Outputs the committed transactions.
+    tx_output: Sender<CommittedTransactions>,
+
+    /// The committed certificates waiting for their batches, in commit order.
+    pending: VecDeque<Certificate>,
+    /// The batches referenced by the pending certificates that we do not have yet.
+    missing: HashSet<Digest>,
+    /// The batches referenced by the pending certificates that we already received.
+    batches: HashMap<Digest, Vec<Transaction>>,
+    /// A network sender to send requests to our workers.
+    network: SimpleSender,
+    /// The number of committed certificates output so far.
+    index: u64,
+    /// The highest committed round output so far.
+    round: Round,
+    /// The highest committed round of the latest checkpoint.
+    checkpoint_round: Round,
+}
+
+impl CommitOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        sync_retry_delay: u64,
+        store: Store,
+        checkpoint_interval: u64,
+        rx_consensus: Receiver<Certificate>,
+        tx_garbage_collector: Sender<Certificate>,
+        rx_workers: Receiver<(Digest, Vec<Transaction>)>,
+        tx_output: Sender<CommittedTransactions>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee,
+                sync_retry_delay,
+                store,
+                checkpoint_interval,
+                rx_consensus,
+                tx_garbage_collector,
+                rx_workers,
+                tx_output,
+                pending: VecDeque::new(),
+                missing: HashSet::new(),
+                batches: HashMap::new(),
+                network: SimpleSender::new(),
+                index: 0,
+                round: 0,
+                checkpoint_round: 0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Ask our workers for the missing batches of the certificate. Our workers first sync the batches
+    /// they do not have from the worker of the certificate's author.
+    async fn request_batches(&mut self, certificate: &Certificate) {
+        let mut requests: HashMap<WorkerId, Vec<Digest>> = HashMap::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            if self.missing.contains(digest) {
+                requests.entry(*worker_id).or_default().push(digest.clone());
+            }
+        }
+
+        for (worker_id, digests) in requests {
+            let address = match self.committee.worker(&self.name, &worker_id) {
+                Ok(x) => x.primary_to_worker,
+                Err(e) => {
+                    warn!("Cannot fetch the batches of {:?}: {}", certificate, e);
+                    continue;
+                }
+            };
+            if certificate.origin() != self.name {
+                let message = PrimaryWorkerMessage::Synchronize(digests.clone(), certificate.origin());
+                let bytes = bincode::serialize(&message).expect("Failed to serialize sync request");
+                self.network.send(address, Bytes::from(bytes)).await;
+            }
+            let message = PrimaryWorkerMessage::RequestBatches(digests);
+            let bytes = bincode::serialize(&message).expect("Failed to serialize batch request");
+            self.network.send(address, Bytes::from(bytes)).await;
+        }
+    }
+
+    /// Resume the commit sequence from the latest checkpoint (if any).
+    async fn load_checkpoint(&mut self) {
+        match Checkpoint::load(&mut self.store).await {
+            Ok(Some(checkpoint)) => {
+                info!(
+                    "Resuming commit sequence from checkpoint at round {} (index {})",
+                    checkpoint.round, checkpoint.index
+                );
+                self.index = checkpoint.index;
+                self.round = checkpoint.round;
+                self.checkpoint_round = checkpoint.round;
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Failed to load the latest checkpoint: {}", e),
+        }
+    }
+
+    /// Advance the commit sequence with the certificate and persist a checkpoint if we committed
+    /// `checkpoint_interval` rounds since the latest one.
+    async fn checkpoint(&mut self, certificate: &Certificate) -> Option<Checkpoint> {
+        self.index += 1;
+        self.round = self.round.max(certificate.round());
+        if self.checkpoint_interval == 0
+            || self.round < self.checkpoint_round + self.checkpoint_interval
+        {
+            return None;
+        }
+
+        let checkpoint = Checkpoint {
+            round: self.round,
+            index: self.index,
+            digest: certificate.digest(),
+        };
+        debug!("Checkpoint at round {} (index {})", checkpoint.round, checkpoint.index);
+        let bytes = bincode::serialize(&checkpoint).expect("Failed to serialize checkpoint");
+        self.store.write(CHECKPOINT_KEY.to_vec(), bytes).await;
+        self.checkpoint_round = self.round;
+        Some(checkpoint)
+    }
+
+    /// Output the transactions of the pending certificates (in commit order) for which we have all batches.
+    async fn output_ready(&mut self) {
+        while let Some(certificate) = self.pending.front() {
+            if certificate
+                .header
+                .payload
+                .keys()
+                .any(|x| self.missing.contains(x))
+            {
+                break;
+            }
+
+            let certificate = self.pending.pop_front().unwrap();
+            let transactions = certificate
+                .header
+                .payload
+                .keys()
+                .filter_map(|x| self.batches.remove(x))
+                .flatten()
+                .collect();
+            let checkpoint = self.checkpoint(&certificate).await;
+            let output = CommittedTransactions {
+                certificate,
+                transactions,
+                checkpoint,
+            };
+            if let Err(e) = self.tx_output.send(output).await {
+                warn!("Failed to output committed transactions: {}", e);
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        self.load_checkpoint().await;
+
+        let timer = sleep(Duration::from_millis(self.sync_retry_delay));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_consensus.recv() => {
+                    self.tx_garbage_collector
+                        .send(certificate.clone())
+                        .await
+                        .expect("Failed to send certificate to the garbage collector");
+
+                    self.missing.extend(certificate.header.payload.keys().cloned());
+                    self.request_batches(&certificate).await;
+                    self.pending.push_back(certificate);
+                },
+
+                Some((digest, transactions)) = self.rx_workers.recv() => {
+                    if self.missing.remove(&digest) {
+                        self.batches.insert(digest, transactions);
+                    } else {
+                        debug!("Dropping unexpected batch {}", digest);
+                    }
+                },
+
+                () = &mut timer => {
+                    // Request again the batches of the certificate blocking the output.
+                    if let Some(certificate) = self.pending.front().cloned() {
+                        debug!("Waiting for the batches of {:?}", certificate);
+                        self.request_batches(&certificate).await;
+                    }
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.sync_retry_delay));
+                }
+            }
+
+            self.output_ready().await;
+        }
+    }
+}
@@ -1,26 +1,125 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::aggregators::{CertificatesAggregator, VotesAggregator};
+use crate::audit_log::{AuditEvent, AuditLog};
+use crate::catch_up::DagProgress;
 use crate::error::{DagError, DagResult};
+use crate::message_tee::{Direction, MessageTee};
 use crate::messages::{Certificate, Header, Vote};
-use crate::primary::{PrimaryMessage, Round};
+use crate::primary::{PendingCertificate, PrimaryMessage, Round};
+use crate::round_index::RoundIndex;
+use crate::supervisor::{fatal, Supervisor};
 use crate::synchronizer::Synchronizer;
+use crate::verification_cache::VerificationCache;
 use async_recursion::async_recursion;
 use bytes::Bytes;
-use config::Committee;
+use config::{Committee, Parameters, ParentQuorumPolicy, Stake};
 use crypto::Hash as _;
-use crypto::{Digest, PublicKey, SignatureService};
+use crypto::{Digest, PublicKey, Signature, SignatureService};
+use futures::future::join_all;
 use log::{debug, error, warn};
-use network::{CancelHandler, ReliableSender};
+use network::{Bandwidth, CancelHandler, Priority, ReliableSender, WarningLimiter};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom as _;
+use rand::SeedableRng as _;
+#[cfg(test)]
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, sleep_until, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/core_tests.rs"]
 pub mod core_tests;
 
+#[cfg(test)]
+thread_local! {
+    /// Counts the certificates verified by the core (used by the tests).
+    static CERTIFICATE_VERIFICATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The time during which we keep the votes that arrived before the header they are for (in ms).
+const EARLY_VOTE_EXPIRY: u64 = 1_000;
+
+/// The store key holding the digest of our header and the votes we gathered for it (see `persist_votes`).
+const PENDING_VOTES_KEY: &[u8] = b"pending_votes";
+
+/// The parameters of the `Core` (derived from the `Parameters` of the node).
+#[derive(Clone)]
+pub struct CoreParameters {
+    /// The depth of the garbage collector.
+    pub gc_depth: Round,
+    /// The stake of parents required to make a valid header.
+    pub parent_quorum_policy: ParentQuorumPolicy,
+    /// The minimum number of distinct authors among the parents of a valid header (0 disables it).
+    pub min_distinct_parent_authors: usize,
+    /// The maximum number of weak links of a valid header.
+    pub max_weak_links: usize,
+    /// Whether to drop certificates we already processed before verifying them.
+    pub dedup_certificates: bool,
+    /// The number of certificate verdicts we remember (0 disables the cache).
+    pub verification_cache_size: usize,
+    /// The maximum number of headers of a burst we verify concurrently (serially if 0 or 1).
+    pub header_verification_parallelism: usize,
+    /// The delay after which we re-send our header to the authorities that did not vote for it (0 disables it).
+    pub vote_timeout: u64,
+    /// The time during which we hold our votes for the headers of an authority (0 disables it).
+    pub vote_batch_window: u64,
+    /// Whether we persist our header and the votes we gathered for it, to resume after a restart.
+    pub persist_votes: bool,
+    /// The number of certificates whose writes we buffer to write them in a single batch (0 disables it).
+    pub write_batch_size: usize,
+    /// The maximum time during which we buffer certificate writes (in ms).
+    pub write_batch_delay: u64,
+    /// If set, we initially send our headers to a random subset of authorities holding a quorum plus this
+    /// stake. Otherwise we send them to everyone.
+    pub header_broadcast_margin: Option<Stake>,
+    /// Whether we send our headers for the rounds we lead to every authority.
+    pub leader_priority: bool,
+    /// Whether we skip the signatures verification of votes and certificates (only for trusted test networks).
+    pub trusted_network: bool,
+}
+
+impl From<&Parameters> for CoreParameters {
+    fn from(parameters: &Parameters) -> Self {
+        Self {
+            gc_depth: parameters.gc_depth,
+            parent_quorum_policy: parameters.parent_quorum_policy,
+            // We only check the diversity of the parents of the other authorities' headers if required.
+            min_distinct_parent_authors: match parameters.enforce_parent_diversity {
+                true => parameters.min_distinct_parent_authors,
+                false => 0,
+            },
+            max_weak_links: parameters.max_weak_links,
+            dedup_certificates: parameters.dedup_certificates,
+            verification_cache_size: parameters.verification_cache_size,
+            header_verification_parallelism: parameters.header_verification_parallelism,
+            vote_timeout: parameters.vote_timeout,
+            vote_batch_window: parameters.vote_batch_window,
+            persist_votes: parameters.persist_pending_votes,
+            write_batch_size: parameters.certificate_write_batch_size,
+            write_batch_delay: parameters.certificate_write_batch_delay,
+            // We only send our headers to a subset of the authorities if we can fall back to the others.
+            header_broadcast_margin: (parameters.partial_header_broadcast
+                && parameters.vote_timeout > 0)
+                .then_some(parameters.header_broadcast_margin),
+            leader_priority: parameters.leader_priority,
+            trusted_network: parameters.trusted_network,
+        }
+    }
+}
+
+impl Default for CoreParameters {
+    fn default() -> Self {
+        Self::from(&Parameters::default())
+    }
+}
+
 pub struct Core {
     /// The public key of this primary.
     name: PublicKey,
@@ -34,8 +133,52 @@ pub struct Core {
     signature_service: SignatureService,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
+    /// Whether the primary is paused (in which case we do not vote).
+    paused: Arc<AtomicBool>,
+    /// The progress of our dag compared to the dag of our peers (used to catch up).
+    progress: Arc<DagProgress>,
+    /// Indexes the digests of the certificates we store by round (read by the `Helper`).
+    round_index: Arc<RoundIndex>,
     /// The depth of the garbage collector.
     gc_depth: Round,
+    /// The stake of parents required to make a valid header.
+    parent_quorum_policy: ParentQuorumPolicy,
+    /// The minimum number of distinct authors among the parents of a valid header (0 disables it).
+    min_distinct_parent_authors: usize,
+    /// The maximum number of weak links of a valid header.
+    max_weak_links: usize,
+    /// Whether to drop certificates we already processed before verifying them.
+    dedup_certificates: bool,
+    /// The delay after which we re-send our header to the authorities that did not vote for it (0 disables it).
+    vote_timeout: u64,
+    /// The time during which we hold our votes for the headers of an authority, to send them in a single
+    /// message (0 disables it).
+    vote_batch_window: u64,
+    /// Whether we persist our header and the votes we gathered for it, to resume after a restart.
+    persist_votes: bool,
+    /// The number of certificates whose writes we buffer to write them in a single batch (0 disables it).
+    write_batch_size: usize,
+    /// The maximum time during which we buffer certificate writes (in ms).
+    write_batch_delay: u64,
+    /// If set, we initially send our headers to a random subset of authorities holding a quorum plus this
+    /// stake (the others get them once the vote timeout expires). Otherwise we send them to everyone.
+    header_broadcast_margin: Option<Stake>,
+    /// Whether we send our headers for the rounds we lead to every authority (even if the
+    /// `header_broadcast_margin` is set).
+    leader_priority: bool,
+    /// Whether we skip the signatures verification of votes and certificates (only for trusted test networks).
+    trusted_network: bool,
+    /// Records our state transitions (if enabled).
+    audit_log: Option<AuditLog>,
+    /// Reports the votes gathered for our latest header (read by the health snapshot).
+    pending_certificate: Arc<Mutex<Option<PendingCertificate>>>,
+    /// Counts the votes we received for headers we did not author (read by the health snapshot).
+    misrouted_votes: Arc<AtomicU64>,
+    /// Hands over our certificates to the `CertificateRebroadcaster` (if enabled), which then broadcasts
+    /// them in our stead.
+    tx_rebroadcaster: Option<Sender<Certificate>>,
+    /// Captures the messages we send to the other primaries (if enabled).
+    message_tee: Option<MessageTee>,
 
     /// Receiver for dag messages (headers, votes, certificates).
     rx_primaries: Receiver<PrimaryMessage>,
@@ -49,6 +192,8 @@ pub struct Core {
     tx_consensus: Sender<Certificate>,
     /// Send valid a quorum of certificates' ids to the `Proposer` (along with their round).
     tx_proposer: Sender<(Vec<Certificate>, Round)>,
+    /// Publishes the round of our dag whenever it advances.
+    tx_round: watch::Sender<Round>,
 
     /// The last garbage collected round.
     gc_round: Round,
@@ -56,16 +201,41 @@ pub struct Core {
     last_voted: HashMap<Round, HashSet<PublicKey>>,
     /// The set of headers we are currently processing.
     processing: HashMap<Round, HashSet<Digest>>,
+    /// The digests of the certificates we already processed.
+    processed_certificates: HashMap<Round, HashSet<Digest>>,
     /// The last header we proposed (for which we are waiting votes).
     current_header: Header,
     /// Aggregates votes into a certificate.
     votes_aggregator: VotesAggregator,
+    /// The votes for our headers that arrived before we proposed them (with their arrival time).
+    early_votes: HashMap<PublicKey, (Vote, Instant)>,
     /// Aggregates certificates to use as parents for new headers.
     certificates_aggregators: HashMap<Round, Box<CertificatesAggregator>>,
     /// A network sender to send the batches to the other workers.
     network: ReliableSender,
+    /// The votes we hold (by recipient) until the vote batch window expires.
+    pending_votes: HashMap<SocketAddr, Vec<Vote>>,
+    /// The time at which we send the votes we hold (if any).
+    votes_deadline: Option<Instant>,
+    /// The certificates whose writes we buffer (they are not handed over to the consensus and the
+    /// `Proposer` before they are written).
+    pending_writes: Vec<Certificate>,
+    /// The time at which we write the buffered certificates (if any).
+    writes_deadline: Option<Instant>,
+    /// The digest of the certificate we formed last, until it is written and announced to the other nodes.
+    unannounced: Option<Digest>,
     /// Keeps the cancel handlers of the messages we sent.
     cancel_handlers: HashMap<Round, Vec<CancelHandler>>,
+    /// Remembers the verdicts of the certificates we recently verified.
+    verification_cache: VerificationCache,
+    /// The maximum number of headers of a burst we verify concurrently (serially if 0 or 1).
+    header_verification_parallelism: usize,
+    /// Coalesces identical warnings (e.g., a faulty peer repeatedly sending invalid messages).
+    warnings: WarningLimiter,
+    /// The authorities without stake that voted for our headers (we warn about each of them once).
+    voters_without_stake: HashSet<PublicKey>,
+    /// The randomness used to pick the authorities we send our headers to.
+    rng: StdRng,
 }
 
 impl Core {
@@ -77,15 +247,49 @@ impl Core {
         synchronizer: Synchronizer,
         signature_service: SignatureService,
         consensus_round: Arc<AtomicU64>,
-        gc_depth: Round,
+        paused: Arc<AtomicBool>,
+        progress: Arc<DagProgress>,
+        round_index: Arc<RoundIndex>,
+        parameters: CoreParameters,
+        audit_log: Option<AuditLog>,
+        pending_certificate: Arc<Mutex<Option<PendingCertificate>>>,
+        misrouted_votes: Arc<AtomicU64>,
+        tx_rebroadcaster: Option<Sender<Certificate>>,
+        protocol_version: Option<u32>,
+        message_tee: Option<MessageTee>,
+        bandwidth: Bandwidth,
         rx_primaries: Receiver<PrimaryMessage>,
         rx_header_waiter: Receiver<Header>,
         rx_certificate_waiter: Receiver<Certificate>,
         rx_proposer: Receiver<Header>,
         tx_consensus: Sender<Certificate>,
         tx_proposer: Sender<(Vec<Certificate>, Round)>,
+        tx_round: watch::Sender<Round>,
+        supervisor: &Supervisor,
     ) {
-        tokio::spawn(async move {
+        let CoreParameters {
+            gc_depth,
+            parent_quorum_policy,
+            min_distinct_parent_authors,
+            max_weak_links,
+            dedup_certificates,
+            verification_cache_size,
+            header_verification_parallelism,
+            vote_timeout,
+            vote_batch_window,
+            persist_votes,
+            write_batch_size,
+            write_batch_delay,
+            header_broadcast_margin,
+            leader_priority,
+            trusted_network,
+        } = parameters;
+        let identity = committee
+            .primary_or_observer(&name)
+            .ok()
+            .map(|x| x.primary_to_primary);
+        supervisor.spawn(
+            "core",
             Self {
                 name,
                 committee,
@@ -93,54 +297,331 @@ impl Core {
                 synchronizer,
                 signature_service,
                 consensus_round,
+                paused,
+                progress,
+                round_index,
                 gc_depth,
+                parent_quorum_policy,
+                min_distinct_parent_authors,
+                max_weak_links,
+                dedup_certificates,
+                vote_timeout,
+                vote_batch_window,
+                persist_votes,
+                write_batch_size,
+                write_batch_delay,
+                header_broadcast_margin,
+                leader_priority,
+                trusted_network,
+                audit_log,
+                pending_certificate,
+                misrouted_votes,
+                tx_rebroadcaster,
+                message_tee,
                 rx_primaries,
                 rx_header_waiter,
                 rx_certificate_waiter,
                 rx_proposer,
                 tx_consensus,
                 tx_proposer,
+                tx_round,
                 gc_round: 0,
                 last_voted: HashMap::with_capacity(2 * gc_depth as usize),
                 processing: HashMap::with_capacity(2 * gc_depth as usize),
-                current_header: Header::default(),
+                processed_certificates: HashMap::with_capacity(2 * gc_depth as usize),
+                current_header: Header {
+                    author: name,
+                    ..Header::default()
+                },
                 votes_aggregator: VotesAggregator::new(),
+                early_votes: HashMap::new(),
                 certificates_aggregators: HashMap::with_capacity(2 * gc_depth as usize),
-                network: ReliableSender::new(),
+                network: ReliableSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth),
+                pending_votes: HashMap::new(),
+                votes_deadline: None,
+                pending_writes: Vec::new(),
+                writes_deadline: None,
+                unannounced: None,
                 cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
-            }
-            .run()
-            .await;
-        });
+                verification_cache: VerificationCache::new(verification_cache_size),
+                header_verification_parallelism,
+                warnings: WarningLimiter::default(),
+                voters_without_stake: HashSet::new(),
+                rng: StdRng::from_entropy(),
+            },
+            Self::rebuild,
+            |x| Box::pin(x.run()),
+        );
+    }
+
+    /// Rebuild the core after a panic (see `Supervisor::spawn`). We keep the authors we voted for (so that we
+    /// never vote for two headers of the same author and round) and the certificates waiting to be written,
+    /// and our latest header, and drop the rest of our in-memory state. The votes for our latest header are
+    /// resumed from the store (if enabled).
+    fn rebuild(self) -> Self {
+        let capacity = 2 * self.gc_depth as usize;
+        Self {
+            processing: HashMap::with_capacity(capacity),
+            processed_certificates: HashMap::with_capacity(capacity),
+            votes_aggregator: VotesAggregator::new(),
+            early_votes: HashMap::new(),
+            certificates_aggregators: HashMap::with_capacity(capacity),
+            pending_votes: HashMap::new(),
+            votes_deadline: None,
+            cancel_handlers: HashMap::with_capacity(capacity),
+            ..self
+        }
+    }
+
+    /// Record an event in the audit log (if enabled).
+    fn audit(&mut self, event: AuditEvent) {
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.append(&event);
+        }
+    }
+
+    /// Make the aggregator of the votes for our header, reporting how close the header is to being
+    /// certified as the votes arrive (nothing once it is certified).
+    fn votes_aggregator(&self, header: &Header) -> VotesAggregator {
+        let pending_certificate = self.pending_certificate.clone();
+        let (id, round) = (header.id.clone(), header.round);
+        let report = move |stake, threshold| {
+            let pending = (stake < threshold).then(|| PendingCertificate {
+                header: id.clone(),
+                round,
+                stake,
+                threshold,
+            });
+            *pending_certificate
+                .lock()
+                .expect("Failed to lock the pending certificate") = pending;
+        };
+        report(0, self.committee.quorum_threshold());
+        VotesAggregator::new().with_progress(Box::new(report))
+    }
+
+    /// Persist our header (under its digest) and the votes we gathered for it so far (if enabled).
+    async fn persist_votes(&mut self) -> DagResult<()> {
+        if !self.persist_votes {
+            return Ok(());
+        }
+        let header = &self.current_header;
+        let pending = (&header.id, self.votes_aggregator.votes());
+        let entries = vec![
+            (
+                header.id.to_vec(),
+                bincode::serialize(header).expect("Failed to serialize header"),
+            ),
+            (
+                PENDING_VOTES_KEY.to_vec(),
+                bincode::serialize(&pending).expect("Failed to serialize pending votes"),
+            ),
+        ];
+        self.store.write_batch(entries).await?;
+        Ok(())
+    }
+
+    /// Resume collecting the votes for the header we persisted before restarting (unless it is certified,
+    /// garbage collected, or no longer our current header).
+    async fn restore_votes(&mut self) -> DagResult<()> {
+        let (id, votes): (Digest, Vec<(PublicKey, Signature)>) =
+            match self.store.read(PENDING_VOTES_KEY.to_vec()).await? {
+                Some(bytes) => bincode::deserialize(&bytes)?,
+                None => return Ok(()),
+            };
+        let header: Header = match self.store.read(id.to_vec()).await? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => return Ok(()),
+        };
+
+        // We only keep the current header across a supervisor restart (see `rebuild`); at startup it is the
+        // placeholder of round 0.
+        let replaced = self.current_header.round != 0 && self.current_header.id != header.id;
+        let gc_round = self
+            .consensus_round
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.gc_depth)
+            .max(self.gc_round);
+        if replaced || header.round < gc_round {
+            debug!("Discarding the persisted votes for {}", header);
+            return Ok(());
+        }
+
+        // The digest of a certificate does not depend on its votes.
+        let digest = Certificate {
+            header: header.clone(),
+            votes: Vec::new(),
+        }
+        .digest();
+        if self.store.read(digest.to_vec()).await?.is_some() {
+            return Ok(());
+        }
+
+        debug!(
+            "Resuming the collection of votes for {} ({} votes)",
+            header,
+            votes.len()
+        );
+        self.current_header = header.clone();
+        self.votes_aggregator = self.votes_aggregator(&header);
+        for (author, signature) in votes {
+            let vote = Vote {
+                id: header.id.clone(),
+                round: header.round,
+                origin: header.author,
+                author,
+                signature,
+            };
+            self.process_vote(vote).await?;
+        }
+        Ok(())
     }
 
     async fn process_own_header(&mut self, header: Header) -> DagResult<()> {
         // Reset the votes aggregator.
         self.current_header = header.clone();
-        self.votes_aggregator = VotesAggregator::new();
+        self.votes_aggregator = self.votes_aggregator(&header);
+        self.persist_votes().await?;
 
         // Broadcast the new header in a reliable manner.
-        let addresses = self
-            .committee
-            .others_primaries(&self.name)
-            .iter()
-            .map(|(_, x)| x.primary_to_primary)
-            .collect();
+        let addresses = self.header_recipients(&header);
         let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
             .expect("Failed to serialize our own header");
-        let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        let handlers = self
+            .network
+            .broadcast_with_priority(addresses, Bytes::from(bytes), Priority::High)
+            .await;
         self.cancel_handlers
             .entry(header.round)
             .or_insert_with(Vec::new)
             .extend(handlers);
 
         // Process the header.
-        self.process_header(&header).await
+        self.process_header(&header).await?;
+
+        // Apply the votes for this header that arrived before it (if any).
+        self.apply_early_votes(&header).await;
+        Ok(())
+    }
+
+    /// The addresses of the authorities we initially send our header to: everyone, or a random subset
+    /// holding a quorum of stake plus the configured margin (counting our own stake). The headers of the
+    /// rounds we lead go to everyone if we prioritize them.
+    fn header_recipients(&mut self, header: &Header) -> Vec<SocketAddr> {
+        let mut others = self.committee.others_primaries(&self.name);
+        let leading = self.leader_priority
+            && header.round.is_multiple_of(2)
+            && self.committee.leader(header.round as usize) == self.name;
+        let margin = match self.header_broadcast_margin {
+            Some(x) if !leading => x,
+            _ => return others.into_iter().map(|(_, x)| x.primary_to_primary).collect(),
+        };
+        others.shuffle(&mut self.rng);
+
+        let target = self.committee.quorum_threshold().saturating_add(margin);
+        let mut stake = self.committee.stake(&self.name);
+        let mut addresses = Vec::new();
+        for (name, x) in others {
+            if stake >= target {
+                break;
+            }
+            stake += self.committee.stake(&name);
+            addresses.push(x.primary_to_primary);
+        }
+        addresses
+    }
+
+    /// Drop a vote for a header we did not author: it is either misrouted or adversarial.
+    fn drop_misrouted_vote(&mut self, vote: Vote) {
+        let count = self.misrouted_votes.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!(
+            "Dropping {:?} for a header of {} ({} misrouted votes so far)",
+            vote, vote.origin, count
+        );
+    }
+
+    /// Check whether the vote is for one of our headers that we did not propose yet.
+    fn is_early_vote(&self, vote: &Vote) -> bool {
+        vote.origin == self.name && vote.round > self.current_header.round
+    }
+
+    /// Buffer a vote that arrived before the header it is for (e.g., due to network reordering).
+    /// We keep at most one vote per authority.
+    fn buffer_early_vote(&mut self, vote: Vote) -> DagResult<()> {
+        self.verify_vote(&vote)?;
+        debug!("Buffering early {:?}", vote);
+        self.early_votes.insert(vote.author, (vote, Instant::now()));
+        Ok(())
+    }
+
+    /// Process the buffered votes for our new header and drop those that expired or became stale.
+    async fn apply_early_votes(&mut self, header: &Header) {
+        let expiry = Duration::from_millis(EARLY_VOTE_EXPIRY);
+        let mut votes = Vec::new();
+        self.early_votes.retain(|_, (vote, received)| {
+            if received.elapsed() >= expiry {
+                return false;
+            }
+            if vote.id == header.id {
+                votes.push(vote.clone());
+                return false;
+            }
+            vote.round > header.round
+        });
+        for vote in votes {
+            if let Err(e) = self.process_vote(vote).await {
+                self.warnings.warn(e.to_string());
+            }
+        }
+    }
+
+    /// Re-send our current header to the authorities whose votes are still missing.
+    async fn rerequest_votes(&mut self) {
+        // The core handles messages one at a time so no vote can be processed between computing the
+        // missing voters and sending the header. A vote that is still in flight merely results in a
+        // redundant header, which its author does not vote for again.
+        let missing = self.votes_aggregator.missing_voters(&self.committee);
+        let addresses: Vec<_> = self
+            .committee
+            .others_primaries(&self.name)
+            .into_iter()
+            .filter(|(name, _)| missing.contains(name))
+            .map(|(_, x)| x.primary_to_primary)
+            .collect();
+        if addresses.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Re-requesting votes for {} from {} authorities",
+            self.current_header,
+            addresses.len()
+        );
+        let bytes = bincode::serialize(&PrimaryMessage::Header(self.current_header.clone()))
+            .expect("Failed to serialize our own header");
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        let handlers = self
+            .network
+            .broadcast_with_priority(addresses, Bytes::from(bytes), Priority::High)
+            .await;
+        self.cancel_handlers
+            .entry(self.current_header.round)
+            .or_default()
+            .extend(handlers);
     }
 
     #[async_recursion]
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
-        debug!("Processing {:?}", header);
+        round_debug!(header.round, "Processing {:?}", header);
+        self.progress.observe(header.author, header.round);
+
         // Indicate that we are processing this header.
         self.processing
             .entry(header.round)
@@ -150,25 +631,50 @@ impl Core {
         // Ensure we have the parents. If at least one parent is missing, the synchronizer returns an empty
         // vector; it will gather the missing parents (as well as all ancestors) from other nodes and then
         // reschedule processing of this header.
-        let parents = self.synchronizer.get_parents(header).await?;
+        let mut parents = self.synchronizer.get_parents(header).await?;
         if parents.is_empty() {
             debug!("Processing of {} suspended: missing parent(s)", header.id);
             return Ok(());
         }
 
-        // Check the parent certificates. Ensure the parents form a quorum and are all from the previous round.
+        // Ensure all parents (and weak links) were created by members of the current committee.
+        for x in &parents {
+            ensure!(
+                self.committee.authorities.contains_key(&x.origin()),
+                DagError::UnknownParentAuthor(x.digest(), x.origin())
+            );
+        }
+
+        // Check the parent certificates. Ensure the parents reach the parents' threshold (a quorum by default)
+        // and are all from the previous round.
+        let weak_links = parents.split_off(header.parents.len());
         let mut stake = 0;
+        let mut authors = HashSet::new();
         for x in parents {
             ensure!(
                 x.round() + 1 == header.round,
                 DagError::MalformedHeader(header.id.clone())
             );
             stake += self.committee.stake(&x.origin());
+            authors.insert(x.origin());
         }
         ensure!(
-            stake >= self.committee.quorum_threshold(),
+            stake >= self.parent_quorum_policy.threshold(&self.committee),
             DagError::HeaderRequiresQuorum(header.id.clone())
         );
+        ensure!(
+            authors.len() >= self.min_distinct_parent_authors.min(self.committee.size()),
+            DagError::HeaderRequiresDiverseParents(header.id.clone())
+        );
+
+        // Check the weak links (if any). They must reference certificates from rounds earlier than the
+        // parents' round and do not count towards the parents' threshold.
+        for x in weak_links {
+            ensure!(
+                x.round() + 1 < header.round,
+                DagError::MalformedHeader(header.id.clone())
+            );
+        }
 
         // Ensure we have the payload. If we don't, the synchronizer will ask our workers to get it, and then
         // reschedule processing of this header once we have it.
@@ -180,8 +686,17 @@ impl Core {
         // Store the header.
         let bytes = bincode::serialize(header).expect("Failed to serialize header");
         self.store.write(header.id.to_vec(), bytes).await;
+        self.audit(AuditEvent::HeaderAccepted {
+            id: header.id.clone(),
+            round: header.round,
+            author: header.author,
+        });
 
         // Check if we can vote for this header.
+        if self.paused.load(Ordering::Relaxed) {
+            debug!("Not voting for {} while paused", header);
+            return Ok(());
+        }
         if self
             .last_voted
             .entry(header.round)
@@ -190,7 +705,12 @@ impl Core {
         {
             // Make a vote and send it to the header's creator.
             let vote = Vote::new(header, &self.name, &mut self.signature_service).await;
-            debug!("Created {:?}", vote);
+            round_debug!(vote.round, "Created {:?}", vote);
+            self.audit(AuditEvent::VoteCast {
+                id: vote.id.clone(),
+                round: vote.round,
+                origin: vote.origin,
+            });
             if vote.origin == self.name {
                 self.process_vote(vote)
                     .await
@@ -201,55 +721,121 @@ impl Core {
                     .primary(&header.author)
                     .expect("Author of valid header is not in the committee")
                     .primary_to_primary;
-                let bytes = bincode::serialize(&PrimaryMessage::Vote(vote))
-                    .expect("Failed to serialize our own vote");
-                let handler = self.network.send(address, Bytes::from(bytes)).await;
-                self.cancel_handlers
-                    .entry(header.round)
-                    .or_insert_with(Vec::new)
-                    .push(handler);
+                if self.vote_batch_window > 0 {
+                    // Hold the vote until the batch window expires.
+                    let window = Duration::from_millis(self.vote_batch_window);
+                    self.votes_deadline.get_or_insert_with(|| Instant::now() + window);
+                    self.pending_votes.entry(address).or_default().push(vote);
+                } else {
+                    self.send_votes(address, vec![vote]).await;
+                }
             }
         }
         Ok(())
     }
 
+    /// Send our votes to the author of the headers they are for, in a single message.
+    async fn send_votes(&mut self, address: SocketAddr, mut votes: Vec<Vote>) {
+        let round = votes.iter().map(|x| x.round).max().unwrap_or_default();
+        let message = match votes.len() {
+            1 => PrimaryMessage::Vote(votes.pop().unwrap()),
+            _ => PrimaryMessage::Votes(votes),
+        };
+        let bytes = bincode::serialize(&message).expect("Failed to serialize our own votes");
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        let handler = self
+            .network
+            .send_with_priority(address, Bytes::from(bytes), Priority::High)
+            .await;
+        self.cancel_handlers
+            .entry(round)
+            .or_insert_with(Vec::new)
+            .push(handler);
+    }
+
+    /// Send the votes we held during the batch window.
+    async fn flush_votes(&mut self) {
+        self.votes_deadline = None;
+        for (address, votes) in std::mem::take(&mut self.pending_votes) {
+            self.send_votes(address, votes).await;
+        }
+    }
+
     #[async_recursion]
     async fn process_vote(&mut self, vote: Vote) -> DagResult<()> {
-        debug!("Processing {:?}", vote);
+        round_debug!(vote.round, "Processing {:?}", vote);
+
+        // The authorities without stake are not expected to vote (their votes count for nothing).
+        let without_stake = self.committee.stake(&vote.author) == 0;
+        if without_stake && self.voters_without_stake.insert(vote.author) {
+            warn!("Received a vote from {} (which has no stake)", vote.author);
+        }
 
         // Add it to the votes' aggregator and try to make a new certificate.
-        if let Some(certificate) =
+        let certificate =
             self.votes_aggregator
-                .append(vote, &self.committee, &self.current_header)?
-        {
-            debug!("Assembled {:?}", certificate);
+                .append(vote, &self.committee, &self.current_header)?;
+        self.persist_votes().await?;
+        if let Some(certificate) = certificate {
+            round_debug!(certificate.round(), "Assembled {:?}", certificate);
+            self.audit(AuditEvent::CertificateFormed {
+                digest: certificate.digest(),
+                round: certificate.round(),
+            });
+
+            // Process the new certificate. We only announce it once it is written (see
+            // `process_stored_certificate`).
+            self.unannounced = Some(certificate.digest());
+            self.process_certificate(certificate)
+                .await
+                .expect("Failed to process valid certificate");
+        }
+        Ok(())
+    }
 
-            // Broadcast the certificate.
-            let addresses = self
-                .committee
-                .others_primaries(&self.name)
+    /// Announce our own certificate to the other nodes (once it is written).
+    async fn announce_certificate(&mut self, certificate: &Certificate) {
+        // Broadcast the certificate (or let the rebroadcaster do it). The observers only learn about
+        // the dag through the certificates of the authorities, so we also send it to them.
+        let mut addresses = Vec::new();
+        if let Some(tx_rebroadcaster) = &self.tx_rebroadcaster {
+            tx_rebroadcaster
+                .send(certificate.clone())
+                .await
+                .expect("Failed to send certificate to the rebroadcaster");
+        } else {
+            addresses.extend(
+                self.committee
+                    .others_primaries(&self.name)
+                    .iter()
+                    .map(|(_, x)| x.primary_to_primary),
+            );
+        }
+        addresses.extend(
+            self.committee
+                .observers_primaries()
                 .iter()
-                .map(|(_, x)| x.primary_to_primary)
-                .collect();
+                .map(|(_, x)| x.primary_to_primary),
+        );
+        if !addresses.is_empty() {
             let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate.clone()))
                 .expect("Failed to serialize our own certificate");
+            if let Some(tee) = &self.message_tee {
+                tee.capture(Direction::Outbound, &bytes);
+            }
             let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
             self.cancel_handlers
                 .entry(certificate.round())
                 .or_insert_with(Vec::new)
                 .extend(handlers);
-
-            // Process the new certificate.
-            self.process_certificate(certificate)
-                .await
-                .expect("Failed to process valid certificate");
         }
-        Ok(())
     }
 
     #[async_recursion]
     async fn process_certificate(&mut self, certificate: Certificate) -> DagResult<()> {
-        debug!("Processing {:?}", certificate);
+        round_debug!(certificate.round(), "Processing {:?}", certificate);
 
         // Process the header embedded in the certificate if we haven't already voted for it (if we already
         // voted, it means we already processed it). Since this header got certified, we are sure that all
@@ -274,17 +860,87 @@ impl Core {
             return Ok(());
         }
 
-        // Store the certificate.
+        // Store the certificate (or buffer its write, in which case we only go on once it is written).
+        if self.write_batch_size > 0 {
+            if !self.pending_writes.contains(&certificate) {
+                self.pending_writes.push(certificate);
+            }
+            let delay = Duration::from_millis(self.write_batch_delay);
+            self.writes_deadline.get_or_insert_with(|| Instant::now() + delay);
+            if self.pending_writes.len() >= self.write_batch_size {
+                self.flush_certificates().await?;
+            }
+            return Ok(());
+        }
         let bytes = bincode::serialize(&certificate).expect("Failed to serialize certificate");
         self.store.write(certificate.digest().to_vec(), bytes).await;
+        let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+        self.store
+            .write(index_key, certificate.digest().to_vec())
+            .await;
+        self.process_stored_certificate(certificate).await
+    }
+
+    /// Write the buffered certificates in a single batch, and then resume their processing.
+    async fn flush_certificates(&mut self) -> DagResult<()> {
+        self.writes_deadline = None;
+        let certificates = std::mem::take(&mut self.pending_writes);
+        let mut entries = Vec::with_capacity(2 * certificates.len());
+        for certificate in &certificates {
+            let bytes = bincode::serialize(certificate).expect("Failed to serialize certificate");
+            entries.push((certificate.digest().to_vec(), bytes));
+            let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+            entries.push((index_key, certificate.digest().to_vec()));
+        }
+        self.store.write_batch(entries).await?;
+        for certificate in certificates {
+            self.process_stored_certificate(certificate).await?;
+        }
+        Ok(())
+    }
+
+    /// Process a certificate once it is stored.
+    async fn process_stored_certificate(&mut self, certificate: Certificate) -> DagResult<()> {
+        // Announce our own certificate now that it is durable.
+        if self.unannounced.as_ref() == Some(&certificate.digest()) {
+            self.unannounced = None;
+            self.announce_certificate(&certificate).await;
+        }
+
+        self.progress.advance(certificate.round());
+        self.round_index
+            .insert(certificate.round(), certificate.digest());
+        self.synchronizer
+            .backfill_payload(&certificate.header)
+            .await?;
+        self.audit(AuditEvent::CertificateAccepted {
+            digest: certificate.digest(),
+            round: certificate.round(),
+            origin: certificate.origin(),
+        });
+        if self.dedup_certificates {
+            self.processed_certificates
+                .entry(certificate.round())
+                .or_default()
+                .insert(certificate.digest());
+        }
 
         // Check if we have enough certificates to enter a new dag round and propose a header.
+        let threshold = self.parent_quorum_policy.threshold(&self.committee);
         if let Some(parents) = self
             .certificates_aggregators
             .entry(certificate.round())
-            .or_insert_with(|| Box::new(CertificatesAggregator::new()))
+            .or_insert_with(|| Box::new(CertificatesAggregator::new(threshold)))
             .append(certificate.clone(), &self.committee)?
         {
+            self.audit(AuditEvent::RoundAdvanced {
+                round: certificate.round() + 1,
+            });
+            if certificate.round() + 1 > *self.tx_round.borrow() {
+                // There is no error if nobody subscribed to the rounds.
+                let _ = self.tx_round.send(certificate.round() + 1);
+            }
+
             // Send it to the `Proposer`.
             self.tx_proposer
                 .send((parents, certificate.round()))
@@ -304,13 +960,29 @@ impl Core {
     }
 
     fn sanitize_header(&mut self, header: &Header) -> DagResult<()> {
+        Self::verify_header(header, &self.committee, self.gc_round, self.max_weak_links)
+    }
+
+    /// Run the checks of `sanitize_header`. It does not access our state, so it can run on any thread.
+    fn verify_header(
+        header: &Header,
+        committee: &Committee,
+        gc_round: Round,
+        max_weak_links: usize,
+    ) -> DagResult<()> {
         ensure!(
-            self.gc_round <= header.round,
+            gc_round <= header.round,
             DagError::TooOld(header.id.clone(), header.round)
         );
 
+        // Ensure the header does not make us fetch and store an unbounded number of weak links.
+        ensure!(
+            header.weak_links.len() <= max_weak_links,
+            DagError::TooManyWeakLinks(header.id.clone(), header.weak_links.len())
+        );
+
         // Verify the header's signature.
-        header.verify(&self.committee)?;
+        header.verify(committee)?;
 
         // TODO [issue #3]: Prevent bad nodes from sending junk headers with high round numbers.
 
@@ -332,7 +1004,42 @@ impl Core {
         );
 
         // Verify the vote.
-        vote.verify(&self.committee).map_err(DagError::from)
+        self.verify_vote(vote)
+    }
+
+    fn verify_vote(&self, vote: &Vote) -> DagResult<()> {
+        if self.trusted_network {
+            return vote.verify_structure(&self.committee);
+        }
+        vote.verify(&self.committee)
+    }
+
+    /// Check whether we already processed this certificate (in which case there is no need to verify it again).
+    /// We only store certificates once processed, so the store tells us which ones we processed before a restart.
+    async fn already_processed(&mut self, certificate: &Certificate) -> DagResult<bool> {
+        if !self.dedup_certificates {
+            return Ok(false);
+        }
+
+        let digest = certificate.digest();
+        if self
+            .processed_certificates
+            .get(&certificate.round())
+            .map_or_else(|| false, |x| x.contains(&digest))
+        {
+            return Ok(true);
+        }
+
+        if certificate.round() < self.gc_round
+            || self.store.read(digest.to_vec()).await?.is_none()
+        {
+            return Ok(false);
+        }
+        self.processed_certificates
+            .entry(certificate.round())
+            .or_default()
+            .insert(digest);
+        Ok(true)
     }
 
     fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
@@ -341,17 +1048,136 @@ impl Core {
             DagError::TooOld(certificate.digest(), certificate.round())
         );
 
+        // Check whether we recently verified the same certificate (with the same votes).
+        let key = self.verification_cache.key(certificate);
+        if let Some(valid) = key.as_ref().and_then(|x| self.verification_cache.get(x)) {
+            ensure!(valid, DagError::KnownInvalidCertificate(certificate.digest()));
+            return Ok(());
+        }
+
         // Verify the certificate (and the embedded header).
-        certificate.verify(&self.committee).map_err(DagError::from)
+        #[cfg(test)]
+        CERTIFICATE_VERIFICATIONS.with(|x| x.set(x.get() + 1));
+        let result = if self.trusted_network {
+            certificate.verify_structure(&self.committee)
+        } else {
+            certificate.verify(&self.committee)
+        };
+        if let Some(key) = key {
+            self.verification_cache.insert(key, result.is_ok());
+        }
+        result
+    }
+
+    /// Handle the votes and certificates we receive from other primaries.
+    async fn handle_message(&mut self, message: PrimaryMessage) -> DagResult<()> {
+        match message {
+            PrimaryMessage::Vote(vote) if vote.origin != self.name => {
+                self.drop_misrouted_vote(vote);
+                Ok(())
+            }
+            PrimaryMessage::Vote(vote) if self.is_early_vote(&vote) => self.buffer_early_vote(vote),
+            PrimaryMessage::Vote(vote) => match self.sanitize_vote(&vote) {
+                Ok(()) => self.process_vote(vote).await,
+                error => error,
+            },
+            PrimaryMessage::Certificate(certificate) => {
+                match self.already_processed(&certificate).await {
+                    Ok(true) => {
+                        debug!("Dropping already processed {:?}", certificate);
+                        Ok(())
+                    }
+                    Ok(false) => match self.sanitize_certificate(&certificate) {
+                        Ok(()) => self.process_certificate(certificate).await,
+                        error => error,
+                    },
+                    Err(e) => Err(e),
+                }
+            }
+            _ => panic!("Unexpected core message"),
+        }
+    }
+
+    /// Handle a header along with the headers queued right behind it (up to `header_verification_parallelism`
+    /// headers in total). We verify them concurrently, but process them one at a time in the order they arrived.
+    async fn handle_headers(&mut self, header: Header) -> DagResult<()> {
+        let mut headers = vec![header];
+        let mut next = None;
+        while headers.len() < self.header_verification_parallelism {
+            match self.rx_primaries.try_recv() {
+                Ok(PrimaryMessage::Header(header)) => headers.push(header),
+                Ok(message) => {
+                    next = Some(message);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        // Verify the headers on the blocking threads (this is CPU-bound).
+        let committee = Arc::new(self.committee.clone());
+        let (gc_round, max_weak_links) = (self.gc_round, self.max_weak_links);
+        let verifications = headers.iter().map(|header| {
+            let (header, committee) = (header.clone(), committee.clone());
+            spawn_blocking(move || {
+                Self::verify_header(&header, &committee, gc_round, max_weak_links)
+            })
+        });
+        let verdicts = join_all(verifications).await;
+
+        for (header, verdict) in headers.into_iter().zip(verdicts) {
+            let result = match verdict.expect("Failed to verify header") {
+                Ok(()) => self.process_header(&header).await,
+                error => error,
+            };
+            self.handle_result(result);
+        }
+
+        // Handle the message that ended the burst of headers (if any).
+        match next {
+            Some(message) => self.handle_message(message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Log the outcome of handling a message (and crash on storage failures).
+    fn handle_result(&mut self, result: DagResult<()>) {
+        match result {
+            Ok(()) => (),
+            Err(DagError::StoreError(e)) => {
+                error!("{}", e);
+                fatal("Storage failure: killing node.");
+            }
+            Err(e @ DagError::TooOld(..)) => debug!("{}", e),
+            Err(e) => {
+                self.warnings.warn(e.to_string());
+            }
+        }
     }
 
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        // Report the progress of the votes for our initial header, or resume collecting the votes for the
+        // header we persisted before restarting.
+        self.votes_aggregator = self.votes_aggregator(&self.current_header);
+        if self.persist_votes {
+            let result = self.restore_votes().await;
+            self.handle_result(result);
+        }
+
+        let timer = sleep(Duration::from_millis(self.vote_timeout));
+        tokio::pin!(timer);
+
         loop {
+            let votes_deadline = self.votes_deadline.unwrap_or_else(Instant::now);
+            let writes_deadline = self.writes_deadline.unwrap_or_else(Instant::now);
             let result = tokio::select! {
                 // We receive here messages from other primaries.
                 Some(message) = self.rx_primaries.recv() => {
                     match message {
+                        PrimaryMessage::Header(header) if self.header_verification_parallelism > 1 => {
+                            self.handle_headers(header).await
+                        },
                         PrimaryMessage::Header(header) => {
                             match self.sanitize_header(&header) {
                                 Ok(()) => self.process_header(&header).await,
@@ -359,19 +1185,7 @@ impl Core {
                             }
 
                         },
-                        PrimaryMessage::Vote(vote) => {
-                            match self.sanitize_vote(&vote) {
-                                Ok(()) => self.process_vote(vote).await,
-                                error => error
-                            }
-                        },
-                        PrimaryMessage::Certificate(certificate) => {
-                            match self.sanitize_certificate(&certificate) {
-                                Ok(()) =>  self.process_certificate(certificate).await,
-                                error => error
-                            }
-                        },
-                        _ => panic!("Unexpected core message")
+                        message => self.handle_message(message).await
                     }
                 },
 
@@ -385,17 +1199,32 @@ impl Core {
                 Some(certificate) = self.rx_certificate_waiter.recv() => self.process_certificate(certificate).await,
 
                 // We also receive here our new headers created by the `Proposer`.
-                Some(header) = self.rx_proposer.recv() => self.process_own_header(header).await,
+                Some(header) = self.rx_proposer.recv() => {
+                    let deadline = Instant::now() + Duration::from_millis(self.vote_timeout);
+                    timer.as_mut().reset(deadline);
+                    self.process_own_header(header).await
+                },
+
+                // Re-request the votes we are still missing for our current header.
+                () = &mut timer, if self.vote_timeout > 0 => {
+                    self.rerequest_votes().await;
+                    let deadline = Instant::now() + Duration::from_millis(self.vote_timeout);
+                    timer.as_mut().reset(deadline);
+                    Ok(())
+                },
+
+                // Send the votes we held once the batch window expires.
+                () = sleep_until(votes_deadline), if self.votes_deadline.is_some() => {
+                    self.flush_votes().await;
+                    Ok(())
+                },
+
+                // Write the buffered certificates once the batch delay expires.
+                () = sleep_until(writes_deadline), if self.writes_deadline.is_some() => {
+                    self.flush_certificates().await
+                },
             };
-            match result {
-                Ok(()) => (),
-                Err(DagError::StoreError(e)) => {
-                    error!("{}", e);
-                    panic!("Storage failure: killing node.");
-                }
-                Err(e @ DagError::TooOld(..)) => debug!("{}", e),
-                Err(e) => warn!("{}", e),
-            }
+            self.handle_result(result);
 
             // Cleanup internal state.
             let round = self.consensus_round.load(Ordering::Relaxed);
@@ -403,8 +1232,10 @@ impl Core {
                 let gc_round = round - self.gc_depth;
                 self.last_voted.retain(|k, _| k >= &gc_round);
                 self.processing.retain(|k, _| k >= &gc_round);
+                self.processed_certificates.retain(|k, _| k >= &gc_round);
                 self.certificates_aggregators.retain(|k, _| k >= &gc_round);
                 self.cancel_handlers.retain(|k, _| k >= &gc_round);
+                self.round_index.prune(gc_round);
                 self.gc_round = gc_round;
             }
         }
@@ -0,0 +1,183 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::Round;
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::Instant;
+
+#[cfg(test)]
+#[path = "tests/dag_stats_tests.rs"]
+pub mod dag_stats_tests;
+
+/// The statistics of a dag round, as seen by this primary.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundStats {
+    /// The dag round.
+    pub round: Round,
+    /// The number of certificates of the round we stored.
+    pub certificates: usize,
+    /// The number of distinct authors of these certificates.
+    pub authors: usize,
+    /// The number of batches referenced by these certificates. The primary only sees the batches'
+    /// digests, so it cannot tell their size in bytes.
+    pub payload_batches: usize,
+    /// The number of these certificates that got committed.
+    pub committed: usize,
+    /// The average delay between storing and committing the committed certificates (in ms), if any.
+    pub commit_latency: Option<u64>,
+}
+
+impl RoundStats {
+    /// The header line of the CSV representation of the statistics.
+    pub const CSV_HEADER: &'static str =
+        "round,certificates,authors,payload_batches,committed,commit_latency_ms";
+
+    /// Format the statistics as a CSV line (matching `CSV_HEADER`).
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.round,
+            self.certificates,
+            self.authors,
+            self.payload_batches,
+            self.committed,
+            self.commit_latency.map(|x| x.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+/// The statistics of a round that may still change.
+#[derive(Default)]
+struct RoundAccumulator {
+    /// The time at which we stored each certificate of the round.
+    stored: HashMap<Digest, Instant>,
+    authors: HashSet<PublicKey>,
+    payload_batches: usize,
+    committed: usize,
+    /// The sum of the commit latencies of the committed certificates (in ms).
+    total_latency: u128,
+}
+
+/// Sits between the `Core` and the consensus layer to gather per-round statistics of the dag. The
+/// statistics of a round are output once it is garbage collected, that is, once none of its certificates
+/// can be committed anymore.
+pub struct DagStats {
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+
+    /// Receives the certificates stored by the `Core`.
+    rx_core: Receiver<Certificate>,
+    /// Forwards the certificates to the consensus layer.
+    tx_consensus: Sender<Certificate>,
+    /// Receives the ordered certificates from the consensus layer.
+    rx_consensus: Receiver<Certificate>,
+    /// Forwards the ordered certificates to the `GarbageCollector`.
+    tx_garbage_collector: Sender<Certificate>,
+    /// Outputs the statistics of each round (in round order).
+    tx_stats: Sender<RoundStats>,
+
+    /// The rounds whose statistics may still change.
+    rounds: BTreeMap<Round, RoundAccumulator>,
+    /// The last round committed by the consensus layer.
+    last_committed_round: Round,
+}
+
+impl DagStats {
+    pub fn spawn(
+        gc_depth: Round,
+        rx_core: Receiver<Certificate>,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<Certificate>,
+        tx_garbage_collector: Sender<Certificate>,
+        tx_stats: Sender<RoundStats>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                gc_depth,
+                rx_core,
+                tx_consensus,
+                rx_consensus,
+                tx_garbage_collector,
+                tx_stats,
+                rounds: BTreeMap::new(),
+                last_committed_round: 0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    fn record_stored(&mut self, certificate: &Certificate) {
+        let stats = self.rounds.entry(certificate.round()).or_default();
+        if stats
+            .stored
+            .insert(certificate.digest(), Instant::now())
+            .is_none()
+        {
+            stats.authors.insert(certificate.origin());
+            stats.payload_batches += certificate.header.payload.len();
+        }
+    }
+
+    fn record_committed(&mut self, certificate: &Certificate) {
+        if let Some(stats) = self.rounds.get_mut(&certificate.round()) {
+            if let Some(stored) = stats.stored.get(&certificate.digest()) {
+                stats.total_latency += stored.elapsed().as_millis();
+                stats.committed += 1;
+            }
+        }
+    }
+
+    /// Output the statistics of the rounds below the garbage collection round.
+    async fn flush(&mut self) {
+        let gc_round = self.last_committed_round.saturating_sub(self.gc_depth);
+        let kept = self.rounds.split_off(&gc_round);
+        let flushed = std::mem::replace(&mut self.rounds, kept);
+        for (round, stats) in flushed {
+            let output = RoundStats {
+                round,
+                certificates: stats.stored.len(),
+                authors: stats.authors.len(),
+                payload_batches: stats.payload_batches,
+                committed: stats.committed,
+                commit_latency: (stats.committed > 0)
+                    .then(|| (stats.total_latency / stats.committed as u128) as u64),
+            };
+            if let Err(e) = self.tx_stats.send(output).await {
+                warn!("Failed to output dag statistics: {}", e);
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_core.recv() => {
+                    self.record_stored(&certificate);
+                    if self.tx_consensus.send(certificate).await.is_err() {
+                        warn!("Failed to deliver certificate to the consensus");
+                    }
+                },
+                Some(certificate) = self.rx_consensus.recv() => {
+                    self.record_committed(&certificate);
+
+                    // Consensus never commits certificates below the garbage collection round.
+                    let round = certificate.round();
+                    if round > self.last_committed_round {
+                        self.last_committed_round = round;
+                        self.flush().await;
+                    }
+
+                    self.tx_garbage_collector
+                        .send(certificate)
+                        .await
+                        .expect("Failed to deliver certificate to the garbage collector");
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::PrimaryClientMessage;
+use crypto::Digest;
+use std::error::Error;
+use std::fmt::Debug;
+
+#[cfg(test)]
+#[path = "tests/delivery_tests.rs"]
+pub mod delivery_tests;
+
+/// Defines how clients parse the deliveries they receive from the primary. Integrators with their
+/// own delivery framing implement this trait to extract application-level data.
+pub trait DeliveryDecoder: Clone + Send + Sync + 'static {
+    /// The application-level data carried by a delivery.
+    type Output: Debug + Send;
+
+    /// Parse a serialized delivery.
+    fn decode(&self, delivery: &[u8]) -> Result<Self::Output, Box<dyn Error + Send + Sync>>;
+
+    /// The ids of the sample transactions (see `TxEncoding::parse`) carried by a delivery, used to measure
+    /// their latency. Deliveries do not carry transactions by default.
+    fn sample_ids(&self, _output: &Self::Output) -> Vec<u64> {
+        Vec::new()
+    }
+}
+
+/// The default decoder, extracting the digest of the delivered batch.
+#[derive(Clone, Default)]
+pub struct DigestDecoder;
+
+impl DeliveryDecoder for DigestDecoder {
+    type Output = Digest;
+
+    fn decode(&self, delivery: &[u8]) -> Result<Self::Output, Box<dyn Error + Send + Sync>> {
+        match bincode::deserialize(delivery)? {
+            PrimaryClientMessage::BatchDelivered(digest) => Ok(digest),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::{BufMut as _, BytesMut};
+use rand::Rng;
+use std::convert::TryInto as _;
+use std::str::FromStr;
+
+#[cfg(test)]
+#[path = "tests/encoding_tests.rs"]
+pub mod encoding_tests;
+
+/// The layout of the sample transactions sent by the benchmark clients. Sample transactions always
+/// start with a zero byte and are identified by their first 8 bytes (read as a big-endian integer),
+/// which is how the workers report them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TxEncoding {
+    /// A 4-byte counter (with the first byte zeroed) followed by a 4-byte random client id.
+    #[default]
+    Counter,
+    /// An 8-byte monotonic id (with the first byte zeroed).
+    Monotonic,
+    /// A 16-byte random UUID (version 4, with the first byte zeroed).
+    Uuid,
+}
+
+impl FromStr for TxEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counter" => Ok(Self::Counter),
+            "monotonic" => Ok(Self::Monotonic),
+            "uuid" => Ok(Self::Uuid),
+            x => Err(format!("Unknown transaction encoding '{}'", x)),
+        }
+    }
+}
+
+impl TxEncoding {
+    /// The minimum size of a transaction using this encoding.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Counter | Self::Monotonic => 8,
+            Self::Uuid => 16,
+        }
+    }
+
+    /// Write the header of a sample transaction into `tx` and return its id. The `counter`
+    /// must increase with every transaction sent by the client; `rng` provides the randomness
+    /// of the UUIDs.
+    pub fn encode<R: Rng>(&self, tx: &mut BytesMut, counter: u64, client: u32, rng: &mut R) -> u64 {
+        let start = tx.len();
+        match self {
+            Self::Counter => {
+                let mut counter = (counter as u32).to_be_bytes();
+                counter[0] = 0u8;
+                tx.put_slice(&counter);
+                tx.put_u32(client);
+            }
+            Self::Monotonic => tx.put_u64(counter & (u64::MAX >> 8)),
+            Self::Uuid => {
+                let mut uuid: [u8; 16] = rng.gen();
+                uuid[0] = 0u8;
+                uuid[6] = (uuid[6] & 0x0f) | 0x40; // Version 4.
+                uuid[8] = (uuid[8] & 0x3f) | 0x80; // RFC 4122 variant.
+                tx.put_slice(&uuid);
+            }
+        }
+        Self::parse(&tx[start..]).expect("Malformed sample transaction")
+    }
+
+    /// Returns the id of a sample transaction, or `None` if the transaction is not a sample.
+    pub fn parse(tx: &[u8]) -> Option<u64> {
+        match tx.first() {
+            Some(0) => tx.get(..8)?.try_into().ok().map(u64::from_be_bytes),
+            _ => None,
+        }
+    }
+}
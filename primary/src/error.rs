@@ -33,15 +33,24 @@ pub enum DagError {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] Box<bincode::ErrorKind>),
 
+    #[error("Failed to decompress message: {0}")]
+    DecompressionError(String),
+
     #[error("Invalid header id")]
     InvalidHeaderId,
 
+    #[error("Header {0} is not in the store")]
+    MissingHeader(Digest),
+
     #[error("Malformed header {0}")]
     MalformedHeader(Digest),
 
     #[error("Received message from unknown authority {0}")]
     UnknownAuthority(PublicKey),
 
+    #[error("Parent {0} was created by unknown authority {1}")]
+    UnknownParentAuthor(Digest, PublicKey),
+
     #[error("Authority {0} appears in quorum more than once")]
     AuthorityReuse(PublicKey),
 
@@ -51,9 +60,21 @@ pub enum DagError {
     #[error("Received certificate without a quorum")]
     CertificateRequiresQuorum,
 
+    #[error("Certificate {0} previously failed verification")]
+    KnownInvalidCertificate(Digest),
+
     #[error("Parents of header {0} are not a quorum")]
     HeaderRequiresQuorum(Digest),
 
+    #[error("Parents of header {0} come from too few distinct authors")]
+    HeaderRequiresDiverseParents(Digest),
+
+    #[error("Header {0} has too many weak links ({1})")]
+    TooManyWeakLinks(Digest, usize),
+
     #[error("Message {0} (round {1}) too old")]
     TooOld(Digest, Round),
+
+    #[error("Certificate {0} (round {1}) waited too long for its ancestors")]
+    CertificateExpired(Digest, Round),
 }
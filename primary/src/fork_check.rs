@@ -0,0 +1,91 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crate::primary::Round;
+use crypto::{Digest, Hash as _, PublicKey};
+use std::collections::{BTreeMap, BTreeSet};
+use store::ConsistencyCheck;
+
+#[cfg(test)]
+#[path = "tests/fork_check_tests.rs"]
+pub mod fork_check_tests;
+
+/// Detects a forked store (e.g., one that was copied or merged incorrectly), holding several certificates
+/// of the same authority at the same round. Only the rounds above the garbage collection round (the
+/// `gc_depth` rounds below the highest stored certificate) are checked, since we never process older
+/// certificates again.
+pub struct ForkCheck {
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// The highest round of the certificates inspected so far.
+    max_round: Round,
+    /// The digests of the certificates of each authority, by round (above the garbage collection round).
+    certificates: BTreeMap<Round, BTreeMap<PublicKey, BTreeSet<Digest>>>,
+}
+
+impl ForkCheck {
+    pub fn new(gc_depth: Round) -> Self {
+        Self {
+            gc_depth,
+            max_round: 0,
+            certificates: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConsistencyCheck for ForkCheck {
+    fn inspect(&mut self, key: &[u8], value: &[u8]) {
+        // Certificates are stored under their digest; other records do not decode to a certificate
+        // with the digest of their key.
+        let certificate: Certificate = match bincode::deserialize(value) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let digest = certificate.digest();
+        if digest.as_ref() != key {
+            return;
+        }
+
+        let round = certificate.round();
+        if round > self.max_round {
+            self.max_round = round;
+            let gc_round = round.saturating_sub(self.gc_depth);
+            self.certificates = self.certificates.split_off(&gc_round);
+        }
+        if round + self.gc_depth >= self.max_round {
+            self.certificates
+                .entry(round)
+                .or_default()
+                .entry(certificate.origin())
+                .or_default()
+                .insert(digest);
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let forks: Vec<_> = self
+            .certificates
+            .iter()
+            .flat_map(|(round, authorities)| {
+                authorities
+                    .iter()
+                    .filter(|(_, digests)| digests.len() > 1)
+                    .map(move |(name, digests)| {
+                        format!(
+                            "{} has {} certificates at round {} ({:?})",
+                            name,
+                            digests.len(),
+                            round,
+                            digests
+                        )
+                    })
+            })
+            .collect();
+        match forks.is_empty() {
+            true => Ok(()),
+            false => Err(format!(
+                "The store is forked: {}. Restore it from a backup or wipe it and let the node sync",
+                forks.join("; ")
+            )),
+        }
+    }
+}
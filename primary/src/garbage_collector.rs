@@ -1,15 +1,30 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::Certificate;
-use crate::primary::PrimaryWorkerMessage;
+use crate::primary::{PrimaryWorkerMessage, Round};
+use crate::supervisor::{fatal, Supervisor};
 use bytes::Bytes;
 use config::Committee;
 use crypto::PublicKey;
+use log::{debug, error};
 use network::SimpleSender;
+#[cfg(test)]
+use std::cell::RefCell;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use store::Store;
 use tokio::sync::mpsc::Receiver;
 
+#[cfg(test)]
+#[path = "tests/garbage_collector_tests.rs"]
+pub mod garbage_collector_tests;
+
+#[cfg(test)]
+thread_local! {
+    /// Records the key ranges the garbage collector compacted (used by the tests).
+    static COMPACTIONS: RefCell<Vec<(Vec<u8>, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+}
+
 /// Receives the highest round reached by consensus and update it for all tasks.
 pub struct GarbageCollector {
     /// The current consensus round (used for cleanup).
@@ -20,36 +35,61 @@ pub struct GarbageCollector {
     addresses: Vec<SocketAddr>,
     /// A network sender to notify our workers of cleanup events.
     network: SimpleSender,
+    /// The persistent storage (we delete the round index entries of the garbage collected rounds).
+    store: Store,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// The number of committed rounds between two compactions of the index entries we deleted (0 disables
+    /// compaction).
+    compaction_interval: Round,
 }
 
 impl GarbageCollector {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: &PublicKey,
         committee: &Committee,
         consensus_round: Arc<AtomicU64>,
         rx_consensus: Receiver<Certificate>,
+        store: Store,
+        gc_depth: Round,
+        compaction_interval: Round,
+        supervisor: &Supervisor,
     ) {
-        let addresses = committee
-            .our_workers(name)
-            .expect("Our public key or worker id is not in the committee")
-            .iter()
-            .map(|x| x.primary_to_worker)
-            .collect();
+        // Observers have no workers to clean up.
+        let addresses = if committee.observers.contains_key(name) {
+            Vec::new()
+        } else {
+            committee
+                .our_workers(name)
+                .expect("Our public key or worker id is not in the committee")
+                .iter()
+                .map(|x| x.primary_to_worker)
+                .collect()
+        };
 
-        tokio::spawn(async move {
+        supervisor.spawn(
+            "garbage_collector",
             Self {
                 consensus_round,
                 rx_consensus,
                 addresses,
                 network: SimpleSender::new(),
-            }
-            .run()
-            .await;
-        });
+                store,
+                gc_depth,
+                compaction_interval,
+            },
+            // The collector keeps no state besides its configuration and channels.
+            std::convert::identity,
+            |x| Box::pin(x.run()),
+        );
     }
 
     async fn run(&mut self) {
         let mut last_committed_round = 0;
+        let mut last_gc_round = 0;
+        let mut last_compacted_round = 0;
+        let mut last_compacted_gc_round = 0;
         while let Some(certificate) = self.rx_consensus.recv().await {
             // TODO [issue #9]: Re-include batch digests that have not been sequenced into our next block.
 
@@ -66,6 +106,37 @@ impl GarbageCollector {
                 self.network
                     .broadcast(self.addresses.clone(), Bytes::from(bytes))
                     .await;
+
+                // Prune the round index of the store (see `Certificate::index_key`).
+                let gc_round = round.saturating_sub(self.gc_depth);
+                if gc_round > last_gc_round {
+                    let (start, end) = Certificate::index_range(last_gc_round, gc_round);
+                    if let Err(e) = self.store.delete_range(start, end).await {
+                        error!("{}", e);
+                        fatal("Storage failure: killing node.");
+                    }
+                    last_gc_round = gc_round;
+                }
+
+                // Periodically compact the index entries we deleted since the last compaction to reclaim
+                // their disk space.
+                if self.compaction_interval > 0
+                    && round >= last_compacted_round + self.compaction_interval
+                    && last_gc_round > last_compacted_gc_round
+                {
+                    debug!(
+                        "Compacting the round index from round {} to {}",
+                        last_compacted_gc_round, last_gc_round
+                    );
+                    let (start, end) =
+                        Certificate::index_range(last_compacted_gc_round, last_gc_round);
+                    #[cfg(test)]
+                    COMPACTIONS.with(|x| x.borrow_mut().push((start.clone(), end.clone())));
+
+                    self.store.compact_range(Some(start), Some(end)).await;
+                    last_compacted_round = round;
+                    last_compacted_gc_round = last_gc_round;
+                }
             }
         }
     }
@@ -1,22 +1,32 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
+use crate::message_tee::{Direction, MessageTee};
 use crate::messages::Header;
+use crate::peer_scores::PeerScores;
 use crate::primary::{PrimaryMessage, PrimaryWorkerMessage, Round};
+use crate::supervisor::{fatal, Supervisor};
 use bytes::Bytes;
 use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
-use futures::future::try_join_all;
+use futures::future::{try_join_all, BoxFuture};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::{debug, error};
-use network::SimpleSender;
-use std::collections::HashMap;
+use log::{debug, error, warn};
+use network::{Bandwidth, ReliableSender, SimpleSender};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::{sleep, Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/header_waiter_tests.rs"]
+pub mod header_waiter_tests;
 
 /// The resolution of the timer that checks whether we received replies to our sync requests, and triggers
 /// new sync requests if we didn't.
@@ -27,6 +37,10 @@ const TIMER_RESOLUTION: u64 = 1_000;
 pub enum WaiterMessage {
     SyncBatches(HashMap<Digest, WorkerId>, Header),
     SyncParents(Vec<Digest>, Header),
+    /// Sync all the rounds from the specified one up to the header (excluded), and wait for its parents.
+    RangeSync(Vec<Digest>, Header, /* from */ Round),
+    /// Have our workers sync the batches of a certified header ahead of its commit (nothing waits for them).
+    BackfillBatches(HashMap<Digest, WorkerId>, /* author */ PublicKey, Round),
 }
 
 /// Waits for missing parent certificates and batches' digests.
@@ -45,23 +59,50 @@ pub struct HeaderWaiter {
     sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request.
     sync_retry_nodes: usize,
+    /// The permits of our outstanding sync requests.
+    sync_permits: Arc<Semaphore>,
+    /// The maximum number of headers waiting for their dependencies or for a permit (0 if unlimited).
+    max_pending: usize,
+    /// The number of headers dropped because too many headers were waiting.
+    evicted: u64,
 
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<WaiterMessage>,
     /// Loops back to the core headers for which we got all parents and batches.
     tx_core: Sender<Header>,
 
-    /// Network driver allowing to send messages.
+    /// Network driver allowing to send messages to our workers.
     network: SimpleSender,
+    /// Network driver allowing to send messages to the other primaries.
+    primary_network: SimpleSender,
+    /// Network driver sending our sync requests to the peers we pick (their acknowledgements tell how
+    /// responsive the peers are).
+    sync_network: ReliableSender,
+    /// Captures the messages we send to the other primaries (if enabled).
+    message_tee: Option<MessageTee>,
+    /// The responsiveness of the peers, used to pick the peers we send sync requests to.
+    peer_scores: PeerScores,
+    /// The acknowledgements of our sync requests: each resolves with the peer and its response latency
+    /// (or `None` if it did not respond in time).
+    sync_responses: FuturesUnordered<BoxFuture<'static, (PublicKey, Option<Duration>)>>,
+    /// The randomness used to pick peers.
+    rng: StdRng,
     /// Keeps the digests of the all certificates for which we sent a sync request,
     /// along with a timestamp (`u128`) indicating when we sent the request.
     parent_requests: HashMap<Digest, (Round, u128)>,
     /// Keeps the digests of the all tx batches for which we sent a sync request,
     /// similarly to `header_requests`.
     batch_requests: HashMap<Digest, Round>,
+    /// The last round covered by our latest range sync request, along with a timestamp (`u128`)
+    /// indicating when we sent the request.
+    range_request: Option<(Round, u128)>,
     /// List of digests (either certificates, headers or tx batch) that are waiting
     /// to be processed. Their processing will resume when we get all their dependencies.
     pending: HashMap<Digest, (Round, Sender<()>)>,
+    /// The headers waiting for their dependencies.
+    waiting: FuturesUnordered<BoxFuture<'static, DagResult<Option<Header>>>>,
+    /// The sync commands waiting for a permit (in the order we received them).
+    backlog: VecDeque<WaiterMessage>,
 }
 
 impl HeaderWaiter {
@@ -74,28 +115,71 @@ impl HeaderWaiter {
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        sync_permits: Arc<Semaphore>,
+        max_pending: usize,
+        protocol_version: Option<u32>,
+        message_tee: Option<MessageTee>,
+        bandwidth: Bandwidth,
         rx_synchronizer: Receiver<WaiterMessage>,
         tx_core: Sender<Header>,
+        supervisor: &Supervisor,
     ) {
-        tokio::spawn(async move {
+        let identity = committee
+            .primary_or_observer(&name)
+            .ok()
+            .map(|x| x.primary_to_primary);
+        supervisor.spawn(
+            "header_waiter",
             Self {
                 name,
-                committee,
+                committee: committee.clone(),
                 store,
                 consensus_round,
                 gc_depth,
                 sync_retry_delay,
                 sync_retry_nodes,
+                sync_permits,
+                max_pending,
+                evicted: 0,
                 rx_synchronizer,
                 tx_core,
                 network: SimpleSender::new(),
+                primary_network: SimpleSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth.clone()),
+                sync_network: ReliableSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth),
+                message_tee,
+                peer_scores: PeerScores::new(committee.clone()),
+                sync_responses: FuturesUnordered::new(),
+                rng: StdRng::from_entropy(),
                 parent_requests: HashMap::new(),
                 batch_requests: HashMap::new(),
+                range_request: None,
                 pending: HashMap::new(),
-            }
-            .run()
-            .await;
-        });
+                waiting: FuturesUnordered::new(),
+                backlog: VecDeque::new(),
+            },
+            Self::rebuild,
+            |x| Box::pin(x.run()),
+        );
+    }
+
+    /// Rebuild the waiter after a panic (see `Supervisor::spawn`), dropping the headers waiting for their
+    /// dependencies along with our sync requests: the headers are delivered again by their authors or
+    /// through the sync of their children.
+    fn rebuild(self) -> Self {
+        Self {
+            sync_responses: FuturesUnordered::new(),
+            parent_requests: HashMap::new(),
+            batch_requests: HashMap::new(),
+            range_request: None,
+            pending: HashMap::new(),
+            waiting: FuturesUnordered::new(),
+            backlog: VecDeque::new(),
+            ..self
+        }
     }
 
     /// Helper function. It waits for particular data to become available in the storage
@@ -104,6 +188,7 @@ impl HeaderWaiter {
         mut missing: Vec<(Vec<u8>, Store)>,
         deliver: Header,
         mut handler: Receiver<()>,
+        _permit: Option<OwnedSemaphorePermit>,
     ) -> DagResult<Option<Header>> {
         let waiting: Vec<_> = missing
             .iter_mut()
@@ -117,119 +202,305 @@ impl HeaderWaiter {
         }
     }
 
+    /// Whether the command makes us wait for dependencies (and thus needs a sync permit).
+    fn needs_permit(message: &WaiterMessage) -> bool {
+        !matches!(message, WaiterMessage::BackfillBatches(..))
+    }
+
+    /// Send a sync request to `sync_retry_nodes` other primaries, favoring those with a large stake that
+    /// responded quickly to our previous requests.
+    async fn request_from_peers(&mut self, message: &PrimaryMessage) {
+        let bytes = Bytes::from(bincode::serialize(message).expect("Failed to serialize sync request"));
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        let others = self
+            .committee
+            .others_primaries(&self.name)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let peers = self.peer_scores.pick(others, self.sync_retry_nodes, &mut self.rng);
+        for peer in peers {
+            let address = match self.committee.primary(&peer) {
+                Ok(x) => x.primary_to_primary,
+                Err(e) => {
+                    warn!("Cannot send sync request to {}: {}", peer, e);
+                    continue;
+                }
+            };
+            let handler = self.sync_network.send(address, bytes.clone()).await;
+            let delay = Duration::from_millis(self.sync_retry_delay);
+            let start = Instant::now();
+            self.sync_responses.push(Box::pin(async move {
+                match timeout(delay, handler).await {
+                    Ok(Ok(_)) => (peer, Some(start.elapsed())),
+                    _ => (peer, None),
+                }
+            }));
+        }
+    }
+
+    /// Process a command of the `Synchronizer`. The permit (if any) is held until the dependencies of the
+    /// header arrive or the sync is canceled.
+    async fn handle(&mut self, message: WaiterMessage, permit: Option<OwnedSemaphorePermit>) {
+        match message {
+            WaiterMessage::SyncBatches(missing, header) => {
+                debug!("Synching the payload of {}", header);
+                let header_id = header.id.clone();
+                let round = header.round;
+                let author = header.author;
+
+                // Ensure we sync only once per header.
+                if self.pending.contains_key(&header_id) {
+                    return;
+                }
+
+                // Add the header to the waiter pool. The waiter will return it to when all
+                // its parents are in the store.
+                let wait_for = missing
+                    .iter()
+                    .map(|(digest, worker_id)| {
+                        let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
+                        (key.to_vec(), self.store.clone())
+                    })
+                    .collect();
+                let (tx_cancel, rx_cancel) = channel(1);
+                self.pending.insert(header_id, (round, tx_cancel));
+                let fut = Self::waiter(wait_for, header, rx_cancel, permit);
+                self.waiting.push(Box::pin(fut));
+
+                // Ensure we didn't already send a sync request for these parents.
+                let mut requires_sync = HashMap::new();
+                for (digest, worker_id) in missing.into_iter() {
+                    self.batch_requests.entry(digest.clone()).or_insert_with(|| {
+                        requires_sync.entry(worker_id).or_insert_with(Vec::new).push(digest);
+                        round
+                    });
+                }
+                for (worker_id, digests) in requires_sync {
+                    let address = self.committee
+                        .worker(&author, &worker_id)
+                        .expect("Author of valid header is not in the committee")
+                        .primary_to_worker;
+                    let message = PrimaryWorkerMessage::Synchronize(digests, author);
+                    let bytes = bincode::serialize(&message)
+                        .expect("Failed to serialize batch sync request");
+                    self.network.send(address, Bytes::from(bytes)).await;
+                }
+            }
+
+            WaiterMessage::BackfillBatches(missing, author, round) => {
+                // Ensure we didn't already send a sync request for these batches.
+                let mut requires_sync = HashMap::new();
+                for (digest, worker_id) in missing.into_iter() {
+                    if !self.batch_requests.contains_key(&digest) {
+                        requires_sync
+                            .entry(worker_id)
+                            .or_insert_with(Vec::new)
+                            .push(digest);
+                    }
+                }
+                for (worker_id, digests) in requires_sync {
+                    debug!("Backfilling {} batches of {}", digests.len(), author);
+                    let address = match self.committee.worker(&self.name, &worker_id) {
+                        Ok(x) => x.primary_to_worker,
+                        Err(e) => {
+                            warn!("Cannot backfill the batches of {}: {}", author, e);
+                            continue;
+                        }
+                    };
+                    let message = PrimaryWorkerMessage::Synchronize(digests.clone(), author);
+                    let bytes = bincode::serialize(&message)
+                        .expect("Failed to serialize batch sync request");
+                    self.network.send(address, Bytes::from(bytes)).await;
+
+                    // Only remember the requests we actually sent (the others may be retried).
+                    self.batch_requests
+                        .extend(digests.into_iter().map(|x| (x, round)));
+                }
+            }
+
+            WaiterMessage::SyncParents(missing, header) => {
+                debug!("Synching the parents of {}", header);
+                let header_id = header.id.clone();
+                let round = header.round;
+                let author = header.author;
+
+                // Ensure we sync only once per header.
+                if self.pending.contains_key(&header_id) {
+                    return;
+                }
+
+                // Add the header to the waiter pool. The waiter will return it to us
+                // when all its parents are in the store.
+                let wait_for = missing
+                    .iter()
+                    .cloned()
+                    .map(|x| (x.to_vec(), self.store.clone()))
+                    .collect();
+                let (tx_cancel, rx_cancel) = channel(1);
+                self.pending.insert(header_id, (round, tx_cancel));
+                let fut = Self::waiter(wait_for, header, rx_cancel, permit);
+                self.waiting.push(Box::pin(fut));
+
+                // Ensure we didn't already sent a sync request for these parents.
+                // Optimistically send the sync request to the node that created the certificate.
+                // If this fails (after a timeout), we broadcast the sync request.
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Failed to measure time")
+                    .as_millis();
+                let mut requires_sync = Vec::new();
+                for missing in missing {
+                    self.parent_requests.entry(missing.clone()).or_insert_with(|| {
+                        requires_sync.push(missing);
+                        (round, now)
+                    });
+                }
+                if !requires_sync.is_empty() {
+                    let address = self.committee
+                        .primary(&author)
+                        .expect("Author of valid header not in the committee")
+                        .primary_to_primary;
+                    let message = PrimaryMessage::CertificatesRequest(requires_sync, self.name);
+                    let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
+                    if let Some(tee) = &self.message_tee {
+                        tee.capture(Direction::Outbound, &bytes);
+                    }
+                    self.primary_network.send(address, Bytes::from(bytes)).await;
+                }
+            }
+
+            WaiterMessage::RangeSync(missing, header, from) => {
+                let to = header.round - 1;
+                debug!("Synching rounds {} to {} for {}", from, to, header);
+                let header_id = header.id.clone();
+                let round = header.round;
+
+                // Ensure we sync only once per header.
+                if self.pending.contains_key(&header_id) {
+                    return;
+                }
+
+                // Add the header to the waiter pool. The waiter will return it to us
+                // when all its parents are in the store.
+                let wait_for = missing
+                    .iter()
+                    .map(|x| (x.to_vec(), self.store.clone()))
+                    .collect();
+                let (tx_cancel, rx_cancel) = channel(1);
+                self.pending.insert(header_id, (round, tx_cancel));
+                let fut = Self::waiter(wait_for, header, rx_cancel, permit);
+                self.waiting.push(Box::pin(fut));
+
+                // Ensure we didn't recently send a range request covering these rounds. Many
+                // headers are typically ahead of us at once.
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Failed to measure time")
+                    .as_millis();
+                let covered = matches!(
+                    self.range_request,
+                    Some((last, timestamp)) if last >= to && timestamp + (self.sync_retry_delay as u128) >= now
+                );
+                if !covered {
+                    self.range_request = Some((to, now));
+                    let message = PrimaryMessage::CertificatesRangeRequest(from, to, self.name);
+                    self.request_from_peers(&message).await;
+                }
+            }
+        }
+    }
+
+    /// The header waiting for its dependencies because of the command (if any).
+    fn header(message: &WaiterMessage) -> Option<&Header> {
+        match message {
+            WaiterMessage::SyncBatches(_, header)
+            | WaiterMessage::SyncParents(_, header)
+            | WaiterMessage::RangeSync(_, header, _) => Some(header),
+            WaiterMessage::BackfillBatches(..) => None,
+        }
+    }
+
+    /// Drop the lowest-round headers while more than `max_pending` headers wait for their dependencies or
+    /// for a permit. Those waiting for their dependencies release their permit.
+    async fn evict(&mut self) {
+        if self.max_pending == 0 {
+            return;
+        }
+        while self.pending.len() + self.backlog.len() > self.max_pending {
+            let lowest_pending = self
+                .pending
+                .iter()
+                .map(|(digest, (round, _))| (*round, digest.clone()))
+                .min();
+            let lowest_backlog = self
+                .backlog
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| Self::header(x).map(|header| (header.round, i)))
+                .min();
+
+            // Among the headers of the same round, drop first those that did not get a permit yet.
+            let (round, digest) = match (lowest_pending, lowest_backlog) {
+                (Some((round, digest)), backlog) if backlog.is_none_or(|(r, _)| round < r) => {
+                    if let Some((_, handler)) = self.pending.remove(&digest) {
+                        let _ = handler.send(()).await;
+                    }
+                    (round, digest)
+                }
+                (_, Some((round, i))) => {
+                    let message = self.backlog.remove(i).unwrap();
+                    (round, Self::header(&message).unwrap().id.clone())
+                }
+                // Nothing left to drop.
+                _ => break,
+            };
+            self.evicted += 1;
+            warn!(
+                "Too many pending headers: dropped {} (round {}, {} dropped so far)",
+                digest, round, self.evicted
+            );
+        }
+    }
+
     /// Main loop listening to the `Synchronizer` messages.
     async fn run(&mut self) {
-        let mut waiting = FuturesUnordered::new();
-
         let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
         tokio::pin!(timer);
 
         loop {
             tokio::select! {
                 Some(message) = self.rx_synchronizer.recv() => {
-                    match message {
-                        WaiterMessage::SyncBatches(missing, header) => {
-                            debug!("Synching the payload of {}", header);
-                            let header_id = header.id.clone();
-                            let round = header.round;
-                            let author = header.author;
-
-                            // Ensure we sync only once per header.
-                            if self.pending.contains_key(&header_id) {
-                                continue;
-                            }
-
-                            // Add the header to the waiter pool. The waiter will return it to when all
-                            // its parents are in the store.
-                            let wait_for = missing
-                                .iter()
-                                .map(|(digest, worker_id)| {
-                                    let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-                                    (key.to_vec(), self.store.clone())
-                                })
-                                .collect();
-                            let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
-                            let fut = Self::waiter(wait_for, header, rx_cancel);
-                            waiting.push(fut);
-
-                            // Ensure we didn't already send a sync request for these parents.
-                            let mut requires_sync = HashMap::new();
-                            for (digest, worker_id) in missing.into_iter() {
-                                self.batch_requests.entry(digest.clone()).or_insert_with(|| {
-                                    requires_sync.entry(worker_id).or_insert_with(Vec::new).push(digest);
-                                    round
-                                });
-                            }
-                            for (worker_id, digests) in requires_sync {
-                                let address = self.committee
-                                    .worker(&author, &worker_id)
-                                    .expect("Author of valid header is not in the committee")
-                                    .primary_to_worker;
-                                let message = PrimaryWorkerMessage::Synchronize(digests, author);
-                                let bytes = bincode::serialize(&message)
-                                    .expect("Failed to serialize batch sync request");
-                                self.network.send(address, Bytes::from(bytes)).await;
-                            }
-                        }
-
-                        WaiterMessage::SyncParents(missing, header) => {
-                            debug!("Synching the parents of {}", header);
-                            let header_id = header.id.clone();
-                            let round = header.round;
-                            let author = header.author;
-
-                            // Ensure we sync only once per header.
-                            if self.pending.contains_key(&header_id) {
-                                continue;
-                            }
-
-                            // Add the header to the waiter pool. The waiter will return it to us
-                            // when all its parents are in the store.
-                            let wait_for = missing
-                                .iter()
-                                .cloned()
-                                .map(|x| (x.to_vec(), self.store.clone()))
-                                .collect();
-                            let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
-                            let fut = Self::waiter(wait_for, header, rx_cancel);
-                            waiting.push(fut);
-
-                            // Ensure we didn't already sent a sync request for these parents.
-                            // Optimistically send the sync request to the node that created the certificate.
-                            // If this fails (after a timeout), we broadcast the sync request.
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .expect("Failed to measure time")
-                                .as_millis();
-                            let mut requires_sync = Vec::new();
-                            for missing in missing {
-                                self.parent_requests.entry(missing.clone()).or_insert_with(|| {
-                                    requires_sync.push(missing);
-                                    (round, now)
-                                });
-                            }
-                            if !requires_sync.is_empty() {
-                                let address = self.committee
-                                    .primary(&author)
-                                    .expect("Author of valid header not in the committee")
-                                    .primary_to_primary;
-                                let message = PrimaryMessage::CertificatesRequest(requires_sync, self.name);
-                                let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                                self.network.send(address, Bytes::from(bytes)).await;
+                    // Syncs that cannot get a permit wait (in order) for other syncs to complete.
+                    if !Self::needs_permit(&message) {
+                        self.handle(message, None).await;
+                    } else if !self.backlog.is_empty() {
+                        self.backlog.push_back(message);
+                    } else {
+                        match self.sync_permits.clone().try_acquire_owned() {
+                            Ok(permit) => self.handle(message, Some(permit)).await,
+                            Err(_) => {
+                                debug!("Too many pending sync requests, delaying sync");
+                                self.backlog.push_back(message);
                             }
                         }
                     }
                 },
 
-                Some(result) = waiting.next() => match result {
+                Ok(permit) = self.sync_permits.clone().acquire_owned(), if !self.backlog.is_empty() => {
+                    let message = self.backlog.pop_front().unwrap();
+                    self.handle(message, Some(permit)).await;
+                },
+
+                Some(result) = self.waiting.next() => match result {
                     Ok(Some(header)) => {
                         let _ = self.pending.remove(&header.id);
                         for x in header.payload.keys() {
                             let _ = self.batch_requests.remove(x);
                         }
-                        for x in &header.parents {
+                        for x in header.parents.iter().chain(&header.weak_links) {
                             let _ = self.parent_requests.remove(x);
                         }
                         self.tx_core.send(header).await.expect("Failed to send header");
@@ -239,14 +510,22 @@ impl HeaderWaiter {
                     },
                     Err(e) => {
                         error!("{}", e);
-                        panic!("Storage failure: killing node.");
+                        fatal("Storage failure: killing node.");
+                    }
+                },
+
+                Some((peer, latency)) = self.sync_responses.next() => match latency {
+                    Some(latency) => self.peer_scores.record_success(&peer, latency),
+                    None => {
+                        debug!("{} did not acknowledge our sync request in time", peer);
+                        self.peer_scores.record_failure(&peer);
                     }
                 },
 
                 () = &mut timer => {
                     // We optimistically sent sync requests to a single node. If this timer triggers,
                     // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to all nodes.
+                    // send the request to other nodes (favoring the most responsive ones).
                     let now = SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .expect("Failed to measure time")
@@ -260,20 +539,19 @@ impl HeaderWaiter {
                         }
                     }
 
-                    let addresses = self.committee
-                        .others_primaries(&self.name)
-                        .iter()
-                        .map(|(_, x)| x.primary_to_primary)
-                        .collect();
-                    let message = PrimaryMessage::CertificatesRequest(retry, self.name);
-                    let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                    self.network.lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes).await;
+                    if !retry.is_empty() {
+                        let message = PrimaryMessage::CertificatesRequest(retry, self.name);
+                        self.request_from_peers(&message).await;
+                    }
 
                     // Reschedule the timer.
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
                 }
             }
 
+            // Bound the number of headers we buffer.
+            self.evict().await;
+
             // Cleanup internal state.
             let round = self.consensus_round.load(Ordering::Relaxed);
             if round > self.gc_depth {
@@ -285,6 +563,8 @@ impl HeaderWaiter {
                     }
                 }
                 self.pending.retain(|_, (r, _)| r > &mut gc_round);
+                self.backlog
+                    .retain(|x| Self::header(x).is_none_or(|header| header.round > gc_round));
                 self.batch_requests.retain(|_, r| r > &mut gc_round);
                 self.parent_requests.retain(|_, (r, _)| r > &mut gc_round);
             }
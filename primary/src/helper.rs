@@ -1,71 +1,253 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::primary::PrimaryMessage;
+use crate::message_tee::{Direction, MessageTee};
+use crate::error::{DagError, DagResult};
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use crate::round_index::RoundIndex;
+use crate::supervisor::Supervisor;
 use bytes::Bytes;
 use config::Committee;
 use crypto::{Digest, PublicKey};
 use log::{error, warn};
-use network::SimpleSender;
+use network::{Bandwidth, SimpleSender};
+use std::convert::TryInto as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
+#[cfg(test)]
+#[path = "tests/helper_tests.rs"]
+pub mod helper_tests;
+
+/// The maximum number of rounds we serve in reply to a single range request.
+pub const MAX_RANGE_REQUEST: Round = 100;
+
+/// The maximum size of the certificates carried by a compressed sync response, once decompressed (in bytes).
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Serialize and compress (with zstd) the certificates of a bulk sync response.
+pub fn compress_certificates(certificates: &[Certificate]) -> Vec<u8> {
+    let serialized = bincode::serialize(certificates).expect("Failed to serialize our own certificates");
+    zstd::bulk::compress(&serialized, zstd::DEFAULT_COMPRESSION_LEVEL)
+        .expect("Failed to compress our own certificates")
+}
+
+/// Decompress and deserialize the certificates of a bulk sync response, refusing responses that would
+/// decompress to more than `MAX_DECOMPRESSED_SIZE` bytes.
+pub fn decompress_certificates(compressed: &[u8]) -> DagResult<Vec<Certificate>> {
+    let serialized = zstd::bulk::decompress(compressed, MAX_DECOMPRESSED_SIZE)
+        .map_err(|e: io::Error| DagError::DecompressionError(e.to_string()))?;
+    Ok(bincode::deserialize(&serialized)?)
+}
+
 /// A task dedicated to help other authorities by replying to their certificates requests.
 pub struct Helper {
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
     store: Store,
+    /// The digests of the certificates we stored by round (for the rounds it covers).
+    round_index: Arc<RoundIndex>,
     /// Input channel to receive certificates requests.
     rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+    /// Input channel to receive requests for all the certificates of a range of rounds.
+    rx_range_requests: Receiver<(Round, Round, PublicKey)>,
+    /// Input channel to receive requests for the certificates of specific authors at specific rounds.
+    rx_missing_requests: Receiver<(Vec<(PublicKey, Round)>, PublicKey)>,
     /// A network sender to reply to the sync requests.
     network: SimpleSender,
+    /// Captures the messages we send to the other primaries (if enabled).
+    message_tee: Option<MessageTee>,
+    /// Whether we compress our replies to range and missing certificates requests (only if we handshake
+    /// with the requestors).
+    compress_responses: bool,
 }
 
 impl Helper {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
+        name: PublicKey,
         committee: Committee,
         store: Store,
+        round_index: Arc<RoundIndex>,
+        protocol_version: Option<u32>,
+        message_tee: Option<MessageTee>,
+        bandwidth: Bandwidth,
+        compress_responses: bool,
         rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        rx_range_requests: Receiver<(Round, Round, PublicKey)>,
+        rx_missing_requests: Receiver<(Vec<(PublicKey, Round)>, PublicKey)>,
+        supervisor: &Supervisor,
     ) {
-        tokio::spawn(async move {
+        let identity = committee
+            .primary_or_observer(&name)
+            .ok()
+            .map(|x| x.primary_to_primary);
+        supervisor.spawn(
+            "helper",
             Self {
                 committee,
                 store,
+                round_index,
                 rx_primaries,
-                network: SimpleSender::new(),
+                rx_range_requests,
+                rx_missing_requests,
+                network: SimpleSender::with_handshake(protocol_version)
+                    .with_identity(identity)
+                    .with_bandwidth(bandwidth),
+                message_tee,
+                // Only peers that agreed on our message format during the handshake are sure to understand
+                // compressed replies.
+                compress_responses: compress_responses && protocol_version.is_some(),
+            },
+            // The helper keeps no state between requests.
+            std::convert::identity,
+            |x| Box::pin(x.run()),
+        );
+    }
+
+    /// Get the address of the requestor.
+    fn address(&self, origin: &PublicKey) -> Option<SocketAddr> {
+        match self.committee.primary_or_observer(origin) {
+            Ok(x) => Some(x.primary_to_primary),
+            Err(e) => {
+                warn!("Unexpected certificate request: {}", e);
+                None
             }
-            .run()
-            .await;
-        });
+        }
     }
 
-    async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_primaries.recv().await {
-            // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
-
-            // get the requestors address.
-            let address = match self.committee.primary(&origin) {
-                Ok(x) => x.primary_to_primary,
-                Err(e) => {
-                    warn!("Unexpected certificate request: {}", e);
-                    continue;
+    /// Send a message to the requestor.
+    async fn send(&mut self, address: SocketAddr, message: &PrimaryMessage) {
+        let bytes = bincode::serialize(message).expect("Failed to serialize our own message");
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Outbound, &bytes);
+        }
+        self.network.send(address, Bytes::from(bytes)).await;
+    }
+
+    /// Returns the certificate stored under the input digest (if any).
+    async fn certificate(&mut self, digest: &Digest) -> Option<Certificate> {
+        match self.store.read(digest.to_vec()).await {
+            // TODO: Remove this deserialization-serialization in the critical path.
+            Ok(Some(data)) => {
+                Some(bincode::deserialize(&data).expect("Failed to deserialize our own certificate"))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the certificate of `author` at `round` (if we have it).
+    async fn indexed_certificate(&mut self, author: &PublicKey, round: Round) -> Option<Certificate> {
+        match self.store.read(Certificate::index_key(round, author)).await {
+            Ok(Some(data)) => {
+                let digest = Digest(data[..].try_into().expect("Corrupted round index"));
+                self.certificate(&digest).await
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Send the certificate stored under the input digest (if any).
+    async fn reply(&mut self, address: SocketAddr, digest: &Digest) {
+        if let Some(certificate) = self.certificate(digest).await {
+            self.send(address, &PrimaryMessage::Certificate(certificate)).await;
+        }
+    }
+
+    /// Send the certificates replying to a bulk request: in a single compressed message (if enabled), or
+    /// one by one.
+    async fn reply_bulk(&mut self, address: SocketAddr, certificates: Vec<Certificate>) {
+        if self.compress_responses {
+            if !certificates.is_empty() {
+                let compressed = compress_certificates(&certificates);
+                self.send(address, &PrimaryMessage::CompressedCertificates(compressed))
+                    .await;
+            }
+            return;
+        }
+        for certificate in certificates {
+            self.send(address, &PrimaryMessage::Certificate(certificate)).await;
+        }
+    }
+
+    /// Send all the certificates we have for the rounds `from` to `to` (included), in round order.
+    async fn reply_range(&mut self, address: SocketAddr, from: Round, to: Round) {
+        // Genesis certificates are never stored, and we cap the work done for a single request.
+        let from = from.max(1);
+        let to = to.min(from.saturating_add(MAX_RANGE_REQUEST - 1));
+
+        let authorities: Vec<_> = self.committee.authorities.keys().cloned().collect();
+        let mut certificates = Vec::new();
+        for round in from..=to {
+            match self.round_index.digests(round) {
+                Some(digests) => {
+                    for digest in &digests {
+                        certificates.extend(self.certificate(digest).await);
+                    }
                 }
-            };
-
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
-                        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
-                            .expect("Failed to serialize our own certificate");
-                        self.network.send(address, Bytes::from(bytes)).await;
+                None => {
+                    for author in &authorities {
+                        certificates.extend(self.indexed_certificate(author, round).await);
                     }
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
                 }
             }
         }
+        self.reply_bulk(address, certificates).await;
+    }
+
+    /// Send the certificates we have among the requested (author, round) pairs. We serve at most
+    /// as many certificates as for a range request.
+    async fn reply_missing(&mut self, address: SocketAddr, missing: Vec<(PublicKey, Round)>) {
+        let max = MAX_RANGE_REQUEST as usize * self.committee.size();
+        let mut certificates = Vec::new();
+        for (author, round) in missing.into_iter().take(max) {
+            certificates.extend(self.indexed_certificate(&author, round).await);
+        }
+        self.reply_bulk(address, certificates).await;
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                Some((digests, origin)) = self.rx_primaries.recv() => {
+                    // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+                    let address = match self.address(&origin) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+
+                    // Reply to the request (the best we can).
+                    for digest in digests {
+                        self.reply(address, &digest).await;
+                    }
+                },
+                Some((from, to, origin)) = self.rx_range_requests.recv() => {
+                    let address = match self.address(&origin) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    self.reply_range(address, from, to).await;
+                },
+                Some((missing, origin)) = self.rx_missing_requests.recv() => {
+                    let address = match self.address(&origin) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    self.reply_missing(address, missing).await;
+                },
+                else => break,
+            }
+        }
     }
 }
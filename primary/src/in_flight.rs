@@ -0,0 +1,54 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+#[cfg(test)]
+#[path = "tests/in_flight_tests.rs"]
+pub mod in_flight_tests;
+
+/// Bounds the number of sample transactions in flight (sent but not yet delivered), to run the client
+/// in closed loop: a transaction can only be sent once a slot is free, and its delivery frees the slot.
+/// Clones share the same slots.
+#[derive(Clone)]
+pub struct InFlight {
+    /// The free slots.
+    slots: Arc<Semaphore>,
+    /// The ids of the transactions in flight.
+    pending: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl InFlight {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(limit)),
+            pending: Arc::new(Mutex::new(HashSet::with_capacity(limit))),
+        }
+    }
+
+    /// Wait for a free slot and record the submission of a sample transaction.
+    pub async fn sent(&self, id: u64) {
+        self.slots
+            .acquire()
+            .await
+            .expect("In-flight slots closed")
+            .forget();
+        if !self.pending.lock().expect("In-flight ids poisoned").insert(id) {
+            // The transaction was already in flight.
+            self.slots.add_permits(1);
+        }
+    }
+
+    /// Record the delivery of a sample transaction, freeing its slot. Unknown (or already delivered)
+    /// transactions are ignored.
+    pub fn delivered(&self, id: u64) {
+        if self.pending.lock().expect("In-flight ids poisoned").remove(&id) {
+            self.slots.add_permits(1);
+        }
+    }
+
+    /// Returns the number of transactions in flight.
+    pub fn outstanding(&self) -> usize {
+        self.pending.lock().expect("In-flight ids poisoned").len()
+    }
+}
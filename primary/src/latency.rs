@@ -0,0 +1,99 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/latency_tests.rs"]
+pub mod latency_tests;
+
+/// The latency percentiles of the delivered sample transactions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySummary {
+    /// The number of latency samples.
+    pub samples: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p50 {} ms, p95 {} ms, p99 {} ms ({} samples)",
+            self.p50.as_millis(),
+            self.p95.as_millis(),
+            self.p99.as_millis(),
+            self.samples
+        )
+    }
+}
+
+/// Measures the submission-to-delivery latency of sample transactions by pairing the ids of the sent
+/// transactions with those of the delivered ones. Memory is bounded: the sampler tracks at most `capacity`
+/// undelivered transactions (forgetting the oldest ones) and keeps the `capacity` most recent latencies.
+pub struct LatencySampler {
+    capacity: usize,
+    /// The time at which each undelivered transaction was sent.
+    pending: HashMap<u64, Instant>,
+    /// The ids of the sent transactions, oldest first (some may already be delivered).
+    pending_order: VecDeque<u64>,
+    /// The most recent latencies, oldest first.
+    latencies: VecDeque<Duration>,
+}
+
+impl LatencySampler {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pending: HashMap::with_capacity(capacity),
+            pending_order: VecDeque::with_capacity(capacity),
+            latencies: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Record the submission of a sample transaction.
+    pub fn sent(&mut self, id: u64, at: Instant) {
+        if self.capacity == 0 || self.pending.insert(id, at).is_some() {
+            return;
+        }
+        self.pending_order.push_back(id);
+        while self.pending_order.len() > self.capacity {
+            if let Some(oldest) = self.pending_order.pop_front() {
+                self.pending.remove(&oldest);
+            }
+        }
+    }
+
+    /// Record the delivery of a sample transaction. Unknown (or already delivered) transactions are ignored.
+    pub fn delivered(&mut self, id: u64, at: Instant) {
+        let sent = match self.pending.remove(&id) {
+            Some(x) => x,
+            None => return,
+        };
+        if self.latencies.len() == self.capacity {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(at.saturating_duration_since(sent));
+    }
+
+    /// The latency percentiles of the recent deliveries (nearest-rank method), if any.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.latencies.iter().cloned().collect();
+        sorted.sort();
+        let percentile = |p: usize| {
+            let rank = (p * sorted.len()).div_ceil(100);
+            sorted[rank.max(1) - 1]
+        };
+        Some(LatencySummary {
+            samples: sorted.len(),
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        })
+    }
+}
@@ -1,21 +1,58 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 #[macro_use]
 mod error;
+#[macro_use]
+mod round_log;
 mod aggregators;
+mod audit_log;
+mod audit_replay;
+#[cfg(any(test, feature = "testing"))]
+mod builders;
+mod catch_up;
+mod certificate_monitor;
+mod certificate_rebroadcaster;
 mod certificate_waiter;
+mod committed_output;
 mod core;
+mod dag_stats;
+mod delivery;
+mod encoding;
+mod fork_check;
 mod garbage_collector;
 mod header_waiter;
 mod helper;
+mod in_flight;
+mod latency;
+mod message_tee;
 mod messages;
 mod payload_receiver;
+mod peer_scores;
 mod primary;
 mod proposer;
+mod round_index;
+mod store_migrations;
+mod supervisor;
 mod synchronizer;
+mod verification_cache;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::messages::{Certificate, Header};
-pub use crate::primary::{Primary, PrimaryWorkerMessage, PrimaryClientMessage, PrimaryClientReceiverHandler, PrimaryClientReceiverHandlerNoPrint, Round, WorkerPrimaryMessage};
+pub use crate::audit_log::{AuditEvent, AuditLog};
+pub use crate::audit_replay::{replay_audit_log, InvalidDecision, ReplayError};
+#[cfg(any(test, feature = "testing"))]
+pub use crate::builders::{CertificateBuilder, HeaderBuilder};
+pub use crate::committed_output::{Checkpoint, CommittedTransactions, Transaction, CHECKPOINT_KEY};
+pub use crate::dag_stats::RoundStats;
+pub use crate::delivery::{DeliveryDecoder, DigestDecoder};
+pub use crate::encoding::TxEncoding;
+pub use crate::error::DagError;
+pub use crate::fork_check::ForkCheck;
+pub use crate::in_flight::InFlight;
+pub use crate::latency::{LatencySampler, LatencySummary};
+pub use crate::message_tee::{Direction as CaptureDirection, MessageTee};
+pub use crate::messages::{resolve_header, Certificate, Header};
+pub use crate::primary::{AuthorityBandwidth, PendingCertificate, Primary, PrimaryHandle, PrimaryHealth, PrimaryWorkerMessage, PrimaryClientMessage, PrimaryClientReceiverHandler, PrimaryClientReceiverHandlerNoPrint, Round, UnderReplicatedBatch, WorkerPrimaryMessage};
+pub use crate::round_log::{round_log_level, round_log_range, set_round_log_range};
+pub use crate::store_migrations::store_migrations;
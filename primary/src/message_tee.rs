@@ -0,0 +1,93 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::PrimaryMessage;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/message_tee_tests.rs"]
+pub mod message_tee_tests;
+
+/// Whether we received or sent a captured message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Inbound => 0,
+            Self::Outbound => 1,
+        }
+    }
+}
+
+/// Copies the serialized `PrimaryMessage`s we exchange with the other primaries to a capture file, in the
+/// order they are received or sent (messages broadcast to several authorities are captured once). The
+/// capture is a stream of length-delimited frames (a 4-byte big-endian length followed by the frame), each
+/// holding the direction of the message (1 byte) followed by the message, so it can be parsed with a
+/// `LengthDelimitedCodec` (see also `MessageTee::read`).
+#[derive(Clone)]
+pub struct MessageTee {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl MessageTee {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    fn try_capture(&self, direction: Direction, serialized: &[u8]) -> io::Result<()> {
+        let length: u32 = (serialized.len() + 1)
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too large"))?;
+        let mut writer = self.writer.lock().expect("Failed to lock the capture");
+        writer.write_all(&length.to_be_bytes())?;
+        writer.write_all(&[direction.tag()])?;
+        writer.write_all(serialized)?;
+        writer.flush()
+    }
+
+    /// Capture a serialized message. Failing to write the capture does not affect the primary.
+    pub fn capture(&self, direction: Direction, serialized: &[u8]) {
+        if let Err(e) = self.try_capture(direction, serialized) {
+            warn!("Failed to write to the message capture: {}", e);
+        }
+    }
+
+    /// Read the messages of a capture file, in the order they were captured.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<(Direction, PrimaryMessage)>> {
+        let data = fs::read(path)?;
+        let invalid = |e: &str| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        let mut messages = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            if rest.len() < 5 {
+                return Err(invalid("Truncated frame"));
+            }
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            if length == 0 || rest.len() < 4 + length {
+                return Err(invalid("Truncated frame"));
+            }
+            let direction = match rest[4] {
+                0 => Direction::Inbound,
+                1 => Direction::Outbound,
+                _ => return Err(invalid("Unknown message direction")),
+            };
+            let message = bincode::deserialize(&rest[5..4 + length])
+                .map_err(|e| invalid(&e.to_string()))?;
+            messages.push((direction, message));
+            rest = &rest[4 + length..];
+        }
+        Ok(messages)
+    }
+}
@@ -9,13 +9,26 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::TryInto;
 use std::fmt;
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
 
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Header {
-    pub author: PublicKey,
-    pub round: Round,
-    pub payload: BTreeMap<Digest, WorkerId>,
-    pub parents: BTreeSet<Digest>,
+    pub(crate) author: PublicKey,
+    pub(crate) round: Round,
+    /// The batches' digests (and the workers holding them). They are kept sorted by digest so that
+    /// the same payload always serializes (and hashes) the same way, whatever the order in which the
+    /// proposer received the digests.
+    pub(crate) payload: BTreeMap<Digest, WorkerId>,
+    /// The digests of the parent certificates. They are kept sorted by digest as well, so the same set of
+    /// parents always produces the same header digest, whatever the order in which they were gathered.
+    pub(crate) parents: BTreeSet<Digest>,
+    /// References to certificates of earlier rounds that were not included as parents (they do not
+    /// count towards the parents' threshold).
+    pub weak_links: BTreeSet<Digest>,
     pub id: Digest,
     pub signature: Signature,
 }
@@ -26,6 +39,7 @@ impl Header {
         round: Round,
         payload: BTreeMap<Digest, WorkerId>,
         parents: BTreeSet<Digest>,
+        weak_links: BTreeSet<Digest>,
         signature_service: &mut SignatureService,
     ) -> Self {
         let header = Self {
@@ -33,6 +47,7 @@ impl Header {
             round,
             payload,
             parents,
+            weak_links,
             id: Digest::default(),
             signature: Signature::default(),
         };
@@ -45,7 +60,35 @@ impl Header {
         }
     }
 
+    pub fn author(&self) -> PublicKey {
+        self.author
+    }
+
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    /// The digests of the batches referenced by the header, sorted.
+    pub fn payload_digests(&self) -> impl Iterator<Item = &Digest> {
+        self.payload.keys()
+    }
+
+    /// The digests of the parent certificates of the header.
+    pub fn parents(&self) -> &BTreeSet<Digest> {
+        &self.parents
+    }
+
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        self.verify_structure(committee)?;
+
+        // Check the signature.
+        self.signature
+            .verify(&self.id, &self.author)
+            .map_err(DagError::from)
+    }
+
+    /// Run all the checks of `verify` but the signature verification. This is only safe among trusted nodes.
+    pub fn verify_structure(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the header id is well formed.
         ensure!(self.digest() == self.id, DagError::InvalidHeaderId);
 
@@ -59,11 +102,7 @@ impl Header {
                 .worker(&self.author, worker_id)
                 .map_err(|_| DagError::MalformedHeader(self.id.clone()))?;
         }
-
-        // Check the signature.
-        self.signature
-            .verify(&self.id, &self.author)
-            .map_err(DagError::from)
+        Ok(())
     }
 }
 
@@ -79,6 +118,13 @@ impl Hash for Header {
         for x in &self.parents {
             hasher.update(x);
         }
+        if !self.weak_links.is_empty() {
+            // Prefix the weak links by their number so they cannot be confused with parents.
+            hasher.update((self.weak_links.len() as u64).to_le_bytes());
+            for x in &self.weak_links {
+                hasher.update(x);
+            }
+        }
         Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
     }
 }
@@ -129,17 +175,24 @@ impl Vote {
     }
 
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
-        // Ensure the authority has voting rights.
-        ensure!(
-            committee.stake(&self.author) > 0,
-            DagError::UnknownAuthority(self.author)
-        );
+        self.verify_structure(committee)?;
 
         // Check the signature.
         self.signature
             .verify(&self.digest(), &self.author)
             .map_err(DagError::from)
     }
+
+    /// Run all the checks of `verify` but the signature verification. This is only safe among trusted nodes.
+    pub fn verify_structure(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the author is a member of the committee. The votes of the members without stake are
+        // well formed, they just do not count towards the quorum (see `VotesAggregator`).
+        ensure!(
+            committee.authorities.contains_key(&self.author),
+            DagError::UnknownAuthority(self.author)
+        );
+        Ok(())
+    }
 }
 
 impl Hash for Vote {
@@ -172,6 +225,8 @@ pub struct Certificate {
 }
 
 impl Certificate {
+    /// Returns the genesis certificates (round 0) of the committee, one per authority. They only depend on
+    /// the committee so all nodes agree on them; they are ordered by author.
     pub fn genesis(committee: &Committee) -> Vec<Self> {
         committee
             .authorities
@@ -196,6 +251,28 @@ impl Certificate {
         self.header.verify(committee)?;
 
         // Ensure the certificate has a quorum.
+        self.verify_quorum(committee)?;
+
+        // Check the signatures.
+        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+    }
+
+    /// Run all the checks of `verify` but the signatures verification (of the votes and of the embedded
+    /// header). This is only safe among trusted nodes.
+    pub fn verify_structure(&self, committee: &Committee) -> DagResult<()> {
+        // Genesis certificates are always valid.
+        if Self::genesis(committee).contains(self) {
+            return Ok(());
+        }
+
+        // Check the embedded header.
+        self.header.verify_structure(committee)?;
+
+        // Ensure the certificate has a quorum.
+        self.verify_quorum(committee)
+    }
+
+    fn verify_quorum(&self, committee: &Committee) -> DagResult<()> {
         let mut weight = 0;
         let mut used = HashSet::new();
         for (name, _) in self.votes.iter() {
@@ -209,9 +286,7 @@ impl Certificate {
             weight >= committee.quorum_threshold(),
             DagError::CertificateRequiresQuorum
         );
-
-        // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+        Ok(())
     }
 
     pub fn round(&self) -> Round {
@@ -221,6 +296,27 @@ impl Certificate {
     pub fn origin(&self) -> PublicKey {
         self.header.author
     }
+
+    /// The store key indexing the digest of the certificate of `author` at `round`. It lets us serve
+    /// certificates by round (e.g., to peers catching up) without knowing their digests.
+    pub fn index_key(round: Round, author: &PublicKey) -> Vec<u8> {
+        let mut key = Self::index_prefix(round);
+        key.extend_from_slice(author.as_ref());
+        key
+    }
+
+    /// The store keys bounding the index entries of the rounds `from` (included) to `to` (excluded).
+    pub fn index_range(from: Round, to: Round) -> (Vec<u8>, Vec<u8>) {
+        (Self::index_prefix(from), Self::index_prefix(to))
+    }
+
+    /// The common prefix of the index keys of the certificates of a round (rounds are big-endian, so the
+    /// index entries are sorted by round).
+    fn index_prefix(round: Round) -> Vec<u8> {
+        let mut key = b"round".to_vec();
+        key.extend_from_slice(&round.to_be_bytes());
+        key
+    }
 }
 
 impl Hash for Certificate {
@@ -254,3 +350,12 @@ impl PartialEq for Certificate {
         ret
     }
 }
+
+/// Fetch from the store the header certified by a certificate (the `Core` stores the headers it processes).
+pub async fn resolve_header(certificate: &Certificate, store: &Store) -> DagResult<Header> {
+    let id = &certificate.header.id;
+    match store.clone().read(id.to_vec()).await? {
+        Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+        None => Err(DagError::MissingHeader(id.clone())),
+    }
+}
@@ -1,9 +1,22 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use config::WorkerId;
 use crypto::Digest;
+use log::{debug, error};
+#[cfg(test)]
+use std::cell::Cell;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
+#[cfg(test)]
+#[path = "tests/payload_receiver_tests.rs"]
+pub mod payload_receiver_tests;
+
+#[cfg(test)]
+thread_local! {
+    /// Counts the payload records written by the payload receiver (used by the tests).
+    static PAYLOAD_WRITES: Cell<u64> = const { Cell::new(0) };
+}
+
 /// Receives batches' digests of other authorities. These are only needed to verify incoming
 /// headers (ie. make sure we have their payload).
 pub struct PayloadReceiver {
@@ -11,19 +24,44 @@ pub struct PayloadReceiver {
     store: Store,
     /// Receives batches' digests from the network.
     rx_workers: Receiver<(Digest, WorkerId)>,
+    /// The number of digests reported more than once (e.g., by a worker after a restart).
+    duplicates: u64,
 }
 
 impl PayloadReceiver {
     pub fn spawn(store: Store, rx_workers: Receiver<(Digest, WorkerId)>) {
         tokio::spawn(async move {
-            Self { store, rx_workers }.run().await;
+            Self {
+                store,
+                rx_workers,
+                duplicates: 0,
+            }
+            .run()
+            .await;
         });
     }
 
     async fn run(&mut self) {
         while let Some((digest, worker_id)) = self.rx_workers.recv().await {
             let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
-            self.store.write(key.to_vec(), Vec::default()).await;
+
+            // Workers may report the same batch more than once; we only need to record it once.
+            match self.store.read(key.clone()).await {
+                Ok(Some(_)) => {
+                    self.duplicates += 1;
+                    debug!(
+                        "Skipping duplicate digest {} of worker {} ({} duplicates so far)",
+                        digest, worker_id, self.duplicates
+                    );
+                    continue;
+                }
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+
+            #[cfg(test)]
+            PAYLOAD_WRITES.with(|x| x.set(x.get() + 1));
+            self.store.write(key, Vec::default()).await;
         }
     }
 }
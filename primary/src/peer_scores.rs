@@ -0,0 +1,95 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::Committee;
+use crypto::PublicKey;
+use rand::Rng;
+use std::collections::HashMap;
+use tokio::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/peer_scores_tests.rs"]
+pub mod peer_scores_tests;
+
+/// The weight of the latest observation in the moving averages of the peers' responsiveness.
+const EWMA_WEIGHT: f64 = 0.3;
+
+/// The response latency (in ms) that halves the weight of a peer.
+const LATENCY_SCALE: f64 = 100.0;
+
+/// The lowest success rate of a peer; it keeps flaky peers selectable (with a low probability) so we
+/// notice when they recover.
+const MIN_SUCCESS_RATE: f64 = 0.05;
+
+/// The recent responsiveness of a peer.
+#[derive(Clone, Copy, Debug)]
+struct Responsiveness {
+    /// The moving average of the response latency (in ms).
+    latency: f64,
+    /// The moving average of the success of the requests (1 for a response, 0 for a timeout).
+    success: f64,
+}
+
+impl Default for Responsiveness {
+    fn default() -> Self {
+        Self {
+            latency: 0.0,
+            success: 1.0,
+        }
+    }
+}
+
+/// Scores the peers we send sync requests to by combining their stake with their recent responsiveness,
+/// so that slow or flaky peers are asked less often.
+pub struct PeerScores {
+    committee: Committee,
+    scores: HashMap<PublicKey, Responsiveness>,
+}
+
+impl PeerScores {
+    pub fn new(committee: Committee) -> Self {
+        Self {
+            committee,
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Record that a peer responded to a request after `latency`.
+    pub fn record_success(&mut self, peer: &PublicKey, latency: Duration) {
+        let score = self.scores.entry(*peer).or_default();
+        score.latency += EWMA_WEIGHT * (latency.as_millis() as f64 - score.latency);
+        score.success += EWMA_WEIGHT * (1.0 - score.success);
+    }
+
+    /// Record that a peer did not respond to a request in time.
+    pub fn record_failure(&mut self, peer: &PublicKey) {
+        let score = self.scores.entry(*peer).or_default();
+        score.success -= EWMA_WEIGHT * score.success;
+    }
+
+    /// The selection weight of a peer: its stake, discounted by its recent latency and failures.
+    pub fn weight(&self, peer: &PublicKey) -> f64 {
+        let score = self.scores.get(peer).cloned().unwrap_or_default();
+        let stake = self.committee.stake(peer) as f64;
+        stake * score.success.max(MIN_SUCCESS_RATE) / (1.0 + score.latency / LATENCY_SCALE)
+    }
+
+    /// Pick (at most) `n` distinct peers among the candidates, at random with a probability proportional to
+    /// their weight.
+    pub fn pick<R: Rng>(&self, mut candidates: Vec<PublicKey>, n: usize, rng: &mut R) -> Vec<PublicKey> {
+        let mut picked = Vec::with_capacity(n);
+        while picked.len() < n && !candidates.is_empty() {
+            let weights: Vec<_> = candidates.iter().map(|x| self.weight(x)).collect();
+            let total: f64 = weights.iter().sum();
+            let mut target = rng.gen::<f64>() * total;
+            let mut index = candidates.len() - 1;
+            for (i, weight) in weights.iter().enumerate() {
+                if target < *weight {
+                    index = i;
+                    break;
+                }
+                target -= weight;
+            }
+            picked.push(candidates.swap_remove(index));
+        }
+        picked
+    }
+}
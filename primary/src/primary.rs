@@ -1,31 +1,54 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::audit_log::AuditLog;
+use crate::catch_up::{CatchUp, DagProgress};
+use crate::certificate_monitor::CertificateMonitor;
+use crate::certificate_rebroadcaster::CertificateRebroadcaster;
 use crate::certificate_waiter::CertificateWaiter;
-use crate::core::Core;
+use crate::committed_output::{CommitOutput, CommittedTransactions, Transaction};
+use crate::core::{Core, CoreParameters};
+use crate::dag_stats::{DagStats, RoundStats};
+use crate::delivery::{DeliveryDecoder, DigestDecoder};
 use crate::error::DagError;
 use crate::garbage_collector::GarbageCollector;
 use crate::header_waiter::HeaderWaiter;
-use crate::helper::Helper;
+use crate::helper::{decompress_certificates, Helper};
+use crate::in_flight::InFlight;
+use crate::latency::LatencySampler;
+use crate::message_tee::{Direction, MessageTee};
 use crate::messages::{Certificate, Header, Vote};
 use crate::payload_receiver::PayloadReceiver;
 use crate::proposer::Proposer;
+use crate::round_index::RoundIndex;
+use crate::round_log::round_log_range;
+use crate::supervisor::Supervisor;
 use crate::synchronizer::Synchronizer;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{Committee, Epoch, Parameters, Stake, WorkerId};
 use crypto::{Digest, PublicKey, SignatureService};
 use futures::sink::SinkExt as _;
-use log::{info, error};
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use log::{debug, error, info, warn};
+use network::{
+    Bandwidth, MessageHandler, Receiver as NetworkReceiver, ReceiverOptions, Writer,
+    PROTOCOL_VERSION,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::error::Error;
-use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{watch, Semaphore};
+use tokio::time::Instant;
 
 /// The default channel capacity for each channel of the primary.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The number of under-replicated batches the primary's health lists.
+pub const MAX_UNDER_REPLICATED_BATCHES: usize = 100;
+
 /// The round number.
 pub type Round = u64;
 
@@ -35,6 +58,13 @@ pub enum PrimaryMessage {
     Vote(Vote),
     Certificate(Certificate),
     CertificatesRequest(Vec<Digest>, /* requestor */ PublicKey),
+    CertificatesRangeRequest(/* from */ Round, /* to */ Round, /* requestor */ PublicKey),
+    MissingCertificatesRequest(Vec<(/* author */ PublicKey, Round)>, /* requestor */ PublicKey),
+    /// Certificates replying to a range or missing certificates request, serialized and compressed with
+    /// zstd (see `compress_sync_responses`).
+    CompressedCertificates(Vec<u8>),
+    /// Several votes for the headers of the recipient (see `vote_batch_window`).
+    Votes(Vec<Vote>),
 }
 
 /// The messages sent by the primary to its workers.
@@ -44,6 +74,8 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary requests the content of batches (to output the committed transactions).
+    RequestBatches(Vec<Digest>),
 }
 
 /// The messages sent by the workers to their primary.
@@ -53,6 +85,11 @@ pub enum WorkerPrimaryMessage {
     OurBatch(Digest, WorkerId),
     /// The worker indicates it received a batch's digest from another authority.
     OthersBatch(Digest, WorkerId),
+    /// The worker replies with the transactions of a batch requested by the primary.
+    Batch(Digest, Vec<Transaction>),
+    /// The worker reports how many workers (including itself) acknowledged one of its batches by the
+    /// time it delivered it.
+    Replication(Digest, WorkerId, usize),
 }
 
 /// The messages sent by the primary to clients to inform them of batch deliveries.
@@ -61,20 +98,267 @@ pub enum PrimaryClientMessage {
     BatchDelivered(Digest)
 }
 
+/// How close the latest header of the primary is to being certified.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PendingCertificate {
+    /// The id of the header.
+    pub header: Digest,
+    /// The round of the header.
+    pub round: Round,
+    /// The stake of the votes gathered so far.
+    pub stake: Stake,
+    /// The stake required to make a certificate.
+    pub threshold: Stake,
+}
+
+/// A batch of our workers that fewer workers than the `batch_replication_target` acknowledged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnderReplicatedBatch {
+    /// The digest of the batch.
+    pub digest: Digest,
+    /// The id of the worker that sealed it.
+    pub worker_id: WorkerId,
+    /// The number of workers (including ours) that acknowledged it.
+    pub replication: usize,
+}
+
+/// A snapshot of the health of a running primary.
+#[derive(Clone, Debug, Default)]
+pub struct PrimaryHealth {
+    /// Whether the primary is ready to serve: it finished booting and (in catch-up mode) caught up
+    /// with its peers.
+    pub ready: bool,
+    /// Whether the primary is paused.
+    pub paused: bool,
+    /// The rounds whose events are logged verbosely (see `set_round_log_range`), if any.
+    pub log_rounds: Option<(Round, Round)>,
+    /// The number of certificates uncommitted for longer than the age threshold.
+    pub stalled_certificates: u64,
+    /// The votes gathered for our latest header, until it is certified.
+    pub pending_certificate: Option<PendingCertificate>,
+    /// The number of votes received for headers we did not author (and dropped).
+    pub misrouted_votes: u64,
+    /// The latest batches of our workers that fewer workers than the replication target acknowledged
+    /// (at most `MAX_UNDER_REPLICATED_BATCHES`, oldest first).
+    pub under_replicated_batches: Vec<UnderReplicatedBatch>,
+    /// The bytes exchanged with each of the other primaries.
+    pub bandwidth: Vec<AuthorityBandwidth>,
+    /// The epoch of the committee the primary runs with.
+    pub epoch: Epoch,
+    /// The authorities of that committee and their stake.
+    pub committee: BTreeMap<PublicKey, Stake>,
+}
+
+/// The bytes exchanged with the primary of an authority, excluding framing. Incoming traffic is
+/// attributed by the address the authority advertises during the handshake, so it is only accounted if
+/// the handshake is enabled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorityBandwidth {
+    /// The name of the authority.
+    pub name: PublicKey,
+    /// The bytes sent to its primary.
+    pub sent: u64,
+    /// The bytes received from its primary.
+    pub received: u64,
+}
+
+/// A handle to control a running primary.
+#[derive(Clone)]
+pub struct PrimaryHandle {
+    /// Whether the primary is an observer (it never resumes).
+    observer: bool,
+    /// Whether the primary is paused. Shared with the `Core` and the `Proposer`.
+    paused: Arc<AtomicBool>,
+    /// Whether the primary is ready to serve. Written by the `CatchUp` (if any).
+    ready: Arc<AtomicBool>,
+    /// The number of certificates uncommitted for longer than the age threshold.
+    stalled_certificates: Arc<AtomicU64>,
+    /// The votes gathered for our latest header. Written by the `Core`.
+    pending_certificate: Arc<Mutex<Option<PendingCertificate>>>,
+    /// The number of votes received for headers we did not author. Written by the `Core`.
+    misrouted_votes: Arc<AtomicU64>,
+    /// The latest under-replicated batches of our workers. Written by the `WorkerReceiverHandler`.
+    under_replicated_batches: Arc<Mutex<VecDeque<UnderReplicatedBatch>>>,
+    /// Supervises the tasks of the primary.
+    supervisor: Supervisor,
+    /// The bytes exchanged with the other primaries.
+    bandwidth: Bandwidth,
+    /// The other primaries and their addresses.
+    peers: Vec<(PublicKey, SocketAddr)>,
+    /// The committee the primary runs with.
+    committee: Committee,
+    /// The round of our dag. Written by the `Core`.
+    rx_round: watch::Receiver<Round>,
+}
+
+impl PrimaryHandle {
+    /// Stop proposing headers and voting. The primary keeps storing incoming certificates and
+    /// replying to sync requests.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+        info!("Primary paused");
+    }
+
+    /// Resume proposing headers and voting from the latest round we know of (observers never resume).
+    pub fn resume(&self) {
+        if self.observer {
+            warn!("Observers cannot resume");
+            return;
+        }
+        self.paused.store(false, Ordering::Relaxed);
+        info!("Primary resumed");
+    }
+
+    /// Whether the primary is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// The number of certificates stored but uncommitted for longer than the age threshold (always
+    /// 0 if the certificate age alarm is disabled).
+    pub fn stalled_certificates(&self) -> u64 {
+        self.stalled_certificates.load(Ordering::Relaxed)
+    }
+
+    /// A snapshot of the health of the primary. It tells (among others) how close our latest header
+    /// is to being certified, which shows how far a stalled round is from completion.
+    pub fn health(&self) -> PrimaryHealth {
+        PrimaryHealth {
+            ready: self.ready.load(Ordering::Relaxed),
+            paused: self.is_paused(),
+            log_rounds: round_log_range(),
+            stalled_certificates: self.stalled_certificates(),
+            pending_certificate: self
+                .pending_certificate
+                .lock()
+                .expect("Failed to lock the pending certificate")
+                .clone(),
+            misrouted_votes: self.misrouted_votes.load(Ordering::Relaxed),
+            under_replicated_batches: self
+                .under_replicated_batches
+                .lock()
+                .expect("Failed to lock the under-replicated batches")
+                .iter()
+                .cloned()
+                .collect(),
+            bandwidth: self
+                .peers
+                .iter()
+                .map(|(name, address)| AuthorityBandwidth {
+                    name: *name,
+                    sent: self.bandwidth.sent(address),
+                    received: self.bandwidth.received(address),
+                })
+                .collect(),
+            epoch: self.committee.epoch,
+            committee: self
+                .committee
+                .authorities
+                .iter()
+                .map(|(name, x)| (*name, x.stake))
+                .collect(),
+        }
+    }
+
+    /// Subscribe to the round of our dag: the receiver always holds the latest round, and is notified
+    /// whenever it advances.
+    pub fn subscribe_rounds(&self) -> watch::Receiver<Round> {
+        self.rx_round.clone()
+    }
+
+    /// Wait until a task of the primary panics and aborts (see `SupervisionPolicy`), and return its name.
+    /// The node should then shut down.
+    pub async fn failure(&self) -> String {
+        self.supervisor.failure().await
+    }
+}
+
 pub struct Primary;
 
 impl Primary {
+    /// Spawn a primary. The `SignatureService` signs on behalf of the primary (whose public key is `name`);
+    /// it may be backed by any `Signer`, such that the primary never holds the secret key. If `name` is
+    /// one of the committee's observers, the primary stores (and serves) the certificates of the committee
+    /// but never proposes nor votes.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        keypair: KeyPair,
+        name: PublicKey,
+        signature_service: SignatureService,
         committee: Committee,
         parameters: Parameters,
         store: Store,
+        audit_log: Option<AuditLog>,
+        dag_stats: Option<Sender<RoundStats>>,
+        message_tee: Option<MessageTee>,
         tx_consensus: Sender<Certificate>,
         rx_consensus: Receiver<Certificate>,
-    ) {
+    ) -> PrimaryHandle {
+        Self::start(
+            name,
+            signature_service,
+            committee,
+            parameters,
+            store,
+            audit_log,
+            dag_stats,
+            message_tee,
+            tx_consensus,
+            rx_consensus,
+            /* tx_output */ None,
+        )
+    }
+
+    /// Spawn a primary that also outputs the transactions of the committed certificates, in commit order.
+    /// The transactions are fetched from our workers. Every `checkpoint_interval` committed rounds, the
+    /// output also carries a checkpoint of the commit sequence (persisted in the store).
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_with_output(
+        name: PublicKey,
+        signature_service: SignatureService,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        audit_log: Option<AuditLog>,
+        dag_stats: Option<Sender<RoundStats>>,
+        message_tee: Option<MessageTee>,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<Certificate>,
+    ) -> (PrimaryHandle, Receiver<CommittedTransactions>) {
+        let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+        let handle = Self::start(
+            name,
+            signature_service,
+            committee,
+            parameters,
+            store,
+            audit_log,
+            dag_stats,
+            message_tee,
+            tx_consensus,
+            rx_consensus,
+            Some(tx_output),
+        );
+        (handle, rx_output)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        name: PublicKey,
+        signature_service: SignatureService,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        audit_log: Option<AuditLog>,
+        dag_stats: Option<Sender<RoundStats>>,
+        message_tee: Option<MessageTee>,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<Certificate>,
+        tx_output: Option<Sender<CommittedTransactions>>,
+    ) -> PrimaryHandle {
         let (tx_others_digests, rx_others_digests) = channel(CHANNEL_CAPACITY);
         let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
         let (tx_parents, rx_parents) = channel(CHANNEL_CAPACITY);
+        let (tx_round, rx_round) = watch::channel(0);
         let (tx_headers, rx_headers) = channel(CHANNEL_CAPACITY);
         let (tx_sync_headers, rx_sync_headers) = channel(CHANNEL_CAPACITY);
         let (tx_sync_certificates, rx_sync_certificates) = channel(CHANNEL_CAPACITY);
@@ -82,23 +366,121 @@ impl Primary {
         let (tx_certificates_loopback, rx_certificates_loopback) = channel(CHANNEL_CAPACITY);
         let (tx_primary_messages, rx_primary_messages) = channel(CHANNEL_CAPACITY);
         let (tx_cert_requests, rx_cert_requests) = channel(CHANNEL_CAPACITY);
+        let (tx_range_requests, rx_range_requests) = channel(CHANNEL_CAPACITY);
+        let (tx_missing_requests, rx_missing_requests) = channel(CHANNEL_CAPACITY);
 
         // Write the parameters to the logs.
         parameters.log();
 
-        // Parse the public and secret key of this authority.
-        let name = keypair.name;
-        let secret = keypair.secret;
-
         // Atomic variable use to synchronizer all tasks with the latest consensus round. This is only
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
         let consensus_round = Arc::new(AtomicU64::new(0));
 
+        // Applies the supervision policy of the critical tasks when they panic.
+        let supervisor = Supervisor::new(parameters.supervision_policies.clone());
+
+        // Observers follow the dag of the committee without ever proposing or voting.
+        let observer = committee.observers.contains_key(&name);
+        let addresses = committee
+            .primary_or_observer(&name)
+            .expect("Our public key or worker id is not in the committee");
+        if observer {
+            info!("Primary {} running as observer", name);
+        }
+
+        // Atomic flag indicating whether the primary is paused (see `PrimaryHandle`). The `CatchUp` task
+        // (if any) pauses us while we lag behind our peers. Observers remain paused.
+        let paused = Arc::new(AtomicBool::new(observer));
+
+        // Tracks how far our dag is compared to the dag of our peers. It is written by the `Core`.
+        let progress = Arc::new(DagProgress::default());
+
+        // Indexes the digests of the certificates we store by round. It is written by the `Core` and
+        // read by the `Helper` to serve range requests.
+        let round_index = Arc::new(RoundIndex::default());
+
+        // The votes gathered for our latest header. It is written by the `Core`.
+        let pending_certificate = Arc::new(Mutex::new(None));
+
+        // The number of votes received for headers we did not author. It is written by the `Core`.
+        let misrouted_votes = Arc::new(AtomicU64::new(0));
+
+        // The latest under-replicated batches of our workers. It is written by the `WorkerReceiverHandler`.
+        let under_replicated_batches = Arc::new(Mutex::new(VecDeque::new()));
+
+        // The `CertificateMonitor` sits between the `Core` and the consensus to raise an alarm when
+        // certificates remain uncommitted for too long.
+        let stalled_certificates = Arc::new(AtomicU64::new(0));
+        let (tx_consensus, rx_consensus) = if parameters.certificate_age_threshold > 0 {
+            let (tx_monitor, rx_monitor) = channel(CHANNEL_CAPACITY);
+            let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+            CertificateMonitor::spawn(
+                parameters.certificate_age_threshold,
+                parameters.gc_depth,
+                stalled_certificates.clone(),
+                /* rx_core */ rx_monitor,
+                tx_consensus,
+                rx_consensus,
+                /* tx_garbage_collector */ tx_feedback,
+            );
+            (tx_monitor, rx_feedback)
+        } else {
+            (tx_consensus, rx_consensus)
+        };
+
+        // The `DagStats` optionally sits between the `Core` and the consensus to gather per-round statistics.
+        let (tx_consensus, rx_consensus) = match dag_stats {
+            Some(tx_stats) => {
+                let (tx_stats_core, rx_stats_core) = channel(CHANNEL_CAPACITY);
+                let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+                DagStats::spawn(
+                    parameters.gc_depth,
+                    /* rx_core */ rx_stats_core,
+                    tx_consensus,
+                    rx_consensus,
+                    /* tx_garbage_collector */ tx_feedback,
+                    tx_stats,
+                );
+                (tx_stats_core, rx_feedback)
+            }
+            None => (tx_consensus, rx_consensus),
+        };
+
+        // The `CommitOutput` optionally sits between the consensus and the `GarbageCollector` to output
+        // the transactions of the committed certificates.
+        let (tx_batches, rx_consensus) = match tx_output {
+            Some(tx_output) => {
+                let (tx_batches, rx_batches) = channel(CHANNEL_CAPACITY);
+                let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+                CommitOutput::spawn(
+                    name,
+                    committee.clone(),
+                    parameters.sync_retry_delay,
+                    store.clone(),
+                    parameters.checkpoint_interval,
+                    rx_consensus,
+                    /* tx_garbage_collector */ tx_feedback,
+                    /* rx_workers */ rx_batches,
+                    tx_output,
+                );
+                (Some(tx_batches), rx_feedback)
+            }
+            None => (None, rx_consensus),
+        };
+
+        // Primaries optionally agree on the message format version before exchanging messages.
+        let protocol_version = parameters.enable_handshake.then_some(PROTOCOL_VERSION);
+
+        // Accounts the bytes we exchange with the other primaries (see `PrimaryHealth`).
+        let bandwidth = Bandwidth::default();
+        let peers = committee
+            .others_primaries(&name)
+            .into_iter()
+            .map(|(name, x)| (name, x.primary_to_primary))
+            .collect();
+
         // Spawn the network receiver listening to messages from the other primaries.
-        let mut address = committee
-            .primary(&name)
-            .expect("Our public key or worker id is not in the committee")
-            .primary_to_primary;
+        let mut address = addresses.primary_to_primary;
         address.set_ip("0.0.0.0".parse().unwrap());
         NetworkReceiver::spawn(
             address,
@@ -106,7 +488,14 @@ impl Primary {
             PrimaryReceiverHandler {
                 tx_primary_messages,
                 tx_cert_requests,
+                tx_range_requests,
+                tx_missing_requests,
+                message_tee: message_tee.clone(),
             },
+            ReceiverOptions::default()
+                .with_max_connections(parameters.max_inbound_connections)
+                .with_handshake(protocol_version)
+                .with_bandwidth(bandwidth.clone()),
         );
         info!(
             "Primary {} listening to primary messages on {}",
@@ -114,10 +503,7 @@ impl Primary {
         );
 
         // Spawn the network receiver listening to messages from our workers.
-        let mut address = committee
-            .primary(&name)
-            .expect("Our public key or worker id is not in the committee")
-            .worker_to_primary;
+        let mut address = addresses.worker_to_primary;
         address.set_ip("0.0.0.0".parse().unwrap());
         NetworkReceiver::spawn(
             address,
@@ -125,7 +511,11 @@ impl Primary {
             WorkerReceiverHandler {
                 tx_our_digests,
                 tx_others_digests,
+                tx_batches,
+                replication_target: parameters.batch_replication_target,
+                under_replicated_batches: under_replicated_batches.clone(),
             },
+            ReceiverOptions::default().with_max_connections(parameters.max_inbound_connections),
         );
         info!(
             "Primary {} listening to workers messages on {}",
@@ -137,12 +527,30 @@ impl Primary {
             name,
             &committee,
             store.clone(),
+            parameters.sync_round_gap,
+            progress.clone(),
             /* tx_header_waiter */ tx_sync_headers,
             /* tx_certificate_waiter */ tx_sync_certificates,
         );
 
-        // The `SignatureService` is used to require signatures on specific digests.
-        let signature_service = SignatureService::new(secret);
+        // The `CertificateRebroadcaster` optionally broadcasts our certificates on behalf of the `Core` and
+        // re-broadcasts them until a quorum acknowledges them.
+        let tx_rebroadcaster = (parameters.certificate_rebroadcast_delay > 0).then(|| {
+            let (tx_rebroadcaster, rx_rebroadcaster) = channel(CHANNEL_CAPACITY);
+            CertificateRebroadcaster::spawn(
+                name,
+                committee.clone(),
+                store.clone(),
+                parameters.certificate_rebroadcast_delay,
+                parameters.gc_depth,
+                consensus_round.clone(),
+                protocol_version,
+                message_tee.clone(),
+                bandwidth.clone(),
+                /* rx_core */ rx_rebroadcaster,
+            );
+            tx_rebroadcaster
+        });
 
         // The `Core` receives and handles headers, votes, and certificates from the other primaries.
         Core::spawn(
@@ -152,21 +560,48 @@ impl Primary {
             synchronizer,
             signature_service.clone(),
             consensus_round.clone(),
-            parameters.gc_depth,
+            paused.clone(),
+            progress.clone(),
+            round_index.clone(),
+            CoreParameters::from(&parameters),
+            audit_log,
+            pending_certificate.clone(),
+            misrouted_votes.clone(),
+            tx_rebroadcaster,
+            protocol_version,
+            message_tee.clone(),
+            bandwidth.clone(),
             /* rx_primaries */ rx_primary_messages,
             /* rx_header_waiter */ rx_headers_loopback,
             /* rx_certificate_waiter */ rx_certificates_loopback,
             /* rx_proposer */ rx_headers,
             tx_consensus,
             /* tx_proposer */ tx_parents,
+            tx_round,
+            &supervisor,
         );
 
         // Keeps track of the latest consensus round and allows other tasks to clean up their their internal state
-        GarbageCollector::spawn(&name, &committee, consensus_round.clone(), rx_consensus);
+        GarbageCollector::spawn(
+            &name,
+            &committee,
+            consensus_round.clone(),
+            rx_consensus,
+            store.clone(),
+            parameters.gc_depth,
+            parameters.compaction_interval,
+            &supervisor,
+        );
 
         // Receives batch digests from other workers. They are only used to validate headers.
         PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
 
+        // The outstanding sync requests of the `HeaderWaiter` are capped, pacing the recovery of the primary.
+        let sync_permits = Arc::new(Semaphore::new(match parameters.max_pending_sync_requests {
+            0 => Semaphore::MAX_PERMITS,
+            x => x,
+        }));
+
         // Whenever the `Synchronizer` does not manage to validate a header due to missing parent certificates of
         // batch digests, it commands the `HeaderWaiter` to synchronizer with other nodes, wait for their reply, and
         // re-schedule execution of the header once we have all missing data.
@@ -174,20 +609,28 @@ impl Primary {
             name,
             committee.clone(),
             store.clone(),
-            consensus_round,
+            consensus_round.clone(),
             parameters.gc_depth,
             parameters.sync_retry_delay,
             parameters.sync_retry_nodes,
+            sync_permits,
+            parameters.max_pending_headers,
+            protocol_version,
+            message_tee.clone(),
+            bandwidth.clone(),
             /* rx_synchronizer */ rx_sync_headers,
             /* tx_core */ tx_headers_loopback,
+            &supervisor,
         );
 
         // The `CertificateWaiter` waits to receive all the ancestors of a certificate before looping it back to the
         // `Core` for further processing.
         CertificateWaiter::spawn(
             store.clone(),
+            parameters.waiting_certificate_max_age,
             /* rx_synchronizer */ rx_sync_certificates,
             /* tx_core */ tx_certificates_loopback,
+            &supervisor,
         );
 
         // When the `Core` collects enough parent certificates, the `Proposer` generates a new header with new batch
@@ -198,24 +641,82 @@ impl Primary {
             signature_service,
             parameters.header_size,
             parameters.max_header_delay,
+            parameters.parent_quorum_policy,
+            parameters.max_weak_links,
+            parameters.min_distinct_parent_authors,
+            parameters.empty_header_delay,
+            parameters.max_dag_depth,
+            parameters.slow_peer_rounds,
+            parameters.leader_priority,
+            paused.clone(),
+            consensus_round,
             /* rx_core */ rx_parents,
             /* rx_workers */ rx_our_digests,
             /* tx_core */ tx_headers,
+            &supervisor,
         );
 
+        // The primary is ready to serve once it booted and (in catch-up mode) caught up with its peers.
+        let ready = Arc::new(AtomicBool::new(false));
+        let catch_up = parameters.catch_up_round_gap > 0 && !observer;
+
+        // A primary lagging behind its peers optionally syncs their dag before it resumes proposing.
+        if catch_up {
+            CatchUp::spawn(
+                name,
+                committee.clone(),
+                parameters.catch_up_round_gap,
+                parameters.sync_retry_delay,
+                parameters.sync_retry_nodes,
+                progress,
+                paused.clone(),
+                ready.clone(),
+                protocol_version,
+                message_tee.clone(),
+                bandwidth.clone(),
+            );
+        }
+
         // The `Helper` is dedicated to reply to certificates requests from other primaries.
-        Helper::spawn(committee.clone(), store, rx_cert_requests);
+        Helper::spawn(
+            name,
+            committee.clone(),
+            store,
+            round_index,
+            protocol_version,
+            message_tee,
+            bandwidth.clone(),
+            parameters.compress_sync_responses,
+            rx_cert_requests,
+            rx_range_requests,
+            rx_missing_requests,
+            &supervisor,
+        );
 
         // NOTE: This log entry is used to compute performance.
         info!(
             "Primary {} successfully booted on {}",
             name,
-            committee
-                .primary(&name)
-                .expect("Our public key or worker id is not in the committee")
-                .primary_to_primary
-                .ip()
+            addresses.primary_to_primary.ip()
         );
+        if !catch_up {
+            ready.store(true, Ordering::Relaxed);
+        }
+
+        PrimaryHandle {
+            observer,
+            paused,
+            ready,
+            stalled_certificates,
+            pending_certificate,
+            misrouted_votes,
+            under_replicated_batches,
+            supervisor,
+            bandwidth,
+            peers,
+            committee,
+            rx_round,
+        }
     }
 }
 
@@ -224,6 +725,9 @@ impl Primary {
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
     tx_cert_requests: Sender<(Vec<Digest>, PublicKey)>,
+    tx_range_requests: Sender<(Round, Round, PublicKey)>,
+    tx_missing_requests: Sender<(Vec<(PublicKey, Round)>, PublicKey)>,
+    message_tee: Option<MessageTee>,
 }
 
 #[async_trait]
@@ -232,6 +736,10 @@ impl MessageHandler for PrimaryReceiverHandler {
         // Reply with an ACK.
         let _ = writer.send(Bytes::from("Ack")).await;
 
+        if let Some(tee) = &self.message_tee {
+            tee.capture(Direction::Inbound, &serialized);
+        }
+
         // Deserialize and parse the message.
         match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
             PrimaryMessage::CertificatesRequest(missing, requestor) => self
@@ -239,6 +747,32 @@ impl MessageHandler for PrimaryReceiverHandler {
                 .send((missing, requestor))
                 .await
                 .expect("Failed to send primary message"),
+            PrimaryMessage::CertificatesRangeRequest(from, to, requestor) => self
+                .tx_range_requests
+                .send((from, to, requestor))
+                .await
+                .expect("Failed to send primary message"),
+            PrimaryMessage::MissingCertificatesRequest(missing, requestor) => self
+                .tx_missing_requests
+                .send((missing, requestor))
+                .await
+                .expect("Failed to send primary message"),
+            PrimaryMessage::Votes(votes) => {
+                for vote in votes {
+                    self.tx_primary_messages
+                        .send(PrimaryMessage::Vote(vote))
+                        .await
+                        .expect("Failed to send vote");
+                }
+            }
+            PrimaryMessage::CompressedCertificates(compressed) => {
+                for certificate in decompress_certificates(&compressed)? {
+                    self.tx_primary_messages
+                        .send(PrimaryMessage::Certificate(certificate))
+                        .await
+                        .expect("Failed to send certificate");
+                }
+            }
             request => self
                 .tx_primary_messages
                 .send(request)
@@ -249,22 +783,106 @@ impl MessageHandler for PrimaryReceiverHandler {
     }
 }
 
-/// Defines how the network receiver handles incoming primary messages.
-#[derive(Clone)]
-pub struct PrimaryClientReceiverHandler {}
+/// Defines how the network receiver of clients handles the deliveries of the primary. Deliveries
+/// are parsed by the decoder and then forwarded to the output channel (or logged if there is none).
+pub struct PrimaryClientReceiverHandler<Decoder: DeliveryDecoder = DigestDecoder> {
+    decoder: Decoder,
+    tx_output: Option<Sender<Decoder::Output>>,
+    /// Measures the latency of the delivered sample transactions (if enabled).
+    latency: Option<Arc<Mutex<LatencySampler>>>,
+    /// Frees the slots of the delivered sample transactions (if the client runs in closed loop).
+    in_flight: Option<InFlight>,
+}
+
+// Implemented by hand as deriving `Clone` would require the decoder's output to be `Clone`.
+impl<Decoder: DeliveryDecoder> Clone for PrimaryClientReceiverHandler<Decoder> {
+    fn clone(&self) -> Self {
+        Self {
+            decoder: self.decoder.clone(),
+            tx_output: self.tx_output.clone(),
+            latency: self.latency.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl Default for PrimaryClientReceiverHandler {
+    fn default() -> Self {
+        Self::new(DigestDecoder)
+    }
+}
+
+impl<Decoder: DeliveryDecoder> PrimaryClientReceiverHandler<Decoder> {
+    /// Make a handler logging the decoded deliveries.
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            tx_output: None,
+            latency: None,
+            in_flight: None,
+        }
+    }
+
+    /// Make a handler forwarding the decoded deliveries to `tx_output`.
+    pub fn with_output(decoder: Decoder, tx_output: Sender<Decoder::Output>) -> Self {
+        Self {
+            decoder,
+            tx_output: Some(tx_output),
+            latency: None,
+            in_flight: None,
+        }
+    }
+
+    /// Record the deliveries of sample transactions into `latency` (which the client feeds with the
+    /// transactions it sends).
+    pub fn with_latency(mut self, latency: Arc<Mutex<LatencySampler>>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Record the deliveries of sample transactions into `in_flight` (which the client feeds with the
+    /// transactions it sends).
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+}
 
 #[async_trait]
-impl MessageHandler for PrimaryClientReceiverHandler {
+impl<Decoder: DeliveryDecoder> MessageHandler for PrimaryClientReceiverHandler<Decoder> {
     async fn dispatch(
         &self,
         _writer: &mut Writer,
         serialized: Bytes,
     ) -> Result<(), Box<dyn Error>> {
-        // Deserialize the message and send it to the synchronizer.
-        match bincode::deserialize::<PrimaryClientMessage>(&serialized) {
-            Err(e) => error!("Failed to deserialize primary message: {}", e),
-            Ok(PrimaryClientMessage::BatchDelivered(digest)) =>
-                info!("Committed -> {:?}", digest),
+        let output = match self.decoder.decode(&serialized) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Failed to decode primary delivery: {}", e);
+                return Ok(());
+            }
+        };
+        if self.latency.is_some() || self.in_flight.is_some() {
+            let now = Instant::now();
+            let ids = self.decoder.sample_ids(&output);
+            if let Some(latency) = &self.latency {
+                let mut latency = latency.lock().expect("Failed to lock the latency sampler");
+                for id in &ids {
+                    latency.delivered(*id, now);
+                }
+            }
+            if let Some(in_flight) = &self.in_flight {
+                for id in &ids {
+                    in_flight.delivered(*id);
+                }
+            }
+        }
+        match &self.tx_output {
+            Some(tx_output) => tx_output
+                .send(output)
+                .await
+                .expect("Failed to deliver decoded output"),
+            None => info!("Committed -> {:?}", output),
         }
         Ok(())
     }
@@ -295,6 +913,37 @@ impl MessageHandler for PrimaryClientReceiverHandlerNoPrint {
 struct WorkerReceiverHandler {
     tx_our_digests: Sender<(Digest, WorkerId)>,
     tx_others_digests: Sender<(Digest, WorkerId)>,
+    /// Receives the batches' content (only if we output the committed transactions).
+    tx_batches: Option<Sender<(Digest, Vec<Transaction>)>>,
+    /// The number of workers expected to acknowledge the batches of our workers (0 if not reported).
+    replication_target: usize,
+    /// The latest batches of our workers reported below the replication target.
+    under_replicated_batches: Arc<Mutex<VecDeque<UnderReplicatedBatch>>>,
+}
+
+impl WorkerReceiverHandler {
+    /// Record the replication of a batch of our workers, keeping the latest under-replicated ones.
+    fn record_replication(&self, digest: Digest, worker_id: WorkerId, replication: usize) {
+        if replication >= self.replication_target {
+            return;
+        }
+        warn!(
+            "Batch {} of worker {} is under-replicated: {} workers acknowledged it (target {})",
+            digest, worker_id, replication, self.replication_target
+        );
+        let mut batches = self
+            .under_replicated_batches
+            .lock()
+            .expect("Failed to lock the under-replicated batches");
+        if batches.len() == MAX_UNDER_REPLICATED_BATCHES {
+            batches.pop_front();
+        }
+        batches.push_back(UnderReplicatedBatch {
+            digest,
+            worker_id,
+            replication,
+        });
+    }
 }
 
 #[async_trait]
@@ -316,6 +965,16 @@ impl MessageHandler for WorkerReceiverHandler {
                 .send((digest, worker_id))
                 .await
                 .expect("Failed to send workers' digests"),
+            WorkerPrimaryMessage::Batch(digest, transactions) => match &self.tx_batches {
+                Some(tx_batches) => tx_batches
+                    .send((digest, transactions))
+                    .await
+                    .expect("Failed to send workers' batches"),
+                None => debug!("Dropping unrequested batch {}", digest),
+            },
+            WorkerPrimaryMessage::Replication(digest, worker_id, replication) => {
+                self.record_replication(digest, worker_id, replication)
+            }
         }
         Ok(())
     }
@@ -1,13 +1,17 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::{Certificate, Header};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
+use crate::supervisor::Supervisor;
+use config::{Committee, ParentQuorumPolicy, Stake, WorkerId};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
 #[cfg(feature = "benchmark")]
 use log::info;
 use log::{debug, log_enabled, warn};
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::{sleep, Duration, Instant};
 
@@ -15,6 +19,10 @@ use tokio::time::{sleep, Duration, Instant};
 #[path = "tests/proposer_tests.rs"]
 pub mod proposer_tests;
 
+/// The number of rounds we keep proposing (even empty headers) after the last round carrying a payload.
+/// This lets the leaders referencing that payload gather enough support to be committed.
+const PAYLOAD_LIVENESS_ROUNDS: Round = 5;
+
 /// The proposer creates new headers and send them to the core for broadcasting and further processing.
 pub struct Proposer {
     /// The public key of this primary.
@@ -27,6 +35,26 @@ pub struct Proposer {
     header_size: usize,
     /// The maximum delay to wait for batches' digests.
     max_header_delay: u64,
+    /// The stake of parents required to propose a new header.
+    parent_quorum_policy: ParentQuorumPolicy,
+    /// The maximum number of weak links (to certificates of earlier rounds) to include in a header.
+    max_weak_links: usize,
+    /// The minimum number of distinct authors among the parents of a header (0 disables it).
+    min_distinct_parent_authors: usize,
+    /// The maximum delay between two headers when there is no payload to propose (0 disables
+    /// the suppression of empty headers).
+    empty_header_delay: u64,
+    /// The maximum number of rounds we may advance past the last committed round (0 disables the limit).
+    max_dag_depth: Round,
+    /// The number of consecutive headers an authority's certificates must miss before we stop waiting
+    /// for it as leader (0 disables it).
+    slow_peer_rounds: Round,
+    /// Whether we propose our headers for the rounds we lead as soon as we have enough parents.
+    leader_priority: bool,
+    /// Whether the primary is paused (in which case we do not propose).
+    paused: Arc<AtomicBool>,
+    /// The last committed round (written by the `GarbageCollector`).
+    consensus_round: Arc<AtomicU64>,
 
     /// Receives the parents to include in the next header (along with their round number).
     rx_core: Receiver<(Vec<Certificate>, Round)>,
@@ -41,10 +69,18 @@ pub struct Proposer {
     last_parents: Vec<Certificate>,
     /// Holds the certificate of the last leader (if any).
     last_leader: Option<Certificate>,
+    /// Holds the ids of the certificates of earlier rounds that we did not reference yet.
+    weak_links: VecDeque<Digest>,
     /// Holds the batches' digests waiting to be included in the next header.
     digests: Vec<(Digest, WorkerId)>,
     /// Keeps track of the size (in bytes) of batches' digests that we received so far.
     payload_size: usize,
+    /// The last round at which we (or one of our parents) had a payload.
+    last_payload_round: Option<Round>,
+    /// The time at which we proposed our last header.
+    last_header: Instant,
+    /// The number of consecutive headers (of ours) that did not reference a certificate of each authority.
+    missed_rounds: HashMap<PublicKey, Round>,
 }
 
 impl Proposer {
@@ -55,39 +91,87 @@ impl Proposer {
         signature_service: SignatureService,
         header_size: usize,
         max_header_delay: u64,
+        parent_quorum_policy: ParentQuorumPolicy,
+        max_weak_links: usize,
+        min_distinct_parent_authors: usize,
+        empty_header_delay: u64,
+        max_dag_depth: Round,
+        slow_peer_rounds: Round,
+        leader_priority: bool,
+        paused: Arc<AtomicBool>,
+        consensus_round: Arc<AtomicU64>,
         rx_core: Receiver<(Vec<Certificate>, Round)>,
         rx_workers: Receiver<(Digest, WorkerId)>,
         tx_core: Sender<Header>,
+        supervisor: &Supervisor,
     ) {
         let genesis = Certificate::genesis(&committee);
-        tokio::spawn(async move {
+        supervisor.spawn(
+            "proposer",
             Self {
                 name,
                 committee,
                 signature_service,
                 header_size,
                 max_header_delay,
+                parent_quorum_policy,
+                max_weak_links,
+                min_distinct_parent_authors,
+                empty_header_delay,
+                max_dag_depth,
+                slow_peer_rounds,
+                leader_priority,
+                paused,
+                consensus_round,
                 rx_core,
                 rx_workers,
                 tx_core,
                 round: 0,
                 last_parents: genesis,
                 last_leader: None,
+                weak_links: VecDeque::with_capacity(max_weak_links),
                 digests: Vec::with_capacity(2 * header_size),
                 payload_size: 0,
-            }
-            .run()
-            .await;
-        });
+                last_payload_round: None,
+                last_header: Instant::now(),
+                missed_rounds: HashMap::new(),
+            },
+            Self::rebuild,
+            |x| Box::pin(x.run()),
+        );
+    }
+
+    /// Rebuild the proposer after a panic (see `Supervisor::spawn`). We keep our round (so that we never
+    /// propose two headers for the same round) and the digests of our batches, and wait for new parents
+    /// from the `Core`.
+    fn rebuild(self) -> Self {
+        let payload_size = self.digests.iter().map(|(x, _)| x.size()).sum();
+        Self {
+            last_parents: Vec::new(),
+            last_leader: None,
+            weak_links: VecDeque::with_capacity(self.max_weak_links),
+            payload_size,
+            last_payload_round: None,
+            last_header: Instant::now(),
+            missed_rounds: HashMap::new(),
+            ..self
+        }
     }
 
     async fn make_header(&mut self) {
+        if !self.digests.is_empty() {
+            self.last_payload_round = Some(self.round);
+        }
+        self.last_header = Instant::now();
+        self.record_missed_parents();
+
         // Make a new header.
         let header = Header::new(
             self.name,
             self.round,
             self.digests.drain(..).collect(),
             self.last_parents.drain(..).map(|x| x.digest()).collect(),
+            self.weak_links.drain(..).collect(),
             &mut self.signature_service,
         )
         .await;
@@ -106,7 +190,30 @@ impl Proposer {
             .expect("Failed to send header");
     }
 
-    /// Update the last leader.
+    /// Count, for every authority, the consecutive headers that do not reference any of its certificates.
+    fn record_missed_parents(&mut self) {
+        if self.slow_peer_rounds == 0 {
+            return;
+        }
+        let authors: HashSet<_> = self.last_parents.iter().map(|x| x.origin()).collect();
+        for name in self.committee.authorities.keys() {
+            if authors.contains(name) {
+                self.missed_rounds.remove(name);
+            } else {
+                *self.missed_rounds.entry(*name).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Check whether the authority has been persistently unresponsive. Slow authorities still count towards
+    /// every quorum; we merely stop waiting for them when it is not needed for safety.
+    fn is_slow(&self, name: &PublicKey) -> bool {
+        self.slow_peer_rounds > 0
+            && self.missed_rounds.get(name).is_some_and(|x| *x >= self.slow_peer_rounds)
+    }
+
+    /// Update the last leader. Returns whether we can advance without waiting for the leader (we have
+    /// its certificate or it is slow).
     fn update_leader(&mut self) -> bool {
         let leader_name = self.committee.leader(self.round as usize);
         self.last_leader = self
@@ -117,6 +224,9 @@ impl Proposer {
 
         if let Some(leader) = self.last_leader.as_ref() {
             debug!("Got leader {} for round {}", leader.origin(), self.round);
+        } else if self.is_slow(&leader_name) {
+            debug!("Not waiting for slow leader {} of round {}", leader_name, self.round);
+            return true;
         }
 
         self.last_leader.is_some()
@@ -155,28 +265,119 @@ impl Proposer {
         enough_votes
     }
 
+    /// Check whether the parents reach the threshold set by the parent quorum policy and come from
+    /// enough distinct authors.
+    fn enough_parents(&self) -> bool {
+        let stake: Stake = self
+            .last_parents
+            .iter()
+            .map(|x| self.committee.stake(&x.origin()))
+            .sum();
+        let authors: HashSet<_> = self.last_parents.iter().map(|x| x.origin()).collect();
+        stake >= self.parent_quorum_policy.threshold(&self.committee)
+            && authors.len() >= self.min_distinct_parent_authors.min(self.committee.size())
+    }
+
+    /// Remember certificates we will not reference as parents, to include them as weak links in
+    /// our next header. We only keep the most recent ones.
+    fn add_weak_links(&mut self, certificates: Vec<Certificate>) {
+        if self.max_weak_links == 0 {
+            return;
+        }
+        for certificate in certificates {
+            if self.weak_links.len() == self.max_weak_links {
+                self.weak_links.pop_front();
+            }
+            self.weak_links.push_back(certificate.digest());
+        }
+    }
+
+    /// Check whether we can skip proposing a header: we have no payload, the dag has not carried any
+    /// payload for a while, and we proposed recently enough.
+    fn idle(&self) -> bool {
+        self.empty_header_delay > 0
+            && self.digests.is_empty()
+            && self
+                .last_payload_round
+                .is_none_or(|x| self.round >= x + PAYLOAD_LIVENESS_ROUNDS)
+            && self.last_header.elapsed() < Duration::from_millis(self.empty_header_delay)
+    }
+
+    /// Check whether we prioritize our header for the next round, because we are its leader (leaders are
+    /// elected for even rounds, as by the consensus).
+    fn leading(&self) -> bool {
+        let round = self.round + 1;
+        self.leader_priority
+            && round.is_multiple_of(2)
+            && self.committee.leader(round as usize) == self.name
+    }
+
+    /// Check whether advancing to the next round would take us more than `max_dag_depth` rounds past the
+    /// last committed round (typically during a long partition, when consensus cannot commit).
+    fn throttled(&self) -> bool {
+        let committed = self.consensus_round.load(AtomicOrdering::Relaxed);
+        self.max_dag_depth > 0 && self.round + 1 > committed + self.max_dag_depth
+    }
+
     /// Main loop listening to incoming messages.
     pub async fn run(&mut self) {
         debug!("Dag starting at round {}", self.round);
         let mut advance = true;
+        let mut idle = false;
+        let mut throttled = false;
 
         let timer = sleep(Duration::from_millis(self.max_header_delay));
         tokio::pin!(timer);
 
         loop {
-            // Check if we can propose a new header. We propose a new header when we have a quorum of parents
+            // Check if we can propose a new header. We propose a new header when we have enough parents
             // and one of the following conditions is met:
             // (i) the timer expired (we timed out on the leader or gave up gather votes for the leader),
             // (ii) we have enough digests (minimum header size) and we are on the happy path (we can vote for
             // the leader or the leader has enough votes to enable a commit).
-            let enough_parents = !self.last_parents.is_empty();
+            // When we skipped a header for lack of payload, we propose as soon as there is payload again.
+            // If we lead the next round (and prioritize it), we propose as soon as we have enough parents.
+            let enough_parents = self.enough_parents();
             let enough_digests = self.payload_size >= self.header_size;
             let timer_expired = timer.is_elapsed();
+            let resume = idle && !self.idle();
+            let leading = self.leading();
+            let propose = (timer_expired || resume || leading || (enough_digests && advance))
+                && enough_parents;
 
-            if (timer_expired || (enough_digests && advance)) && enough_parents {
+            if self.paused.load(AtomicOrdering::Relaxed) {
+                // Keep collecting parents and digests but do not propose. We resume from the
+                // latest round we know of.
+                if timer_expired {
+                    let deadline = Instant::now() + Duration::from_millis(self.max_header_delay);
+                    timer.as_mut().reset(deadline);
+                }
+            } else if propose && self.throttled() {
+                // Hold our round until consensus commits again.
+                if !throttled {
+                    warn!(
+                        "Partition-throttled: not advancing past round {} until round {} is committed",
+                        self.round,
+                        self.round + 1 - self.max_dag_depth
+                    );
+                }
+                throttled = true;
+                let deadline = Instant::now() + Duration::from_millis(self.max_header_delay);
+                timer.as_mut().reset(deadline);
+            } else if propose && self.idle() {
+                // Skip this header until some payload arrives or until the liveness deadline.
+                idle = true;
+                let deadline = self.last_header + Duration::from_millis(self.empty_header_delay);
+                timer.as_mut().reset(deadline);
+            } else if propose {
+                idle = false;
+                throttled = false;
                 if timer_expired {
                     warn!("Timer expired for round {}", self.round);
                 }
+                if leading {
+                    debug!("Prioritizing our header as leader of round {}", self.round + 1);
+                }
 
                 // Advance to the next round.
                 self.round += 1;
@@ -199,10 +400,13 @@ impl Proposer {
                             // We accept round bigger than our current round to jump ahead in case we were
                             // late (or just joined the network).
                             self.round = round;
-                            self.last_parents = parents;
+                            let skipped = std::mem::replace(&mut self.last_parents, parents);
+                            self.add_weak_links(skipped);
                         },
                         Ordering::Less => {
-                            // Ignore parents from older rounds.
+                            // Parents from older rounds arrived too late to be parents, but we may
+                            // still reference them as weak links.
+                            self.add_weak_links(parents);
                         },
                         Ordering::Equal => {
                             // The core gives us the parents the first time they are enough to form a quorum.
@@ -211,6 +415,11 @@ impl Proposer {
                         }
                     }
 
+                    // Remember whether the dag still carries payload.
+                    if self.last_parents.iter().any(|x| !x.header.payload.is_empty()) {
+                        self.last_payload_round = self.last_payload_round.max(Some(self.round));
+                    }
+
                     // Check whether we can advance to the next round. Note that if we timeout,
                     // we ignore this check and advance anyway.
                     advance = match self.round % 2 {
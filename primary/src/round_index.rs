@@ -0,0 +1,46 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use crypto::Digest;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+#[cfg(test)]
+#[path = "tests/round_index_tests.rs"]
+pub mod round_index_tests;
+
+/// Indexes the digests of the certificates we stored by round, so that serving a range of rounds only
+/// touches the certificates it returns. It is written by the `Core` and only covers the rounds above the
+/// garbage collection round (and the certificates stored since the primary started); the `Helper` looks
+/// the other rounds up in the store (see `Certificate::index_key`), whose index is pruned by the
+/// `GarbageCollector`.
+#[derive(Default)]
+pub struct RoundIndex {
+    rounds: Mutex<BTreeMap<Round, BTreeSet<Digest>>>,
+}
+
+impl RoundIndex {
+    /// Record that we stored the certificate with the input digest at the input round.
+    pub fn insert(&self, round: Round, digest: Digest) {
+        self.rounds
+            .lock()
+            .expect("Round index poisoned")
+            .entry(round)
+            .or_default()
+            .insert(digest);
+    }
+
+    /// The digests of the certificates we stored at the input round (if it is indexed).
+    pub fn digests(&self, round: Round) -> Option<BTreeSet<Digest>> {
+        self.rounds
+            .lock()
+            .expect("Round index poisoned")
+            .get(&round)
+            .cloned()
+    }
+
+    /// Drop the rounds below the garbage collection round.
+    pub fn prune(&self, gc_round: Round) {
+        let mut rounds = self.rounds.lock().expect("Round index poisoned");
+        *rounds = rounds.split_off(&gc_round);
+    }
+}
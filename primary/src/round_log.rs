@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use log::Level;
+use std::sync::RwLock;
+
+#[cfg(test)]
+#[path = "tests/round_log_tests.rs"]
+pub mod round_log_tests;
+
+/// The rounds whose events are logged verbosely, if any.
+static ROUND_LOG_RANGE: RwLock<Option<(Round, Round)>> = RwLock::new(None);
+
+/// Log the events of the rounds `[start, end]` verbosely (see `round_debug!`), or stop doing so if `None`.
+/// It can be changed at any time (e.g., through the admin endpoint of the node).
+pub fn set_round_log_range(range: Option<(Round, Round)>) {
+    *ROUND_LOG_RANGE
+        .write()
+        .expect("Failed to lock the round log range") = range;
+}
+
+/// The rounds whose events are logged verbosely, if any.
+pub fn round_log_range() -> Option<(Round, Round)> {
+    *ROUND_LOG_RANGE
+        .read()
+        .expect("Failed to lock the round log range")
+}
+
+/// The level of a verbose event of `round`: the events of the rounds within the range are elevated to
+/// the info level (so they are shown with the default verbosity), the others remain debug events.
+pub fn round_log_level(round: Round) -> Level {
+    match round_log_range() {
+        Some((start, end)) if start <= round && round <= end => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// Log a debug event of a specific round, elevated to the info level if the round is within the range
+/// set by `set_round_log_range`.
+#[macro_export]
+macro_rules! round_debug {
+    ($round:expr, $($arg:tt)+) => {
+        log::log!($crate::round_log_level($round), $($arg)+)
+    };
+}
@@ -0,0 +1,9 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use store::Migration;
+
+/// The migrations upgrading a store written by an older version of the node, one per change of the
+/// encoding of the stored records (headers, certificates, batches...). Add a migration (with the next
+/// format version) whenever such an encoding changes.
+pub fn store_migrations() -> Vec<Box<dyn Migration>> {
+    Vec::new()
+}
@@ -0,0 +1,114 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::SupervisionPolicy;
+use futures::future::{BoxFuture, FutureExt as _};
+use log::error;
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[cfg(test)]
+#[path = "tests/supervisor_tests.rs"]
+pub mod supervisor_tests;
+
+/// The payload of the panics that kill the node whatever the `SupervisionPolicy` of the task (e.g., storage
+/// failures), since running the task again would not help.
+#[derive(Debug)]
+pub struct Fatal(pub String);
+
+/// Panic with a `Fatal` payload, so that the supervisor never restarts the task.
+pub fn fatal(message: &str) -> ! {
+    std::panic::panic_any(Fatal(message.to_string()))
+}
+
+/// Runs the tasks of the primary and applies their `SupervisionPolicy` when they panic: the task either
+/// runs again (rebuilt from the task that panicked) or the supervisor reports the failure, so that the
+/// node shuts down rather than running without one of its components. Fatal panics always abort.
+#[derive(Clone)]
+pub struct Supervisor {
+    /// The policy of each task (by name). The tasks not listed abort.
+    policies: Arc<HashMap<String, SupervisionPolicy>>,
+    /// Holds the name of the first task that aborted (if any).
+    tx_failure: Arc<watch::Sender<Option<String>>>,
+    rx_failure: watch::Receiver<Option<String>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl Supervisor {
+    pub fn new(policies: HashMap<String, SupervisionPolicy>) -> Self {
+        let (tx_failure, rx_failure) = watch::channel(None);
+        Self {
+            policies: Arc::new(policies),
+            tx_failure: Arc::new(tx_failure),
+            rx_failure,
+        }
+    }
+
+    /// The policy applied to the specified task.
+    pub fn policy(&self, task: &str) -> SupervisionPolicy {
+        self.policies.get(task).cloned().unwrap_or_default()
+    }
+
+    /// Wait until a task aborts and return its name.
+    pub async fn failure(&self) -> String {
+        let mut rx_failure = self.rx_failure.clone();
+        while rx_failure.borrow().is_none() {
+            rx_failure
+                .changed()
+                .await
+                .expect("The supervisor holds the failure sender");
+        }
+        let task = rx_failure.borrow().clone();
+        task.unwrap()
+    }
+
+    /// Spawn a task whose main loop is `run`. Since a panic may leave the state of the task inconsistent,
+    /// the task runs again from the state made by `rebuild` out of the state of the task that panicked
+    /// (keeping its configuration and channels, and dropping its in-memory bookkeeping).
+    pub fn spawn<T, R, F>(&self, task: &'static str, mut state: T, rebuild: R, run: F)
+    where
+        T: Send + 'static,
+        R: Fn(T) -> T + Send + 'static,
+        F: for<'a> Fn(&'a mut T) -> BoxFuture<'a, ()> + Send + 'static,
+    {
+        let policy = self.policy(task);
+        let tx_failure = self.tx_failure.clone();
+        tokio::spawn(async move {
+            while let Err(e) = AssertUnwindSafe(run(&mut state)).catch_unwind().await {
+                let reason = panic_message(&e);
+                match policy {
+                    SupervisionPolicy::Restart if !e.is::<Fatal>() => {
+                        error!("Task {} panicked ({}), restarting it", task, reason);
+                        state = rebuild(state);
+                    }
+                    _ => {
+                        error!("Task {} panicked ({}), shutting down", task, reason);
+                        if tx_failure.borrow().is_none() {
+                            let _ = tx_failure.send(Some(task.to_string()));
+                        }
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Extract a printable message from the payload of a panic.
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(Fatal(message)) = payload.downcast_ref::<Fatal>() {
+        message.clone()
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown cause".to_string()
+    }
+}
@@ -1,21 +1,34 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::catch_up::DagProgress;
 use crate::error::DagResult;
 use crate::header_waiter::WaiterMessage;
 use crate::messages::{Certificate, Header};
-use config::Committee;
+use crate::primary::Round;
+use config::{Committee, WorkerId};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use std::collections::HashMap;
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::Sender;
 
+#[cfg(test)]
+#[path = "tests/synchronizer_tests.rs"]
+pub mod synchronizer_tests;
+
 /// The `Synchronizer` checks if we have all batches and parents referenced by a header. If we don't, it sends
 /// a command to the `Waiter` to request the missing data.
 pub struct Synchronizer {
     /// The public key of this primary.
     name: PublicKey,
+    /// Whether we have workers to store the payload (observers do not).
+    has_workers: bool,
     /// The persistent storage.
     store: Store,
+    /// The number of rounds a header may be ahead of our dag before we range-sync (0 disables it).
+    sync_round_gap: Round,
+    /// The progress of our dag (updated by the `Core`).
+    progress: Arc<DagProgress>,
     /// Send commands to the `HeaderWaiter`.
     tx_header_waiter: Sender<WaiterMessage>,
     /// Send commands to the `CertificateWaiter`.
@@ -29,12 +42,17 @@ impl Synchronizer {
         name: PublicKey,
         committee: &Committee,
         store: Store,
+        sync_round_gap: Round,
+        progress: Arc<DagProgress>,
         tx_header_waiter: Sender<WaiterMessage>,
         tx_certificate_waiter: Sender<Certificate>,
     ) -> Self {
         Self {
             name,
+            has_workers: committee.authorities.contains_key(&name),
             store,
+            sync_round_gap,
+            progress,
             tx_header_waiter,
             tx_certificate_waiter,
             genesis: Certificate::genesis(committee)
@@ -48,11 +66,25 @@ impl Synchronizer {
     /// synchronize with other nodes (through our workers), and re-schedule processing of the
     /// header for when we will have its complete payload.
     pub async fn missing_payload(&mut self, header: &Header) -> DagResult<bool> {
-        // We don't store the payload of our own workers.
-        if header.author == self.name {
+        // We don't store the payload of our own workers (and observers have no workers).
+        if header.author == self.name || !self.has_workers {
+            return Ok(false);
+        }
+
+        let missing = self.missing_batches(header).await?;
+        if missing.is_empty() {
             return Ok(false);
         }
 
+        self.tx_header_waiter
+            .send(WaiterMessage::SyncBatches(missing, header.clone()))
+            .await
+            .expect("Failed to send sync batch request");
+        Ok(true)
+    }
+
+    /// Returns the batches of the header that none of our workers stored yet.
+    async fn missing_batches(&mut self, header: &Header) -> DagResult<HashMap<Digest, WorkerId>> {
         let mut missing = HashMap::new();
         for (digest, worker_id) in header.payload.iter() {
             // Check whether we have the batch. If one of our worker has the batch, the primary stores the pair
@@ -72,24 +104,35 @@ impl Synchronizer {
             }
         }
 
-        if missing.is_empty() {
-            return Ok(false);
+        Ok(missing)
+    }
+
+    /// Have our workers sync the batches of a certified header that they do not have yet, so the payload
+    /// is available by the time the certificate is committed. Nothing waits for these batches.
+    pub async fn backfill_payload(&mut self, header: &Header) -> DagResult<()> {
+        // We don't store the payload of our own workers (and observers have no workers).
+        if header.author == self.name || !self.has_workers {
+            return Ok(());
         }
 
-        self.tx_header_waiter
-            .send(WaiterMessage::SyncBatches(missing, header.clone()))
-            .await
-            .expect("Failed to send sync batch request");
-        Ok(true)
+        let missing = self.missing_batches(header).await?;
+        if !missing.is_empty() {
+            self.tx_header_waiter
+                .send(WaiterMessage::BackfillBatches(missing, header.author, header.round))
+                .await
+                .expect("Failed to send backfill request");
+        }
+        Ok(())
     }
 
-    /// Returns the parents of a header if we have them all. If at least one parent is missing,
-    /// we return an empty vector, synchronize with other nodes, and re-schedule processing
-    /// of the header for when we will have all the parents.
+    /// Returns the parents of a header (followed by its weak links) if we have them all. If at least
+    /// one of them is missing, we return an empty vector, synchronize with other nodes, and re-schedule
+    /// processing of the header for when we will have all the parents. If the header is far ahead of
+    /// our dag, we sync all the rounds we are missing at once rather than only its parents.
     pub async fn get_parents(&mut self, header: &Header) -> DagResult<Vec<Certificate>> {
         let mut missing = Vec::new();
         let mut parents = Vec::new();
-        for digest in &header.parents {
+        for digest in header.parents.iter().chain(&header.weak_links) {
             if let Some(genesis) = self
                 .genesis
                 .iter()
@@ -110,8 +153,14 @@ impl Synchronizer {
             return Ok(parents);
         }
 
+        let dag_round = self.progress.dag_round();
+        let message = if self.sync_round_gap > 0 && header.round > dag_round + self.sync_round_gap {
+            WaiterMessage::RangeSync(missing, header.clone(), /* from */ dag_round + 1)
+        } else {
+            WaiterMessage::SyncParents(missing, header.clone())
+        };
         self.tx_header_waiter
-            .send(WaiterMessage::SyncParents(missing, header.clone()))
+            .send(message)
             .await
             .expect("Failed to send sync parents request");
         Ok(Vec::new())
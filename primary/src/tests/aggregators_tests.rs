@@ -0,0 +1,65 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, header, votes};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn notify_votes_progress() {
+    let committee = committee();
+    let header = header();
+
+    // Record every notification of the aggregator.
+    let notifications = Arc::new(Mutex::new(Vec::new()));
+    let recorded = notifications.clone();
+    let mut aggregator = VotesAggregator::new().with_progress(Box::new(move |stake, threshold| {
+        recorded.lock().unwrap().push((stake, threshold))
+    }));
+
+    // Append all the votes; the certificate is made once they reach the quorum.
+    let mut certified = Vec::new();
+    for vote in votes(&header) {
+        let certificate = aggregator.append(vote, &committee, &header).unwrap();
+        certified.push(certificate.is_some());
+    }
+    assert_eq!(certified, vec![false, false, true, false]);
+
+    // Ensure we were notified of the increasing stake after every vote.
+    let threshold = committee.quorum_threshold();
+    let expected: Vec<_> = (1..=4).map(|stake| (stake, threshold)).collect();
+    assert_eq!(*notifications.lock().unwrap(), expected);
+
+    // A duplicate vote adds no stake and is not notified.
+    let vote = votes(&header).pop().unwrap();
+    assert!(aggregator.append(vote, &committee, &header).is_err());
+    assert_eq!(notifications.lock().unwrap().len(), 4);
+}
+
+#[test]
+fn ignore_votes_without_stake() {
+    let mut committee = committee();
+    let header = header();
+
+    // Remove the stake of the authority voting first.
+    let mut votes = votes(&header);
+    let zero = votes[0].author;
+    committee.authorities.get_mut(&zero).unwrap().stake = 0;
+
+    // Its vote is well formed but adds no stake.
+    assert!(votes[0].verify(&committee).is_ok());
+    let mut aggregator = VotesAggregator::new();
+    let vote = votes.remove(0);
+    assert!(aggregator
+        .append(vote, &committee, &header)
+        .unwrap()
+        .is_none());
+    assert_eq!(aggregator.weight, 0);
+
+    // Ensure the certificate is made from a quorum of the other votes only.
+    let mut certificate = None;
+    for vote in votes {
+        certificate = aggregator.append(vote, &committee, &header).unwrap();
+    }
+    let certificate = certificate.unwrap();
+    assert!(certificate.votes.iter().all(|(name, _)| name != &zero));
+    assert!(certificate.verify(&committee).is_ok());
+}
@@ -0,0 +1,29 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::keys;
+
+#[test]
+fn rotate_when_full() {
+    let path = ".db_test_rotate_audit_log";
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(AuditLog::rotated(Path::new(path)));
+
+    // Make a log holding two events at most.
+    let (author, _) = keys().pop().unwrap();
+    let events: Vec<_> = (1..=5)
+        .map(|round| AuditEvent::HeaderAccepted {
+            id: Digest::default(),
+            round,
+            author,
+        })
+        .collect();
+    let size = bincode::serialize(&events[0]).unwrap().len() as u64;
+    let mut audit_log = AuditLog::new(path, 2 * size).unwrap();
+    for event in &events {
+        audit_log.append(event);
+    }
+
+    // Ensure we keep the last events (in order) and the log stays bounded.
+    assert_eq!(AuditLog::read(path).unwrap(), events[2..].to_vec());
+    assert_eq!(fs::metadata(path).unwrap().len(), size);
+}
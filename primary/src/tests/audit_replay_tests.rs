@@ -0,0 +1,93 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::audit_log::AuditLog;
+use crate::common::{certificate, committee, header, headers};
+use crate::messages::Header;
+use crypto::Hash as _;
+use std::fs;
+
+// Record the decisions of a primary certifying its header of round 1 and moving to round 2.
+fn captured_audit_log(path: &str) -> Vec<AuditEvent> {
+    let _ = fs::remove_file(path);
+    let mut audit_log = AuditLog::new(path, /* max_size */ 1_000_000).unwrap();
+
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &certificates {
+        audit_log.append(&AuditEvent::HeaderAccepted {
+            id: x.header.id.clone(),
+            round: 1,
+            author: x.origin(),
+        });
+        audit_log.append(&AuditEvent::VoteCast {
+            id: x.header.id.clone(),
+            round: 1,
+            origin: x.origin(),
+        });
+    }
+    audit_log.append(&AuditEvent::CertificateFormed {
+        digest: certificate(&header()).digest(),
+        round: 1,
+    });
+    for (i, x) in certificates.iter().enumerate() {
+        audit_log.append(&AuditEvent::CertificateAccepted {
+            digest: x.digest(),
+            round: 1,
+            origin: x.origin(),
+        });
+        if i == 2 {
+            audit_log.append(&AuditEvent::RoundAdvanced { round: 2 });
+        }
+    }
+    audit_log.append(&AuditEvent::CertificateFormed {
+        digest: certificate(&Header { round: 2, ..header() }).digest(),
+        round: 2,
+    });
+    AuditLog::read(path).unwrap()
+}
+
+#[test]
+fn replay_valid_decisions() {
+    let events = captured_audit_log(".db_test_replay_valid_decisions.audit");
+    assert_eq!(events.len(), 15);
+    assert!(replay_audit_log(&events, &committee(), ParentQuorumPolicy::Quorum).is_empty());
+}
+
+#[test]
+fn flag_invalid_decisions() {
+    let mut events = captured_audit_log(".db_test_flag_invalid_decisions.audit");
+
+    // Inject a vote for a second header of the same author and round.
+    let equivocation = Header {
+        id: Digest([1; 32]),
+        ..header()
+    };
+    events.push(AuditEvent::HeaderAccepted {
+        id: equivocation.id.clone(),
+        round: 1,
+        author: equivocation.author,
+    });
+    events.push(AuditEvent::VoteCast {
+        id: equivocation.id.clone(),
+        round: 1,
+        origin: equivocation.author,
+    });
+
+    // Inject a move to round 3 without any certificate of round 2.
+    events.push(AuditEvent::RoundAdvanced { round: 3 });
+
+    let invalid = replay_audit_log(&events, &committee(), ParentQuorumPolicy::Quorum);
+    let flagged: Vec<_> = invalid.iter().map(|x| (x.index, x.error.clone())).collect();
+    assert_eq!(
+        flagged,
+        vec![
+            (
+                16,
+                ReplayError::DoubleVote {
+                    origin: equivocation.author,
+                    round: 1
+                }
+            ),
+            (17, ReplayError::RoundWithoutParents(3)),
+        ]
+    );
+}
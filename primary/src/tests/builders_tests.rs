@@ -0,0 +1,30 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+
+#[test]
+fn build_valid_certificate() {
+    let committee = committee();
+    let keys = keys();
+    let (author, secret) = &keys[0];
+
+    // Build a header of round 1 referencing a batch.
+    let header = HeaderBuilder::new(*author)
+        .round(1)
+        .genesis_parents(&committee)
+        .payload([(Digest([1; 32]), 0)].iter().cloned().collect())
+        .build(secret);
+    assert!(header.verify(&committee).is_ok());
+
+    // Ensure a certificate with the votes of a quorum verifies.
+    let certificate = CertificateBuilder::new(header.clone())
+        .signers(&keys[..3])
+        .build();
+    assert_eq!(certificate.round(), 1);
+    assert_eq!(certificate.origin(), *author);
+    assert!(certificate.verify(&committee).is_ok());
+
+    // Ensure a certificate without a quorum of votes does not.
+    let certificate = CertificateBuilder::new(header).signers(&keys[..1]).build();
+    assert!(certificate.verify(&committee).is_err());
+}
@@ -0,0 +1,134 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener};
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn sync_before_proposing() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_700);
+    let progress = Arc::new(DagProgress::default());
+    let paused = Arc::new(AtomicBool::new(false));
+
+    // Spawn listeners to receive the range requests.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, x)| listener(x.primary_to_primary))
+        .collect();
+
+    // Our peers are far ahead of us.
+    for (author, _) in committee.others_primaries(&name) {
+        progress.observe(author, 20);
+    }
+
+    // Spawn the catch-up task.
+    CatchUp::spawn(
+        name,
+        committee,
+        /* round_gap */ 5,
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3,
+        progress.clone(),
+        paused.clone(),
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+    );
+
+    // Ensure we pause and request the rounds we are missing.
+    let expected = bincode::serialize(&PrimaryMessage::CertificatesRangeRequest(1, 20, name)).unwrap();
+    for handle in handles {
+        let received = handle.await.unwrap();
+        assert_eq!(received, expected);
+    }
+    assert!(paused.load(Ordering::Relaxed));
+
+    // Ensure we resume proposing once we synced within the round gap of our peers.
+    progress.advance(16);
+    sleep(Duration::from_millis(300)).await;
+    assert!(!paused.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn ready_once_caught_up() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(15_350);
+    let others: Vec<_> = committee
+        .others_primaries(&name)
+        .into_iter()
+        .map(|(x, _)| x)
+        .collect();
+    let progress = Arc::new(DagProgress::default());
+    let paused = Arc::new(AtomicBool::new(false));
+    let ready = Arc::new(AtomicBool::new(false));
+
+    // Spawn the catch-up task.
+    CatchUp::spawn(
+        name,
+        committee,
+        /* round_gap */ 5,
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3,
+        progress.clone(),
+        paused.clone(),
+        ready.clone(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+    );
+
+    // We are not ready until we hear from our peers.
+    sleep(Duration::from_millis(300)).await;
+    assert!(!ready.load(Ordering::Relaxed));
+
+    // A single peer (possibly Byzantine) cannot make us lag behind.
+    progress.observe(others[0], 1_000_000);
+    sleep(Duration::from_millis(300)).await;
+    assert!(!paused.load(Ordering::Relaxed));
+
+    // Nor are we ready while f+1 of our peers are too far ahead.
+    progress.observe(others[1], 20);
+    sleep(Duration::from_millis(300)).await;
+    assert!(!ready.load(Ordering::Relaxed));
+    assert!(paused.load(Ordering::Relaxed));
+
+    // Ensure we are ready once we synced within the round gap of our peers.
+    progress.advance(16);
+    sleep(Duration::from_millis(300)).await;
+    assert!(ready.load(Ordering::Relaxed));
+    assert!(!paused.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn keep_operator_pause() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(15_725);
+    let progress = Arc::new(DagProgress::default());
+    let paused = Arc::new(AtomicBool::new(true));
+
+    // Spawn the catch-up task while the primary is paused (e.g., by an operator).
+    CatchUp::spawn(
+        name,
+        committee.clone(),
+        /* round_gap */ 5,
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3,
+        progress.clone(),
+        paused.clone(),
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+    );
+
+    // Ensure catching up does not resume a primary it did not pause.
+    for (author, _) in committee.others_primaries(&name) {
+        progress.observe(author, 20);
+    }
+    sleep(Duration::from_millis(300)).await;
+    progress.advance(16);
+    sleep(Duration::from_millis(300)).await;
+    assert!(paused.load(Ordering::Relaxed));
+}
@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use tokio::sync::mpsc::channel;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn alarm_on_stalled_certificate() {
+    let stalled = Arc::new(AtomicU64::new(0));
+    let (tx_core, rx_core) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_feedback, rx_feedback) = channel(1);
+    let (tx_garbage_collector, mut rx_garbage_collector) = channel(1);
+
+    // Spawn the monitor.
+    CertificateMonitor::spawn(
+        /* threshold */ 50,
+        /* gc_depth */ 50,
+        stalled.clone(),
+        rx_core,
+        tx_consensus,
+        /* rx_consensus */ rx_feedback,
+        tx_garbage_collector,
+    );
+
+    // Deliver a certificate and ensure it is forwarded to the consensus.
+    let certificate = certificate(&header());
+    tx_core.send(certificate.clone()).await.unwrap();
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificate);
+
+    // Hold it uncommitted past the threshold and ensure the alarm fires.
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(stalled.load(Ordering::Relaxed), 1);
+
+    // Commit it and ensure the alarm clears.
+    tx_feedback.send(certificate.clone()).await.unwrap();
+    assert_eq!(rx_garbage_collector.recv().await.unwrap(), certificate);
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(stalled.load(Ordering::Relaxed), 0);
+}
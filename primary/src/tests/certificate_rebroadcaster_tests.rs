@@ -0,0 +1,138 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use std::fs;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Acknowledge every message received on the address (over any number of connections) and forward
+// them to the returned channel.
+fn ack_listener(address: SocketAddr, tx: Sender<Bytes>) {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+                while let Some(Ok(message)) = transport.next().await {
+                    let _ = transport.send(Bytes::from("Ack")).await;
+                    let _ = tx.send(message.freeze()).await;
+                }
+            });
+        }
+    });
+}
+
+// Read the digests of the certificates persisted as pending.
+async fn pending_digests(store: &mut Store) -> Vec<Digest> {
+    match store.read(PENDING_BROADCASTS_KEY.to_vec()).await.unwrap() {
+        Some(bytes) => bincode::deserialize(&bytes).unwrap(),
+        None => Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn rebroadcast_after_outage() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_100);
+    let certificate = certificate(&header());
+    let path = ".db_test_rebroadcast_after_outage";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn the rebroadcaster and hand it over a certificate while no other authority is reachable.
+    let (tx_core, rx_core) = channel(1);
+    CertificateRebroadcaster::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        /* rebroadcast_delay */ 100,
+        /* gc_depth */ 50,
+        /* consensus_round */ Arc::default(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_core,
+    );
+    tx_core.send(certificate.clone()).await.unwrap();
+
+    // The certificate remains pending during the outage.
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(pending_digests(&mut store).await, vec![certificate.digest()]);
+
+    // The other authorities come back: they eventually receive the certificate.
+    let (tx_received, mut rx_received) = channel(100);
+    let others = committee.others_primaries(&name);
+    for (_, address) in &others {
+        ack_listener(address.primary_to_primary, tx_received.clone());
+    }
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificate)).unwrap();
+    for _ in 0..others.len() {
+        let received = timeout(Duration::from_secs(10), rx_received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, expected);
+    }
+
+    // Once acknowledged by a quorum, the certificate is no longer pending.
+    timeout(Duration::from_secs(5), async {
+        while !pending_digests(&mut store).await.is_empty() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn rebroadcast_after_restart() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_500);
+    let certificate = certificate(&header());
+    let path = ".db_test_rebroadcast_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Persist a pending certificate, as left by a previous run.
+    let bytes = bincode::serialize(&certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let bytes = bincode::serialize(&vec![certificate.digest()]).unwrap();
+    store.write(PENDING_BROADCASTS_KEY.to_vec(), bytes).await;
+
+    // Spawn the listeners of the other authorities.
+    let (tx_received, mut rx_received) = channel(100);
+    let others = committee.others_primaries(&name);
+    for (_, address) in &others {
+        ack_listener(address.primary_to_primary, tx_received.clone());
+    }
+
+    // Spawn the rebroadcaster: it broadcasts the pending certificate right away.
+    let (_tx_core, rx_core) = channel(1);
+    CertificateRebroadcaster::spawn(
+        name,
+        committee,
+        store,
+        /* rebroadcast_delay */ 100,
+        /* gc_depth */ 50,
+        /* consensus_round */ Arc::default(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_core,
+    );
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificate)).unwrap();
+    for _ in 0..others.len() {
+        let received = timeout(Duration::from_secs(5), rx_received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, expected);
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use crypto::Digest;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout};
+
+#[tokio::test]
+async fn drop_expired_certificates() {
+    let (tx_sync_certificates, rx_sync_certificates) = channel(10);
+    let (tx_certificates_loopback, mut rx_certificates_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_drop_expired_certificates";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn the waiter, dropping the certificates waiting for more than 200 ms.
+    CertificateWaiter::spawn(
+        store.clone(),
+        /* max_age */ 200,
+        rx_sync_certificates,
+        tx_certificates_loopback,
+        &Supervisor::default(),
+    );
+
+    // Two certificates wait for their (missing) parents.
+    let certificates: Vec<_> = (0..2u8)
+        .map(|i| {
+            certificate(&Header {
+                id: Digest([i + 50; 32]),
+                parents: [Digest([i; 32])].iter().cloned().collect(),
+                ..header()
+            })
+        })
+        .collect();
+    for x in &certificates {
+        tx_sync_certificates.send(x.clone()).await.unwrap();
+    }
+
+    // The first parent arrives in time.
+    sleep(Duration::from_millis(50)).await;
+    store.write(vec![0; 32], Vec::new()).await;
+    let output = rx_certificates_loopback.recv().await.unwrap();
+    assert_eq!(output, certificates[0]);
+
+    // The second parent is still missing past the max age: its certificate is dropped, so it is not
+    // delivered even once the parent eventually arrives.
+    sleep(Duration::from_millis(300)).await;
+    store.write(vec![1; 32], Vec::new()).await;
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, rx_certificates_loopback.recv()).await.is_err());
+}
@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, header, keys};
+use crate::messages::Header;
+use std::collections::BTreeMap;
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn output_in_commit_order() {
+    let (name, _) = keys().pop().unwrap();
+    let (tx_consensus, rx_consensus) = channel(2);
+    let (tx_garbage_collector, mut rx_garbage_collector) = channel(2);
+    let (tx_workers, rx_workers) = channel(3);
+    let (tx_output, mut rx_output) = channel(2);
+
+    let path = ".db_test_output_in_commit_order";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the commit output.
+    CommitOutput::spawn(
+        name,
+        committee_with_base_port(13_900),
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        store,
+        /* checkpoint_interval */ 0,
+        rx_consensus,
+        tx_garbage_collector,
+        rx_workers,
+        tx_output,
+    );
+
+    // Commit two certificates: the second one references two batches.
+    let batch = |i: u8| (Digest([i; 32]), vec![vec![i; 10], vec![i + 100; 10]]);
+    let certificate = |batches: &[u8]| Certificate {
+        header: Header {
+            payload: batches.iter().map(|i| (Digest([*i; 32]), 0)).collect::<BTreeMap<_, _>>(),
+            ..header()
+        },
+        ..Certificate::default()
+    };
+    let first = certificate(&[3]);
+    let second = certificate(&[2, 1]);
+    for x in [&first, &second] {
+        tx_consensus.send(x.clone()).await.unwrap();
+    }
+
+    // The garbage collector receives the certificates right away.
+    assert_eq!(rx_garbage_collector.recv().await.unwrap(), first);
+    assert_eq!(rx_garbage_collector.recv().await.unwrap(), second);
+
+    // Our workers reply with the batches out of order.
+    for i in [2, 3, 1] {
+        tx_workers.send(batch(i)).await.unwrap();
+    }
+
+    // Ensure the transactions are output in commit order, and then in the order of the payload.
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.certificate, first);
+    assert_eq!(output.transactions, batch(3).1);
+
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.certificate, second);
+    let expected: Vec<_> = batch(1).1.into_iter().chain(batch(2).1).collect();
+    assert_eq!(output.transactions, expected);
+}
+
+#[tokio::test]
+async fn output_checkpoints() {
+    let (name, _) = keys().pop().unwrap();
+    let (tx_consensus, rx_consensus) = channel(10);
+    let (tx_garbage_collector, _rx_garbage_collector) = channel(10);
+    let (_tx_workers, rx_workers) = channel(1);
+    let (tx_output, mut rx_output) = channel(10);
+
+    let path = ".db_test_output_checkpoints";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn the commit output, checkpointing every 2 committed rounds.
+    CommitOutput::spawn(
+        name,
+        committee_with_base_port(13_950),
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        store.clone(),
+        /* checkpoint_interval */ 2,
+        rx_consensus,
+        tx_garbage_collector,
+        rx_workers,
+        tx_output,
+    );
+
+    // Commit certificates (without payload) of rounds 1, 2, 2, 3, 5, and 6.
+    let certificates: Vec<_> = [1, 2, 2, 3, 5, 6]
+        .iter()
+        .enumerate()
+        .map(|(i, round)| Certificate {
+            header: Header {
+                round: *round,
+                id: Digest([i as u8; 32]),
+                ..header()
+            },
+            ..Certificate::default()
+        })
+        .collect();
+    for x in &certificates {
+        tx_consensus.send(x.clone()).await.unwrap();
+    }
+
+    // Ensure checkpoints are output with the first certificates of rounds 2 and 5 (round 6 is only one
+    // round after the latest checkpoint).
+    let mut checkpoints = Vec::new();
+    for x in &certificates {
+        let output = rx_output.recv().await.unwrap();
+        assert_eq!(&output.certificate, x);
+        checkpoints.push(output.checkpoint);
+    }
+    let checkpoint = |i: usize| Checkpoint {
+        round: certificates[i].round(),
+        index: i as u64 + 1,
+        digest: certificates[i].digest(),
+    };
+    let expected = vec![None, Some(checkpoint(1)), None, None, Some(checkpoint(4)), None];
+    assert_eq!(checkpoints, expected);
+
+    // Ensure the latest checkpoint is persisted.
+    assert_eq!(Checkpoint::load(&mut store).await.unwrap(), Some(checkpoint(4)));
+}
@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::builders::{CertificateBuilder, HeaderBuilder};
 use crate::messages::{Certificate, Header, Vote};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
@@ -8,6 +9,7 @@ use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
@@ -34,6 +36,7 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .enumerate()
@@ -63,6 +66,7 @@ pub fn committee() -> Committee {
                 )
             })
             .collect(),
+        observers: BTreeMap::new(),
     }
 }
 
@@ -95,20 +99,10 @@ pub fn committee_with_base_port(base_port: u16) -> Committee {
 // Fixture
 pub fn header() -> Header {
     let (author, secret) = keys().pop().unwrap();
-    let header = Header {
-        author,
-        round: 1,
-        parents: Certificate::genesis(&committee())
-            .iter()
-            .map(|x| x.digest())
-            .collect(),
-        ..Header::default()
-    };
-    Header {
-        id: header.digest(),
-        signature: Signature::new(&header.digest(), &secret),
-        ..header
-    }
+    HeaderBuilder::new(author)
+        .round(1)
+        .genesis_parents(&committee())
+        .build(&secret)
 }
 
 // Fixture
@@ -116,20 +110,10 @@ pub fn headers() -> Vec<Header> {
     keys()
         .into_iter()
         .map(|(author, secret)| {
-            let header = Header {
-                author,
-                round: 1,
-                parents: Certificate::genesis(&committee())
-                    .iter()
-                    .map(|x| x.digest())
-                    .collect(),
-                ..Header::default()
-            };
-            Header {
-                id: header.digest(),
-                signature: Signature::new(&header.digest(), &secret),
-                ..header
-            }
+            HeaderBuilder::new(author)
+                .round(1)
+                .genesis_parents(&committee())
+                .build(&secret)
         })
         .collect()
 }
@@ -156,13 +140,9 @@ pub fn votes(header: &Header) -> Vec<Vote> {
 
 // Fixture
 pub fn certificate(header: &Header) -> Certificate {
-    Certificate {
-        header: header.clone(),
-        votes: votes(&header)
-            .into_iter()
-            .map(|x| (x.author, x.signature))
-            .collect(),
-    }
+    CertificateBuilder::new(header.clone())
+        .signers(&keys())
+        .build()
 }
 
 // Fixture
@@ -3,9 +3,14 @@ use super::*;
 use crate::common::{
     certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
 };
+use crate::header_waiter::WaiterMessage;
+use crypto::{generate_keypair, SecretKey, Signature};
 use futures::future::try_join_all;
+use rand::rngs::StdRng;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn process_header() {
@@ -45,6 +50,8 @@ async fn process_header() {
         name,
         &committee,
         store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -57,13 +64,25 @@ async fn process_header() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
     );
 
     // Send a header to the core.
@@ -112,6 +131,8 @@ async fn process_header_missing_parent() {
         name,
         &committee(),
         store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -124,13 +145,25 @@ async fn process_header_missing_parent() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
     );
 
     // Send a header to the core.
@@ -172,6 +205,8 @@ async fn process_header_missing_payload() {
         name,
         &committee(),
         store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -184,13 +219,25 @@ async fn process_header_missing_payload() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
     );
 
     // Send a header to the core.
@@ -234,6 +281,8 @@ async fn process_votes() {
         name,
         &committee,
         store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -246,17 +295,33 @@ async fn process_votes() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
     );
 
-    // Make the certificate we expect to receive.
-    let expected = certificate(&Header::default());
+    // Make the certificate we expect to receive (for the placeholder header the core starts with).
+    let header = Header {
+        author: name,
+        ..Header::default()
+    };
+    let expected = certificate(&header);
 
     // Spawn all listeners to receive our newly formed certificate.
     let handles: Vec<_> = committee
@@ -266,7 +331,7 @@ async fn process_votes() {
         .collect();
 
     // Send a votes to the core.
-    for vote in votes(&Header::default()) {
+    for vote in votes(&header) {
         tx_primary_messages
             .send(PrimaryMessage::Vote(vote))
             .await
@@ -306,6 +371,8 @@ async fn process_certificates() {
         name,
         &committee(),
         store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
     );
@@ -318,13 +385,25 @@ async fn process_certificates() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
     );
 
     // Send enough certificates to the core.
@@ -359,3 +438,2053 @@ async fn process_certificates() {
         assert_eq!(stored, Some(serialized));
     }
 }
+
+#[tokio::test]
+async fn drop_processed_certificates() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(3);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_drop_processed_certificates";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send the same certificate twice, followed by another certificate.
+    let certificates: Vec<_> = headers().iter().take(2).map(certificate).collect();
+    for x in [&certificates[0], &certificates[0], &certificates[1]] {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x.clone()))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core only outputs each certificate once.
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificates[0]);
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificates[1]);
+
+    // Ensure the duplicate certificate was not verified again.
+    assert_eq!(CERTIFICATE_VERIFICATIONS.with(|x| x.get()), 2);
+}
+
+#[tokio::test]
+async fn rerequest_missing_votes() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(13_300);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_rerequest_missing_votes";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn listeners to receive our header.
+    let addresses: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(name, x)| (*name, x.primary_to_primary))
+        .collect();
+    let handles: Vec<_> = addresses.iter().map(|(_, x)| listener(*x)).collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            vote_timeout: 500,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send our header to the core and ensure it is broadcast.
+    tx_headers.send(header()).await.unwrap();
+    try_join_all(handles).await.unwrap();
+
+    // Only one other authority votes for our header.
+    let (voter, _) = addresses[0];
+    let vote = votes(&header())
+        .into_iter()
+        .find(|x| x.author == voter)
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Vote(vote))
+        .await
+        .unwrap();
+
+    // Ensure only the authorities that did not vote receive the header again.
+    let mut handles: Vec<_> = addresses.iter().map(|(_, x)| listener(*x)).collect();
+    let voter_handle = handles.remove(0);
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Header(x) => assert_eq!(x, header()),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    assert!(timeout(Duration::from_millis(500), voter_handle)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn partial_header_broadcast() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_200);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_partial_header_broadcast";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn listeners to receive our header.
+    let addresses: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(name, x)| (*name, x.primary_to_primary))
+        .collect();
+    let handles: Vec<_> = addresses.iter().map(|(_, x)| listener(*x)).collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            vote_timeout: 500,
+            header_broadcast_margin: Some(0),
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send our header to the core and ensure it only reaches a quorum (counting ourselves).
+    tx_headers.send(header()).await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+    let mut pending = Vec::new();
+    for mut handle in handles {
+        match timeout(Duration::from_millis(10), &mut handle).await {
+            Ok(received) => match bincode::deserialize(&received.unwrap()).unwrap() {
+                PrimaryMessage::Header(x) => assert_eq!(x, header()),
+                x => panic!("Unexpected message: {:?}", x),
+            },
+            Err(_) => pending.push(handle),
+        }
+    }
+    assert_eq!(pending.len(), 1);
+
+    // Ensure the remaining authority gets the header once the votes time out.
+    let received = timeout(Duration::from_millis(1_000), pending.pop().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn process_header_with_weak_links() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(13_400);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store holding the certificates of round 1.
+    let path = ".db_test_process_header_with_weak_links";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let parents: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &parents {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+
+    // Make headers of round 2 weakly linking to the genesis.
+    let genesis: Vec<_> = Certificate::genesis(&committee)
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+    let make_header = |parents: &[Certificate], weak_links: &[Digest]| {
+        let header = Header {
+            author,
+            round: 2,
+            parents: parents.iter().map(|x| x.digest()).collect(),
+            weak_links: weak_links.iter().cloned().collect(),
+            ..Header::default()
+        };
+        Header {
+            id: header.digest(),
+            signature: Signature::new(&header.digest(), &author_secret),
+            ..header
+        }
+    };
+    let invalid = make_header(&parents[..2], &genesis[..1]);
+    let too_many_links = make_header(&parents[..3], &genesis[..2]);
+    let valid = make_header(&parents[..3], &genesis[..1]);
+
+    // Spawn a listener to receive the vote.
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            max_weak_links: 1,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Weak links do not count towards the quorum of parents: the first header is rejected. The second
+    // header is rejected because it has more weak links than allowed.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(invalid))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Header(too_many_links))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Header(valid.clone()))
+        .await
+        .unwrap();
+
+    // Ensure we only vote for the header with a quorum of parents and few enough weak links.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x.id, valid.id),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn cache_invalid_certificates() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(3);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_cache_invalid_certificates";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            verification_cache_size: 10,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send twice a certificate without a quorum of votes, followed by the valid certificate
+    // (which has the same digest).
+    let valid = certificate(&header());
+    let invalid = Certificate {
+        votes: valid.votes[..1].to_vec(),
+        ..valid.clone()
+    };
+    for x in [&invalid, &invalid, &valid] {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x.clone()))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the valid certificate is not affected by the verdict of the invalid one.
+    assert_eq!(rx_consensus.recv().await.unwrap(), valid);
+
+    // Ensure the invalid certificate was only verified once.
+    assert_eq!(CERTIFICATE_VERIFICATIONS.with(|x| x.get()), 2);
+}
+
+#[tokio::test]
+async fn apply_early_votes() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_apply_early_votes";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee_with_base_port(13_600),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee_with_base_port(13_600),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Deliver the votes of the other authorities before our header.
+    let header = header();
+    for vote in votes(&header).into_iter().filter(|x| x.author != name) {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(50)).await;
+
+    // Propose the header and ensure the early votes are counted.
+    tx_headers.send(header.clone()).await.unwrap();
+    let certificate = timeout(Duration::from_millis(1_000), rx_consensus.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(certificate.header.id, header.id);
+}
+
+#[tokio::test]
+async fn drop_certificates_processed_before_restart() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(2);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(2);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store holding a certificate we processed before restarting.
+    let path = ".db_test_drop_certificates_processed_before_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates: Vec<_> = headers().iter().take(2).map(certificate).collect();
+    let bytes = bincode::serialize(&certificates[0]).unwrap();
+    store.write(certificates[0].digest().to_vec(), bytes).await;
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core (with an empty memory, as after a restart).
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send the stored certificate followed by another certificate.
+    for x in &certificates {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x.clone()))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core only processes (and verifies) the new certificate.
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificates[1]);
+    assert_eq!(CERTIFICATE_VERIFICATIONS.with(|x| x.get()), 1);
+}
+
+#[tokio::test]
+async fn skip_signatures_on_trusted_network() {
+    // Make a structurally valid certificate without any valid signature.
+    let valid = certificate(&header());
+    let unsigned = Certificate {
+        header: Header {
+            signature: Signature::default(),
+            ..valid.header.clone()
+        },
+        votes: valid
+            .votes
+            .iter()
+            .map(|(name, _)| (*name, Signature::default()))
+            .collect(),
+    };
+
+    for trusted_network in [true, false] {
+        let (name, secret) = keys().pop().unwrap();
+        let signature_service = SignatureService::new(secret);
+
+        let (tx_sync_headers, _rx_sync_headers) = channel(1);
+        let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+        let (tx_primary_messages, rx_primary_messages) = channel(1);
+        let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+        let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+        let (_tx_headers, rx_headers) = channel(1);
+        let (tx_consensus, mut rx_consensus) = channel(1);
+        let (tx_parents, _rx_parents) = channel(1);
+
+        // Create a new test store.
+        let path = format!(".db_test_skip_signatures_on_trusted_network_{}", trusted_network);
+        let _ = fs::remove_dir_all(&path);
+        let store = Store::new(&path).unwrap();
+
+        // Make a synchronizer for the core.
+        let synchronizer = Synchronizer::new(
+            name,
+            &committee(),
+            store.clone(),
+            /* sync_round_gap */ 0,
+            Arc::new(DagProgress::default()),
+            /* tx_header_waiter */ tx_sync_headers,
+            /* tx_certificate_waiter */ tx_sync_certificates,
+        );
+
+        // Spawn the core.
+        Core::spawn(
+            name,
+            committee(),
+            store.clone(),
+            synchronizer,
+            signature_service,
+            /* consensus_round */ Arc::new(AtomicU64::new(0)),
+            /* paused */ Arc::new(AtomicBool::new(false)),
+            /* progress */ Arc::new(DagProgress::default()),
+            /* round_index */ Arc::default(),
+            CoreParameters {
+                trusted_network,
+                ..CoreParameters::default()
+            },
+            /* audit_log */ None,
+            /* pending_certificate */ Arc::default(),
+            /* misrouted_votes */ Arc::default(),
+            /* tx_rebroadcaster */ None,
+            /* protocol_version */ None,
+            /* message_tee */ None,
+            /* bandwidth */ Bandwidth::default(),
+            /* rx_primaries */ rx_primary_messages,
+            /* rx_header_waiter */ rx_headers_loopback,
+            /* rx_certificate_waiter */ rx_certificates_loopback,
+            /* rx_proposer */ rx_headers,
+            tx_consensus,
+            /* tx_proposer */ tx_parents,
+            /* tx_round */ watch::channel(0).0,
+            &Supervisor::default(),
+        );
+
+        // Send the unsigned certificate to the core.
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(unsigned.clone()))
+            .await
+            .unwrap();
+
+        // Ensure the certificate is only accepted on a trusted network.
+        let delivered = timeout(Duration::from_millis(500), rx_consensus.recv()).await;
+        if trusted_network {
+            assert_eq!(delivered.unwrap().unwrap(), unsigned);
+        } else {
+            assert!(delivered.is_err());
+        }
+    }
+}
+
+#[tokio::test]
+async fn record_audit_log() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(3);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store and audit log.
+    let path = ".db_test_record_audit_log";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let audit_path = ".db_test_record_audit_log.audit";
+    let _ = fs::remove_file(audit_path);
+    let audit_log = AuditLog::new(audit_path, /* max_size */ 1_000_000).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        Some(audit_log),
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send enough certificates to the core to move to the next round.
+    let certificates: Vec<_> = headers().iter().take(3).map(certificate).collect();
+    for x in certificates.clone() {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x))
+            .await
+            .unwrap();
+    }
+    for _ in &certificates {
+        rx_consensus.recv().await.unwrap();
+    }
+
+    // Ensure the audit log holds all our decisions, in order.
+    let mut expected = Vec::new();
+    for x in &certificates {
+        expected.push(AuditEvent::HeaderAccepted {
+            id: x.header.id.clone(),
+            round: 1,
+            author: x.origin(),
+        });
+        expected.push(AuditEvent::VoteCast {
+            id: x.header.id.clone(),
+            round: 1,
+            origin: x.origin(),
+        });
+        expected.push(AuditEvent::CertificateAccepted {
+            digest: x.digest(),
+            round: 1,
+            origin: x.origin(),
+        });
+    }
+    expected.push(AuditEvent::RoundAdvanced { round: 2 });
+    assert_eq!(AuditLog::read(audit_path).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn report_pending_certificate() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_report_pending_certificate";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    let pending_certificate = Arc::new(Mutex::new(None));
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        pending_certificate.clone(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send votes short of a quorum to the core.
+    let header = Header {
+        author: name,
+        ..Header::default()
+    };
+    for vote in votes(&header).into_iter().take(2) {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core reports the stake of the votes it gathered.
+    let expected = PendingCertificate {
+        header: header.id,
+        round: 0,
+        stake: 2,
+        threshold: committee().quorum_threshold(),
+    };
+    timeout(Duration::from_millis(1_000), async {
+        while pending_certificate.lock().unwrap().as_ref() != Some(&expected) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn backfill_payload_of_certificates() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, mut rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_backfill_payload_of_certificates";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send the certificate of another authority whose payload we do not have.
+    let (author, secret) = keys().remove(0);
+    let payload: BTreeMap<_, _> = [(Digest([1; 32]), 0), (Digest([2; 32]), 0)]
+        .iter()
+        .cloned()
+        .collect();
+    let header = Header::new(
+        author,
+        /* round */ 1,
+        payload.clone(),
+        headers()[0].parents.clone(),
+        BTreeSet::new(),
+        &mut SignatureService::new(secret),
+    )
+    .await;
+    let certificate = certificate(&header);
+    tx_primary_messages
+        .send(PrimaryMessage::Certificate(certificate.clone()))
+        .await
+        .unwrap();
+
+    // The header's processing is suspended until we get its payload.
+    match rx_sync_headers.recv().await {
+        Some(WaiterMessage::SyncBatches(missing, x)) => {
+            assert_eq!(x.id, header.id);
+            assert_eq!(missing.len(), payload.len());
+        }
+        x => panic!("Unexpected waiter message: {:?}", x),
+    }
+
+    // Ensure the certificate is stored and that our workers are asked to sync its whole payload.
+    match rx_sync_headers.recv().await {
+        Some(WaiterMessage::BackfillBatches(missing, x, round)) => {
+            assert_eq!(missing, payload.into_iter().collect());
+            assert_eq!(x, author);
+            assert_eq!(round, 1);
+        }
+        x => panic!("Unexpected waiter message: {:?}", x),
+    }
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificate);
+    let stored = store.read(certificate.digest().to_vec()).await.unwrap();
+    assert_eq!(stored, Some(bincode::serialize(&certificate).unwrap()));
+}
+
+#[tokio::test]
+async fn reject_parents_from_unknown_authorities() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_000);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store holding the certificates of round 1, including one created by an
+    // authority outside the committee.
+    let path = ".db_test_reject_parents_from_unknown_authorities";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut parents: Vec<_> = headers().iter().map(certificate).collect();
+    let (outsider, outsider_secret) = generate_keypair(&mut StdRng::from_seed([1; 32]));
+    let forged = Header {
+        author: outsider,
+        round: 1,
+        parents: Certificate::genesis(&committee)
+            .iter()
+            .map(|x| x.digest())
+            .collect(),
+        ..Header::default()
+    };
+    let forged = Header {
+        id: forged.digest(),
+        signature: Signature::new(&forged.digest(), &outsider_secret),
+        ..forged
+    };
+    parents.push(certificate(&forged));
+    for x in &parents {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+
+    // Make headers of round 2, one of them referencing the outsider's certificate.
+    let make_header = |parents: &[Certificate]| {
+        let header = Header {
+            author,
+            round: 2,
+            parents: parents.iter().map(|x| x.digest()).collect(),
+            ..Header::default()
+        };
+        Header {
+            id: header.digest(),
+            signature: Signature::new(&header.digest(), &author_secret),
+            ..header
+        }
+    };
+    let invalid = make_header(&parents);
+    let valid = make_header(&parents[..4]);
+
+    // Spawn a listener to receive the vote.
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // The header referencing the outsider's certificate is rejected (although its other parents
+    // form a quorum).
+    tx_primary_messages
+        .send(PrimaryMessage::Header(invalid))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Header(valid.clone()))
+        .await
+        .unwrap();
+
+    // Ensure we only vote for the header referencing committee members.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x.id, valid.id),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn batch_votes() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_400);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store holding the certificates of round 1.
+    let path = ".db_test_batch_votes";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let parents: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &parents {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+
+    // Make two headers of the same author (of rounds 1 and 2), and the votes we expect for them.
+    let next_header = Header {
+        author,
+        round: 2,
+        parents: parents.iter().map(|x| x.digest()).collect(),
+        ..Header::default()
+    };
+    let next_header = Header {
+        id: next_header.digest(),
+        signature: Signature::new(&next_header.digest(), &author_secret),
+        ..next_header
+    };
+    let expected = vec![
+        Vote::new(&header(), &name, &mut signature_service).await,
+        Vote::new(&next_header, &name, &mut signature_service).await,
+    ];
+
+    // Spawn a listener to receive the votes.
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            vote_batch_window: 200,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send both headers to the core within the batch window.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Header(next_header))
+        .await
+        .unwrap();
+
+    // Ensure the author receives both votes in a single message.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Votes(x) => assert_eq!(x, expected),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn broadcast_leader_header_to_all() {
+    let committee = committee_with_base_port(15_450);
+    let leader = committee.leader(2);
+    let (name, secret) = keys().into_iter().find(|(x, _)| x == &leader).unwrap();
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_broadcast_leader_header_to_all";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make our header for round 2, which we lead.
+    let header = Header {
+        author: name,
+        round: 2,
+        parents: headers().iter().map(|x| certificate(x).digest()).collect(),
+        ..Header::default()
+    };
+    let header = Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &secret),
+        ..header
+    };
+    let signature_service = SignatureService::new(secret);
+
+    // Spawn listeners to receive our header.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, x)| listener(x.primary_to_primary))
+        .collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core, only sending its headers to a quorum.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            vote_timeout: 1_000,
+            header_broadcast_margin: Some(0),
+            leader_priority: true,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Ensure every authority promptly gets our header, despite the partial broadcast.
+    tx_headers.send(header.clone()).await.unwrap();
+    for handle in handles {
+        let received = timeout(Duration::from_millis(200), handle)
+            .await
+            .expect("The leader header was not sent to every authority")
+            .unwrap();
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Header(x) => assert_eq!(x, header),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+}
+
+#[tokio::test]
+async fn batch_certificate_writes() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(3);
+    let (tx_parents, mut rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_batch_certificate_writes";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            write_batch_size: 3,
+            write_batch_delay: 60_000,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send enough certificates to the core, but one short of a full batch.
+    let certificates: Vec<_> = headers()
+        .iter()
+        .take(3)
+        .map(certificate)
+        .collect();
+    for x in certificates.iter().take(2).cloned() {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the certificates are neither stored nor announced before the batch is written.
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, rx_consensus.recv()).await.is_err());
+    for x in &certificates {
+        assert_eq!(store.read(x.digest().to_vec()).await.unwrap(), None);
+    }
+
+    // Fill the batch.
+    tx_primary_messages
+        .send(PrimaryMessage::Certificate(certificates[2].clone()))
+        .await
+        .unwrap();
+
+    // Ensure the core sends the parents of the certificates to the proposer, and the certificates to
+    // the consensus.
+    let received = rx_parents.recv().await.unwrap();
+    assert_eq!(received, (certificates.clone(), 1));
+    for x in certificates.clone() {
+        let received = rx_consensus.recv().await.unwrap();
+        assert_eq!(received, x);
+    }
+
+    // Ensure the certificates are stored.
+    for x in &certificates {
+        let stored = store.read(x.digest().to_vec()).await.unwrap();
+        let serialized = bincode::serialize(x).unwrap();
+        assert_eq!(stored, Some(serialized));
+    }
+}
+
+#[tokio::test]
+async fn announce_own_certificate_once_written() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(17_050);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(10);
+    let (tx_parents, _rx_parents) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_announce_own_certificate_once_written";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core, writing certificates by batches of 2 (and paused, so it does not vote for the headers
+    // of the certificates it receives).
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(true)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            write_batch_size: 2,
+            write_batch_delay: 60_000,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Spawn all listeners to receive our newly formed certificate.
+    let mut handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, address)| listener(address.primary_to_primary))
+        .collect();
+
+    // Make the core form a certificate for the placeholder header it starts with.
+    let header = Header {
+        author: name,
+        ..Header::default()
+    };
+    let expected = certificate(&header);
+    for vote in votes(&header) {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+
+    // Ensure our certificate is neither stored nor announced before the batch is written.
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, try_join_all(handles.iter_mut()))
+        .await
+        .is_err());
+    assert_eq!(store.read(expected.digest().to_vec()).await.unwrap(), None);
+
+    // Fill the batch with the certificate of another authority.
+    tx_primary_messages
+        .send(PrimaryMessage::Certificate(certificate(&headers()[0])))
+        .await
+        .unwrap();
+
+    // Ensure all listeners got our certificate once it is stored.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Certificate(x) => assert_eq!(x, expected),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    assert!(store
+        .read(expected.digest().to_vec())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn publish_round_advances() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(10);
+    let (tx_parents, _rx_parents) = channel(10);
+    let (tx_round, mut rx_round) = watch::channel(0);
+
+    // Create a new test store.
+    let path = ".db_test_publish_round_advances";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_round,
+        &Supervisor::default(),
+    );
+
+    // Complete rounds 1 and 2 of the dag, one after the other.
+    let mut parents: BTreeSet<_> = Certificate::genesis(&committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+    for round in 1..=2 {
+        let certificates: Vec<_> = keys()
+            .into_iter()
+            .map(|(author, secret)| {
+                let header = Header {
+                    author,
+                    round,
+                    parents: parents.clone(),
+                    ..Header::default()
+                };
+                certificate(&Header {
+                    id: header.digest(),
+                    signature: Signature::new(&header.digest(), &secret),
+                    ..header
+                })
+            })
+            .collect();
+        for x in certificates.iter().cloned() {
+            tx_primary_messages
+                .send(PrimaryMessage::Certificate(x))
+                .await
+                .unwrap();
+        }
+
+        // Ensure the watch channel reflects the new round.
+        rx_round.changed().await.unwrap();
+        assert_eq!(*rx_round.borrow(), round + 1);
+        parents = certificates.iter().map(|x| x.digest()).collect();
+    }
+}
+
+#[tokio::test]
+async fn drop_misrouted_votes() {
+    // The votes are for a header authored by another node.
+    let (name, secret) = keys().remove(0);
+    let misrouted_votes = Arc::new(AtomicU64::new(0));
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_drop_misrouted_votes";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        misrouted_votes.clone(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Send the votes to the core.
+    let header = header();
+    assert_ne!(header.author, name);
+    for vote in votes(&header) {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the votes are counted and dropped (they make no certificate).
+    timeout(Duration::from_millis(1_000), async {
+        while misrouted_votes.load(Ordering::Relaxed) < votes(&header).len() as u64 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, rx_consensus.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn verify_headers_in_parallel() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_625);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_verify_headers_in_parallel";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Queue a burst of headers from the other authorities (with an invalid one in the middle).
+    let mut burst: Vec<_> = headers().into_iter().filter(|x| x.author != name).collect();
+    let invalid = Header {
+        round: 2,
+        ..burst[0].clone()
+    };
+    let invalid = Header {
+        id: invalid.digest(),
+        ..invalid
+    };
+    burst.insert(1, invalid.clone());
+    for header in &burst {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header.clone()))
+            .await
+            .unwrap();
+    }
+
+    // Spawn listeners to receive our votes.
+    let handles: Vec<_> = burst
+        .iter()
+        .filter(|x| x.id != invalid.id)
+        .map(|x| listener(committee.primary(&x.author).unwrap().primary_to_primary))
+        .collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            header_verification_parallelism: 4,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Ensure we voted for every valid header.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Vote(x) => assert_eq!(x.author, name),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+
+    // Ensure the valid headers are stored, but not the invalid one.
+    for header in &burst {
+        let stored = store
+            .read(header.id.to_vec())
+            .await
+            .unwrap()
+            .map(|x| bincode::deserialize::<Header>(&x).unwrap());
+        match header.id == invalid.id {
+            true => assert_eq!(stored, None),
+            false => assert_eq!(stored, Some(header.clone())),
+        }
+    }
+}
+
+/// Spawn a core persisting its votes in `store`, returning the sender of its primary messages.
+fn spawn_core_persisting_votes(
+    name: PublicKey,
+    secret: SecretKey,
+    committee: &Committee,
+    store: &Store,
+    consensus_round: Round,
+    pending_certificate: Arc<Mutex<Option<PendingCertificate>>>,
+) -> Sender<PrimaryMessage> {
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        SignatureService::new(secret),
+        Arc::new(AtomicU64::new(consensus_round)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters {
+            persist_votes: true,
+            ..CoreParameters::default()
+        },
+        /* audit_log */ None,
+        pending_certificate,
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+    tx_primary_messages
+}
+
+#[tokio::test]
+async fn resume_votes_after_restart() {
+    let (name, secret) = keys().pop().unwrap();
+    let committee = committee_with_base_port(15_675);
+
+    // Create a new test store.
+    let path = ".db_test_resume_votes_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // The votes for the placeholder header the core starts with.
+    let header = Header {
+        author: name,
+        ..Header::default()
+    };
+    let mut votes = votes(&header);
+    let last_vote = votes.pop().unwrap();
+    votes.truncate(2);
+
+    // Send the core a part of the votes, and wait until it processed them.
+    let pending_certificate = Arc::new(Mutex::new(None));
+    let tx_primary_messages = spawn_core_persisting_votes(
+        name,
+        secret,
+        &committee,
+        &store,
+        /* consensus_round */ 0,
+        pending_certificate.clone(),
+    );
+    for vote in votes {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+    loop {
+        let stake = pending_certificate
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|x| x.stake);
+        if stake == Some(2) {
+            break;
+        }
+        tokio::task::yield_now().await;
+    }
+
+    // Restart the core (the previous instance no longer receives any message, but we keep its channel
+    // open until the end of the test so it stays idle).
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, address)| listener(address.primary_to_primary))
+        .collect();
+    let (_, secret) = keys().pop().unwrap();
+    let restarted = spawn_core_persisting_votes(
+        name,
+        secret,
+        &committee,
+        &store,
+        /* consensus_round */ 0,
+        Arc::default(),
+    );
+
+    // Ensure the restarted core resumes from the persisted votes: the last vote completes the quorum.
+    restarted
+        .send(PrimaryMessage::Vote(last_vote))
+        .await
+        .unwrap();
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Certificate(x) => {
+                assert_eq!(x, certificate(&header));
+                assert_eq!(x.votes.len(), 3);
+            }
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    drop(tx_primary_messages);
+}
+
+#[tokio::test]
+async fn discard_garbage_collected_votes() {
+    let (name, secret) = keys().pop().unwrap();
+    let committee = committee_with_base_port(15_825);
+
+    // Create a new test store.
+    let path = ".db_test_discard_garbage_collected_votes";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Persist the votes of all but one authority for our header of round 1.
+    let header = header();
+    let mut votes = votes(&header);
+    let last_vote = votes.pop().unwrap();
+    let votes: Vec<_> = votes.into_iter().map(|x| (x.author, x.signature)).collect();
+    let entries = vec![
+        (header.id.to_vec(), bincode::serialize(&header).unwrap()),
+        (
+            PENDING_VOTES_KEY.to_vec(),
+            bincode::serialize(&(&header.id, votes)).unwrap(),
+        ),
+    ];
+    store.write_batch(entries).await.unwrap();
+
+    // Restart the core once consensus moved far past the round of the header.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, address)| listener(address.primary_to_primary))
+        .collect();
+    let consensus_round = header.round + CoreParameters::default().gc_depth + 1;
+    let restarted = spawn_core_persisting_votes(
+        name,
+        secret,
+        &committee,
+        &store,
+        consensus_round,
+        Arc::default(),
+    );
+
+    // Ensure the core discarded the persisted votes: the last vote does not make a certificate.
+    restarted
+        .send(PrimaryMessage::Vote(last_vote))
+        .await
+        .unwrap();
+    let wait = Duration::from_millis(500);
+    assert!(timeout(wait, try_join_all(handles)).await.is_err());
+}
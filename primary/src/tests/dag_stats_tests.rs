@@ -0,0 +1,90 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, headers};
+use crate::messages::Header;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+// Make one certificate per authority for the round. The i-th certificate references i batches.
+fn certificates(round: Round) -> Vec<Certificate> {
+    headers()
+        .into_iter()
+        .enumerate()
+        .map(|(i, header)| {
+            let payload = (0..i)
+                .map(|j| (Digest([(round * 10 + j as u64) as u8; 32]), 0))
+                .collect();
+            certificate(&Header {
+                round,
+                payload,
+                ..header
+            })
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn round_stats() {
+    let (tx_core, rx_core) = channel(100);
+    let (tx_consensus, mut rx_consensus) = channel(100);
+    let (tx_feedback, rx_feedback) = channel(100);
+    let (tx_garbage_collector, mut rx_garbage_collector) = channel(100);
+    let (tx_stats, mut rx_stats) = channel(100);
+
+    // Spawn the stats gatherer.
+    DagStats::spawn(
+        /* gc_depth */ 2,
+        rx_core,
+        tx_consensus,
+        /* rx_consensus */ rx_feedback,
+        tx_garbage_collector,
+        tx_stats,
+    );
+
+    // Store 4 rounds of certificates.
+    let rounds: Vec<_> = (1..=4).map(certificates).collect();
+    for certificate in rounds.iter().flatten() {
+        tx_core.send(certificate.clone()).await.unwrap();
+        assert_eq!(rx_consensus.recv().await.as_ref(), Some(certificate));
+    }
+
+    // Commit 3 certificates of round 1 and all the certificates of round 2 (but none of round 3).
+    sleep(Duration::from_millis(50)).await;
+    let committed = rounds[0].iter().take(3).chain(&rounds[1]).chain(&rounds[3][..1]);
+    for certificate in committed {
+        tx_feedback.send(certificate.clone()).await.unwrap();
+        assert_eq!(rx_garbage_collector.recv().await.as_ref(), Some(certificate));
+    }
+
+    // Committing round 4 garbage collects round 1.
+    let stats = rx_stats.recv().await.unwrap();
+    assert_eq!(stats.round, 1);
+    assert_eq!(stats.certificates, 4);
+    assert_eq!(stats.authors, 4);
+    assert_eq!(stats.payload_batches, 6);
+    assert_eq!(stats.committed, 3);
+    assert!(stats.commit_latency.unwrap() >= 50);
+
+    // Committing round 6 garbage collects rounds 2 and 3.
+    let certificate = certificates(6).pop().unwrap();
+    tx_feedback.send(certificate).await.unwrap();
+    let stats = rx_stats.recv().await.unwrap();
+    assert_eq!(stats.round, 2);
+    assert_eq!(stats.certificates, 4);
+    assert_eq!(stats.committed, 4);
+    assert!(stats.commit_latency.is_some());
+    let stats = rx_stats.recv().await.unwrap();
+    assert_eq!(
+        stats,
+        RoundStats {
+            round: 3,
+            certificates: 4,
+            authors: 4,
+            payload_batches: 6,
+            committed: 0,
+            commit_latency: None,
+        }
+    );
+    assert_eq!(stats.to_csv(), "3,4,4,6,0,");
+    assert!(rx_stats.try_recv().is_err());
+}
@@ -0,0 +1,104 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::encoding::TxEncoding;
+use crate::latency::LatencySampler;
+use crate::primary::PrimaryClientReceiverHandler;
+use bytes::{Buf as _, BufMut as _, BytesMut};
+use futures::sink::SinkExt as _;
+use network::{Receiver, ReceiverOptions};
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// An application delivering batches as a list of length-prefixed transactions.
+#[derive(Clone)]
+struct TransactionsDecoder;
+
+impl DeliveryDecoder for TransactionsDecoder {
+    type Output = Vec<Vec<u8>>;
+
+    fn decode(&self, mut delivery: &[u8]) -> Result<Self::Output, Box<dyn Error + Send + Sync>> {
+        let mut transactions = Vec::new();
+        while delivery.has_remaining() {
+            let size = delivery.get_u32() as usize;
+            if delivery.remaining() < size {
+                return Err("Truncated transaction".into());
+            }
+            transactions.push(delivery[..size].to_vec());
+            delivery.advance(size);
+        }
+        Ok(transactions)
+    }
+
+    fn sample_ids(&self, output: &Self::Output) -> Vec<u64> {
+        output.iter().filter_map(|x| TxEncoding::parse(x)).collect()
+    }
+}
+
+#[test]
+fn decode_digest() {
+    let digest = Digest([1u8; 32]);
+    let message = PrimaryClientMessage::BatchDelivered(digest.clone());
+    let serialized = bincode::serialize(&message).unwrap();
+    assert_eq!(DigestDecoder.decode(&serialized).unwrap(), digest);
+}
+
+#[tokio::test]
+async fn custom_decoder() {
+    // Spawn a client receiver decoding application transactions.
+    let address = "127.0.0.1:13500".parse::<SocketAddr>().unwrap();
+    let (tx_output, mut rx_output) = channel(1);
+    let handler = PrimaryClientReceiverHandler::with_output(TransactionsDecoder, tx_output);
+    Receiver::spawn(address, handler, ReceiverOptions::default());
+    sleep(Duration::from_millis(50)).await;
+
+    // Deliver a batch of two transactions.
+    let transactions = vec![b"hello".to_vec(), b"world!".to_vec()];
+    let mut delivery = BytesMut::new();
+    for tx in &transactions {
+        delivery.put_u32(tx.len() as u32);
+        delivery.put_slice(tx);
+    }
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(delivery.freeze()).await.unwrap();
+
+    // Ensure the application receives the decoded transactions.
+    assert_eq!(rx_output.recv().await.unwrap(), transactions);
+}
+
+#[tokio::test]
+async fn measure_latency() {
+    // Spawn a client receiver measuring the latency of sample transactions.
+    let address = "127.0.0.1:13501".parse::<SocketAddr>().unwrap();
+    let (tx_output, mut rx_output) = channel(1);
+    let latency = Arc::new(Mutex::new(LatencySampler::new(100)));
+    let handler = PrimaryClientReceiverHandler::with_output(TransactionsDecoder, tx_output)
+        .with_latency(latency.clone());
+    Receiver::spawn(address, handler, ReceiverOptions::default());
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a sample transaction.
+    let sample = 7u64.to_be_bytes().to_vec();
+    latency.lock().unwrap().sent(7, Instant::now());
+    sleep(Duration::from_millis(100)).await;
+
+    // Deliver it along with a regular transaction.
+    let mut delivery = BytesMut::new();
+    for tx in [&sample[..], b"hello"] {
+        delivery.put_u32(tx.len() as u32);
+        delivery.put_slice(tx);
+    }
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(delivery.freeze()).await.unwrap();
+    rx_output.recv().await.unwrap();
+
+    // Ensure the latency of the sample transaction is measured.
+    let summary = latency.lock().unwrap().summary().unwrap();
+    assert_eq!(summary.samples, 1);
+    assert!(summary.p50 >= Duration::from_millis(100));
+}
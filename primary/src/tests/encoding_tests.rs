@@ -0,0 +1,39 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn round_trip() {
+    for encoding in &[TxEncoding::Counter, TxEncoding::Monotonic, TxEncoding::Uuid] {
+        let mut ids = Vec::new();
+        for counter in 0..10 {
+            let mut tx = BytesMut::new();
+            let id = encoding.encode(&mut tx, counter, /* client */ 42, &mut rand::thread_rng());
+            assert_eq!(tx.len(), encoding.size());
+            tx.resize(100, 0u8);
+            assert_eq!(TxEncoding::parse(&tx), Some(id));
+            ids.push(id);
+        }
+
+        // Ensure all sample transactions are distinct.
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 10);
+    }
+}
+
+#[test]
+fn parse_standard_transactions() {
+    let mut tx = BytesMut::new();
+    tx.put_u32(u32::MAX);
+    tx.put_u32(1);
+    assert_eq!(TxEncoding::parse(&tx), None);
+    assert_eq!(TxEncoding::parse(&[0u8; 4]), None);
+}
+
+#[test]
+fn parse_encoding_names() {
+    assert_eq!("counter".parse(), Ok(TxEncoding::Counter));
+    assert_eq!("monotonic".parse(), Ok(TxEncoding::Monotonic));
+    assert_eq!("uuid".parse(), Ok(TxEncoding::Uuid));
+    assert!("unknown".parse::<TxEncoding>().is_err());
+}
@@ -0,0 +1,63 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use crate::store_migrations::store_migrations;
+use std::fs;
+use store::{OpenError, Store};
+use tokio::time::{sleep, Duration};
+
+// Returns a certificate of the author of the `header` fixture at the specified round. Certificates with
+// different `variant`s have different digests.
+fn certificate_at(round: Round, variant: u8) -> Certificate {
+    certificate(&Header {
+        round,
+        id: Digest([variant; 32]),
+        ..header()
+    })
+}
+
+// Write the certificates to a new store (along with a record that is not a certificate) and close it.
+async fn write_store(path: &str, certificates: &[Certificate]) {
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(header().id.to_vec(), bincode::serialize(&header()).unwrap()).await;
+    for x in certificates {
+        store.write(x.digest().to_vec(), bincode::serialize(x).unwrap()).await;
+        let index_key = Certificate::index_key(x.round(), &x.origin());
+        store.write(index_key, x.digest().to_vec()).await;
+    }
+    let _ = store.read(header().id.to_vec()).await;
+    drop(store);
+    sleep(Duration::from_millis(50)).await;
+}
+
+#[tokio::test]
+async fn refuse_forked_store() {
+    let path = ".db_test_refuse_forked_store";
+    let certificates: Vec<_> = (1..=10).map(|r| certificate_at(r, r as u8)).collect();
+    let fork = certificate_at(/* round */ 8, /* variant */ 0);
+    write_store(path, &[certificates, vec![fork]].concat()).await;
+
+    // Ensure the store refuses to open, pointing at the fork.
+    let mut check = ForkCheck::new(/* gc_depth */ 5);
+    match Store::open_checked(path, &store_migrations(), &mut check) {
+        Err(OpenError::Inconsistent(diagnostic)) => {
+            let expected = format!("{} has 2 certificates at round 8", header().author);
+            assert!(diagnostic.contains(&expected), "{}", diagnostic);
+        }
+        _ => panic!("Opened a forked store"),
+    }
+}
+
+#[tokio::test]
+async fn ignore_forks_below_gc() {
+    let path = ".db_test_ignore_forks_below_gc";
+    let certificates: Vec<_> = (1..=10).map(|r| certificate_at(r, r as u8)).collect();
+    let fork = certificate_at(/* round */ 2, /* variant */ 0);
+    write_store(path, &[certificates, vec![fork]].concat()).await;
+
+    // Ensure the store opens: the fork is below the garbage collection round.
+    let mut check = ForkCheck::new(/* gc_depth */ 5);
+    assert!(Store::open_checked(path, &store_migrations(), &mut check).is_ok());
+}
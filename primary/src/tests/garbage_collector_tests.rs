@@ -0,0 +1,107 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys};
+use crate::messages::Header;
+use crypto::Hash as _;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn compact_after_cleanup() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_200);
+    let consensus_round = Arc::new(AtomicU64::new(0));
+    let (tx_consensus, rx_consensus) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_compact_after_cleanup";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        consensus_round.clone(),
+        rx_consensus,
+        store,
+        /* gc_depth */ 1,
+        /* compaction_interval */ 2,
+        &Supervisor::default(),
+    );
+
+    // Commit certificates of increasing rounds.
+    for round in 1..=5 {
+        let certificate = Certificate {
+            header: Header {
+                round,
+                ..Header::default()
+            },
+            ..Certificate::default()
+        };
+        tx_consensus.send(certificate).await.unwrap();
+    }
+
+    // Ensure the index entries pruned since the last compaction are compacted every 2 committed rounds:
+    // at round 2 (pruning round 0) and at round 4 (pruning rounds 1 and 2).
+    while consensus_round.load(Ordering::Relaxed) < 5 {
+        sleep(Duration::from_millis(10)).await;
+    }
+    sleep(Duration::from_millis(50)).await;
+    let expected = vec![
+        Certificate::index_range(0, 1),
+        Certificate::index_range(1, 3),
+    ];
+    COMPACTIONS.with(|x| assert_eq!(*x.borrow(), expected));
+}
+
+#[tokio::test]
+async fn prune_round_index() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(17_010);
+    let consensus_round = Arc::new(AtomicU64::new(0));
+    let (tx_consensus, rx_consensus) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_prune_round_index";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Index certificates of several rounds.
+    let certificates: Vec<_> = (1..=5)
+        .map(|round| certificate(&Header { round, ..header() }))
+        .collect();
+    for x in &certificates {
+        let index_key = Certificate::index_key(x.round(), &x.origin());
+        store.write(index_key, x.digest().to_vec()).await;
+    }
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        consensus_round.clone(),
+        rx_consensus,
+        store.clone(),
+        /* gc_depth */ 2,
+        /* compaction_interval */ 0,
+        &Supervisor::default(),
+    );
+
+    // Commit the certificates.
+    for x in certificates.iter().cloned() {
+        tx_consensus.send(x).await.unwrap();
+    }
+
+    // Ensure the index entries of the rounds below the garbage collection round (3) are deleted.
+    let index_key = |x: &Certificate| Certificate::index_key(x.round(), &x.origin());
+    let pruned = index_key(&certificates[1]);
+    while store.read(pruned.clone()).await.unwrap().is_some() {
+        sleep(Duration::from_millis(10)).await;
+    }
+    for x in &certificates {
+        let expected = (x.round() >= 3).then(|| x.digest().to_vec());
+        assert_eq!(store.read(index_key(x)).await.unwrap(), expected);
+    }
+}
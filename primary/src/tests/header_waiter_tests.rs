@@ -0,0 +1,388 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys, listener};
+use crate::certificate_waiter::CertificateWaiter;
+use crate::messages::Certificate;
+use futures::sink::SinkExt as _;
+use std::fs;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Forwards the digests of every certificates request received at the address (over any connection).
+fn requests_listener(address: SocketAddr) -> Receiver<Vec<Digest>> {
+    let (tx, rx) = channel(100);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        while let Ok((socket, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+                while let Some(Ok(bytes)) = transport.next().await {
+                    if let Ok(PrimaryMessage::CertificatesRequest(digests, _)) =
+                        bincode::deserialize(&bytes)
+                    {
+                        let _ = transport.send(Bytes::from("Ack")).await;
+                        let _ = tx.send(digests).await;
+                    }
+                }
+            });
+        }
+    });
+    rx
+}
+
+#[tokio::test]
+async fn cap_pending_sync_requests() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_100);
+    let sync_permits = Arc::new(Semaphore::new(1));
+    let (tx_sync_headers, rx_sync_headers) = channel(10);
+    let (tx_headers_loopback, _rx_headers_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_cap_pending_sync_requests";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Listen to the sync requests sent to the author of the headers.
+    let author = header().author;
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let mut rx_requests = requests_listener(address);
+
+    // Spawn the waiter with a single permit.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        sync_permits.clone(),
+        /* max_pending */ 0,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_sync_headers,
+        tx_headers_loopback,
+        &Supervisor::default(),
+    );
+
+    // Many headers have missing parents.
+    for i in 10..15u8 {
+        let missing = Digest([i; 32]);
+        let header = Header {
+            id: Digest([i + 100; 32]),
+            parents: [missing.clone()].iter().cloned().collect(),
+            ..header()
+        };
+        tx_sync_headers
+            .send(WaiterMessage::SyncParents(vec![missing], header))
+            .await
+            .unwrap();
+    }
+
+    // Only one sync request fits under the cap.
+    let first = rx_requests.recv().await.unwrap();
+    assert_eq!(first, vec![Digest([10; 32])]);
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, rx_requests.recv()).await.is_err());
+    assert_eq!(sync_permits.available_permits(), 0);
+
+    // Each completed sync lets another request through.
+    store.write(vec![10; 32], Vec::new()).await;
+    let second = rx_requests.recv().await.unwrap();
+    assert_eq!(second, vec![Digest([11; 32])]);
+    assert!(timeout(wait, rx_requests.recv()).await.is_err());
+
+    store.write(vec![11; 32], Vec::new()).await;
+    let third = rx_requests.recv().await.unwrap();
+    assert_eq!(third, vec![Digest([12; 32])]);
+    assert!(timeout(wait, rx_requests.recv()).await.is_err());
+    assert_eq!(sync_permits.available_permits(), 0);
+}
+
+#[tokio::test]
+async fn recover_deeper_than_cap() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(14_975);
+    let sync_permits = Arc::new(Semaphore::new(2));
+    let (tx_sync_headers, rx_sync_headers) = channel(10);
+    let (tx_headers_loopback, mut rx_headers_loopback) = channel(10);
+    let (tx_sync_certificates, rx_sync_certificates) = channel(10);
+    let (tx_certificates_loopback, mut rx_certificates_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_recover_deeper_than_cap";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // The author of the headers replies to our sync requests (we store the certificates it sends).
+    let author = header().author;
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let mut rx_requests = requests_listener(address);
+    let mut replier = store.clone();
+    tokio::spawn(async move {
+        while let Some(digests) = rx_requests.recv().await {
+            for x in digests {
+                replier.write(x.to_vec(), Vec::new()).await;
+            }
+        }
+    });
+
+    // Spawn the waiters, with 2 permits.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        sync_permits,
+        /* max_pending */ 0,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_sync_headers,
+        tx_headers_loopback,
+        &Supervisor::default(),
+    );
+    CertificateWaiter::spawn(
+        store.clone(),
+        /* max_age */ 0,
+        rx_sync_certificates,
+        tx_certificates_loopback,
+        &Supervisor::default(),
+    );
+
+    // Each level of a chain deeper than the cap has a certificate and a header waiting for the same
+    // missing parent; the certificates arrive first.
+    let depth = 5u8;
+    let parents: Vec<_> = (0..depth).map(|i| Digest([i; 32])).collect();
+    let certificates: Vec<_> = parents
+        .iter()
+        .enumerate()
+        .map(|(i, parent)| {
+            certificate(&Header {
+                id: Digest([i as u8 + 50; 32]),
+                round: i as Round + 2,
+                parents: [parent.clone()].iter().cloned().collect(),
+                ..header()
+            })
+        })
+        .collect();
+    for x in &certificates {
+        tx_sync_certificates.send(x.clone()).await.unwrap();
+    }
+    for x in &certificates {
+        tx_sync_headers
+            .send(WaiterMessage::SyncParents(
+                x.header.parents.iter().cloned().collect(),
+                x.header.clone(),
+            ))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the whole chain is delivered.
+    let wait = Duration::from_millis(2_000);
+    for _ in 0..depth {
+        timeout(wait, rx_headers_loopback.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let _: Certificate = timeout(wait, rx_certificates_loopback.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn evict_lowest_round_headers() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_600);
+    let (tx_sync_headers, rx_sync_headers) = channel(10);
+    let (tx_headers_loopback, mut rx_headers_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_evict_lowest_round_headers";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn a waiter buffering at most 3 headers.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        Arc::new(Semaphore::new(10)),
+        /* max_pending */ 3,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_sync_headers,
+        tx_headers_loopback,
+        &Supervisor::default(),
+    );
+
+    // Overflow the waiter with headers of various rounds, all missing a parent.
+    let rounds = [5, 2, 7, 1, 4];
+    for (i, round) in rounds.iter().enumerate() {
+        let missing = Digest([i as u8; 32]);
+        let header = Header {
+            id: Digest([i as u8 + 100; 32]),
+            round: *round,
+            parents: [missing.clone()].iter().cloned().collect(),
+            ..header()
+        };
+        tx_sync_headers
+            .send(WaiterMessage::SyncParents(vec![missing], header))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // Deliver all the missing parents.
+    for i in 0..rounds.len() {
+        store.write(vec![i as u8; 32], Vec::new()).await;
+    }
+
+    // Ensure only the highest-round headers were kept.
+    let mut delivered = Vec::new();
+    for _ in 0..3 {
+        delivered.push(rx_headers_loopback.recv().await.unwrap().round);
+    }
+    delivered.sort_unstable();
+    assert_eq!(delivered, vec![4, 5, 7]);
+    let wait = Duration::from_millis(300);
+    assert!(timeout(wait, rx_headers_loopback.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn backfill_batches_of_known_workers() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_875);
+    let (tx_sync_headers, rx_sync_headers) = channel(10);
+    let (tx_headers_loopback, _rx_headers_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_backfill_batches_of_known_workers";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Listen to the sync requests sent to our worker.
+    let address = committee.worker(&name, &0).unwrap().primary_to_worker;
+    let handle = listener(address);
+
+    // Spawn the waiter.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        Arc::new(Semaphore::new(10)),
+        /* max_pending */ 0,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_sync_headers,
+        tx_headers_loopback,
+        &Supervisor::default(),
+    );
+
+    // Backfill batches of our worker and of a worker we do not have.
+    let author = header().author;
+    let missing = [(Digest([1; 32]), 0), (Digest([2; 32]), 99)]
+        .iter()
+        .cloned()
+        .collect();
+    tx_sync_headers
+        .send(WaiterMessage::BackfillBatches(missing, author, 1))
+        .await
+        .unwrap();
+
+    // Ensure the unknown worker does not prevent syncing the batches of our worker.
+    let received = timeout(Duration::from_millis(1_000), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Synchronize(digests, x) => {
+            assert_eq!(digests, vec![Digest([1; 32])]);
+            assert_eq!(x, author);
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn forget_delivered_weak_links() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_925);
+    let (tx_sync_headers, rx_sync_headers) = channel(10);
+    let (tx_headers_loopback, mut rx_headers_loopback) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_forget_delivered_weak_links";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Listen to the sync requests sent to the author of the header.
+    let author = header().author;
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let mut rx_requests = requests_listener(address);
+
+    // Spawn a waiter retrying its sync requests (to all the other nodes) after 100ms.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 100,
+        /* sync_retry_nodes */ 3,
+        Arc::new(Semaphore::new(10)),
+        /* max_pending */ 0,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        rx_sync_headers,
+        tx_headers_loopback,
+        &Supervisor::default(),
+    );
+
+    // A header misses both its parent and its weak link.
+    let (parent, weak_link) = (Digest([1; 32]), Digest([2; 32]));
+    let header = Header {
+        id: Digest([100; 32]),
+        round: 3,
+        parents: [parent.clone()].iter().cloned().collect(),
+        weak_links: [weak_link.clone()].iter().cloned().collect(),
+        ..header()
+    };
+    let missing = vec![parent.clone(), weak_link.clone()];
+    tx_sync_headers
+        .send(WaiterMessage::SyncParents(missing.clone(), header.clone()))
+        .await
+        .unwrap();
+    assert_eq!(rx_requests.recv().await.unwrap(), missing);
+
+    // Deliver the missing certificates.
+    store.write(parent.to_vec(), Vec::new()).await;
+    store.write(weak_link.to_vec(), Vec::new()).await;
+    assert_eq!(rx_headers_loopback.recv().await.unwrap(), header);
+
+    // Ensure nothing is requested again.
+    let wait = Duration::from_millis(1_500);
+    assert!(timeout(wait, rx_requests.recv()).await.is_err());
+}
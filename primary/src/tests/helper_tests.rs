@@ -0,0 +1,298 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, headers, keys, listener};
+use crate::messages::Header;
+use crypto::Hash as _;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use network::PROTOCOL_VERSION;
+use std::fs;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::channel;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Spawn a listener accepting the handshake of the first connection (by echoing it) and returning the first
+// message it then receives.
+fn handshake_listener(address: SocketAddr) -> JoinHandle<Bytes> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let handshake = transport.next().await.unwrap().unwrap();
+        transport.send(handshake.freeze()).await.unwrap();
+        let received = transport.next().await.unwrap().unwrap();
+        transport.send(Bytes::from("Ack")).await.unwrap();
+        received.freeze()
+    })
+}
+
+#[tokio::test]
+async fn reply_range_request() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(13_800);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let (tx_range_requests, rx_range_requests) = channel(1);
+    let (_tx_missing_requests, rx_missing_requests) = channel(1);
+
+    // Create a new test store holding a certificate indexed by its round.
+    let path = ".db_test_reply_range_request";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificate = certificate(&headers()[0]);
+    let bytes = bincode::serialize(&certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+    store.write(index_key, certificate.digest().to_vec()).await;
+
+    // Spawn a listener to receive the reply.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Spawn the helper.
+    Helper::spawn(
+        name,
+        committee,
+        store,
+        /* round_index */ Arc::default(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* compress_responses */ false,
+        rx_primaries,
+        rx_range_requests,
+        rx_missing_requests,
+        &Supervisor::default(),
+    );
+
+    // Request a range of rounds starting at genesis.
+    tx_range_requests.send((0, 10, requestor)).await.unwrap();
+
+    // Ensure the helper replies with the certificate.
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificate)).unwrap();
+    let received = handle.await.unwrap();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn reply_missing_certificates_request() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(13_850);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let (_tx_range_requests, rx_range_requests) = channel(1);
+    let (tx_missing_requests, rx_missing_requests) = channel(1);
+
+    // Create a new test store holding two certificates of round 1 indexed by their round.
+    let path = ".db_test_reply_missing_certificates_request";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates: Vec<_> = headers().iter().take(2).map(certificate).collect();
+    for certificate in &certificates {
+        let bytes = bincode::serialize(certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+        store.write(index_key, certificate.digest().to_vec()).await;
+    }
+
+    // Spawn a listener collecting all the replies.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut received = Vec::new();
+        while let Ok(Some(Ok(bytes))) = timeout(Duration::from_millis(500), transport.next()).await {
+            transport.send(Bytes::from("Ack")).await.unwrap();
+            received.push(bytes.freeze());
+        }
+        received
+    });
+
+    // Spawn the helper.
+    Helper::spawn(
+        name,
+        committee,
+        store,
+        /* round_index */ Arc::default(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* compress_responses */ false,
+        rx_primaries,
+        rx_range_requests,
+        rx_missing_requests,
+        &Supervisor::default(),
+    );
+
+    // Request the first certificate along with a certificate we do not have.
+    let author = certificates[0].origin();
+    let missing = vec![(author, 2), (author, 1)];
+    tx_missing_requests.send((missing, requestor)).await.unwrap();
+
+    // Ensure the helper only replies with the first certificate.
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificates[0].clone())).unwrap();
+    let received = handle.await.unwrap();
+    assert_eq!(received, vec![Bytes::from(expected)]);
+}
+
+#[tokio::test]
+async fn compress_range_response() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_500);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let (tx_range_requests, rx_range_requests) = channel(1);
+    let (_tx_missing_requests, rx_missing_requests) = channel(1);
+
+    // Create a new test store holding the certificates of many rounds, indexed by their round.
+    let path = ".db_test_compress_range_response";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut certificates = Vec::new();
+    for round in 1..=50 {
+        for name in committee.authorities.keys() {
+            certificates.push(certificate(&Header {
+                author: *name,
+                round,
+                ..headers()[0].clone()
+            }));
+        }
+    }
+    for certificate in &certificates {
+        let bytes = bincode::serialize(certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+        store.write(index_key, certificate.digest().to_vec()).await;
+    }
+
+    // Spawn a listener to receive the reply.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = handshake_listener(address);
+
+    // Spawn the helper, compressing its replies.
+    Helper::spawn(
+        name,
+        committee,
+        store,
+        /* round_index */ Arc::default(),
+        /* protocol_version */ Some(PROTOCOL_VERSION),
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* compress_responses */ true,
+        rx_primaries,
+        rx_range_requests,
+        rx_missing_requests,
+        &Supervisor::default(),
+    );
+
+    // Request all the rounds.
+    tx_range_requests.send((1, 50, requestor)).await.unwrap();
+
+    // Ensure the helper replies with a single compressed message, which decompresses to the certificates.
+    let received = handle.await.unwrap();
+    let compressed = match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::CompressedCertificates(x) => x,
+        x => panic!("Unexpected reply: {:?}", x),
+    };
+    assert!(compressed.len() < bincode::serialize(&certificates).unwrap().len());
+    assert_eq!(decompress_certificates(&compressed).unwrap(), certificates);
+}
+
+// A helper configured to compress its replies does not compress them if it does not handshake with the
+// requestors, which may not understand compressed replies.
+#[tokio::test]
+async fn compress_only_with_handshake() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_775);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let (tx_range_requests, rx_range_requests) = channel(1);
+    let (_tx_missing_requests, rx_missing_requests) = channel(1);
+
+    // Create a new test store holding a certificate indexed by its round.
+    let path = ".db_test_compress_only_with_handshake";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificate = certificate(&headers()[0]);
+    let bytes = bincode::serialize(&certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let index_key = Certificate::index_key(certificate.round(), &certificate.origin());
+    store.write(index_key, certificate.digest().to_vec()).await;
+
+    // Spawn a listener (not expecting a handshake) to receive the reply.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Spawn the helper, configured to compress its replies but without handshake.
+    Helper::spawn(
+        name,
+        committee,
+        store,
+        /* round_index */ Arc::default(),
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* compress_responses */ true,
+        rx_primaries,
+        rx_range_requests,
+        rx_missing_requests,
+        &Supervisor::default(),
+    );
+
+    // Request a range of rounds.
+    tx_range_requests.send((1, 10, requestor)).await.unwrap();
+
+    // Ensure the helper replies with the uncompressed certificate.
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificate)).unwrap();
+    let received = handle.await.unwrap();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn reply_range_request_from_round_index() {
+    let (requestor, _) = keys().pop().unwrap();
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(15_550);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let (tx_range_requests, rx_range_requests) = channel(1);
+    let (_tx_missing_requests, rx_missing_requests) = channel(1);
+
+    // Create a new test store holding a certificate, only indexed by the round index.
+    let path = ".db_test_reply_range_request_from_round_index";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificate = certificate(&headers()[0]);
+    let bytes = bincode::serialize(&certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let round_index = Arc::new(RoundIndex::default());
+    round_index.insert(certificate.round(), certificate.digest());
+
+    // Spawn a listener to receive the reply.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Spawn the helper.
+    Helper::spawn(
+        name,
+        committee,
+        store,
+        round_index,
+        /* protocol_version */ None,
+        /* message_tee */ None,
+        /* bandwidth */ Bandwidth::default(),
+        /* compress_responses */ false,
+        rx_primaries,
+        rx_range_requests,
+        rx_missing_requests,
+        &Supervisor::default(),
+    );
+
+    // Ensure the helper finds the certificate through the round index.
+    tx_range_requests.send((1, 1, requestor)).await.unwrap();
+    let expected = bincode::serialize(&PrimaryMessage::Certificate(certificate)).unwrap();
+    let received = handle.await.unwrap();
+    assert_eq!(received, expected);
+}
@@ -0,0 +1,36 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::time::{sleep, timeout, Duration};
+
+#[tokio::test]
+async fn bound_outstanding_transactions() {
+    const LIMIT: usize = 5;
+    let in_flight = InFlight::new(LIMIT);
+
+    // Send transactions as fast as the slots allow.
+    let sender = in_flight.clone();
+    let handle = tokio::spawn(async move {
+        for id in 0..100 {
+            sender.sent(id).await;
+            assert!(sender.outstanding() <= LIMIT);
+        }
+    });
+
+    // The sender stalls once the window is full.
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(in_flight.outstanding(), LIMIT);
+    assert!(!handle.is_finished());
+
+    // Deliver the transactions (ignoring unknown ones), checking the window is never exceeded.
+    in_flight.delivered(1_000);
+    for id in 0..100 {
+        while !in_flight.pending.lock().unwrap().contains(&id) {
+            assert!(in_flight.outstanding() <= LIMIT);
+            sleep(Duration::from_millis(1)).await;
+        }
+        assert!(in_flight.outstanding() <= LIMIT);
+        in_flight.delivered(id);
+    }
+    timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+    assert_eq!(in_flight.outstanding(), 0);
+}
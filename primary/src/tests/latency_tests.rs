@@ -0,0 +1,54 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn percentiles() {
+    let mut sampler = LatencySampler::new(1_000);
+    let start = Instant::now();
+
+    // Send 100 transactions and deliver them with latencies of 1 to 100 ms (out of order).
+    for id in 0..100 {
+        sampler.sent(id, start);
+    }
+    for id in (0..100).rev() {
+        sampler.delivered(id, start + Duration::from_millis(id + 1));
+    }
+
+    // Deliveries of unknown or already delivered transactions are ignored.
+    sampler.delivered(7, start + Duration::from_secs(10));
+    sampler.delivered(1_000, start + Duration::from_secs(10));
+
+    let summary = sampler.summary().unwrap();
+    assert_eq!(summary.samples, 100);
+    assert_eq!(summary.p50, Duration::from_millis(50));
+    assert_eq!(summary.p95, Duration::from_millis(95));
+    assert_eq!(summary.p99, Duration::from_millis(99));
+}
+
+#[test]
+fn bounded_memory() {
+    let mut sampler = LatencySampler::new(10);
+    let start = Instant::now();
+    assert!(sampler.summary().is_none());
+
+    // The oldest undelivered transactions are forgotten.
+    for id in 0..20 {
+        sampler.sent(id, start);
+    }
+    assert!(sampler.pending.len() <= 10);
+    sampler.delivered(0, start + Duration::from_millis(1));
+    assert!(sampler.summary().is_none());
+
+    // Only the most recent latencies are kept.
+    for id in 10..20 {
+        sampler.delivered(id, start + Duration::from_millis(1_000));
+    }
+    for id in 20..25 {
+        sampler.sent(id, start);
+        sampler.delivered(id, start + Duration::from_millis(1));
+    }
+    let summary = sampler.summary().unwrap();
+    assert_eq!(summary.samples, 10);
+    assert_eq!(summary.p50, Duration::from_millis(1));
+    assert_eq!(summary.p99, Duration::from_millis(1_000));
+}
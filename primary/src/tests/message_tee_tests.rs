@@ -0,0 +1,146 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::catch_up::DagProgress;
+use crate::common::{certificate, committee_with_base_port, header, keys, listener};
+use crate::core::{Core, CoreParameters};
+use crate::messages::Vote;
+use crate::supervisor::Supervisor;
+use crate::synchronizer::Synchronizer;
+use crypto::SignatureService;
+use network::Bandwidth;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use store::Store;
+use tokio::sync::mpsc::channel;
+use tokio::sync::watch;
+
+#[test]
+fn capture_in_order() {
+    let path = ".db_test_capture_in_order";
+    let _ = fs::remove_file(path);
+    let tee = MessageTee::new(path).unwrap();
+
+    let messages = vec![
+        (Direction::Inbound, PrimaryMessage::Header(header())),
+        (
+            Direction::Outbound,
+            PrimaryMessage::Certificate(certificate(&header())),
+        ),
+        (Direction::Inbound, PrimaryMessage::Header(header())),
+    ];
+    for (direction, message) in &messages {
+        tee.capture(*direction, &bincode::serialize(message).unwrap());
+    }
+
+    // Ensure the capture holds the serialized messages in the order we captured them.
+    let captured = MessageTee::read(path).unwrap();
+    assert_eq!(captured.len(), messages.len());
+    for ((direction, message), (expected_direction, expected)) in captured.iter().zip(&messages) {
+        assert_eq!(direction, expected_direction);
+        assert_eq!(
+            bincode::serialize(message).unwrap(),
+            bincode::serialize(expected).unwrap()
+        );
+    }
+    let _ = fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn capture_core_messages() {
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_300);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store and capture.
+    let path = ".db_test_capture_core_messages";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let capture = ".db_test_capture_core_messages.capture";
+    let _ = fs::remove_file(capture);
+    let tee = MessageTee::new(capture).unwrap();
+
+    // Make the vote we expect the core to send.
+    let expected = Vote::new(&header(), &name, &mut signature_service).await;
+
+    // Spawn a listener to receive the vote.
+    let address = committee
+        .primary(&header().author)
+        .unwrap()
+        .primary_to_primary;
+    let handle = listener(address);
+
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* sync_round_gap */ 0,
+        Arc::new(DagProgress::default()),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store,
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* progress */ Arc::new(DagProgress::default()),
+        /* round_index */ Arc::default(),
+        CoreParameters::default(),
+        /* audit_log */ None,
+        /* pending_certificate */ Arc::default(),
+        /* misrouted_votes */ Arc::default(),
+        /* tx_rebroadcaster */ None,
+        /* protocol_version */ None,
+        /* message_tee */ Some(tee.clone()),
+        /* bandwidth */ Bandwidth::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        /* tx_round */ watch::channel(0).0,
+        &Supervisor::default(),
+    );
+
+    // Receive a header (the network receiver captures it before handing it to the core).
+    let bytes = bincode::serialize(&PrimaryMessage::Header(header())).unwrap();
+    tee.capture(Direction::Inbound, &bytes);
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+    let received = handle.await.unwrap();
+
+    // Ensure the capture holds the header followed by our vote, as sent over the network.
+    let captured = MessageTee::read(capture).unwrap();
+    assert_eq!(captured.len(), 2);
+    match &captured[0] {
+        (Direction::Inbound, PrimaryMessage::Header(x)) => assert_eq!(x, &header()),
+        x => panic!("Unexpected capture: {:?}", x),
+    }
+    match &captured[1] {
+        (Direction::Outbound, message @ PrimaryMessage::Vote(x)) => {
+            assert_eq!(x, &expected);
+            assert_eq!(bincode::serialize(message).unwrap(), received.to_vec());
+        }
+        x => panic!("Unexpected capture: {:?}", x),
+    }
+    let _ = fs::remove_file(capture);
+}
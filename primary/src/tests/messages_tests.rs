@@ -0,0 +1,281 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, headers, keys, votes};
+use crate::primary::PrimaryMessage;
+use async_trait::async_trait;
+use crypto::{SecretKey, Signer};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+/// The number of random inputs fed to the deserializers by each fuzz test.
+const FUZZ_ITERATIONS: usize = 20_000;
+
+/// Inputs that once made the deserialization path panic. Each is a `PrimaryMessage::Header` whose
+/// author is a well-formed base64 string decoding to less than 32 bytes.
+const REGRESSION_CORPUS: &[&[u8]] = &[
+    // Author "AAAA" (3 bytes).
+    &[0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, b'A', b'A', b'A', b'A'],
+    // Empty author.
+    &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+/// Deserialize the input as each message type and, on success, run the full verification. None
+/// of these steps may panic.
+fn parse_and_verify(bytes: &[u8], committee: &Committee) {
+    if let Ok(header) = bincode::deserialize::<Header>(bytes) {
+        let _ = header.verify(committee);
+    }
+    if let Ok(certificate) = bincode::deserialize::<Certificate>(bytes) {
+        let _ = certificate.verify(committee);
+    }
+    match bincode::deserialize::<PrimaryMessage>(bytes) {
+        Ok(PrimaryMessage::Header(header)) => {
+            let _ = header.verify(committee);
+        }
+        Ok(PrimaryMessage::Vote(vote)) => {
+            let _ = vote.verify(committee);
+        }
+        Ok(PrimaryMessage::Certificate(certificate)) => {
+            let _ = certificate.verify(committee);
+        }
+        _ => (),
+    }
+}
+
+#[test]
+fn fuzz_random_bytes() {
+    let committee = committee();
+    let mut rng = StdRng::from_seed([0; 32]);
+    for _ in 0..FUZZ_ITERATIONS {
+        let length = rng.gen_range(0, 512);
+        let bytes: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+        parse_and_verify(&bytes, &committee);
+    }
+}
+
+#[test]
+fn fuzz_mutated_messages() {
+    let committee = committee();
+    let mut rng = StdRng::from_seed([1; 32]);
+    let seeds: Vec<_> = [
+        PrimaryMessage::Header(header()),
+        PrimaryMessage::Vote(votes(&header()).pop().unwrap()),
+        PrimaryMessage::Certificate(certificate(&header())),
+    ]
+    .iter()
+    .map(|x| bincode::serialize(x).unwrap())
+    .collect();
+
+    for _ in 0..FUZZ_ITERATIONS {
+        let mut bytes = seeds[rng.gen_range(0, seeds.len())].clone();
+        match rng.gen_range(0, 3) {
+            // Flip a few random bytes.
+            0 => {
+                for _ in 0..rng.gen_range(1, 4) {
+                    let i = rng.gen_range(0, bytes.len());
+                    bytes[i] = rng.gen();
+                }
+            }
+            // Truncate the message.
+            1 => bytes.truncate(rng.gen_range(0, bytes.len())),
+            // Corrupt a length prefix (or any other 8-byte word).
+            _ => {
+                let i = rng.gen_range(0, bytes.len() - 8);
+                bytes[i..i + 8].copy_from_slice(&rng.gen::<u64>().to_le_bytes());
+            }
+        }
+        parse_and_verify(&bytes, &committee);
+    }
+}
+
+#[test]
+fn fuzz_regression_corpus() {
+    let committee = committee();
+    for bytes in REGRESSION_CORPUS {
+        parse_and_verify(bytes, &committee);
+        assert!(bincode::deserialize::<PrimaryMessage>(bytes).is_err());
+    }
+}
+
+#[test]
+fn genesis() {
+    let committee = committee();
+
+    // Ensure the genesis is deterministic.
+    let genesis = Certificate::genesis(&committee);
+    assert_eq!(genesis, Certificate::genesis(&committee));
+
+    // Ensure it is a valid set of round-0 parents: one certificate per authority forming a quorum.
+    assert!(genesis.iter().all(|x| x.round() == 0));
+    assert!(genesis.iter().all(|x| x.verify(&committee).is_ok()));
+    let authors: BTreeSet<_> = genesis.iter().map(|x| x.origin()).collect();
+    assert_eq!(authors.len(), committee.size());
+    let stake: config::Stake = authors.iter().map(|x| committee.stake(x)).sum();
+    assert!(stake >= committee.quorum_threshold());
+}
+
+#[tokio::test]
+async fn payload_order_does_not_change_digest() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let digests: Vec<_> = (0..10u8).map(|i| (Digest([i; 32]), 0)).collect();
+    let parents: BTreeSet<_> = Certificate::genesis(&committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+
+    // Make the same logical header twice, receiving the payload's digests in opposite orders.
+    let mut headers = Vec::new();
+    for payload in [digests.clone(), digests.into_iter().rev().collect()] {
+        let header = Header::new(
+            name,
+            /* round */ 1,
+            payload.into_iter().collect(),
+            parents.clone(),
+            BTreeSet::new(),
+            &mut signature_service,
+        )
+        .await;
+        headers.push(header);
+    }
+
+    // Ensure both headers have the same digest and serialization.
+    assert_eq!(headers[0].digest(), headers[1].digest());
+    assert_eq!(
+        bincode::serialize(&headers[0]).unwrap(),
+        bincode::serialize(&headers[1]).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn parents_order_does_not_change_digest() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let parents: Vec<_> = Certificate::genesis(&committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+
+    // Make the same logical header twice, gathering its parents in opposite orders.
+    let mut headers = Vec::new();
+    for parents in [parents.clone(), parents.into_iter().rev().collect()] {
+        let header = Header::new(
+            name,
+            /* round */ 1,
+            BTreeMap::new(),
+            parents.into_iter().collect(),
+            BTreeSet::new(),
+            &mut signature_service,
+        )
+        .await;
+        headers.push(header);
+    }
+
+    // Ensure both headers have the same digest and serialization.
+    assert_eq!(headers[0].digest(), headers[1].digest());
+    assert_eq!(
+        bincode::serialize(&headers[0]).unwrap(),
+        bincode::serialize(&headers[1]).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn header_accessors() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let digests: Vec<_> = (0..3u8).map(|i| Digest([i; 32])).collect();
+    let parents: BTreeSet<_> = Certificate::genesis(&committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect();
+    let header = Header::new(
+        name,
+        /* round */ 1,
+        digests.iter().map(|x| (x.clone(), 0)).collect(),
+        parents.clone(),
+        BTreeSet::new(),
+        &mut signature_service,
+    )
+    .await;
+
+    assert_eq!(header.author(), name);
+    assert_eq!(header.round(), 1);
+    assert_eq!(header.payload_digests().collect::<Vec<_>>(), digests.iter().collect::<Vec<_>>());
+    assert_eq!(header.parents(), &parents);
+}
+
+// A signer standing for an external KMS: it keeps its own secret key and records what it signs.
+struct MockSigner {
+    secret: SecretKey,
+    signed: Arc<Mutex<Vec<Digest>>>,
+}
+
+#[async_trait]
+impl Signer for MockSigner {
+    async fn sign(&self, digest: &Digest) -> Signature {
+        self.signed.lock().unwrap().push(digest.clone());
+        Signature::new(digest, &self.secret)
+    }
+}
+
+#[tokio::test]
+async fn sign_through_signer() {
+    let committee = committee();
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (voter, voter_secret) = keys.pop().unwrap();
+    let signed = Arc::new(Mutex::new(Vec::new()));
+    let mut signature_service = SignatureService::with_signer(MockSigner {
+        secret,
+        signed: signed.clone(),
+    });
+    let mut voter_service = SignatureService::with_signer(MockSigner {
+        secret: voter_secret,
+        signed: signed.clone(),
+    });
+
+    // Make a header and a vote for it, both signed through the signers.
+    let header = Header::new(
+        name,
+        /* round */ 1,
+        BTreeMap::new(),
+        Certificate::genesis(&committee)
+            .iter()
+            .map(|x| x.digest())
+            .collect(),
+        BTreeSet::new(),
+        &mut signature_service,
+    )
+    .await;
+    let vote = Vote::new(&header, &voter, &mut voter_service).await;
+
+    // Ensure the signers signed exactly the header and the vote, and that the signatures are valid.
+    assert_eq!(*signed.lock().unwrap(), vec![header.id.clone(), vote.digest()]);
+    assert!(header.verify(&committee).is_ok());
+    assert!(vote.verify(&committee).is_ok());
+}
+
+#[tokio::test]
+async fn resolve_stored_header() {
+    let path = ".db_test_resolve_stored_header";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Store a header (as the `Core` does) and resolve it from its certificate.
+    let header = header();
+    let bytes = bincode::serialize(&header).unwrap();
+    store.write(header.id.to_vec(), bytes).await;
+    let stored = certificate(&header);
+    let resolved = resolve_header(&stored, &store).await.unwrap();
+    assert_eq!(resolved.id, header.id);
+    assert_eq!(resolved.author, header.author);
+
+    // Resolving the certificate of a header we never stored fails.
+    let missing = certificate(&headers()[0]);
+    match resolve_header(&missing, &store).await {
+        Err(DagError::MissingHeader(id)) => assert_eq!(id, missing.header.id),
+        x => panic!("Unexpected result: {:?}", x.map(|x| x.id)),
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn skip_duplicate_digests() {
+    let (tx_workers, rx_workers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_skip_duplicate_digests";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn the payload receiver.
+    PayloadReceiver::spawn(store.clone(), rx_workers);
+
+    // Report the same batch twice.
+    let digest = Digest([1; 32]);
+    let worker_id = 0;
+    for _ in 0..2 {
+        tx_workers.send((digest.clone(), worker_id)).await.unwrap();
+    }
+
+    // Report another batch: once it is recorded, we know the duplicate was handled.
+    let other = Digest([2; 32]);
+    tx_workers.send((other.clone(), worker_id)).await.unwrap();
+    let key = [other.as_ref(), &worker_id.to_le_bytes()].concat();
+    store.notify_read(key).await.unwrap();
+
+    // Ensure the first batch was only written once.
+    let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
+    assert!(store.read(key).await.unwrap().is_some());
+    assert_eq!(PAYLOAD_WRITES.with(|x| x.get()), 2);
+}
@@ -0,0 +1,58 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+#[test]
+fn deprioritize_slow_peer() {
+    let mut peer_scores = PeerScores::new(committee());
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mut peers: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+    let slow = peers.pop().unwrap();
+
+    // Every peer starts with the same weight (they have the same stake).
+    assert_eq!(peer_scores.weight(&slow), peer_scores.weight(&peers[0]));
+
+    // Send successive requests to 2 peers: the slow peer only responds after 1s or times out.
+    let mut picks = Vec::new();
+    for i in 0..100 {
+        let mut candidates = peers.clone();
+        candidates.push(slow);
+        let picked = peer_scores.pick(candidates, 2, &mut rng);
+        for peer in &picked {
+            if peer != &slow {
+                peer_scores.record_success(peer, Duration::from_millis(10));
+            } else if i % 2 == 0 {
+                peer_scores.record_success(peer, Duration::from_millis(1_000));
+            } else {
+                peer_scores.record_failure(peer);
+            }
+        }
+        picks.push(picked.contains(&slow));
+    }
+
+    // The slow peer ends up with the lowest weight.
+    for peer in &peers {
+        assert!(peer_scores.weight(&slow) < peer_scores.weight(peer) / 10.0);
+    }
+
+    // It is picked less and less often, but never entirely forgotten.
+    let early = picks[..10].iter().filter(|x| **x).count();
+    let late = picks[50..].iter().filter(|x| **x).count();
+    assert!((late as f64 / 50.0) < (early as f64 / 10.0));
+    assert!(peer_scores.weight(&slow) > 0.0);
+}
+
+#[test]
+fn pick_distinct_peers() {
+    let peer_scores = PeerScores::new(committee());
+    let mut rng = StdRng::from_seed([0; 32]);
+    let peers: Vec<_> = keys().into_iter().map(|(name, _)| name).collect();
+
+    let mut picked = peer_scores.pick(peers.clone(), 10, &mut rng);
+    assert_eq!(picked.len(), peers.len());
+    picked.sort();
+    picked.dedup();
+    assert_eq!(picked.len(), peers.len());
+}
@@ -1,7 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{committee, keys};
+use crate::common::{certificate, committee, headers, keys};
+use crypto::SecretKey;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn propose_empty() {
@@ -19,9 +21,19 @@ async fn propose_empty() {
         signature_service,
         /* header_size */ 1_000,
         /* max_header_delay */ 20,
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
+        &Supervisor::default(),
     );
 
     // Ensure the proposer makes a correct empty header.
@@ -47,9 +59,19 @@ async fn propose_payload() {
         signature_service,
         /* header_size */ 32,
         /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
         /* tx_core */ tx_headers,
+        &Supervisor::default(),
     );
 
     // Send enough digests for the header payload.
@@ -66,3 +88,414 @@ async fn propose_payload() {
     assert_eq!(header.payload.get(&digest), Some(&worker_id));
     assert!(header.verify(&committee()).is_ok());
 }
+
+#[tokio::test]
+async fn pause_and_resume() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let paused = Arc::new(AtomicBool::new(true));
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn a paused proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        paused.clone(),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // Ensure the proposer does not make headers while paused.
+    let delay = Duration::from_millis(200);
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // Resume and ensure it proposes.
+    paused.store(false, AtomicOrdering::Relaxed);
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Pause again and provide the parents of the next round: no header is made.
+    paused.store(true, AtomicOrdering::Relaxed);
+    let parents: Vec<_> = headers().iter().take(3).map(certificate).collect();
+    tx_parents.send((parents, 1)).await.unwrap();
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // Resume and ensure it advances to the next round.
+    paused.store(false, AtomicOrdering::Relaxed);
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+}
+
+async fn advance_at_parents_threshold(
+    parent_quorum_policy: ParentQuorumPolicy,
+    min_distinct_parent_authors: usize,
+    threshold: usize,
+) {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        parent_quorum_policy,
+        /* max_weak_links */ 0,
+        min_distinct_parent_authors,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // The first header uses the genesis as parents.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Provide the parents of the next round one by one: the proposer only advances once
+    // it reaches the threshold.
+    let delay = Duration::from_millis(200);
+    for (i, certificate) in headers().iter().map(certificate).enumerate() {
+        tx_parents.send((vec![certificate], 1)).await.unwrap();
+        if i + 1 < threshold {
+            assert!(timeout(delay, rx_headers.recv()).await.is_err());
+        } else {
+            break;
+        }
+    }
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.parents.len(), threshold);
+}
+
+#[tokio::test]
+async fn advance_at_quorum() {
+    // The committee has 4 authorities with equal stake: the quorum is 3.
+    advance_at_parents_threshold(ParentQuorumPolicy::Quorum, 0, 3).await;
+}
+
+#[tokio::test]
+async fn advance_at_validity() {
+    // The committee has 4 authorities with equal stake: the validity threshold is 2.
+    advance_at_parents_threshold(ParentQuorumPolicy::Validity, 0, 2).await;
+}
+
+#[tokio::test]
+async fn advance_with_diverse_parents() {
+    // The quorum is 3 but we require parents from all 4 authorities.
+    advance_at_parents_threshold(ParentQuorumPolicy::Quorum, 4, 4).await;
+}
+
+#[tokio::test]
+async fn attach_weak_links() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 2,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Provide a quorum of parents for round 2.
+    let mut certificates: Vec<_> = headers().iter().map(certificate).collect();
+    let late = certificates.pop().unwrap();
+    tx_parents.send((certificates, 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert!(header.weak_links.is_empty());
+
+    // The last certificate of round 1 arrives too late to be a parent.
+    tx_parents.send((vec![late.clone()], 1)).await.unwrap();
+
+    // Provide a quorum of parents for round 3.
+    let parents: Vec<_> = headers()
+        .into_iter()
+        .take(3)
+        .map(|x| certificate(&Header { round: 2, ..x }))
+        .collect();
+    tx_parents.send((parents, 2)).await.unwrap();
+
+    // Ensure the late certificate is referenced as a weak link and does not count as a parent.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 3);
+    assert_eq!(header.parents.len(), 3);
+    assert_eq!(header.weak_links.len(), 1);
+    assert!(header.weak_links.contains(&late.digest()));
+    assert!(header.verify(&committee()).is_ok());
+}
+
+#[tokio::test]
+async fn suppress_empty_headers() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 500,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // Ensure the proposer does not make empty headers before the liveness deadline.
+    let delay = Duration::from_millis(300);
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // Ensure the proposer makes an empty header at the liveness deadline.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert!(header.payload.is_empty());
+
+    // Provide a quorum of (empty) parents for round 2 and ensure we are idle again.
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    tx_parents.send((certificates, 1)).await.unwrap();
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // Ensure the proposer resumes as soon as some payload arrives.
+    let digest = Digest(name.0);
+    tx_our_digests.send((digest.clone(), 0)).await.unwrap();
+    let header = timeout(delay, rx_headers.recv()).await.unwrap().unwrap();
+    assert_eq!(header.round, 2);
+    assert!(header.payload.contains_key(&digest));
+}
+
+#[tokio::test]
+async fn halt_at_max_dag_depth() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+    let consensus_round = Arc::new(AtomicU64::new(0));
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 20,
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 2,
+        /* slow_peer_rounds */ 0,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        consensus_round.clone(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // The proposer advances up to 2 rounds past the last committed round (nothing is committed).
+    for round in 1..=2 {
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round, round);
+        let parents: Vec<_> = headers()
+            .into_iter()
+            .map(|x| certificate(&Header { round, ..x }))
+            .collect();
+        tx_parents.send((parents, round)).await.unwrap();
+    }
+
+    // Ensure it halts while commits are stuck.
+    let delay = Duration::from_millis(300);
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // It advances again once consensus commits.
+    consensus_round.store(1, AtomicOrdering::Relaxed);
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 3);
+}
+
+// One authority never delivers its certificates. The proposer waits for it when it is the leader,
+// unless it considers it slow.
+async fn skip_slow_leader(slow_peer_rounds: Round) -> Option<Header> {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let slow = committee().leader(2);
+    assert_ne!(slow, name);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 0,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        slow_peer_rounds,
+        /* leader_priority */ false,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // Provide the parents of rounds 1 and 2 from all authorities but the slow one.
+    for round in 1..=2 {
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round, round);
+        let parents: Vec<_> = headers()
+            .into_iter()
+            .filter(|x| x.author != slow)
+            .map(|x| certificate(&Header { round, ..x }))
+            .collect();
+        tx_parents.send((parents, round)).await.unwrap();
+    }
+
+    // Return the header of round 3 (if any).
+    let delay = Duration::from_millis(300);
+    timeout(delay, rx_headers.recv()).await.ok().flatten()
+}
+
+#[tokio::test]
+async fn wait_for_leader() {
+    assert!(skip_slow_leader(/* slow_peer_rounds */ 0).await.is_none());
+}
+
+#[tokio::test]
+async fn skip_slow_leader_after_missed_rounds() {
+    let header = skip_slow_leader(/* slow_peer_rounds */ 1).await.unwrap();
+    assert_eq!(header.round, 3);
+}
+
+// The proposer is at round 1 with enough parents, but neither has a full payload nor reaches its header delay.
+// Returns the header it proposes for round 2 (if any).
+async fn propose_next_round(name: PublicKey, secret: SecretKey, leader_priority: bool) -> Option<Header> {
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        committee(),
+        signature_service,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        ParentQuorumPolicy::Quorum,
+        /* max_weak_links */ 0,
+        /* min_distinct_parent_authors */ 0,
+        /* empty_header_delay */ 0,
+        /* max_dag_depth */ 0,
+        /* slow_peer_rounds */ 0,
+        leader_priority,
+        /* paused */ Arc::new(AtomicBool::new(false)),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* tx_core */ tx_headers,
+        &Supervisor::default(),
+    );
+
+    // Provide the parents of round 1.
+    let parents: Vec<_> = headers().iter().map(certificate).collect();
+    tx_parents.send((parents, 1)).await.unwrap();
+
+    let delay = Duration::from_millis(300);
+    timeout(delay, rx_headers.recv()).await.ok().flatten()
+}
+
+#[tokio::test]
+async fn prioritize_leader_header() {
+    let leader = committee().leader(2);
+    let (name, secret) = keys().into_iter().find(|(x, _)| x == &leader).unwrap();
+
+    // The leader of round 2 proposes its header as soon as it has enough parents.
+    let header = propose_next_round(name, secret, /* leader_priority */ true).await;
+    let header = header.expect("The leader did not propose its header");
+    assert_eq!(header.round, 2);
+    assert_eq!(header.author, leader);
+
+    // Other authorities (or a leader without priority) wait for a payload or the header delay.
+    let (name, secret) = keys().into_iter().find(|(x, _)| x != &leader).unwrap();
+    assert!(propose_next_round(name, secret, /* leader_priority */ true).await.is_none());
+    let (name, secret) = keys().into_iter().find(|(x, _)| x == &leader).unwrap();
+    assert!(propose_next_round(name, secret, /* leader_priority */ false).await.is_none());
+}
@@ -0,0 +1,30 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn index_digests_by_round() {
+    let index = RoundIndex::default();
+    let digest = |round: Round, i: u8| Digest([round as u8 * 10 + i; 32]);
+
+    // Populate several rounds.
+    for round in 1..=5 {
+        for i in 0..round as u8 {
+            index.insert(round, digest(round, i));
+        }
+    }
+    index.insert(3, digest(3, 0)); // Duplicates are ignored.
+
+    // Ensure the index returns exactly the digests of each round.
+    for round in 1..=5 {
+        let expected: BTreeSet<_> = (0..round as u8).map(|i| digest(round, i)).collect();
+        assert_eq!(index.digests(round), Some(expected));
+    }
+    assert_eq!(index.digests(6), None);
+
+    // Ensure the rounds below the garbage collection round are pruned.
+    index.prune(3);
+    assert_eq!(index.digests(1), None);
+    assert_eq!(index.digests(2), None);
+    assert_eq!(index.digests(3).unwrap().len(), 3);
+    assert_eq!(index.digests(5).unwrap().len(), 5);
+}
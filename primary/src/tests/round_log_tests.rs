@@ -0,0 +1,61 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+/// A logger keeping the events shown with the default verbosity (info).
+struct CaptureLogger {
+    events: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.events
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger {
+    events: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn elevate_events_within_round_range() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Info);
+
+    // Log an event for each round, with and without a round range.
+    set_round_log_range(Some((3, 5)));
+    for round in 0..8 {
+        crate::round_debug!(round, "round-log-test in range {}", round);
+    }
+    set_round_log_range(None);
+    for round in 0..8 {
+        crate::round_debug!(round, "round-log-test no range {}", round);
+    }
+
+    // Ensure only the events of the rounds within the range are emitted, at the info level.
+    let events: Vec<_> = LOGGER
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, message)| message.starts_with("round-log-test"))
+        .cloned()
+        .collect();
+    let expected: Vec<_> = (3..=5)
+        .map(|round| (Level::Info, format!("round-log-test in range {}", round)))
+        .collect();
+    assert_eq!(events, expected);
+    assert_eq!(round_log_level(4), Level::Debug);
+}
@@ -0,0 +1,109 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+
+// A task that panics the first time it runs (in the middle of an update of its state) and reports each of
+// its runs, along with whether its state was left inconsistent.
+struct FaultyTask {
+    runs: usize,
+    updating: bool,
+    fatal: bool,
+    tx_runs: Sender<(usize, bool)>,
+}
+
+impl FaultyTask {
+    fn new(tx_runs: Sender<(usize, bool)>) -> Self {
+        Self {
+            runs: 0,
+            updating: false,
+            fatal: false,
+            tx_runs,
+        }
+    }
+
+    fn rebuild(self) -> Self {
+        Self {
+            updating: false,
+            ..self
+        }
+    }
+
+    async fn run(&mut self) {
+        self.runs += 1;
+        self.tx_runs.send((self.runs, self.updating)).await.unwrap();
+        if self.runs == 1 {
+            self.updating = true;
+            match self.fatal {
+                true => fatal("Injected fatal panic"),
+                false => panic!("Injected panic"),
+            }
+        }
+        // Keep running.
+        futures::future::pending::<()>().await;
+    }
+}
+
+fn supervisor(policy: SupervisionPolicy) -> Supervisor {
+    let policies = [("faulty".to_string(), policy)].iter().cloned().collect();
+    Supervisor::new(policies)
+}
+
+#[tokio::test]
+async fn restart_after_panic() {
+    let (tx_runs, mut rx_runs) = channel(10);
+    let supervisor = supervisor(SupervisionPolicy::Restart);
+    let task = FaultyTask::new(tx_runs);
+    supervisor.spawn("faulty", task, FaultyTask::rebuild, |x| Box::pin(x.run()));
+
+    // The task runs again after panicking, from a rebuilt state.
+    assert_eq!(rx_runs.recv().await, Some((1, false)));
+    assert_eq!(rx_runs.recv().await, Some((2, false)));
+
+    // No failure is reported.
+    assert!(timeout(Duration::from_millis(200), supervisor.failure())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn abort_after_panic() {
+    let (tx_runs, mut rx_runs) = channel(10);
+    let supervisor = supervisor(SupervisionPolicy::Abort);
+    let task = FaultyTask::new(tx_runs);
+    supervisor.spawn("faulty", task, FaultyTask::rebuild, |x| Box::pin(x.run()));
+
+    // The failure of the task is reported.
+    let failure = timeout(Duration::from_secs(1), supervisor.failure()).await;
+    assert_eq!(failure.unwrap(), "faulty");
+
+    // The task does not run again.
+    assert_eq!(rx_runs.recv().await, Some((1, false)));
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_runs.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn abort_after_fatal_panic() {
+    let (tx_runs, mut rx_runs) = channel(10);
+    let supervisor = supervisor(SupervisionPolicy::Restart);
+    let task = FaultyTask {
+        fatal: true,
+        ..FaultyTask::new(tx_runs)
+    };
+    supervisor.spawn("faulty", task, FaultyTask::rebuild, |x| Box::pin(x.run()));
+
+    // Fatal panics (e.g., storage failures) kill the node even if the task may restart.
+    let failure = timeout(Duration::from_secs(1), supervisor.failure()).await;
+    assert_eq!(failure.unwrap(), "faulty");
+    assert_eq!(rx_runs.recv().await, Some((1, false)));
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_runs.recv().await.is_none());
+}
+
+#[test]
+fn unlisted_tasks_abort() {
+    let supervisor = supervisor(SupervisionPolicy::Restart);
+    assert_eq!(supervisor.policy("faulty"), SupervisionPolicy::Restart);
+    assert_eq!(supervisor.policy("core"), SupervisionPolicy::Abort);
+}
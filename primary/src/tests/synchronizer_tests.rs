@@ -0,0 +1,67 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, headers, keys};
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+/// Make a header of the input round whose parents are the certificates of the previous round.
+fn header_at_round(round: Round) -> Header {
+    let parents = headers()
+        .into_iter()
+        .map(|x| certificate(&Header { round: round - 1, ..x }).digest())
+        .collect();
+    Header {
+        round,
+        parents,
+        ..header()
+    }
+}
+
+#[tokio::test]
+async fn range_sync_far_ahead_headers() {
+    let (name, _) = keys().pop().unwrap();
+    let (tx_header_waiter, mut rx_header_waiter) = channel(1);
+    let (tx_certificate_waiter, _rx_certificate_waiter) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_range_sync_far_ahead_headers";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Our dag is at round 3.
+    let progress = Arc::new(DagProgress::default());
+    progress.advance(3);
+
+    let mut synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store,
+        /* sync_round_gap */ 5,
+        progress,
+        tx_header_waiter,
+        tx_certificate_waiter,
+    );
+
+    // A header within the round gap makes us request its missing parents.
+    let near = header_at_round(8);
+    assert!(synchronizer.get_parents(&near).await.unwrap().is_empty());
+    match rx_header_waiter.recv().await {
+        Some(WaiterMessage::SyncParents(missing, header)) => {
+            assert_eq!(missing.len(), near.parents.len());
+            assert_eq!(header.id, near.id);
+        }
+        x => panic!("Unexpected waiter message: {:?}", x),
+    }
+
+    // A header far ahead makes us sync all the rounds we are missing.
+    let far = header_at_round(20);
+    assert!(synchronizer.get_parents(&far).await.unwrap().is_empty());
+    match rx_header_waiter.recv().await {
+        Some(WaiterMessage::RangeSync(missing, header, from)) => {
+            assert_eq!(missing.len(), far.parents.len());
+            assert_eq!(header.id, far.id);
+            assert_eq!(from, 4);
+        }
+        x => panic!("Unexpected waiter message: {:?}", x),
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Certificate;
+use crypto::Digest;
+use crypto::Hash as _;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+
+/// A bounded cache of recent certificate verification verdicts. Entries are keyed by the certificate
+/// digest together with its votes: the certificate digest alone does not cover the votes, and a bad
+/// certificate must not poison the verdict of a good one with the same digest. Verdicts are only valid
+/// for the committee that produced them, so the cache must never outlive the committee.
+pub struct VerificationCache {
+    /// The maximum number of verdicts to remember (the cache is disabled if set to 0).
+    capacity: usize,
+    /// The verdicts (valid or not) of the certificates we recently verified.
+    verdicts: HashMap<Digest, bool>,
+    /// The keys of the verdicts, oldest first.
+    order: VecDeque<Digest>,
+}
+
+impl VerificationCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            verdicts: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the key identifying a certificate and its votes (or `None` if the cache is disabled).
+    pub fn key(&self, certificate: &Certificate) -> Option<Digest> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut hasher = Sha512::new();
+        hasher.update(certificate.digest());
+        hasher.update(bincode::serialize(&certificate.votes).expect("Failed to serialize votes"));
+        Some(Digest(hasher.finalize().as_slice()[..32].try_into().unwrap()))
+    }
+
+    /// Returns the verdict of a certificate, if we remember it.
+    pub fn get(&self, key: &Digest) -> Option<bool> {
+        self.verdicts.get(key).copied()
+    }
+
+    /// Remember the verdict of a certificate, evicting the oldest verdict if the cache is full.
+    pub fn insert(&mut self, key: Digest, valid: bool) {
+        if self.verdicts.insert(key.clone(), valid).is_none() {
+            if self.order.len() == self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.verdicts.remove(&oldest);
+                }
+            }
+            self.order.push_back(key);
+        }
+    }
+}
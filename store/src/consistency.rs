@@ -0,0 +1,23 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::migration::{is_metadata, OpenError};
+use rocksdb::{IteratorMode, DB};
+
+/// Checks the consistency of the records of the store before it serves any command (e.g., to detect a store
+/// that was copied or merged incorrectly).
+pub trait ConsistencyCheck {
+    /// Inspect a record. Records are presented in key order (excluding the metadata of the store).
+    fn inspect(&mut self, key: &[u8], value: &[u8]);
+
+    /// Called once every record was inspected. Returns a diagnostic of the inconsistencies found (if any).
+    fn finish(&mut self) -> Result<(), String>;
+}
+
+/// Present every record of the store to the check.
+pub(crate) fn run_check(db: &DB, check: &mut dyn ConsistencyCheck) -> Result<(), OpenError> {
+    for (key, value) in db.iterator(IteratorMode::Start) {
+        if !is_metadata(&key) {
+            check.inspect(&key, &value);
+        }
+    }
+    check.finish().map_err(OpenError::Inconsistent)
+}
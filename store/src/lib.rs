@@ -3,6 +3,12 @@ use std::collections::{HashMap, VecDeque};
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::oneshot;
 
+mod consistency;
+mod migration;
+
+pub use crate::consistency::ConsistencyCheck;
+pub use crate::migration::{Migration, OpenError, FORMAT_VERSION_KEY, MIGRATION_PROGRESS_KEY};
+
 #[cfg(test)]
 #[path = "tests/store_tests.rs"]
 pub mod store_tests;
@@ -15,8 +21,11 @@ type Value = Vec<u8>;
 
 pub enum StoreCommand {
     Write(Key, Value),
+    WriteBatch(Vec<(Key, Value)>, oneshot::Sender<StoreResult<()>>),
+    DeleteRange(Key, Key, oneshot::Sender<StoreResult<()>>),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    Compact(Option<Key>, Option<Key>, oneshot::Sender<()>),
 }
 
 #[derive(Clone)]
@@ -27,6 +36,30 @@ pub struct Store {
 impl Store {
     pub fn new(path: &str) -> StoreResult<Self> {
         let db = rocksdb::DB::open_default(path)?;
+        Ok(Self::spawn(db))
+    }
+
+    /// Open the store and upgrade its records to the latest format version of the migrations before
+    /// serving any command. Interrupted migrations resume where they left off.
+    pub fn open(path: &str, migrations: &[Box<dyn Migration>]) -> Result<Self, OpenError> {
+        let db = rocksdb::DB::open_default(path)?;
+        migration::migrate(&db, migrations)?;
+        Ok(Self::spawn(db))
+    }
+
+    /// Open the store like `open`, and then refuse to serve it if its records fail the consistency check.
+    pub fn open_checked(
+        path: &str,
+        migrations: &[Box<dyn Migration>],
+        check: &mut dyn ConsistencyCheck,
+    ) -> Result<Self, OpenError> {
+        let db = rocksdb::DB::open_default(path)?;
+        migration::migrate(&db, migrations)?;
+        consistency::run_check(&db, check)?;
+        Ok(Self::spawn(db))
+    }
+
+    fn spawn(db: rocksdb::DB) -> Self {
         let mut obligations = HashMap::<_, VecDeque<oneshot::Sender<_>>>::new();
         let (tx, mut rx) = channel(100);
         tokio::spawn(async move {
@@ -40,6 +73,28 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::WriteBatch(entries, sender) => {
+                        let mut batch = rocksdb::WriteBatch::default();
+                        for (key, value) in &entries {
+                            batch.put(key, value);
+                        }
+                        let response = db.write(batch);
+                        if response.is_ok() {
+                            for (key, value) in entries {
+                                if let Some(mut senders) = obligations.remove(&key) {
+                                    while let Some(s) = senders.pop_front() {
+                                        let _ = s.send(Ok(value.clone()));
+                                    }
+                                }
+                            }
+                        }
+                        let _ = sender.send(response);
+                    }
+                    StoreCommand::DeleteRange(start, end, sender) => {
+                        let mut batch = rocksdb::WriteBatch::default();
+                        batch.delete_range(start, end);
+                        let _ = sender.send(db.write(batch));
+                    }
                     StoreCommand::Read(key, sender) => {
                         let response = db.get(&key);
                         let _ = sender.send(response);
@@ -56,10 +111,14 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::Compact(start, end, sender) => {
+                        db.compact_range(start, end);
+                        let _ = sender.send(());
+                    }
                 }
             }
         });
-        Ok(Self { channel: tx })
+        Self { channel: tx }
     }
 
     pub async fn write(&mut self, key: Key, value: Value) {
@@ -68,6 +127,37 @@ impl Store {
         }
     }
 
+    /// Write all the entries atomically (either all of them or none survive a crash), returning once
+    /// they are written.
+    pub async fn write_batch(&mut self, entries: Vec<(Key, Value)>) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::WriteBatch(entries, sender))
+            .await
+        {
+            panic!("Failed to send WriteBatch command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to WriteBatch command from store")
+    }
+
+    /// Delete the keys in the range [start, end), returning once they are deleted.
+    pub async fn delete_range(&mut self, start: Key, end: Key) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::DeleteRange(start, end, sender))
+            .await
+        {
+            panic!("Failed to send DeleteRange command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to DeleteRange command from store")
+    }
+
     pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
@@ -91,4 +181,20 @@ impl Store {
             .await
             .expect("Failed to receive reply to NotifyRead command from store")
     }
+
+    /// Compact the keys in the range [start, end) to reclaim the disk space of deleted and overwritten
+    /// entries. A `None` bound extends the range to the first (or last) key of the store.
+    pub async fn compact_range(&mut self, start: Option<Key>, end: Option<Key>) {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::Compact(start, end, sender))
+            .await
+        {
+            panic!("Failed to send Compact command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Compact command from store")
+    }
 }
@@ -0,0 +1,133 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{Key, StoreError, Value};
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::convert::TryInto;
+use std::fmt;
+
+/// The key holding the format version of the records of the store (a little-endian `u32`). A store
+/// without this key holds records of version 0.
+pub const FORMAT_VERSION_KEY: &[u8] = b"__store_format_version";
+
+/// The key holding the progress of an interrupted migration: the version it upgrades to (a little-endian
+/// `u32`) followed by the last key it upgraded.
+pub const MIGRATION_PROGRESS_KEY: &[u8] = b"__store_migration_progress";
+
+/// The number of records upgraded between two checkpoints of the migration progress.
+const MIGRATION_BATCH_SIZE: usize = 1_000;
+
+/// Upgrades the records of the store to a new format version.
+pub trait Migration: Send + Sync {
+    /// The format version of the records this migration produces (from records of the previous version).
+    fn version(&self) -> u32;
+
+    /// Upgrade a record, returning its new value (or `None` to leave it unchanged). Records may be
+    /// presented again if the node crashes during the migration, so the migration must leave records
+    /// already in the new format unchanged.
+    fn migrate(&self, key: &[u8], value: &[u8]) -> Option<Value>;
+}
+
+/// The reasons why a store cannot be opened.
+#[derive(Debug)]
+pub enum OpenError {
+    Store(StoreError),
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// The consistency check of the store failed (with the specified diagnostic).
+    Inconsistent(String),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Store(e) => write!(f, "{}", e),
+            Self::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Store format version {} is newer than the supported version {}",
+                found, supported
+            ),
+            Self::Inconsistent(diagnostic) => write!(f, "Inconsistent store: {}", diagnostic),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+impl From<StoreError> for OpenError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+pub(crate) fn is_metadata(key: &[u8]) -> bool {
+    key == FORMAT_VERSION_KEY || key == MIGRATION_PROGRESS_KEY
+}
+
+fn format_version(db: &DB) -> Result<u32, OpenError> {
+    Ok(db
+        .get(FORMAT_VERSION_KEY)?
+        .and_then(|x| x.as_slice().try_into().ok())
+        .map(u32::from_le_bytes)
+        .unwrap_or(0))
+}
+
+/// The last key upgraded by an interrupted migration to the specified version (if any).
+fn migration_progress(db: &DB, version: u32) -> Result<Option<Key>, OpenError> {
+    Ok(db
+        .get(MIGRATION_PROGRESS_KEY)?
+        .filter(|x| x.len() >= 4 && x[..4] == version.to_le_bytes())
+        .map(|x| x[4..].to_vec()))
+}
+
+/// Run one migration over all the records of the store, resuming after the last checkpoint (if any).
+fn run_migration(db: &DB, migration: &dyn Migration) -> Result<(), OpenError> {
+    let version = migration.version();
+    let resume_from = migration_progress(db, version)?;
+    let mode = match &resume_from {
+        Some(key) => IteratorMode::From(key, Direction::Forward),
+        None => IteratorMode::Start,
+    };
+
+    let mut batch = WriteBatch::default();
+    let mut upgraded = 0;
+    for (key, value) in db.iterator(mode) {
+        if is_metadata(&key) || resume_from.as_deref() == Some(&key[..]) {
+            continue;
+        }
+        if let Some(value) = migration.migrate(&key, &value) {
+            batch.put(&key, value);
+        }
+        upgraded += 1;
+
+        // Checkpoint our progress along with the upgraded records.
+        if upgraded % MIGRATION_BATCH_SIZE == 0 {
+            let progress = [&version.to_le_bytes()[..], &key].concat();
+            batch.put(MIGRATION_PROGRESS_KEY, progress);
+            db.write(std::mem::take(&mut batch))?;
+        }
+    }
+
+    batch.delete(MIGRATION_PROGRESS_KEY);
+    batch.put(FORMAT_VERSION_KEY, version.to_le_bytes());
+    db.write(batch)?;
+    Ok(())
+}
+
+/// Upgrade the records of the store to the latest version of the migrations (in version order).
+pub(crate) fn migrate(db: &DB, migrations: &[Box<dyn Migration>]) -> Result<(), OpenError> {
+    let mut migrations: Vec<_> = migrations.iter().collect();
+    migrations.sort_by_key(|x| x.version());
+    let supported = migrations.last().map_or(0, |x| x.version());
+
+    let found = format_version(db)?;
+    if found > supported {
+        return Err(OpenError::UnsupportedVersion { found, supported });
+    }
+    for migration in migrations.into_iter().filter(|x| x.version() > found) {
+        run_migration(db, migration.as_ref())?;
+    }
+
+    // Record the version of new stores as well.
+    if db.get(FORMAT_VERSION_KEY)?.is_none() {
+        db.put(FORMAT_VERSION_KEY, supported.to_le_bytes())?;
+    }
+    Ok(())
+}
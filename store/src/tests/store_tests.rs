@@ -1,6 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use std::fs;
+use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn create_store() {
@@ -72,3 +73,166 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn write_batch() {
+    // Create new store.
+    let path = ".db_test_write_batch";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Wait for one of the values of the batch.
+    let mut store_copy = store.clone();
+    let handle = tokio::spawn(async move { store_copy.notify_read(vec![1u8]).await });
+
+    // Write the batch and ensure all its values are readable (and notified).
+    let entries: Vec<_> = (0..3u8).map(|i| (vec![i], vec![i + 10])).collect();
+    assert!(store.write_batch(entries.clone()).await.is_ok());
+    for (key, value) in entries {
+        assert_eq!(store.read(key).await.unwrap(), Some(value));
+    }
+    assert_eq!(handle.await.unwrap().unwrap(), vec![11u8]);
+}
+
+#[tokio::test]
+async fn delete_range() {
+    // Create new store.
+    let path = ".db_test_delete_range";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write values to the store.
+    for i in 0..5u8 {
+        store.write(vec![i], vec![i]).await;
+    }
+
+    // Delete a range of keys and ensure only the keys out of the range are left.
+    store.delete_range(vec![1], vec![3]).await.unwrap();
+    for i in 0..5u8 {
+        let expected = (!(1..3).contains(&i)).then(|| vec![i]);
+        assert_eq!(store.read(vec![i]).await.unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn compact_store() {
+    // Create new store.
+    let path = ".db_test_compact_store";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write value to the store.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    store.write(key.clone(), value.clone()).await;
+
+    // Compact the whole store and ensure the value is still there.
+    store.compact_range(None, None).await;
+    let result = store.read(key).await;
+    assert_eq!(result.unwrap(), Some(value));
+}
+
+// Upgrades records to version 1 by prefixing their value with a tag.
+struct TagRecords;
+
+impl Migration for TagRecords {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn migrate(&self, _key: &[u8], value: &[u8]) -> Option<Value> {
+        match value.starts_with(b"v1:") {
+            true => None,
+            false => Some([&b"v1:"[..], value].concat()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn migrate_on_open() {
+    let path = ".db_test_migrate_on_open";
+    let _ = fs::remove_dir_all(path);
+
+    // Write records in the old format. The migration was interrupted after upgrading the first record.
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        db.put(vec![0], b"v1:zero").unwrap();
+        db.put(vec![1], b"one").unwrap();
+        db.put(vec![2], b"two").unwrap();
+        let progress = [&1u32.to_le_bytes()[..], &[0]].concat();
+        db.put(MIGRATION_PROGRESS_KEY, progress).unwrap();
+    }
+
+    // Open the store: the remaining records are upgraded.
+    let migrations: Vec<Box<dyn Migration>> = vec![Box::new(TagRecords)];
+    let mut store = Store::open(path, &migrations).unwrap();
+    for (key, value) in [(0, "v1:zero"), (1, "v1:one"), (2, "v1:two")] {
+        let read = store.read(vec![key]).await.unwrap();
+        assert_eq!(read, Some(value.as_bytes().to_vec()));
+    }
+    let version = store.read(FORMAT_VERSION_KEY.to_vec()).await.unwrap();
+    assert_eq!(version, Some(1u32.to_le_bytes().to_vec()));
+    let progress = store.read(MIGRATION_PROGRESS_KEY.to_vec()).await.unwrap();
+    assert_eq!(progress, None);
+}
+
+#[tokio::test]
+async fn reject_newer_format_version() {
+    let path = ".db_test_reject_newer_format_version";
+    let _ = fs::remove_dir_all(path);
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        db.put(FORMAT_VERSION_KEY, 2u32.to_le_bytes()).unwrap();
+    }
+
+    let migrations: Vec<Box<dyn Migration>> = vec![Box::new(TagRecords)];
+    match Store::open(path, &migrations) {
+        Err(OpenError::UnsupportedVersion { found, supported }) => {
+            assert_eq!((found, supported), (2, 1))
+        }
+        _ => panic!("Opened a store of a newer format version"),
+    }
+}
+
+// Counts the records and rejects the store if it holds more than `max` of them.
+struct MaxRecords {
+    max: usize,
+    records: usize,
+}
+
+impl ConsistencyCheck for MaxRecords {
+    fn inspect(&mut self, _key: &[u8], _value: &[u8]) {
+        self.records += 1;
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self.records > self.max {
+            true => Err(format!("{} records", self.records)),
+            false => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn check_on_open() {
+    let path = ".db_test_check_on_open";
+    let _ = fs::remove_dir_all(path);
+    {
+        let db = rocksdb::DB::open_default(path).unwrap();
+        db.put(vec![0], b"zero").unwrap();
+        db.put(vec![1], b"one").unwrap();
+    }
+
+    // The metadata of the store is not presented to the check.
+    let mut check = MaxRecords { max: 2, records: 0 };
+    let store = Store::open_checked(path, &[], &mut check).unwrap();
+    assert_eq!(check.records, 2);
+    drop(store);
+    sleep(Duration::from_millis(50)).await;
+
+    let mut check = MaxRecords { max: 1, records: 0 };
+    match Store::open_checked(path, &[], &mut check) {
+        Err(OpenError::Inconsistent(diagnostic)) => assert_eq!(diagnostic, "2 records"),
+        _ => panic!("Opened an inconsistent store"),
+    }
+}
@@ -1,4 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::counters::Counters;
+use crate::deduplicator::Deduplicator;
 use crate::quorum_waiter::QuorumWaiterMessage;
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
@@ -9,10 +11,13 @@ use crypto::PublicKey;
 use ed25519_dalek::{Digest as _, Sha512};
 #[cfg(feature = "benchmark")]
 use log::info;
+use log::debug;
 use network::ReliableSender;
+use std::collections::{HashMap, VecDeque};
 #[cfg(feature = "benchmark")]
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::{sleep, Duration, Instant};
 
@@ -23,43 +28,77 @@ pub mod batch_maker_tests;
 pub type Transaction = Vec<u8>;
 pub type Batch = Vec<Transaction>;
 
+/// The address of the client connection a transaction came from.
+pub type Source = SocketAddr;
+
 /// Assemble clients transactions into batches.
 pub struct BatchMaker {
     /// The preferred batch size (in bytes).
     batch_size: usize,
+    /// The maximum batch size (in bytes). A batch is sealed early rather than exceeding it (0 disables it).
+    max_batch_size: usize,
+    /// The maximum number of transactions per batch (0 disables it).
+    max_batch_txs: usize,
     /// The maximum delay after which to seal the batch (in ms).
     max_batch_delay: u64,
-    /// Channel to receive transactions from the network.
-    rx_transaction: Receiver<Transaction>,
+    /// Whether to interleave the transactions of the different sources (rather than batching them
+    /// in arrival order).
+    fair_batching: bool,
+    /// Channel to receive transactions (along with their source) from the network.
+    rx_transaction: Receiver<(Transaction, Source)>,
     /// Output channel to deliver sealed batches to the `QuorumWaiter`.
     tx_message: Sender<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
     workers_addresses: Vec<(PublicKey, SocketAddr)>,
+    /// Drops transactions that were (likely) already submitted, if enabled.
+    deduplicator: Option<Deduplicator>,
+    /// Keeps track of the transactions received and the batches sealed.
+    counters: Arc<Counters>,
     /// Holds the current batch.
     current_batch: Batch,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// The transactions waiting to be batched, per source (only used for fair batching).
+    queues: HashMap<Source, VecDeque<Transaction>>,
+    /// The sources with waiting transactions, in round-robin order.
+    active_sources: VecDeque<Source>,
+    /// The size of the waiting transactions (in bytes).
+    queued_size: usize,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         batch_size: usize,
+        max_batch_size: usize,
+        max_batch_txs: usize,
         max_batch_delay: u64,
-        rx_transaction: Receiver<Transaction>,
+        fair_batching: bool,
+        rx_transaction: Receiver<(Transaction, Source)>,
         tx_message: Sender<QuorumWaiterMessage>,
         workers_addresses: Vec<(PublicKey, SocketAddr)>,
+        deduplicator: Option<Deduplicator>,
+        counters: Arc<Counters>,
     ) {
         tokio::spawn(async move {
             Self {
                 batch_size,
+                max_batch_size,
+                max_batch_txs,
                 max_batch_delay,
+                fair_batching,
                 rx_transaction,
                 tx_message,
                 workers_addresses,
+                deduplicator,
+                counters,
                 current_batch: Batch::with_capacity(batch_size * 2),
                 current_batch_size: 0,
+                queues: HashMap::new(),
+                active_sources: VecDeque::new(),
+                queued_size: 0,
                 network: ReliableSender::new(),
             }
             .run()
@@ -67,6 +106,62 @@ impl BatchMaker {
         });
     }
 
+    /// Add a transaction to the current batch, and seal the batch if it is full. Returns whether
+    /// a batch was sealed.
+    async fn add(&mut self, transaction: Transaction) -> bool {
+        self.counters.add_transaction();
+        if let Some(deduplicator) = self.deduplicator.as_mut() {
+            if deduplicator.check_and_insert(&transaction) {
+                debug!("Dropping duplicate transaction");
+                return false;
+            }
+        }
+
+        // Seal the current batch early if the transaction would make it exceed the maximum size.
+        let mut sealed = false;
+        if self.max_batch_size > 0
+            && !self.current_batch.is_empty()
+            && self.current_batch_size + transaction.len() > self.max_batch_size
+        {
+            self.seal().await;
+            sealed = true;
+        }
+
+        self.current_batch_size += transaction.len();
+        self.current_batch.push(transaction);
+        if self.current_batch_size >= self.batch_size
+            || (self.max_batch_txs > 0 && self.current_batch.len() >= self.max_batch_txs)
+        {
+            self.seal().await;
+            sealed = true;
+        }
+        sealed
+    }
+
+    /// Queue a transaction behind the other transactions of its source.
+    fn enqueue(&mut self, transaction: Transaction, source: Source) {
+        self.queued_size += transaction.len();
+        let queue = self.queues.entry(source).or_default();
+        if queue.is_empty() {
+            self.active_sources.push_back(source);
+        }
+        queue.push_back(transaction);
+    }
+
+    /// Pick the next waiting transaction, taking turns among the sources.
+    fn dequeue(&mut self) -> Option<Transaction> {
+        let source = self.active_sources.pop_front()?;
+        let queue = self.queues.get_mut(&source).expect("Active source without queue");
+        let transaction = queue.pop_front().expect("Active source without transactions");
+        if queue.is_empty() {
+            self.queues.remove(&source);
+        } else {
+            self.active_sources.push_back(source);
+        }
+        self.queued_size -= transaction.len();
+        Some(transaction)
+    }
+
     /// Main loop receiving incoming transactions and creating batches.
     async fn run(&mut self) {
         let timer = sleep(Duration::from_millis(self.max_batch_delay));
@@ -75,11 +170,25 @@ impl BatchMaker {
         loop {
             tokio::select! {
                 // Assemble client transactions into batches of preset size.
-                Some(transaction) = self.rx_transaction.recv() => {
-                    self.current_batch_size += transaction.len();
-                    self.current_batch.push(transaction);
-                    if self.current_batch_size >= self.batch_size {
-                        self.seal().await;
+                Some((transaction, source)) = self.rx_transaction.recv() => {
+                    let mut sealed = false;
+                    if self.fair_batching {
+                        // Gather the transactions that are already waiting (up to two batches) and
+                        // interleave them across their sources.
+                        self.enqueue(transaction, source);
+                        while self.queued_size < 2 * self.batch_size {
+                            match self.rx_transaction.try_recv() {
+                                Ok((transaction, source)) => self.enqueue(transaction, source),
+                                Err(_) => break,
+                            }
+                        }
+                        while let Some(transaction) = self.dequeue() {
+                            sealed |= self.add(transaction).await;
+                        }
+                    } else {
+                        sealed = self.add(transaction).await;
+                    }
+                    if sealed {
                         timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
                     }
                 },
@@ -113,6 +222,7 @@ impl BatchMaker {
             .collect();
 
         // Serialize the batch.
+        self.counters.add_batch(self.current_batch_size);
         self.current_batch_size = 0;
         let batch: Vec<_> = self.current_batch.drain(..).collect();
         let message = WorkerMessage::Batch(batch);
@@ -0,0 +1,228 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::processor::{batch_digest, SerializedBatchMessage};
+use crate::worker::WorkerMessage;
+use config::{Committee, Stake};
+use crypto::Digest;
+use crypto::Hash as _;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use primary::{Certificate, DagError, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto as _;
+use store::{Store, StoreError};
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/commit_proof_tests.rs"]
+pub mod commit_proof_tests;
+
+/// The id of a transaction (the digest of its bytes).
+pub fn transaction_id(transaction: &[u8]) -> Digest {
+    Digest(Sha512::digest(transaction)[..32].try_into().unwrap())
+}
+
+#[derive(Debug, Error)]
+pub enum CommitProofError {
+    #[error("The proof holds no certificate")]
+    EmptyChain,
+
+    #[error("Certificate {0} is not the leader of round {1}")]
+    NotLeader(Digest, Round),
+
+    #[error("Invalid certificate {0}: {1}")]
+    InvalidCertificate(Digest, DagError),
+
+    #[error("Certificate {0} does not support the leader")]
+    InvalidSupport(Digest),
+
+    #[error("The leader is only supported by {0} stake")]
+    NotEnoughSupport(Stake),
+
+    #[error("Certificate {0} is not a parent of the previous certificate of the chain")]
+    BrokenChain(Digest),
+
+    #[error("Batch {0} is not referenced by the last certificate of the chain")]
+    UnreferencedBatch(Digest),
+
+    #[error("Malformed batch: {0}")]
+    MalformedBatch(#[from] Box<bincode::ErrorKind>),
+
+    #[error("The batch does not contain the transaction")]
+    MissingTransaction,
+}
+
+/// Proves that a transaction was committed by the consensus (Tusk): the transaction belongs to a batch
+/// referenced by a certificate in the causal history of a leader supported by f+1 stake in the next round,
+/// which is enough for every honest node to commit it (along with its causal history, down to the garbage
+/// collection depth). It is verified against the committee alone.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommitProof {
+    /// The committed transaction.
+    pub transaction: Transaction,
+    /// The batch holding the transaction (as sealed by the worker, so it hashes to its digest).
+    pub batch: SerializedBatchMessage,
+    /// The certificates from the committed leader (first) to the certificate referencing the batch (last).
+    /// Each certificate is a parent of the previous one.
+    pub chain: Vec<Certificate>,
+    /// The certificates of the round following the leader that reference it.
+    pub support: Vec<Certificate>,
+}
+
+impl CommitProof {
+    /// Build the proof that the transaction `id` was committed along with `leader` (a leader committed by
+    /// the consensus). The certificates are read from the store of our primary and the batches from the
+    /// store of a worker; we find nothing if the worker does not hold the batch of the transaction, or if
+    /// the transaction is not in the causal history of the leader (e.g., it is not committed yet).
+    pub async fn build(
+        id: &Digest,
+        leader: &Certificate,
+        committee: &Committee,
+        primary_store: &mut Store,
+        worker_store: &mut Store,
+    ) -> Result<Option<Self>, StoreError> {
+        // Search the causal history of the leader (breadth-first) for the batch holding the transaction,
+        // remembering through which certificate we reached each of them.
+        let mut children: HashMap<Digest, Certificate> = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(leader.clone());
+        while let Some(certificate) = queue.pop_front() {
+            for digest in certificate.header.payload_digests() {
+                let batch = match worker_store.read(digest.to_vec()).await? {
+                    Some(batch) => batch,
+                    None => continue,
+                };
+                let transaction = match Self::find(&batch, id) {
+                    Some(transaction) => transaction,
+                    None => continue,
+                };
+
+                // Walk back up to the leader.
+                let mut chain = vec![certificate.clone()];
+                while let Some(child) = children.get(&chain.last().unwrap().digest()) {
+                    chain.push(child.clone());
+                }
+                chain.reverse();
+
+                let support = Self::support(leader, committee, primary_store).await?;
+                return Ok(Some(Self {
+                    transaction,
+                    batch,
+                    chain,
+                    support,
+                }));
+            }
+
+            // The certificates below the garbage collection round (and the genesis) are not in the store.
+            for parent in certificate.header.parents() {
+                if !visited.insert(parent.clone()) {
+                    continue;
+                }
+                if let Some(bytes) = primary_store.read(parent.to_vec()).await? {
+                    let parent_certificate: Certificate =
+                        bincode::deserialize(&bytes).expect("Failed to deserialize certificate");
+                    children.insert(parent.clone(), certificate.clone());
+                    queue.push_back(parent_certificate);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The transaction `id` if the (serialized) batch holds it.
+    fn find(batch: &[u8], id: &Digest) -> Option<Transaction> {
+        match bincode::deserialize(batch) {
+            Ok(WorkerMessage::Batch(transactions)) => {
+                transactions.into_iter().find(|x| &transaction_id(x) == id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Read the certificates of the round following the leader that reference it.
+    async fn support(
+        leader: &Certificate,
+        committee: &Committee,
+        primary_store: &mut Store,
+    ) -> Result<Vec<Certificate>, StoreError> {
+        let digest = leader.digest();
+        let mut support = Vec::new();
+        for name in committee.authorities.keys() {
+            let key = Certificate::index_key(leader.round() + 1, name);
+            let child = match primary_store.read(key).await? {
+                Some(child) => child,
+                None => continue,
+            };
+            if let Some(bytes) = primary_store.read(child).await? {
+                let certificate: Certificate =
+                    bincode::deserialize(&bytes).expect("Failed to deserialize certificate");
+                if certificate.header.parents().contains(&digest) {
+                    support.push(certificate);
+                }
+            }
+        }
+        Ok(support)
+    }
+
+    /// Check the proof against the committee.
+    pub fn verify(&self, committee: &Committee) -> Result<(), CommitProofError> {
+        let leader = self.chain.first().ok_or(CommitProofError::EmptyChain)?;
+        let last = self.chain.last().ok_or(CommitProofError::EmptyChain)?;
+
+        // Ensure the first certificate is the one of an elected leader (Tusk only elects leaders at even rounds).
+        let round = leader.round();
+        if round == 0
+            || !round.is_multiple_of(2)
+            || committee.leader(round as usize) != leader.origin()
+        {
+            return Err(CommitProofError::NotLeader(leader.digest(), round));
+        }
+
+        // Ensure the leader is supported by f+1 stake of the next round.
+        let digest = leader.digest();
+        let mut supporters = HashSet::new();
+        for certificate in &self.support {
+            Self::verify_certificate(certificate, committee)?;
+            if certificate.round() != round + 1 || !certificate.header.parents().contains(&digest) {
+                return Err(CommitProofError::InvalidSupport(certificate.digest()));
+            }
+            supporters.insert(certificate.origin());
+        }
+        let stake: Stake = supporters.iter().map(|x| committee.stake(x)).sum();
+        if stake < committee.validity_threshold() {
+            return Err(CommitProofError::NotEnoughSupport(stake));
+        }
+
+        // Ensure the chain links the leader to the certificate referencing the batch.
+        Self::verify_certificate(leader, committee)?;
+        for pair in self.chain.windows(2) {
+            Self::verify_certificate(&pair[1], committee)?;
+            if !pair[0].header.parents().contains(&pair[1].digest()) {
+                return Err(CommitProofError::BrokenChain(pair[1].digest()));
+            }
+        }
+
+        // Ensure the batch is referenced by the last certificate and holds the transaction.
+        let batch = batch_digest(&self.batch);
+        if !last.header.payload_digests().any(|x| x == &batch) {
+            return Err(CommitProofError::UnreferencedBatch(batch));
+        }
+        match bincode::deserialize(&self.batch)? {
+            WorkerMessage::Batch(transactions) if transactions.contains(&self.transaction) => {
+                Ok(())
+            }
+            _ => Err(CommitProofError::MissingTransaction),
+        }
+    }
+
+    fn verify_certificate(
+        certificate: &Certificate,
+        committee: &Committee,
+    ) -> Result<(), CommitProofError> {
+        certificate
+            .verify(committee)
+            .map_err(|e| CommitProofError::InvalidCertificate(certificate.digest(), e))
+    }
+}
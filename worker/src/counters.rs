@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::WorkerId;
+use log::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::time::{interval, Duration};
+
+/// Throughput counters maintained by the worker. They provide ground-truth throughput figures,
+/// independent of the clients' view.
+#[derive(Default)]
+pub struct Counters {
+    /// The number of transactions received from the clients.
+    transactions: AtomicU64,
+    /// The number of batches sealed.
+    batches: AtomicU64,
+    /// The number of bytes of transactions sealed into batches.
+    bytes: AtomicU64,
+}
+
+impl Counters {
+    /// Record a transaction received from a client.
+    pub fn add_transaction(&self) {
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a sealed batch of `size` bytes.
+    pub fn add_batch(&self, size: usize) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn transactions(&self) -> u64 {
+        self.transactions.load(Ordering::Relaxed)
+    }
+
+    pub fn batches(&self) -> u64 {
+        self.batches.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Spawn a task periodically logging the counters (every `period` ms).
+    pub fn spawn_logger(counters: Arc<Self>, id: WorkerId, period: u64) {
+        tokio::spawn(async move {
+            let mut timer = interval(Duration::from_millis(period));
+            loop {
+                timer.tick().await;
+                info!(
+                    "Worker {} received {} txs and sealed {} batches ({} B)",
+                    id,
+                    counters.transactions(),
+                    counters.batches(),
+                    counters.bytes()
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/deduplicator_tests.rs"]
+pub mod deduplicator_tests;
+
+/// A fixed-size bloom filter over transactions.
+struct BloomFilter {
+    /// The bits of the filter.
+    bits: Vec<u64>,
+    /// The number of bits of the filter.
+    size: u64,
+    /// The number of hash functions.
+    hashes: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        // Standard bloom filter sizing: m = -n ln(p) / ln(2)^2 and k = (m / n) ln(2).
+        let capacity = capacity.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let size = (-capacity * rate.ln() / 2f64.ln().powi(2)).ceil().max(64.0) as u64;
+        let hashes = ((size as f64 / capacity) * 2f64.ln()).round().max(1.0) as u64;
+        Self {
+            bits: vec![0; (size / 64 + 1) as usize],
+            size,
+            hashes,
+        }
+    }
+
+    /// Derive the bit indices of an element from its hash (double hashing).
+    fn indices(&self, hash: &[u8]) -> impl Iterator<Item = u64> + '_ {
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap());
+        (0..self.hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.size)
+    }
+
+    fn contains(&self, hash: &[u8]) -> bool {
+        self.indices(hash)
+            .all(|i| self.bits[(i / 64) as usize] & (1 << (i % 64)) != 0)
+    }
+
+    fn insert(&mut self, hash: &[u8]) {
+        let indices: Vec<_> = self.indices(hash).collect();
+        for i in indices {
+            self.bits[(i / 64) as usize] |= 1 << (i % 64);
+        }
+    }
+}
+
+/// Best-effort detection of transactions submitted more than once. It keeps two generations of bloom
+/// filters: new transactions are recorded in the current generation, and lookups check both. Once the
+/// current generation holds `capacity` transactions it becomes the previous one (dropping the oldest),
+/// which bounds the false-positive rate while remembering at least the last `capacity` transactions.
+pub struct Deduplicator {
+    /// The number of transactions recorded by each generation.
+    capacity: usize,
+    /// The target false-positive rate of each generation.
+    false_positive_rate: f64,
+    /// The generation receiving new transactions.
+    current: BloomFilter,
+    /// The previous generation (only used for lookups).
+    previous: BloomFilter,
+    /// The number of transactions recorded in the current generation.
+    inserted: usize,
+}
+
+impl Deduplicator {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        Self {
+            capacity,
+            false_positive_rate,
+            current: BloomFilter::new(capacity, false_positive_rate),
+            previous: BloomFilter::new(capacity, false_positive_rate),
+            inserted: 0,
+        }
+    }
+
+    /// Returns `true` if the transaction was (likely) already seen. Otherwise, records it and
+    /// returns `false`.
+    pub fn check_and_insert(&mut self, transaction: &Transaction) -> bool {
+        let hash = Sha512::digest(transaction);
+        if self.current.contains(&hash) || self.previous.contains(&hash) {
+            return true;
+        }
+
+        if self.inserted >= self.capacity {
+            let fresh = BloomFilter::new(self.capacity, self.false_positive_rate);
+            self.previous = std::mem::replace(&mut self.current, fresh);
+            self.inserted = 0;
+        }
+        self.current.insert(&hash);
+        self.inserted += 1;
+        false
+    }
+}
@@ -4,13 +4,18 @@ use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
 use log::{error, warn};
 use network::SimpleSender;
+use std::collections::HashMap;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::{Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
+/// The duration of the window over which the helper's rate limits apply (in ms).
+const RATE_LIMIT_WINDOW: u64 = 1_000;
+
 /// A task dedicated to help other authorities by replying to their batch requests.
 pub struct Helper {
     /// The id of this worker.
@@ -19,10 +24,20 @@ pub struct Helper {
     committee: Committee,
     /// The persistent storage.
     store: Store,
+    /// The maximum number of batches served per second (no limit if set to 0).
+    rate_limit: usize,
+    /// The maximum number of batches served per second to a single peer (no limit if set to 0).
+    peer_rate_limit: usize,
     /// Input channel to receive batch requests.
     rx_request: Receiver<(Vec<Digest>, PublicKey)>,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
+    /// The start of the current rate limit window.
+    window: Instant,
+    /// The number of batches served during the current window.
+    served: usize,
+    /// The number of batches served to each peer during the current window.
+    served_per_peer: HashMap<PublicKey, usize>,
 }
 
 impl Helper {
@@ -30,6 +45,8 @@ impl Helper {
         id: WorkerId,
         committee: Committee,
         store: Store,
+        rate_limit: usize,
+        peer_rate_limit: usize,
         rx_request: Receiver<(Vec<Digest>, PublicKey)>,
     ) {
         tokio::spawn(async move {
@@ -37,8 +54,13 @@ impl Helper {
                 id,
                 committee,
                 store,
+                rate_limit,
+                peer_rate_limit,
                 rx_request,
                 network: SimpleSender::new(),
+                window: Instant::now(),
+                served: 0,
+                served_per_peer: HashMap::new(),
             }
             .run()
             .await;
@@ -46,9 +68,7 @@ impl Helper {
     }
 
     async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_request.recv().await {
-            // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
-
+        while let Some((mut digests, origin)) = self.rx_request.recv().await {
             // get the requestors address.
             let address = match self.committee.worker(&origin, &self.id) {
                 Ok(x) => x.worker_to_worker,
@@ -58,6 +78,20 @@ impl Helper {
                 }
             };
 
+            // Drop the part of the request exceeding our rate limits, so that serving sync requests
+            // cannot starve the other duties of the worker. The requestor will retry later.
+            let allowed = self.allowance(&origin);
+            if digests.len() > allowed {
+                warn!(
+                    "Rate limit reached: dropping {} batch requests from {}",
+                    digests.len() - allowed,
+                    origin
+                );
+                digests.truncate(allowed);
+            }
+            self.served += digests.len();
+            *self.served_per_peer.entry(origin).or_default() += digests.len();
+
             // Reply to the request (the best we can).
             for digest in digests {
                 match self.store.read(digest.to_vec()).await {
@@ -68,4 +102,22 @@ impl Helper {
             }
         }
     }
+
+    /// Returns the number of batches we may still serve to `origin` during the current window.
+    fn allowance(&mut self, origin: &PublicKey) -> usize {
+        if self.window.elapsed() >= Duration::from_millis(RATE_LIMIT_WINDOW) {
+            self.window = Instant::now();
+            self.served = 0;
+            self.served_per_peer.clear();
+        }
+        let global = match self.rate_limit {
+            0 => usize::MAX,
+            x => x.saturating_sub(self.served),
+        };
+        let peer = match self.peer_rate_limit {
+            0 => usize::MAX,
+            x => x.saturating_sub(self.served_per_peer.get(origin).copied().unwrap_or(0)),
+        };
+        std::cmp::min(global, peer)
+    }
 }
@@ -1,5 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 mod batch_maker;
+mod commit_proof;
+mod counters;
+mod deduplicator;
 mod helper;
 mod primary_connector;
 mod processor;
@@ -11,4 +14,5 @@ mod worker;
 #[path = "tests/common.rs"]
 mod common;
 
+pub use crate::commit_proof::{transaction_id, CommitProof, CommitProofError};
 pub use crate::worker::Worker;
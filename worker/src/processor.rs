@@ -25,6 +25,11 @@ pub mod processor_tests;
 /// Indicates a serialized `WorkerMessage::Batch` message.
 pub type SerializedBatchMessage = Vec<u8>;
 
+/// The digest of a serialized batch.
+pub fn batch_digest(batch: &[u8]) -> Digest {
+    Digest(Sha512::digest(batch).as_slice()[..32].try_into().unwrap())
+}
+
 /// Hashes and stores batches, it then outputs the batch's digest.
 pub struct Processor;
 
@@ -62,7 +67,7 @@ impl Processor {
 
             while let Some(batch) = rx_batch.recv().await {
                 // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+                let digest = batch_digest(&batch);
 
                 if enable_verification {
                     let batch_deser = bincode::deserialize::<WorkerMessage>(&batch).unwrap();
@@ -1,11 +1,17 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::processor::SerializedBatchMessage;
-use config::{Committee, Stake};
+use crate::processor::{batch_digest, SerializedBatchMessage};
+use crate::worker::SerializedBatchDigestMessage;
+use bytes::Bytes;
+use config::{Committee, QuorumWaitFallback, Stake, WorkerId};
 use crypto::PublicKey;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use network::CancelHandler;
+use log::warn;
+use network::{CancelHandler, ReliableSender};
+use primary::WorkerPrimaryMessage;
+use std::collections::HashSet;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -19,32 +25,60 @@ pub struct QuorumWaiterMessage {
     pub handlers: Vec<(PublicKey, CancelHandler)>,
 }
 
-/// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
+/// The QuorumWaiter waits for enough authorities (2f by default) to acknowledge reception of a batch.
+/// Batches still missing acknowledgements after the (optional) deadline are either re-broadcast to the
+/// workers that did not acknowledge them or delivered with a lower threshold. It may also report to the
+/// primary how many workers acknowledged each batch by the time it is delivered.
 pub struct QuorumWaiter {
+    /// Our worker id.
+    id: WorkerId,
     /// The committee information.
     committee: Committee,
     /// The stake of this authority.
     stake: Stake,
+    /// The stake (including our own) that must acknowledge a batch before we deliver it.
+    threshold: Stake,
+    /// The delay after which we apply the fallback to a batch missing acknowledgements (in ms).
+    /// There is no deadline if set to 0.
+    deadline: u64,
+    /// What we do with the batches missing acknowledgements after the deadline.
+    fallback: QuorumWaitFallback,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver batches for which we have enough acknowledgements.
     tx_batch: Sender<SerializedBatchMessage>,
+    /// Channel to report the replication of the delivered batches to our primary (if enabled).
+    tx_replication: Option<Sender<SerializedBatchDigestMessage>>,
+    /// A network sender to re-broadcast the batches missing acknowledgements.
+    network: ReliableSender,
 }
 
 impl QuorumWaiter {
     /// Spawn a new QuorumWaiter.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
+        id: WorkerId,
         committee: Committee,
         stake: Stake,
+        threshold: Stake,
+        deadline: u64,
+        fallback: QuorumWaitFallback,
         rx_message: Receiver<QuorumWaiterMessage>,
         tx_batch: Sender<Vec<u8>>,
+        tx_replication: Option<Sender<SerializedBatchDigestMessage>>,
     ) {
         tokio::spawn(async move {
             Self {
+                id,
                 committee,
                 stake,
+                threshold,
+                deadline,
+                fallback,
                 rx_message,
                 tx_batch,
+                tx_replication,
+                network: ReliableSender::new(),
             }
             .run()
             .await;
@@ -52,36 +86,107 @@ impl QuorumWaiter {
     }
 
     /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
+    async fn waiter(wait_for: CancelHandler, deliver: PublicKey) -> PublicKey {
         let _ = wait_for.await;
         deliver
     }
 
+    /// Re-broadcast a batch to the specified workers, returning the handlers of the new transmissions.
+    async fn rebroadcast(
+        &mut self,
+        batch: &SerializedBatchMessage,
+        missing: &HashSet<PublicKey>,
+    ) -> Vec<(PublicKey, CancelHandler)> {
+        let bytes = Bytes::from(batch.clone());
+        let mut handlers = Vec::new();
+        for name in missing {
+            match self.committee.worker(name, &self.id) {
+                Ok(x) => {
+                    let handler = self.network.send(x.worker_to_worker, bytes.clone()).await;
+                    handlers.push((*name, handler));
+                }
+                Err(e) => warn!("Failed to re-broadcast batch to {}: {}", name, e),
+            }
+        }
+        handlers
+    }
+
+    /// Report to our primary the number of workers (including ours) that acknowledged a batch.
+    async fn report_replication(&self, batch: &SerializedBatchMessage, replication: usize) {
+        if let Some(tx_replication) = &self.tx_replication {
+            let message = WorkerPrimaryMessage::Replication(batch_digest(batch), self.id, replication);
+            let message = bincode::serialize(&message)
+                .expect("Failed to serialize our own worker-primary message");
+            tx_replication
+                .send(message)
+                .await
+                .expect("Failed to report batch replication");
+        }
+    }
+
     /// Main loop.
     async fn run(&mut self) {
         while let Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv().await {
+            let mut missing: HashSet<_> = handlers.iter().map(|(name, _)| *name).collect();
+            let handlers_count = missing.len();
             let mut wait_for_quorum: FuturesUnordered<_> = handlers
                 .into_iter()
-                .map(|(name, handler)| {
-                    let stake = self.committee.stake(&name);
-                    Self::waiter(handler, stake)
-                })
+                .map(|(name, handler)| Self::waiter(handler, name))
                 .collect();
 
-            // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
-            // delivered and we send its digest to the primary (that will include it into
+            // Wait for enough nodes (the first 2f by default) to send back an Ack. Then we consider
+            // the batch delivered and we send its digest to the primary (that will include it into
             // the dag). This should reduce the amount of synching.
             let mut total_stake = self.stake;
-            while let Some(stake) = wait_for_quorum.next().await {
-                total_stake += stake;
-                if total_stake >= self.committee.quorum_threshold() {
-                    self.tx_batch
-                        .send(batch)
-                        .await
-                        .expect("Failed to deliver batch");
-                    break;
+            let mut threshold = self.threshold;
+            let deadline = sleep(Duration::from_millis(self.deadline));
+            tokio::pin!(deadline);
+            let mut deadline_armed = self.deadline > 0;
+            while total_stake < threshold {
+                tokio::select! {
+                    Some(name) = wait_for_quorum.next() => {
+                        // We may hear twice from the workers we re-broadcast the batch to.
+                        if missing.remove(&name) {
+                            total_stake += self.committee.stake(&name);
+                        }
+                    },
+                    () = &mut deadline, if deadline_armed => match self.fallback {
+                        QuorumWaitFallback::Rebroadcast => {
+                            warn!(
+                                "Batch missing acknowledgements after {} ms: re-broadcasting it to {} workers",
+                                self.deadline,
+                                missing.len()
+                            );
+                            wait_for_quorum.extend(
+                                self.rebroadcast(&batch, &missing)
+                                    .await
+                                    .into_iter()
+                                    .map(|(name, handler)| Self::waiter(handler, name)),
+                            );
+                            deadline
+                                .as_mut()
+                                .reset(Instant::now() + Duration::from_millis(self.deadline));
+                        }
+                        QuorumWaitFallback::LowerThreshold(x) => {
+                            warn!(
+                                "Batch missing acknowledgements after {} ms: lowering its threshold to {} stake",
+                                self.deadline, x
+                            );
+                            threshold = threshold.min(x);
+                            deadline_armed = false;
+                        }
+                    },
+                    else => break,
                 }
             }
+            if total_stake >= threshold {
+                let acknowledged = handlers_count - missing.len();
+                self.report_replication(&batch, 1 + acknowledged).await;
+                self.tx_batch
+                    .send(batch)
+                    .await
+                    .expect("Failed to deliver batch");
+            }
         }
     }
 }
@@ -7,7 +7,7 @@ use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error};
 use network::SimpleSender;
-use primary::PrimaryWorkerMessage;
+use primary::{PrimaryWorkerMessage, WorkerPrimaryMessage};
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::{Store, StoreError};
@@ -157,6 +157,31 @@ impl Synchronizer {
                         let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
                         self.network.send(address, Bytes::from(serialized)).await;
                     },
+                    PrimaryWorkerMessage::RequestBatches(digests) => {
+                        let address = match self.committee.primary(&self.name) {
+                            Ok(x) => x.worker_to_primary,
+                            Err(e) => {
+                                error!("Our primary is not in the committee: {}", e);
+                                continue;
+                            }
+                        };
+                        for digest in digests {
+                            // Reply with the batches we have. The primary asks again for the others.
+                            match self.store.read(digest.to_vec()).await {
+                                Ok(Some(serialized)) => match bincode::deserialize(&serialized) {
+                                    Ok(WorkerMessage::Batch(batch)) => {
+                                        let message = WorkerPrimaryMessage::Batch(digest, batch);
+                                        let serialized = bincode::serialize(&message)
+                                            .expect("Failed to serialize our own message");
+                                        self.network.send(address, Bytes::from(serialized)).await;
+                                    },
+                                    _ => error!("Corrupted batch {} in store", digest),
+                                },
+                                Ok(None) => debug!("Cannot serve missing batch {} to our primary", digest),
+                                Err(e) => error!("{}", e),
+                            }
+                        }
+                    },
                     PrimaryWorkerMessage::Cleanup(round) => {
                         // Keep track of the primary's round number.
                         self.round = round;
@@ -3,6 +3,11 @@ use super::*;
 use crate::common::transaction;
 use tokio::sync::mpsc::channel;
 
+/// Make the address of a client connection.
+fn source(port: u16) -> Source {
+    format!("127.0.0.1:{}", port).parse().unwrap()
+}
+
 #[tokio::test]
 async fn make_batch() {
     let (tx_transaction, rx_transaction) = channel(1);
@@ -11,16 +16,21 @@ async fn make_batch() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
+        /* batch_size */ 200,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 0,
         /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ false,
         rx_transaction,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        /* counters */ Arc::new(Counters::default()),
     );
 
     // Send enough transactions to seal a batch.
-    tx_transaction.send(transaction()).await.unwrap();
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction(), transaction()];
@@ -39,15 +49,20 @@ async fn batch_timeout() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
+        /* batch_size */ 200,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 0,
         /* max_batch_delay */ 50, // Ensure the timer is triggered.
+        /* fair_batching */ false,
         rx_transaction,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        /* counters */ Arc::new(Counters::default()),
     );
 
     // Do not send enough transactions to seal a batch..
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction()];
@@ -57,3 +72,180 @@ async fn batch_timeout() {
         _ => panic!("Unexpected message"),
     }
 }
+
+#[tokio::test]
+async fn drop_duplicate_transactions() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* batch_size */ 200,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 0,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ false,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ Some(Deduplicator::new(1_000, 0.001)),
+        /* counters */ Arc::new(Counters::default()),
+    );
+
+    // Submit the same transaction twice, then a distinct one.
+    let other = vec![1; 100];
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
+    tx_transaction.send((other.clone(), source(1))).await.unwrap();
+
+    // Ensure the duplicate was dropped.
+    let expected_batch = vec![transaction(), other];
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn count_transactions_and_batches() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let counters = Arc::new(Counters::default());
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* batch_size */ 200,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 0,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ false,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        counters.clone(),
+    );
+
+    // Send enough transactions to seal two batches.
+    for _ in 0..4 {
+        tx_transaction.send((transaction(), source(1))).await.unwrap();
+    }
+    for _ in 0..2 {
+        rx_message.recv().await.unwrap();
+    }
+
+    // Ensure the counters match the input.
+    assert_eq!(counters.transactions(), 4);
+    assert_eq!(counters.batches(), 2);
+    assert_eq!(counters.bytes(), 400);
+}
+
+#[tokio::test]
+async fn interleave_sources() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, mut rx_message) = channel(2);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // A fast client submits many transactions before a slow client submits a few.
+    let fast = vec![1; 100];
+    let slow = vec![2; 100];
+    for _ in 0..6 {
+        tx_transaction.send((fast.clone(), source(1))).await.unwrap();
+    }
+    for _ in 0..2 {
+        tx_transaction.send((slow.clone(), source(2))).await.unwrap();
+    }
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        /* batch_size */ 400,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 0,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ true,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        /* counters */ Arc::new(Counters::default()),
+    );
+
+    // Ensure the first batch takes turns among the clients.
+    let expected_batches = vec![
+        vec![fast.clone(), slow.clone(), fast.clone(), slow],
+        vec![fast.clone(), fast.clone(), fast.clone(), fast],
+    ];
+    for expected_batch in expected_batches {
+        let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+        match bincode::deserialize(&batch).unwrap() {
+            WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+            _ => panic!("Unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn seal_at_max_batch_size() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance whose maximum batch size fits two transactions.
+    BatchMaker::spawn(
+        /* batch_size */ 1_000,
+        /* max_batch_size */ 250,
+        /* max_batch_txs */ 0,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ false,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        /* counters */ Arc::new(Counters::default()),
+    );
+
+    // Send a third transaction that does not fit in the batch.
+    for _ in 0..3 {
+        tx_transaction.send((transaction(), source(1))).await.unwrap();
+    }
+
+    // Ensure the batch is sealed before exceeding the maximum size.
+    let expected_batch = vec![transaction(), transaction()];
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn seal_at_max_batch_txs() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance sealing batches of a single transaction.
+    BatchMaker::spawn(
+        /* batch_size */ 1_000,
+        /* max_batch_size */ 0,
+        /* max_batch_txs */ 1,
+        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        /* fair_batching */ false,
+        rx_transaction,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* deduplicator */ None,
+        /* counters */ Arc::new(Counters::default()),
+    );
+
+    // Ensure a single transaction seals a batch.
+    tx_transaction.send((transaction(), source(1))).await.unwrap();
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, vec![transaction()]),
+        _ => panic!("Unexpected message"),
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys, serialized_batch, transaction};
+use primary::{CertificateBuilder, HeaderBuilder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+/// Write a certificate to the store, as the `Core` does.
+async fn write_certificate(store: &mut Store, certificate: &Certificate) {
+    let bytes = bincode::serialize(certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let key = Certificate::index_key(certificate.round(), &certificate.origin());
+    store.write(key, certificate.digest().to_vec()).await;
+}
+
+/// Make a dag whose leader of round 2 is committed: the first certificate of round 1 references `batch`,
+/// every certificate of round 1 is a parent of the leader, and f+1 certificates of round 3 reference it.
+/// Returns the leader.
+async fn committed_dag(store: &mut Store, batch: Digest) -> Certificate {
+    let committee = committee();
+    let keys = keys();
+
+    let mut parents = BTreeSet::new();
+    for (i, (name, secret)) in keys.iter().enumerate() {
+        let payload: BTreeMap<_, _> = match i {
+            0 => [(batch.clone(), 0)].iter().cloned().collect(),
+            _ => BTreeMap::new(),
+        };
+        let header = HeaderBuilder::new(*name)
+            .round(1)
+            .genesis_parents(&committee)
+            .payload(payload)
+            .build(secret);
+        let certificate = CertificateBuilder::new(header).signers(&keys).build();
+        write_certificate(store, &certificate).await;
+        parents.insert(certificate.digest());
+    }
+
+    let (name, secret) = keys
+        .iter()
+        .find(|(name, _)| *name == committee.leader(2))
+        .unwrap();
+    let header = HeaderBuilder::new(*name)
+        .round(2)
+        .parents(parents)
+        .build(secret);
+    let leader = CertificateBuilder::new(header).signers(&keys).build();
+    write_certificate(store, &leader).await;
+
+    for (name, secret) in keys.iter().take(2) {
+        let header = HeaderBuilder::new(*name)
+            .round(3)
+            .parents([leader.digest()].iter().cloned().collect())
+            .build(secret);
+        let certificate = CertificateBuilder::new(header).signers(&keys).build();
+        write_certificate(store, &certificate).await;
+    }
+    leader
+}
+
+#[tokio::test]
+async fn prove_committed_transaction() {
+    let committee = committee();
+
+    // Create new test stores.
+    let path = ".db_test_prove_committed_transaction_primary";
+    let _ = fs::remove_dir_all(path);
+    let mut primary_store = Store::new(path).unwrap();
+    let path = ".db_test_prove_committed_transaction_worker";
+    let _ = fs::remove_dir_all(path);
+    let mut worker_store = Store::new(path).unwrap();
+
+    // Commit a batch holding our transaction.
+    let batch = serialized_batch();
+    let digest = batch_digest(&batch);
+    worker_store.write(digest.to_vec(), batch).await;
+    let leader = committed_dag(&mut primary_store, digest).await;
+
+    // Ensure we can prove the transaction was committed.
+    let id = transaction_id(&transaction());
+    let proof = CommitProof::build(
+        &id,
+        &leader,
+        &committee,
+        &mut primary_store,
+        &mut worker_store,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(proof.transaction, transaction());
+    assert_eq!(proof.chain.len(), 2);
+    assert_eq!(proof.support.len(), 2);
+    assert!(proof.verify(&committee).is_ok());
+
+    // Ensure the proof does not verify without the support of the leader.
+    let unsupported = CommitProof {
+        support: proof.support[..1].to_vec(),
+        ..proof.clone()
+    };
+    assert!(matches!(
+        unsupported.verify(&committee),
+        Err(CommitProofError::NotEnoughSupport(1))
+    ));
+
+    // Ensure the proof does not verify for another transaction.
+    let forged = CommitProof {
+        transaction: vec![1; 100],
+        ..proof
+    };
+    assert!(matches!(
+        forged.verify(&committee),
+        Err(CommitProofError::MissingTransaction)
+    ));
+}
+
+#[tokio::test]
+async fn no_proof_for_uncommitted_transaction() {
+    let committee = committee();
+
+    // Create new test stores.
+    let path = ".db_test_no_proof_for_uncommitted_transaction_primary";
+    let _ = fs::remove_dir_all(path);
+    let mut primary_store = Store::new(path).unwrap();
+    let path = ".db_test_no_proof_for_uncommitted_transaction_worker";
+    let _ = fs::remove_dir_all(path);
+    let mut worker_store = Store::new(path).unwrap();
+
+    // Commit a batch, and store another one that no certificate references.
+    let batch = serialized_batch();
+    let digest = batch_digest(&batch);
+    worker_store.write(digest.to_vec(), batch).await;
+    let leader = committed_dag(&mut primary_store, digest).await;
+
+    let transaction = vec![1; 100];
+    let uncommitted = bincode::serialize(&WorkerMessage::Batch(vec![transaction.clone()])).unwrap();
+    worker_store
+        .write(batch_digest(&uncommitted).to_vec(), uncommitted)
+        .await;
+
+    // Ensure we cannot prove the uncommitted transaction was committed.
+    let id = transaction_id(&transaction);
+    let proof = CommitProof::build(
+        &id,
+        &leader,
+        &committee,
+        &mut primary_store,
+        &mut worker_store,
+    )
+    .await
+    .unwrap();
+    assert!(proof.is_none());
+}
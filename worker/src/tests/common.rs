@@ -10,6 +10,7 @@ use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
+use std::collections::BTreeMap;
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
@@ -25,6 +26,7 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .enumerate()
@@ -54,6 +56,7 @@ pub fn committee() -> Committee {
                 )
             })
             .collect(),
+        observers: BTreeMap::new(),
     }
 }
 
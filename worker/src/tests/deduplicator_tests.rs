@@ -0,0 +1,41 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn drop_duplicates() {
+    let mut deduplicator = Deduplicator::new(/* capacity */ 100, /* false_positive_rate */ 0.001);
+    let transaction = vec![1u8; 100];
+
+    // The first submission goes through but the second is flagged as duplicate.
+    assert!(!deduplicator.check_and_insert(&transaction));
+    assert!(deduplicator.check_and_insert(&transaction));
+}
+
+#[test]
+fn distinct_transactions_pass() {
+    let mut deduplicator = Deduplicator::new(/* capacity */ 1_000, /* false_positive_rate */ 0.001);
+    for i in 0..100u64 {
+        let transaction = i.to_le_bytes().to_vec();
+        assert!(!deduplicator.check_and_insert(&transaction));
+    }
+}
+
+#[test]
+fn rotate_generations() {
+    let mut deduplicator = Deduplicator::new(/* capacity */ 10, /* false_positive_rate */ 0.001);
+    let first = vec![0u8; 8];
+    assert!(!deduplicator.check_and_insert(&first));
+
+    // Fill the current generation: the first transaction moves to the previous generation
+    // and is still remembered.
+    for i in 1..=10u64 {
+        assert!(!deduplicator.check_and_insert(&i.to_le_bytes().to_vec()));
+    }
+    assert!(deduplicator.check_and_insert(&first));
+
+    // Rotate once more: the first transaction is forgotten.
+    for i in 11..=20u64 {
+        assert!(!deduplicator.check_and_insert(&i.to_le_bytes().to_vec()));
+    }
+    assert!(!deduplicator.check_and_insert(&first));
+}
@@ -1,8 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
+use futures::stream::StreamExt as _;
 use std::fs;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::channel;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn batch_reply() {
@@ -22,7 +28,14 @@ async fn batch_reply() {
         .await;
 
     // Spawn an `Helper` instance.
-    Helper::spawn(id, committee.clone(), store, rx_request);
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        /* rate_limit */ 0,
+        /* peer_rate_limit */ 0,
+        rx_request,
+    );
 
     // Spawn a listener to receive the batch reply.
     let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
@@ -36,3 +49,67 @@ async fn batch_reply() {
     // Ensure the requestor received the batch (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+// Counts the batches received until the requestor stays idle for a while.
+fn counting_listener(address: SocketAddr) -> JoinHandle<usize> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let mut received = 0;
+        while let Ok(Some(Ok(_))) = timeout(Duration::from_millis(300), transport.next()).await {
+            received += 1;
+        }
+        received
+    })
+}
+
+#[tokio::test]
+async fn rate_limit_replies() {
+    let (tx_request, rx_request) = channel(1);
+    let mut keys = keys();
+    let (first, _) = keys.pop().unwrap();
+    let (second, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(10_000);
+
+    // Create a new test store.
+    let path = ".db_test_rate_limit_replies";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Add a batch to the store.
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` serving at most 3 batches per second (and 2 per peer).
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        /* rate_limit */ 3,
+        /* peer_rate_limit */ 2,
+        rx_request,
+    );
+
+    // Spawn listeners to receive the batch replies.
+    let handles: Vec<_> = [first, second]
+        .iter()
+        .map(|x| counting_listener(committee.worker(x, &id).unwrap().worker_to_worker))
+        .collect();
+
+    // Flood the helper with batch requests.
+    for requestor in [first, second] {
+        let digests = vec![batch_digest(); 10];
+        tx_request.send((digests, requestor)).await.unwrap();
+    }
+
+    // Ensure the first requestor hits the peer limit and the second one the global limit.
+    let received: Vec<_> = futures::future::join_all(handles)
+        .await
+        .into_iter()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(received, vec![2, 1]);
+}
@@ -1,11 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch, committee_with_base_port, keys, listener};
+use crate::processor::batch_digest;
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
 use futures::future::try_join_all;
 use network::ReliableSender;
+use primary::WorkerPrimaryMessage;
 use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration};
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -15,7 +18,17 @@ async fn wait_for_quorum() {
     let committee = committee_with_base_port(7_000);
 
     // Spawn a `QuorumWaiter` instance.
-    QuorumWaiter::spawn(committee.clone(), /* stake */ 1, rx_message, tx_batch);
+    QuorumWaiter::spawn(
+        /* id */ 0,
+        committee.clone(),
+        /* stake */ 1,
+        /* threshold */ committee.quorum_threshold(),
+        /* deadline */ 0,
+        QuorumWaitFallback::default(),
+        rx_message,
+        tx_batch,
+        /* tx_replication */ None,
+    );
 
     // Make a batch.
     let message = WorkerMessage::Batch(batch());
@@ -52,3 +65,232 @@ async fn wait_for_quorum() {
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn wait_for_configured_threshold() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_050);
+
+    // Spawn a `QuorumWaiter` instance requiring the acknowledgements of all workers.
+    QuorumWaiter::spawn(
+        /* id */ 0,
+        committee.clone(),
+        /* stake */ 1,
+        /* threshold */ 4,
+        /* deadline */ 0,
+        QuorumWaitFallback::default(),
+        rx_message,
+        tx_batch,
+        /* tx_replication */ None,
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Spawn listeners for all but one of the other workers (that is enough for a quorum).
+    let others = committee.others_workers(&myself, /* id */ &0);
+    let (names, addresses): (Vec<_>, Vec<_>) = others
+        .iter()
+        .map(|(name, x)| (*name, x.worker_to_worker))
+        .unzip();
+    let mut listener_handles: Vec<_> = addresses[1..]
+        .iter()
+        .map(|x| listener(*x, Some(expected.clone())))
+        .collect();
+
+    // Broadcast the batch through the network and forward the handlers to the `QuorumWaiter`.
+    let bytes = Bytes::from(serialized.clone());
+    let handlers = ReliableSender::new().broadcast(addresses.clone(), bytes).await;
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // A quorum acknowledged the batch, but that is not enough.
+    sleep(Duration::from_millis(500)).await;
+    assert!(rx_batch.try_recv().is_err());
+
+    // The batch is output once the last worker acknowledges it.
+    listener_handles.push(listener(addresses[0], Some(expected)));
+    let output = timeout(Duration::from_secs(5), rx_batch.recv()).await;
+    assert_eq!(output.unwrap().unwrap(), serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+}
+
+#[tokio::test]
+async fn deadline_rebroadcasts_batch() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_100);
+
+    // Spawn a `QuorumWaiter` instance requiring the acknowledgements of all workers.
+    QuorumWaiter::spawn(
+        /* id */ 0,
+        committee.clone(),
+        /* stake */ 1,
+        /* threshold */ 4,
+        /* deadline */ 200,
+        QuorumWaitFallback::Rebroadcast,
+        rx_message,
+        tx_batch,
+        /* tx_replication */ None,
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Spawn listeners for all the other workers.
+    let others = committee.others_workers(&myself, /* id */ &0);
+    let (names, addresses): (Vec<_>, Vec<_>) = others
+        .iter()
+        .map(|(name, x)| (*name, x.worker_to_worker))
+        .unzip();
+    let listener_handles: Vec<_> = addresses
+        .iter()
+        .map(|x| listener(*x, Some(expected.clone())))
+        .collect();
+
+    // Broadcast the batch to all but one worker (as if our transmission to the last one was lost).
+    let bytes = Bytes::from(serialized.clone());
+    let mut handlers = ReliableSender::new()
+        .broadcast(addresses[1..].to_vec(), bytes)
+        .await;
+    let (_lost, handler) = tokio::sync::oneshot::channel();
+    handlers.insert(0, handler);
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // The batch is missing an acknowledgement until the deadline.
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_batch.try_recv().is_err());
+
+    // The batch is then re-broadcast to the last worker, that acknowledges it.
+    let output = timeout(Duration::from_secs(5), rx_batch.recv()).await;
+    assert_eq!(output.unwrap().unwrap(), serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+}
+
+#[tokio::test]
+async fn deadline_lowers_threshold() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_150);
+
+    // Spawn a `QuorumWaiter` instance requiring the acknowledgements of all workers, but settling
+    // for a quorum after the deadline.
+    QuorumWaiter::spawn(
+        /* id */ 0,
+        committee.clone(),
+        /* stake */ 1,
+        /* threshold */ 4,
+        /* deadline */ 200,
+        QuorumWaitFallback::LowerThreshold(committee.quorum_threshold()),
+        rx_message,
+        tx_batch,
+        /* tx_replication */ None,
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Spawn listeners for all but one of the other workers (the last one is down).
+    let others = committee.others_workers(&myself, /* id */ &0);
+    let (names, addresses): (Vec<_>, Vec<_>) = others
+        .iter()
+        .map(|(name, x)| (*name, x.worker_to_worker))
+        .unzip();
+    let listener_handles: Vec<_> = addresses[1..]
+        .iter()
+        .map(|x| listener(*x, Some(expected.clone())))
+        .collect();
+
+    // Broadcast the batch through the network and forward the handlers to the `QuorumWaiter`.
+    let bytes = Bytes::from(serialized.clone());
+    let handlers = ReliableSender::new().broadcast(addresses, bytes).await;
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // A quorum acknowledged the batch, but that is not enough until the deadline.
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_batch.try_recv().is_err());
+
+    // The batch is output with the lower threshold after the deadline.
+    let output = timeout(Duration::from_secs(5), rx_batch.recv()).await;
+    assert_eq!(output.unwrap().unwrap(), serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+}
+
+#[tokio::test]
+async fn report_replication() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (tx_replication, mut rx_replication) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_200);
+
+    // Spawn a `QuorumWaiter` instance reporting the replication of its batches.
+    QuorumWaiter::spawn(
+        /* id */ 0,
+        committee.clone(),
+        /* stake */ 1,
+        /* threshold */ committee.quorum_threshold(),
+        /* deadline */ 0,
+        QuorumWaitFallback::default(),
+        rx_message,
+        tx_batch,
+        Some(tx_replication),
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Spawn listeners for all but one of the other workers (the last one is down).
+    let others = committee.others_workers(&myself, /* id */ &0);
+    let (names, addresses): (Vec<_>, Vec<_>) = others
+        .iter()
+        .map(|(name, x)| (*name, x.worker_to_worker))
+        .unzip();
+    let listener_handles: Vec<_> = addresses[1..]
+        .iter()
+        .map(|x| listener(*x, Some(expected.clone())))
+        .collect();
+
+    // Broadcast the batch through the network and forward the handlers to the `QuorumWaiter`.
+    let bytes = Bytes::from(serialized.clone());
+    let handlers = ReliableSender::new().broadcast(addresses, bytes).await;
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the `QuorumWaiter` reports the acknowledgements it received (plus our own worker).
+    let output = rx_replication.recv().await.unwrap();
+    let expected = WorkerPrimaryMessage::Replication(
+        batch_digest(&serialized),
+        /* id */ 0,
+        /* replication */ 1 + listener_handles.len(),
+    );
+    assert_eq!(output, bincode::serialize(&expected).unwrap());
+    assert_eq!(rx_batch.recv().await.unwrap(), serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+}
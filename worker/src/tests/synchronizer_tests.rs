@@ -1,6 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener};
+use crate::common::{
+    batch, batch_digest, committee_with_base_port, keys, listener, serialized_batch,
+};
 use std::fs;
 use tokio::sync::mpsc::channel;
 
@@ -45,3 +47,44 @@ async fn synchronize() {
     // Ensure the target receives the sync request.
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn reply_batch_requests() {
+    let (tx_message, rx_message) = channel(1);
+
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(12_000);
+
+    // Create a new test store holding a batch.
+    let path = ".db_test_reply_batch_requests";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(batch_digest().to_vec(), serialized_batch()).await;
+
+    // Spawn a `Synchronizer` instance.
+    Synchronizer::spawn(
+        name,
+        id,
+        committee.clone(),
+        store,
+        /* gc_depth */ 50, // Not used in this test.
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_nodes */ 3, // Not used in this test.
+        rx_message,
+    );
+
+    // Spawn a listener to receive the batch on behalf of our primary.
+    let address = committee.primary(&name).unwrap().worker_to_primary;
+    let message = WorkerPrimaryMessage::Batch(batch_digest(), batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = listener(address, Some(Bytes::from(serialized)));
+
+    // Request the batch (along with a batch we do not have).
+    let missing = Digest::default();
+    let message = PrimaryWorkerMessage::RequestBatches(vec![missing, batch_digest()]);
+    tx_message.send(message).await.unwrap();
+
+    // Ensure our primary receives the batch we have.
+    assert!(handle.await.is_ok());
+}
@@ -3,7 +3,10 @@ use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
 use network::SimpleSender;
 use primary::WorkerPrimaryMessage;
+use futures::stream::StreamExt as _;
 use std::fs;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
@@ -43,3 +46,89 @@ async fn handle_clients_transactions() {
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn reject_oversized_transactions() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_050);
+    let parameters = Parameters {
+        max_batch_size: 150,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_reject_oversized_transactions";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(name, id, committee.clone(), parameters, store);
+
+    // Send a transaction larger than the maximum batch size.
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let stream = loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Bytes::from(vec![0; 200])).await.unwrap();
+
+    // Ensure the worker rejects it.
+    let reply = transport.next().await.unwrap().unwrap();
+    assert!(reply.starts_with(b"Rejected"));
+}
+
+#[tokio::test]
+async fn limit_client_transaction_rate() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_100);
+    let parameters = Parameters {
+        max_client_transaction_rate: 5,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_limit_client_transaction_rate";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(name, id, committee.clone(), parameters, store);
+
+    // Connect two clients.
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let stream = loop {
+            match TcpStream::connect(address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        clients.push(Framed::new(stream, LengthDelimitedCodec::new()));
+    }
+    let mut compliant = clients.pop().unwrap();
+    let mut abusive = clients.pop().unwrap();
+
+    // The abusive client sends twice its limit while the other stays within it.
+    for _ in 0..10 {
+        abusive.send(Bytes::from(transaction())).await.unwrap();
+    }
+    for _ in 0..5 {
+        compliant.send(Bytes::from(transaction())).await.unwrap();
+    }
+
+    // Ensure the excess transactions of the abusive client are rejected.
+    for _ in 0..5 {
+        let reply = abusive.next().await.unwrap().unwrap();
+        assert!(reply.starts_with(b"Rejected"));
+    }
+
+    // Ensure the compliant client is unaffected.
+    let reply = tokio::time::timeout(Duration::from_millis(200), compliant.next()).await;
+    assert!(reply.is_err());
+}
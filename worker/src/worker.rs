@@ -1,5 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::batch_maker::{Batch, BatchMaker, Transaction};
+use crate::batch_maker::{Batch, BatchMaker, Source, Transaction};
+use crate::counters::Counters;
+use crate::deduplicator::Deduplicator;
 use crate::helper::Helper;
 use crate::primary_connector::PrimaryConnector;
 use crate::processor::{Processor, SerializedBatchMessage};
@@ -7,16 +9,19 @@ use crate::quorum_waiter::QuorumWaiter;
 use crate::synchronizer::Synchronizer;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
+use config::{Committee, Parameters, Stake, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::sink::SinkExt as _;
-use log::{error, info, warn};
-use network::{MessageHandler, Receiver, Writer};
+use log::{debug, error, info, warn};
+use network::{MessageHandler, Receiver, ReceiverOptions, Writer};
 use primary::PrimaryWorkerMessage;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use store::Store;
 use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
@@ -25,6 +30,12 @@ pub mod worker_tests;
 /// The default channel capacity for each channel of the worker.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The duration of the window over which the transaction rate of the clients is limited (in ms).
+const TRANSACTION_RATE_WINDOW: u64 = 1_000;
+
+/// The period at which the worker logs its throughput counters (in ms).
+pub const COUNTERS_LOG_PERIOD: u64 = 10_000;
+
 /// The primary round number.
 // TODO: Move to the primary.
 pub type Round = u64;
@@ -113,6 +124,8 @@ impl Worker {
             address,
             /* handler */
             PrimaryReceiverHandler { tx_synchronizer },
+            ReceiverOptions::default()
+                .with_max_connections(self.parameters.max_inbound_connections),
         );
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
@@ -149,15 +162,32 @@ impl Worker {
         address.set_ip("0.0.0.0".parse().unwrap());
         Receiver::spawn(
             address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
+            /* handler */
+            TxReceiverHandler {
+                tx_batch_maker,
+                peer: address, // Replaced by the address of each client connection.
+                max_transaction_size: self.parameters.max_batch_size,
+                max_transaction_rate: self.parameters.max_client_transaction_rate,
+                rate_window: Arc::new(Mutex::new((Instant::now(), 0))),
+            },
+            ReceiverOptions::default()
+                .with_max_connections(self.parameters.max_inbound_connections)
+                .with_idle_timeout(self.parameters.client_idle_timeout),
         );
 
+        // Keep track of the transactions received and the batches sealed, and log them periodically.
+        let counters = Arc::new(Counters::default());
+        Counters::spawn_logger(counters.clone(), self.id, COUNTERS_LOG_PERIOD);
+
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other workers that share the same `id` as us. Finally, it
         // gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
         BatchMaker::spawn(
             self.parameters.batch_size,
+            self.parameters.max_batch_size,
+            self.parameters.max_batch_txs,
             self.parameters.max_batch_delay,
+            self.parameters.fair_batching,
             /* rx_transaction */ rx_batch_maker,
             /* tx_message */ tx_quorum_waiter,
             /* workers_addresses */
@@ -166,15 +196,34 @@ impl Worker {
                 .iter()
                 .map(|(name, addresses)| (*name, addresses.worker_to_worker))
                 .collect(),
+            /* deduplicator */
+            (self.parameters.dedup_capacity > 0).then(|| {
+                Deduplicator::new(
+                    self.parameters.dedup_capacity,
+                    self.parameters.dedup_false_positive_rate,
+                )
+            }),
+            counters,
         );
 
-        // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
-        // the batch to the `Processor`.
+        // The `QuorumWaiter` waits for 2f authorities (or the configured threshold) to acknowledge reception
+        // of the batch. It then forwards the batch to the `Processor`.
+        let total_stake: Stake = self.committee.authorities.values().map(|x| x.stake).sum();
+        let ack_threshold = match self.parameters.batch_ack_threshold {
+            0 => self.committee.quorum_threshold(),
+            x => x.min(total_stake),
+        };
         QuorumWaiter::spawn(
+            self.id,
             self.committee.clone(),
             /* stake */ self.committee.stake(&self.name),
+            ack_threshold,
+            self.parameters.quorum_wait_deadline,
+            self.parameters.quorum_wait_fallback,
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
+            /* tx_replication */
+            (self.parameters.batch_replication_target > 0).then(|| tx_primary.clone()),
         );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
@@ -213,6 +262,8 @@ impl Worker {
                 tx_helper,
                 tx_processor,
             },
+            ReceiverOptions::default()
+                .with_max_connections(self.parameters.max_inbound_connections),
         );
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
@@ -220,6 +271,8 @@ impl Worker {
             self.id,
             self.committee.clone(),
             self.store.clone(),
+            self.parameters.helper_rate_limit,
+            self.parameters.helper_peer_rate_limit,
             /* rx_request */ rx_helper,
         );
 
@@ -244,15 +297,70 @@ impl Worker {
 /// Defines how the network receiver handles incoming transactions.
 #[derive(Clone)]
 struct TxReceiverHandler {
-    tx_batch_maker: Sender<Transaction>,
+    tx_batch_maker: Sender<(Transaction, Source)>,
+    /// The address of the client connection (used to tag its transactions).
+    peer: Source,
+    /// The size (in bytes) above which transactions are rejected, since they could not fit in a
+    /// batch (0 disables it).
+    max_transaction_size: usize,
+    /// The maximum number of transactions accepted per second from the client (0 disables it).
+    max_transaction_rate: usize,
+    /// The start of the current rate window and the number of transactions accepted during it.
+    rate_window: Arc<Mutex<(Instant, usize)>>,
+}
+
+impl TxReceiverHandler {
+    /// Whether the client may send one more transaction within its rate limit (if any).
+    fn within_rate(&self) -> bool {
+        if self.max_transaction_rate == 0 {
+            return true;
+        }
+        let mut guard = self
+            .rate_window
+            .lock()
+            .expect("Failed to lock the rate window");
+        let (window, accepted) = &mut *guard;
+        if window.elapsed() >= Duration::from_millis(TRANSACTION_RATE_WINDOW) {
+            *window = Instant::now();
+            *accepted = 0;
+        }
+        if *accepted >= self.max_transaction_rate {
+            return false;
+        }
+        *accepted += 1;
+        true
+    }
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        // Reject the transactions that cannot fit in a batch and let the client know.
+        if self.max_transaction_size > 0 && message.len() > self.max_transaction_size {
+            debug!("Rejecting transaction of {} B from {}", message.len(), self.peer);
+            let reply = format!(
+                "Rejected: transaction of {} B exceeds the maximum batch size ({} B)",
+                message.len(),
+                self.max_transaction_size
+            );
+            let _ = writer.send(Bytes::from(reply)).await;
+            return Ok(());
+        }
+
+        // Drop the transactions exceeding the rate limit of the client and let it know.
+        if !self.within_rate() {
+            debug!("Dropping transaction from {} (rate limit exceeded)", self.peer);
+            let reply = format!(
+                "Rejected: transaction rate exceeds the limit ({} tx/s)",
+                self.max_transaction_rate
+            );
+            let _ = writer.send(Bytes::from(reply)).await;
+            return Ok(());
+        }
+
         // Send the transaction to the batch maker.
         self.tx_batch_maker
-            .send(message.to_vec())
+            .send((message.to_vec(), self.peer))
             .await
             .expect("Failed to send transaction");
 
@@ -260,6 +368,12 @@ impl MessageHandler for TxReceiverHandler {
         tokio::task::yield_now().await;
         Ok(())
     }
+
+    fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = peer;
+        // Every connection gets its own rate window.
+        self.rate_window = Arc::new(Mutex::new((Instant::now(), 0)));
+    }
 }
 
 /// Defines how the network receiver handles incoming workers messages.